@@ -0,0 +1,511 @@
+use crate::bus::Mem;
+use crate::cpu::CPU;
+use crate::loader::{
+    APPLESOFT_TOKENS, ARYTAB, PRGEND, STREND, TOKEN_DATA, TOKEN_REM, TXTTAB, VARTAB, read_u16,
+};
+use std::fmt;
+use std::io;
+
+/*
+Applesoft program and variables in memory
+
+    Program           Lines from TXTTAB ($67) to PRGEND ($AF). Each line is link (2), line
+                      number (2), tokens and 0. The program ends with a zero link.
+    Simple variables  From VARTAB ($69) to ARYTAB ($6B), 7 bytes each. The high bits of
+                      the two name bytes are the type.
+                          Real      0 0    5 bytes floating point
+                          Integer   1 1    2 bytes, high byte first
+                          String    0 1    length, address of the string
+                          Function  1 0    address of the definition
+    Arrays            From ARYTAB ($6B) to STREND ($6D). Each array is name (2), size (2),
+                      number of dimensions (1) and the size of each dimension (2, high
+                      byte first, last dimension first) followed by the elements. The
+                      first subscript varies fastest.
+*/
+
+const PROGRAM_END: u16 = 0xc000;
+const MAX_ELEMENTS: usize = 0x10000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariableValue {
+    Real(f64),
+    Integer(i16),
+    String(String),
+    Function(u16),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub value: VariableValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayVariable {
+    pub name: String,
+    /// Size of each dimension in the order of the DIM statement
+    pub dimensions: Vec<u16>,
+    pub values: Vec<VariableValue>,
+}
+
+fn invalid_data(s: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, s.to_string())
+}
+
+fn is_word_token(token: u8) -> bool {
+    let keyword = APPLESOFT_TOKENS[(token - 0x80) as usize];
+    keyword.len() > 1 && keyword.as_bytes()[0].is_ascii_alphabetic()
+}
+
+// Returns true when a space is needed after the keyword
+fn append_token(text: &mut String, token: u8) -> bool {
+    let Some(keyword) = APPLESOFT_TOKENS.get((token - 0x80) as usize) else {
+        text.push_str(&format!("{{${token:02X}}}"));
+        return false;
+    };
+
+    if !is_word_token(token) {
+        text.push_str(keyword);
+        return false;
+    }
+
+    if text
+        .chars()
+        .last()
+        .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '"' | ')' | '$' | '%'))
+    {
+        text.push(' ');
+    }
+    text.push_str(keyword);
+    !keyword.ends_with(['(', '=', ':']) && token != TOKEN_REM && token != TOKEN_DATA
+}
+
+fn detokenize_line(line: &[u8]) -> String {
+    let mut text = String::new();
+    let mut data = false;
+    let mut remark = false;
+    let mut quote = false;
+    let mut space = false;
+    for &value in line {
+        if std::mem::take(&mut space) && value != b' ' {
+            text.push(' ');
+        }
+
+        if value < 0x80 || data || quote || remark {
+            if value == b'"' {
+                quote = !quote;
+            } else if value == b':' && !quote {
+                data = false;
+            }
+            text.push((value & 0x7f) as char);
+            continue;
+        }
+
+        data = value == TOKEN_DATA;
+        remark = value == TOKEN_REM;
+        space = append_token(&mut text, value);
+    }
+    text
+}
+
+/// Convert the tokenized Applesoft program stored at `start` to text
+pub fn detokenize_applesoft(program: &[u8], start: u16) -> io::Result<String> {
+    let mut text = String::new();
+    let mut addr = start as usize;
+    loop {
+        let offset = addr - start as usize;
+        let header = program
+            .get(offset..offset + 2)
+            .ok_or_else(|| invalid_data("Applesoft program is truncated"))?;
+        let link = u16::from_le_bytes([header[0], header[1]]) as usize;
+        if header[1] == 0 {
+            break;
+        }
+
+        let header = program
+            .get(offset + 2..offset + 4)
+            .ok_or_else(|| invalid_data("Applesoft program is truncated"))?;
+        let number = u16::from_le_bytes([header[0], header[1]]);
+        let line = &program[offset + 4..];
+        let len = line
+            .iter()
+            .position(|&value| value == 0)
+            .ok_or_else(|| invalid_data("Applesoft program is truncated"))?;
+
+        text.push_str(&format!("{number} {}\n", detokenize_line(&line[..len])));
+
+        // The links are only used to find the next line. Relocated programs still
+        // have the line ending at the end of the tokens
+        let next = addr + 4 + len + 1;
+        if link != next && link <= addr {
+            return Err(invalid_data("Invalid Applesoft line link"));
+        }
+        addr = next;
+    }
+    Ok(text)
+}
+
+/// List the Applesoft program in memory by following the line links from TXTTAB.
+/// The listing stops at PRGEND, at the zero link or at a link that does not increase
+pub fn list_applesoft(cpu: &mut CPU) -> io::Result<String> {
+    let start = read_u16(cpu, TXTTAB);
+    if start == 0 || start >= PROGRAM_END {
+        return Err(invalid_data("Applesoft program not found"));
+    }
+
+    // PRGEND is not set when the program is typed in
+    let end = match read_u16(cpu, PRGEND) {
+        end if end > start && end <= PROGRAM_END => end,
+        _ => PROGRAM_END,
+    };
+
+    let mut text = String::new();
+    let mut addr = start;
+    while end - addr >= 2 {
+        let link = read_u16(cpu, addr);
+        if link >> 8 == 0 {
+            break;
+        }
+        if link <= addr || link > end || link - addr < 5 {
+            return Err(invalid_data("Invalid Applesoft line link"));
+        }
+
+        let number = read_u16(cpu, addr + 2);
+        let line: Vec<u8> = (addr + 4..link)
+            .map(|addr| cpu.bus.unclocked_addr_read(addr))
+            .take_while(|&value| value != 0)
+            .collect();
+        text.push_str(&format!("{number} {}\n", detokenize_line(&line)));
+        addr = link;
+    }
+    Ok(text)
+}
+
+/// Convert the Applesoft floating point number (exponent and 4 bytes mantissa)
+pub fn real_to_f64(value: &[u8; 5]) -> f64 {
+    if value[0] == 0 {
+        return 0.0;
+    }
+    let mantissa = u32::from_be_bytes([value[1] | 0x80, value[2], value[3], value[4]]);
+    let result = mantissa as f64 * 2f64.powi(value[0] as i32 - 128 - 32);
+    if value[1] & 0x80 != 0 {
+        -result
+    } else {
+        result
+    }
+}
+
+/// Format the number with 9 significant digits the same way as PRINT
+pub fn format_real(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+
+    let sign = if value < 0.0 { "-" } else { "" };
+    let formatted = format!("{:.8e}", value.abs());
+    let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let digits = mantissa.replace('.', "");
+    let digits = digits.trim_end_matches('0');
+
+    if !(-2..=8).contains(&exponent) {
+        let fraction = if digits.len() > 1 {
+            format!(".{}", &digits[1..])
+        } else {
+            String::new()
+        };
+        let exp_sign = if exponent < 0 { '-' } else { '+' };
+        return format!(
+            "{sign}{}{fraction}E{exp_sign}{:02}",
+            &digits[..1],
+            exponent.abs()
+        );
+    }
+
+    if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        return format!("{sign}.{zeros}{digits}");
+    }
+
+    let int_len = exponent as usize + 1;
+    if digits.len() <= int_len {
+        format!("{sign}{digits}{}", "0".repeat(int_len - digits.len()))
+    } else {
+        format!("{sign}{}.{}", &digits[..int_len], &digits[int_len..])
+    }
+}
+
+impl fmt::Display for VariableValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariableValue::Real(value) => write!(f, "{}", format_real(*value)),
+            VariableValue::Integer(value) => write!(f, "{value}"),
+            VariableValue::String(value) => write!(f, "\"{value}\""),
+            VariableValue::Function(addr) => write!(f, "${addr:04X}"),
+        }
+    }
+}
+
+impl ArrayVariable {
+    /// The subscripts of the element at `index`
+    pub fn subscripts(&self, index: usize) -> Vec<usize> {
+        let mut index = index;
+        self.dimensions
+            .iter()
+            .map(|&size| {
+                let size = (size as usize).max(1);
+                let subscript = index % size;
+                index /= size;
+                subscript
+            })
+            .collect()
+    }
+}
+
+// Variable kinds from the high bits of the two name bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Real,
+    Integer,
+    String,
+    Function,
+}
+
+fn variable_name(n1: u8, n2: u8) -> (String, Kind) {
+    let kind = match (n1 & 0x80 != 0, n2 & 0x80 != 0) {
+        (false, false) => Kind::Real,
+        (true, true) => Kind::Integer,
+        (false, true) => Kind::String,
+        (true, false) => Kind::Function,
+    };
+
+    let mut name = String::new();
+    if kind == Kind::Function {
+        name.push_str("FN ");
+    }
+    name.push((n1 & 0x7f) as char);
+    if n2 & 0x7f != 0 {
+        name.push((n2 & 0x7f) as char);
+    }
+    match kind {
+        Kind::Integer => name.push('%'),
+        Kind::String => name.push('$'),
+        _ => {}
+    }
+    (name, kind)
+}
+
+fn read_bytes<const N: usize>(cpu: &mut CPU, addr: u16) -> [u8; N] {
+    let mut value = [0u8; N];
+    for (i, item) in value.iter_mut().enumerate() {
+        *item = cpu.bus.unclocked_addr_read(addr.wrapping_add(i as u16));
+    }
+    value
+}
+
+fn read_value(cpu: &mut CPU, kind: Kind, addr: u16) -> VariableValue {
+    match kind {
+        Kind::Real => VariableValue::Real(real_to_f64(&read_bytes(cpu, addr))),
+        Kind::Integer => VariableValue::Integer(i16::from_be_bytes(read_bytes(cpu, addr))),
+        Kind::String => {
+            let [len, lo, hi] = read_bytes(cpu, addr);
+            let start = u16::from_le_bytes([lo, hi]);
+            let value = (0..len as u16)
+                .map(|i| (cpu.bus.unclocked_addr_read(start.wrapping_add(i)) & 0x7f) as char)
+                .collect();
+            VariableValue::String(value)
+        }
+        Kind::Function => VariableValue::Function(read_u16(cpu, addr)),
+    }
+}
+
+fn element_size(kind: Kind) -> u16 {
+    match kind {
+        Kind::Real => 5,
+        Kind::Integer => 2,
+        Kind::String => 3,
+        Kind::Function => 2,
+    }
+}
+
+/// Read the simple variables from VARTAB to ARYTAB
+pub fn simple_variables(cpu: &mut CPU) -> io::Result<Vec<Variable>> {
+    let start = read_u16(cpu, VARTAB);
+    let end = read_u16(cpu, ARYTAB);
+    if end < start {
+        return Err(invalid_data("Invalid Applesoft variable pointers"));
+    }
+
+    let mut variables = Vec::new();
+    for addr in (start..end).step_by(7) {
+        if end - addr < 7 {
+            break;
+        }
+        let [n1, n2] = read_bytes(cpu, addr);
+        let (name, kind) = variable_name(n1, n2);
+        let value = read_value(cpu, kind, addr + 2);
+        variables.push(Variable { name, value });
+    }
+    Ok(variables)
+}
+
+/// Read the arrays from ARYTAB to STREND
+pub fn arrays(cpu: &mut CPU) -> io::Result<Vec<ArrayVariable>> {
+    let start = read_u16(cpu, ARYTAB);
+    let end = read_u16(cpu, STREND);
+    if end < start {
+        return Err(invalid_data("Invalid Applesoft array pointers"));
+    }
+
+    // The addresses are computed as usize and the array must end before STREND, so
+    // the element addresses never wrap around
+    let mut result = Vec::new();
+    let mut addr = start;
+    while end - addr >= 5 {
+        let [n1, n2] = read_bytes(cpu, addr);
+        let size = read_u16(cpu, addr + 2);
+        let count = cpu.bus.unclocked_addr_read(addr + 4);
+        let array_end = addr as usize + size as usize;
+        let data = addr as usize + 5 + count as usize * 2;
+        if size < 5 || array_end > end as usize || data > array_end {
+            return Err(invalid_data("Invalid Applesoft array"));
+        }
+
+        let (name, kind) = variable_name(n1, n2);
+        let mut dimensions: Vec<u16> = (0..count as usize)
+            .map(|i| u16::from_be_bytes(read_bytes(cpu, (addr as usize + 5 + i * 2) as u16)))
+            .collect();
+        dimensions.reverse();
+
+        let elements = dimensions
+            .iter()
+            .try_fold(1usize, |total, &size| total.checked_mul(size as usize))
+            .filter(|&total| total <= MAX_ELEMENTS)
+            .ok_or_else(|| invalid_data("Invalid Applesoft array"))?;
+        let element_len = element_size(kind) as usize;
+        if data + elements * element_len > array_end {
+            return Err(invalid_data("Invalid Applesoft array"));
+        }
+
+        let values = (0..elements)
+            .map(|i| read_value(cpu, kind, (data + i * element_len) as u16))
+            .collect();
+        result.push(ArrayVariable {
+            name,
+            dimensions,
+            values,
+        });
+        addr = array_end as u16;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::loader::tokenize_applesoft;
+
+    #[test]
+    fn detokenize_program() {
+        let text = "10 HOME : FOR I = 1 TO 10\n\
+            20 PRINT \"HELLO, WORLD\";TAB( 5);SCORE\n\
+            30 DATA 1, TWO ,3:HTAB 3\n\
+            40 IF A$ = \"GOTO\" THEN 10\n\
+            50 REM  DONE: PRINT\n";
+        let program = tokenize_applesoft(text, 0x801).unwrap();
+        let listing = detokenize_applesoft(&program, 0x801).unwrap();
+        assert_eq!(
+            listing,
+            "10 HOME :FOR I=1 TO 10\n\
+            20 PRINT \"HELLO, WORLD\";TAB(5);SC OR E\n\
+            30 DATA 1, TWO ,3:HTAB 3\n\
+            40 IF A$=\"GOTO\" THEN 10\n\
+            50 REM  DONE: PRINT\n"
+        );
+        assert_eq!(tokenize_applesoft(&listing, 0x801).unwrap(), program);
+    }
+
+    #[test]
+    fn format_real_numbers() {
+        assert_eq!(real_to_f64(&[0x81, 0x00, 0x00, 0x00, 0x00]), 1.0);
+        assert_eq!(real_to_f64(&[0x84, 0xa0, 0x00, 0x00, 0x00]), -10.0);
+        assert_eq!(real_to_f64(&[0x80, 0x00, 0x00, 0x00, 0x00]), 0.5);
+        assert_eq!(format_real(0.0), "0");
+        assert_eq!(format_real(-10.0), "-10");
+        assert_eq!(format_real(0.5), ".5");
+        assert_eq!(format_real(3.25), "3.25");
+        assert_eq!(format_real(0.015), ".015");
+        assert_eq!(format_real(0.001), "1E-03");
+        assert_eq!(format_real(1e9), "1E+09");
+        assert_eq!(format_real(123456789.0), "123456789");
+        assert_eq!(format_real(1.0 / 3.0), ".333333333");
+    }
+
+    #[test]
+    fn read_variables() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+
+        let variables = [
+            0x41, 0x00, 0x82, 0x40, 0x00, 0x00, 0x00, // A = 3
+            0xc9, 0x80, 0xff, 0xfe, 0x00, 0x00, 0x00, // I% = -2
+            0x4e, 0xc1, 0x02, 0x00, 0x90, 0x00, 0x00, // NA$ = "HI"
+        ];
+        let array = [
+            0xc2, 0x80, 0x15, 0x00, 0x02, 0x00, 0x02, 0x00, 0x03, // B%(2,1)
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x04, 0x00, 0x05,
+        ];
+        cpu.load(&variables, 0x0900);
+        cpu.load(&array, 0x0915);
+        cpu.load(b"HI", 0x9000);
+        cpu.load(&[0x00, 0x09, 0x15, 0x09, 0x2a, 0x09], VARTAB);
+
+        let variables = simple_variables(&mut cpu).unwrap();
+        let names: Vec<_> = variables.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["A", "I%", "NA$"]);
+        assert_eq!(variables[0].value.to_string(), "3");
+        assert_eq!(variables[1].value, VariableValue::Integer(-2));
+        assert_eq!(variables[2].value.to_string(), "\"HI\"");
+
+        let arrays = arrays(&mut cpu).unwrap();
+        assert_eq!(arrays.len(), 1);
+        assert_eq!(arrays[0].name, "B%");
+        assert_eq!(arrays[0].dimensions, [3, 2]);
+        assert_eq!(arrays[0].values[4], VariableValue::Integer(4));
+        assert_eq!(arrays[0].subscripts(4), [1, 1]);
+
+        // The dimensions go past the end of the array
+        cpu.load(&[0xff], 0x0919);
+        assert!(super::arrays(&mut cpu).is_err());
+    }
+
+    #[test]
+    fn list_program_in_memory() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+
+        let text = "10 HOME\n20 PRINT \"HI\"\n30 END\n";
+        let program = tokenize_applesoft(text, 0x801).unwrap();
+        cpu.load(&program, 0x801);
+        cpu.load(&[0x01, 0x08], TXTTAB);
+        assert_eq!(
+            list_applesoft(&mut cpu).unwrap(),
+            "10 HOME\n20 PRINT \"HI\"\n30 END\n"
+        );
+
+        // The listing stops at PRGEND
+        let line2 = read_u16(&mut cpu, 0x801);
+        let line3 = read_u16(&mut cpu, line2);
+        cpu.load(&line3.to_le_bytes(), PRGEND);
+        assert_eq!(
+            list_applesoft(&mut cpu).unwrap(),
+            "10 HOME\n20 PRINT \"HI\"\n"
+        );
+
+        // The link of the first line points to itself
+        cpu.load(&[0x00, 0x00], PRGEND);
+        cpu.load(&[0x01, 0x08], 0x801);
+        assert!(list_applesoft(&mut cpu).is_err());
+    }
+}
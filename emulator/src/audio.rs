@@ -1,5 +1,7 @@
 use crate::bus::Tick;
+use crate::cassette::{self, TapeRecord};
 use crate::mockingboard::Mockingboard;
+use std::collections::VecDeque;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
@@ -17,14 +19,14 @@ const PAL_14M: usize = 15625 * 912;
 // NTSC cpu is clocked at 1.022 MHz (NTSC Horizontal Hz = 15734, Apple Horizontal is 15700)
 const NTSC_14M: usize = 15700 * 912;
 //const NTSC_14M: usize = 14318181;
-const CPU_6502_MHZ: usize = (NTSC_14M * 65) / 912;
+pub(crate) const CPU_6502_MHZ: usize = (NTSC_14M * 65) / 912;
 const CPU_6502_PAL_MHZ: usize = (PAL_14M * 65) / 912;
 const MAX_AMPLITUDE: Channel = Channel::MAX;
 const FAST_DAMPING_RATE: isize = -1900;
 const FAST_RESONANCE_FREQ: usize = 3875;
 const TAPE_TOLERANCE: u8 = 8;
-const TAPE_HIGH_LEVEL: u8 = 224;
-const TAPE_LOW_LEVEL: u8 = 32;
+pub(crate) const TAPE_HIGH_LEVEL: u8 = 224;
+pub(crate) const TAPE_LOW_LEVEL: u8 = 32;
 
 const RIFF_HEADER: &[u8] = b"RIFF";
 const WAVE_CHUNK: &[u8] = b"WAVE";
@@ -250,6 +252,8 @@ struct Tape {
     pos: usize,
     record: bool,
     play: bool,
+    // Records of a .bin, .bas or .ct2 tape for the fast tape load
+    records: VecDeque<TapeRecord>,
 }
 
 impl Tape {
@@ -280,6 +284,7 @@ pub struct Audio {
     enable_save: bool,
     #[cfg_attr(feature = "serde_support", serde(skip))]
     tape: Tape,
+    fast_tape: bool,
 }
 
 #[derive(Debug)]
@@ -313,6 +318,7 @@ impl Audio {
             level: 0.0,
             enable_save: false,
             tape: Tape::default(),
+            fast_tape: false,
         }
     }
 
@@ -464,16 +470,89 @@ impl Audio {
         self.tape.filename = Some(name.into());
         if std::fs::metadata(name).is_ok() {
             let tape = std::fs::read(name)?;
-            self.load_tape_data_array(&tape)?;
+            let extension = name
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| ext.to_ascii_lowercase());
+            match extension.as_deref() {
+                Some("bin") => self.load_tape_binary(&tape),
+                Some("bas") => self.load_tape_basic(&tape),
+                Some("ct2") => self.load_tape_records(cassette::ct2_to_records(&tape)?),
+                _ => self.load_tape_data_array(&tape)?,
+            }
         }
         Ok(())
     }
 
+    /// Load a decoded tape with a single record (for e.g. for the monitor READ)
+    pub fn load_tape_binary(&mut self, data: &[u8]) {
+        self.load_tape_records(vec![TapeRecord::new(data)]);
+    }
+
+    /// Load a tokenized BASIC program as a decoded tape. The tape has the length header
+    /// record followed by the program record. Applesoft LOAD reads one byte more than
+    /// the length of the program
+    pub fn load_tape_basic(&mut self, data: &[u8]) {
+        let [lo, hi] = (data.len() as u16).to_le_bytes();
+        let mut program = data.to_vec();
+        program.push(0);
+        self.load_tape_records(vec![
+            TapeRecord::new(&[lo, hi, 0]),
+            TapeRecord::new(&program),
+        ]);
+    }
+
+    /// Load the tape records. The records are also encoded as tape samples for the
+    /// playback in real time
+    pub fn load_tape_records(&mut self, mut records: Vec<TapeRecord>) {
+        self.tape.reset();
+        self.tape.data = cassette::encode_records(&mut records, AUDIO_SAMPLE_RATE);
+        self.tape.records = records.into();
+    }
+
+    /// Decode the records of the tape
+    pub fn get_tape_records(&self) -> Vec<TapeRecord> {
+        cassette::decode_records(&self.tape.data, AUDIO_SAMPLE_RATE)
+    }
+
+    pub fn set_fast_tape(&mut self, state: bool) {
+        self.fast_tape = state;
+    }
+
+    pub fn is_fast_tape(&self) -> bool {
+        self.fast_tape
+    }
+
+    /// Read the next tape record of `count` bytes. Returns the data and the checksum
+    /// stored on the tape. When the length of the record is different, the byte after
+    /// the data is read as the checksum as done by the monitor READ
+    pub(crate) fn read_tape_record(&mut self, count: usize) -> Option<(Vec<u8>, u8)> {
+        // Skip the records that are played back in real time
+        while let Some(record) = self.tape.records.pop_front() {
+            if record.end <= self.tape.pos {
+                continue;
+            }
+
+            self.tape.pos = record.end;
+            let mut data = record.data;
+            data.push(record.checksum);
+            data.resize(count + 1, 0);
+            let checksum = data.pop().unwrap_or_default();
+            return Some((data, checksum));
+        }
+
+        let record =
+            cassette::decode_record(&self.tape.data, self.tape.pos, count, AUDIO_SAMPLE_RATE)?;
+        self.tape.pos = record.end;
+        Some((record.data, record.checksum))
+    }
+
     pub fn load_tape_data_array(&mut self, data: &[u8]) -> std::io::Result<()> {
         let (samples_per_second, wav_data) = self.parse_wav_header(data)?;
 
         self.tape.data.clear();
         self.tape.reset();
+        self.tape.records.clear();
 
         if wav_data.is_empty() || samples_per_second == 0 {
             return Ok(());
@@ -679,6 +758,7 @@ impl Audio {
         self.tape.filename = None;
         self.tape.data.clear();
         self.tape.reset();
+        self.tape.records.clear();
     }
 
     pub fn get_filter_enabled(&self) -> bool {
@@ -700,71 +780,139 @@ impl Audio {
 
     fn save_tape_data(&self) -> std::io::Result<()> {
         if let Some(filename) = &self.tape.filename {
-            let mut file = std::fs::File::create(filename)?;
-
-            // Convert the square wave to sinusoidal wave
-            let mut data = Vec::new();
-            let mut prev: Option<u8> = None;
-            let mut polarity = false;
-            let mut count = 0;
-            for item in self.tape.data.iter() {
-                if prev != Some(*item) {
-                    if count < 1000 {
-                        for i in 0..count {
-                            let wave = f32::sin(std::f32::consts::PI * i as f32 / count as f32);
-                            let value = if polarity {
-                                128.0 + 96.0 * wave
-                            } else {
-                                128.0 - 96.0 * wave
-                            };
-                            data.push(value as u8);
-                        }
-                    }
-                    polarity = !polarity;
-                    count = 0;
-                    prev = Some(*item);
+            self.save_tape_as(filename)?;
+        }
+        Ok(())
+    }
+
+    /// Save the tape in the format of the file extension. The .bin, .bas and .ct2 files
+    /// store the decoded records. The .wav file is generated from the decoded records, or
+    /// from the recorded samples when they contain data that is not in a record
+    pub fn save_tape_as(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        let records = self.get_tape_records();
+
+        // The .bin and .bas files don't store the checksum. The .ct2 and .wav files keep
+        // the checksum as read from the tape
+        if matches!(extension.as_deref(), Some("bin") | Some("bas"))
+            && let Some(i) = records
+                .iter()
+                .position(|record| !record.is_checksum_valid())
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Tape record {i} has an invalid checksum"),
+            ));
+        }
+
+        let no_record =
+            || std::io::Error::new(std::io::ErrorKind::InvalidInput, "No tape record found");
+
+        let data = match extension.as_deref() {
+            Some("bin") => {
+                if records.is_empty() {
+                    return Err(no_record());
+                }
+                records
+                    .iter()
+                    .flat_map(|record| record.data.clone())
+                    .collect()
+            }
+            Some("bas") => {
+                // The length header record followed by the program record
+                let [header, program, ..] = records.as_slice() else {
+                    return Err(no_record());
+                };
+                if header.data.len() < 2 {
+                    return Err(no_record());
                 }
-                count += 1;
+                let len = u16::from_le_bytes([header.data[0], header.data[1]]) as usize;
+                program.data[..len.min(program.data.len())].to_vec()
             }
+            Some("ct2") => cassette::records_to_ct2(&records)?,
+            _ => {
+                if records.is_empty() || !cassette::records_cover_samples(&self.tape.data, &records)
+                {
+                    Self::wav_data(&self.tape.data)
+                } else {
+                    let mut records = records;
+                    Self::wav_data(&cassette::encode_records(&mut records, AUDIO_SAMPLE_RATE))
+                }
+            }
+        };
 
-            if count != 0 && count < 1000 {
-                for i in 0..count {
-                    let wave = f32::sin(std::f32::consts::PI * i as f32 / count as f32);
-                    let value = if polarity {
-                        128.0 + 96.0 * wave
-                    } else {
-                        128.0 - 96.0 * wave
-                    };
-                    data.push(value as u8);
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(&data)
+    }
+
+    fn wav_data(samples: &[u8]) -> Vec<u8> {
+        // Convert the square wave to sinusoidal wave
+        let mut data = Vec::new();
+        let mut prev: Option<u8> = None;
+        let mut polarity = false;
+        let mut count = 0;
+        for item in samples.iter() {
+            if prev != Some(*item) {
+                if count < 1000 {
+                    for i in 0..count {
+                        let wave = f32::sin(std::f32::consts::PI * i as f32 / count as f32);
+                        let value = if polarity {
+                            128.0 + 96.0 * wave
+                        } else {
+                            128.0 - 96.0 * wave
+                        };
+                        data.push(value as u8);
+                    }
                 }
+                polarity = !polarity;
+                count = 0;
+                prev = Some(*item);
             }
+            count += 1;
+        }
 
-            file.write_all(RIFF_HEADER)?;
-            file.write_all(&((data.len() + 36) as u32).to_le_bytes())?;
-            file.write_all(WAVE_CHUNK)?;
-            file.write_all(FMT_CHUNK)?;
-            file.write_all(&16_u32.to_le_bytes())?;
+        if count != 0 && count < 1000 {
+            for i in 0..count {
+                let wave = f32::sin(std::f32::consts::PI * i as f32 / count as f32);
+                let value = if polarity {
+                    128.0 + 96.0 * wave
+                } else {
+                    128.0 - 96.0 * wave
+                };
+                data.push(value as u8);
+            }
+        }
 
-            // PCM, and mono one channel
-            file.write_all(&1_u16.to_le_bytes())?;
-            file.write_all(&1_u16.to_le_bytes())?;
+        let mut output = Vec::with_capacity(data.len() + 44);
+        output.extend_from_slice(RIFF_HEADER);
+        output.extend_from_slice(&((data.len() + 36) as u32).to_le_bytes());
+        output.extend_from_slice(WAVE_CHUNK);
+        output.extend_from_slice(FMT_CHUNK);
+        output.extend_from_slice(&16_u32.to_le_bytes());
 
-            // Samples per second and byte rate
-            file.write_all(&(AUDIO_SAMPLE_RATE as u32).to_le_bytes())?;
-            file.write_all(&(AUDIO_SAMPLE_RATE as u32).to_le_bytes())?;
+        // PCM, and mono one channel
+        output.extend_from_slice(&1_u16.to_le_bytes());
+        output.extend_from_slice(&1_u16.to_le_bytes());
 
-            // Alignment, bits per sample
-            file.write_all(&1_u16.to_le_bytes())?;
-            file.write_all(&8_u16.to_le_bytes())?;
+        // Samples per second and byte rate
+        output.extend_from_slice(&(AUDIO_SAMPLE_RATE as u32).to_le_bytes());
+        output.extend_from_slice(&(AUDIO_SAMPLE_RATE as u32).to_le_bytes());
 
-            // data header and data len
-            file.write_all(DATA_CHUNK)?;
-            file.write_all(&(data.len() as u32).to_le_bytes())?;
+        // Alignment, bits per sample
+        output.extend_from_slice(&1_u16.to_le_bytes());
+        output.extend_from_slice(&8_u16.to_le_bytes());
 
-            file.write_all(&data)?;
-        }
+        // data header and data len
+        output.extend_from_slice(DATA_CHUNK);
+        output.extend_from_slice(&(data.len() as u32).to_le_bytes());
 
-        Ok(())
+        output.extend_from_slice(&data);
+        output
     }
 }
 
@@ -1,9 +1,11 @@
 use crate::audio::Audio;
+use crate::cffa::Cffa;
 use crate::disk::DiskDrive;
 use crate::harddisk::HardDisk;
 use crate::mmu::AuxType;
 use crate::mmu::Mmu;
 use crate::mmu::Saturn;
+use crate::mockingboard::Mockingboard;
 use crate::mouse::{
     Mouse, STATUS_MOVE_INTERRUPT, STATUS_MOVE_INTERRUPT_X0, STATUS_MOVE_INTERRUPT_Y0,
     STATUS_VBL_INTERRUPT,
@@ -11,8 +13,11 @@ use crate::mouse::{
 use crate::noslotclock::NoSlotClock;
 use crate::parallel::ParallelCard;
 use crate::ramfactor::RamFactor;
+use crate::scsi::Scsi;
+use crate::serial::{Apple2cSerial, SuperSerialCard};
+use crate::thunderclock::Thunderclock;
+use crate::uthernet::Uthernet;
 use crate::video::Video;
-use crate::mockingboard::Mockingboard;
 
 #[cfg(not(target_os = "wasi"))]
 use crate::network::Uthernet2;
@@ -63,6 +68,11 @@ pub enum IODevice {
     Saturn(u8),
     VidHD,
     Videoterm,
+    SuperSerial,
+    Thunderclock,
+    Cffa,
+    Scsi,
+    Uthernet,
 }
 
 impl From<IODevice> for &str {
@@ -83,6 +93,11 @@ impl From<IODevice> for &str {
             IODevice::Saturn(_) => "Saturn",
             IODevice::VidHD => "VidHD",
             IODevice::Videoterm => "Videx Videoterm",
+            IODevice::SuperSerial => "Super Serial Card",
+            IODevice::Thunderclock => "Thunderclock Plus",
+            IODevice::Cffa => "CFFA",
+            IODevice::Scsi => "Apple High-Speed SCSI",
+            IODevice::Uthernet => "Uthernet",
         }
     }
 }
@@ -182,6 +197,42 @@ pub struct Bus {
 
     #[cfg_attr(feature = "serde_support", serde(default))]
     pub disable_noslot_clock: bool,
+
+    /// Second Disk II controller (Drive 3 and Drive 4)
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub disk2: DiskDrive,
+
+    /// Disk II controller index of each slot, assigned when the controller is registered
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    disk_controller: [usize; 8],
+
+    /// Address of the instruction being executed, used by the disk access log
+    #[cfg_attr(feature = "serde_support", serde(skip))]
+    pub pc: u16,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub ssc: SuperSerialCard,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub a2c_serial: Apple2cSerial,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub thunderclock: Thunderclock,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub cffa: Cffa,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub scsi: Scsi,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub uthernet: Uthernet,
+}
+
+const MAX_DISK_CONTROLLERS: usize = 2;
+
+fn is_disk_device(device: IODevice) -> bool {
+    device == IODevice::Disk || device == IODevice::Disk13
 }
 
 pub trait Mem {
@@ -278,6 +329,15 @@ impl Bus {
             vidhd: VidHD,
             videoterm: Videoterm::default(),
             disable_noslot_clock: false,
+            disk2: DiskDrive::default(),
+            disk_controller: [0; 8],
+            pc: 0,
+            ssc: SuperSerialCard::new(),
+            thunderclock: Thunderclock::new(),
+            cffa: Cffa::new(),
+            scsi: Scsi::new(),
+            uthernet: Uthernet::new(),
+            a2c_serial: Apple2cSerial::new(),
         };
 
         bus.init_memory();
@@ -319,6 +379,10 @@ impl Bus {
         self.mem.reset();
         self.video.reset();
         self.ramfactor.reset();
+        self.ssc.reset();
+        self.thunderclock.reset();
+        self.a2c_serial.reset();
+        self.uthernet.reset();
 
         #[cfg(not(target_os = "wasi"))]
         self.uthernet2.reset(true);
@@ -353,7 +417,10 @@ impl Bus {
 
         if !self.disable_disk {
             self.disk.reset();
+            self.disk2.reset();
             self.harddisk.reset();
+            self.cffa.reset();
+            self.scsi.reset();
         }
     }
 
@@ -369,26 +436,55 @@ impl Bus {
 
         if !self.disable_audio {
             if self.audio.ready_update_disk_sound() {
-                let motor_on = self.disk.is_motor_on();
-                let sample_value = if motor_on {
-                    self.disk.update_disk_sound_sample(motor_on);
-                    self.disk.get_disk_sound_sample()
-                } else {
-                    0
-                };
+                let mut sample_value = 0i16;
+                for disk in [&mut self.disk, &mut self.disk2] {
+                    if disk.is_motor_on() {
+                        disk.update_disk_sound_sample(true);
+                        sample_value = sample_value.saturating_add(disk.get_disk_sound_sample());
+                    }
+                }
                 self.audio.update_disk_sound(sample_value);
             }
             self.audio.tick();
         }
 
+        if self.ssc.is_active() && self.is_ssc_installed() {
+            self.ssc.tick(self.cycles);
+        }
+
+        if self.thunderclock.is_active() {
+            self.thunderclock.tick(self.cycles);
+        }
+
+        if self.is_apple2c && self.a2c_serial.is_active() {
+            self.a2c_serial.tick(self.cycles);
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        self.uthernet2.tick(self.cycles);
+
         if !self.disable_disk {
             if self.harddisk.is_busy() {
                 self.harddisk.tick();
             }
 
+            if self.cffa.is_busy() {
+                self.cffa.tick();
+            }
+
+            if self.scsi.is_busy() {
+                self.scsi.tick();
+            }
+
             if self.disk.is_motor_on() {
+                self.disk.set_log_context(self.cycles, self.pc);
                 self.disk.tick();
             }
+
+            if self.disk2.is_motor_on() {
+                self.disk2.set_log_context(self.cycles, self.pc);
+                self.disk2.tick();
+            }
         }
     }
 
@@ -437,15 +533,35 @@ impl Bus {
         self.disk.set_iwm(flag);
     }
 
+    /// Returns both Disk II controllers. Used to apply the same disk settings to all drives
+    pub fn disk_controllers_mut(&mut self) -> [&mut DiskDrive; MAX_DISK_CONTROLLERS] {
+        [&mut self.disk, &mut self.disk2]
+    }
+
+    /// Returns the Disk II controller index for the slot. The first controller
+    /// (Drive 1 and 2) is the one in slot 6 by default, the second controller
+    /// (Drive 3 and 4) is the next one registered
+    #[inline(always)]
+    pub fn disk_controller_index(&self, slot: usize) -> usize {
+        self.disk_controller[slot]
+    }
+
     #[inline(always)]
     pub fn is_80_column_enabled(&self) -> bool {
         self.annunciator[0] && self.io_slot[3] == IODevice::Videoterm
     }
 
+    /// Returns true if the RWTS and ProDOS driver calls can be serviced from the disk image
+    #[inline(always)]
+    pub fn is_high_level_disk(&self) -> bool {
+        self.disk.is_high_level_disk() || self.disk2.is_high_level_disk()
+    }
+
     pub fn is_normal_speed(&self) -> bool {
-        self.disk.is_normal_disk()
+        (self.disk.is_normal_disk() && self.disk2.is_normal_disk())
             || self.audio.is_audio_active()
             || self.disk.is_disk_sound_enabled()
+            || self.disk2.is_disk_sound_enabled()
     }
 
     pub fn get_z80_cirtech(&self) -> bool {
@@ -472,6 +588,10 @@ impl Bus {
         self.paddle_trigger = value;
     }
 
+    fn is_ssc_installed(&self) -> bool {
+        (1..8).any(|i| self.io_slot[i] == IODevice::SuperSerial)
+    }
+
     fn setup_vidhd(&mut self) {
         let vidhd_enabled = (1..8).any(|i| self.io_slot[i] == IODevice::VidHD);
         self.video.set_vidhd(vidhd_enabled);
@@ -480,10 +600,30 @@ impl Bus {
 
     pub fn register_device(&mut self, device: IODevice, slot: usize) {
         if slot < self.io_slot.len() {
-            if device == IODevice::Disk
-                || device == IODevice::Disk13
-                || device == IODevice::HardDisk
+            if is_disk_device(device) && !is_disk_device(self.io_slot[slot]) {
+                // Use a free controller. When all controllers are in use, the last
+                // controller is replaced so that the existing drives keep their numbers
+                let in_use = |index: usize| {
+                    (1..8).any(|i| {
+                        is_disk_device(self.io_slot[i]) && self.disk_controller[i] == index
+                    })
+                };
+                let index = (0..MAX_DISK_CONTROLLERS)
+                    .find(|&index| !in_use(index))
+                    .unwrap_or(MAX_DISK_CONTROLLERS - 1);
+                for i in 1..8 {
+                    if is_disk_device(self.io_slot[i]) && self.disk_controller[i] == index {
+                        self.io_slot[i] = IODevice::None
+                    }
+                }
+                self.disk_controller[slot] = index;
+            } else if device == IODevice::HardDisk
                 || device == IODevice::VidHD
+                || device == IODevice::SuperSerial
+                || device == IODevice::Thunderclock
+                || device == IODevice::Cffa
+                || device == IODevice::Scsi
+                || device == IODevice::Uthernet
             {
                 for i in 1..8 {
                     if i != slot && (self.io_slot[i] == device) {
@@ -512,6 +652,29 @@ impl Bus {
         self.setup_vidhd();
     }
 
+    /// Configure the aux memory required by the WOZ image in the drive. The machine
+    /// model is not changed as it requires the ROM from the frontend
+    pub fn woz_auto_configure(&mut self, drive: usize) {
+        let Some(metadata) = self.disk.get_woz_metadata(drive) else {
+            return;
+        };
+
+        let required_ram = metadata.required_ram_kb() as usize;
+        if required_ram > 128 {
+            let banks = (required_ram - 64).div_ceil(64).min(255);
+            if self.mem.aux_type != AuxType::RW3 || self.mem.get_aux_size() < banks {
+                self.mem.set_aux_size(banks as u8);
+                self.mem.aux_type = AuxType::RW3;
+                self.video.disable_aux = false;
+            }
+        } else if required_ram > 64
+            && (self.mem.aux_type == AuxType::Empty || self.mem.aux_type == AuxType::Std80)
+        {
+            self.mem.aux_type = AuxType::Ext80;
+            self.video.disable_aux = false;
+        }
+    }
+
     fn shadow_memory_to_vram(&mut self, addr: u16, data: u8) {
         // Shadow it to the video ram
         let aux_memory = self.mem.is_aux_memory(addr, true);
@@ -559,16 +722,28 @@ impl Bus {
             self.extended_rom = slot as u8;
         }
 
+        let disk = if self.disk_controller_index(slot) == 0 {
+            &mut self.disk
+        } else {
+            &mut self.disk2
+        };
+        disk.set_log_context(self.cycles, self.pc);
+
         let mut saturn;
         let return_value: Option<&mut dyn Card> = match slot_value {
             IODevice::Printer => Some(&mut self.parallel),
             IODevice::RamFactor => Some(&mut self.ramfactor),
             IODevice::Videoterm => Some(&mut self.videoterm),
             IODevice::Mouse => Some(&mut self.mouse),
-            IODevice::Disk => Some(&mut self.disk),
+            IODevice::SuperSerial => Some(&mut self.ssc),
+            IODevice::Thunderclock => Some(&mut self.thunderclock),
+            IODevice::Cffa => Some(&mut self.cffa),
+            IODevice::Scsi => Some(&mut self.scsi),
+            IODevice::Uthernet => Some(&mut self.uthernet),
+            IODevice::Disk => Some(disk),
             IODevice::Disk13 => {
-                self.disk.force_disk_rom13();
-                Some(&mut self.disk)
+                disk.force_disk_rom13();
+                Some(disk)
             }
             IODevice::HardDisk => Some(&mut self.harddisk),
             IODevice::Mockingboard(_) => None,
@@ -633,16 +808,28 @@ impl Bus {
                     self.extended_rom = slot as u8;
                 }
 
+                let disk = if self.disk_controller_index(slot) == 0 {
+                    &mut self.disk
+                } else {
+                    &mut self.disk2
+                };
+                disk.set_log_context(self.cycles, self.pc);
+
                 let return_value: Option<&mut dyn Card> = match slot_value {
                     IODevice::Printer => Some(&mut self.parallel),
                     IODevice::RamFactor => Some(&mut self.ramfactor),
                     IODevice::VidHD => Some(&mut self.vidhd),
                     IODevice::Videoterm => Some(&mut self.videoterm),
                     IODevice::Mouse => Some(&mut self.mouse),
-                    IODevice::Disk => Some(&mut self.disk),
+                    IODevice::SuperSerial => Some(&mut self.ssc),
+                    IODevice::Thunderclock => Some(&mut self.thunderclock),
+                    IODevice::Cffa => Some(&mut self.cffa),
+                    IODevice::Scsi => Some(&mut self.scsi),
+                    IODevice::Uthernet => Some(&mut self.uthernet),
+                    IODevice::Disk => Some(disk),
                     IODevice::Disk13 => {
-                        self.disk.force_disk_rom13();
-                        Some(&mut self.disk)
+                        disk.force_disk_rom13();
+                        Some(disk)
                     }
                     IODevice::HardDisk => Some(&mut self.harddisk),
                     #[cfg(feature = "z80")]
@@ -787,6 +974,27 @@ impl Bus {
             return Some(irq_val);
         }
 
+        if let Some(irq_val) = self.ssc.poll_irq()
+            && self.is_ssc_installed()
+        {
+            return Some(irq_val);
+        }
+
+        if let Some(irq_val) = self.thunderclock.poll_irq() {
+            return Some(irq_val);
+        }
+
+        if self.is_apple2c
+            && let Some(irq_val) = self.a2c_serial.poll_irq()
+        {
+            return Some(irq_val);
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        if let Some(irq_val) = self.uthernet2.poll_irq() {
+            return Some(irq_val);
+        }
+
         if self.disable_audio {
             return None;
         }
@@ -1354,7 +1562,14 @@ impl Bus {
                 floating_bus
             }
 
-            0x90..=0xff => self.iodevice_io_access(addr, value, write_flag),
+            0x90..=0xff => {
+                if self.is_apple2c
+                    && let Some(value) = self.a2c_serial.io_access(addr, value, write_flag)
+                {
+                    return value;
+                }
+                self.iodevice_io_access(addr, value, write_flag)
+            }
 
             _ => {
                 /*
@@ -1532,3 +1747,43 @@ fn default_io_slot() -> Vec<IODevice> {
 
     io_slot
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn second_disk_controller() {
+        let mut bus = Bus::default();
+        assert_eq!(bus.disk_controller_index(6), 0);
+
+        bus.register_device(IODevice::Disk, 5);
+        assert!(bus.io_slot[6] == IODevice::Disk);
+        assert_eq!(bus.disk_controller_index(6), 0);
+        assert_eq!(bus.disk_controller_index(5), 1);
+
+        // Only two disk controllers are supported
+        bus.register_device(IODevice::Disk13, 4);
+        assert!(bus.io_slot[5] == IODevice::None);
+        assert!(bus.io_slot[6] == IODevice::Disk);
+        assert_eq!(bus.disk_controller_index(6), 0);
+        assert_eq!(bus.disk_controller_index(4), 1);
+
+        // Adding a controller in a higher slot doesn't renumber the drives
+        bus.register_device(IODevice::Disk, 7);
+        assert!(bus.io_slot[4] == IODevice::None);
+        assert_eq!(bus.disk_controller_index(6), 0);
+        assert_eq!(bus.disk_controller_index(7), 1);
+
+        // Replacing a controller keeps its index
+        bus.register_device(IODevice::Disk13, 7);
+        assert!(bus.io_slot[6] == IODevice::Disk);
+        assert_eq!(bus.disk_controller_index(7), 1);
+
+        // A freed controller is reused
+        bus.unregister_device(6);
+        bus.register_device(IODevice::Disk, 5);
+        assert_eq!(bus.disk_controller_index(5), 0);
+        assert_eq!(bus.disk_controller_index(7), 1);
+    }
+}
@@ -0,0 +1,550 @@
+use crate::audio::{TAPE_HIGH_LEVEL, TAPE_LOW_LEVEL};
+use crate::bus::Mem;
+use crate::cpu::{CPU, CpuFlags};
+use std::io;
+
+/*
+Fast cassette load
+
+The monitor READ routine is trapped at its entry point and the tape record is decoded
+offline instead of playing back the tape in real time. Applesoft and Integer BASIC
+LOAD read the header and the program with the monitor READ, so they are loaded fast too.
+
+    READ              $FEFD, reads the bytes from A1 ($3C) to A2 ($3E)
+                          The enhanced //e ROM calls the internal READ at $C5D1 and
+                          returns to $FF03 to switch off the internal ROM
+                          $2E    Checksum (initialized to $FF)
+                          $FF2D  Prints ERR when the checksum does not match
+                          $FF3A  Rings the bell on success
+
+A tape record is a header tone of 770 Hz followed by a sync bit (a 200 us and a 250 us
+half cycle), the data bytes (MSB first) and the checksum. A 0 bit is a cycle of 2000 Hz and
+a 1 bit is a cycle of 1000 Hz.
+*/
+
+pub const TAPE_READ_ENTRY: u16 = 0xfefd;
+
+// JSR RD2BIT, LDA #$16
+const READ_SIGNATURE: [u8; 5] = [0x20, 0xfa, 0xfc, 0xa9, 0x16];
+
+// STA SETINTCXROM, JSR $C5D1
+const ENHANCED_READ_SIGNATURE: [u8; 6] = [0x8d, 0x07, 0xc0, 0x20, 0xd1, 0xc5];
+
+// STA CLRINTCXROM, BEQ BELL, BNE PRERR
+const ENHANCED_READ_RETURN: u16 = 0xff03;
+
+const CHKSUM: u16 = 0x2e;
+const A1: u16 = 0x3c;
+const A2: u16 = 0x3e;
+const PRERR: u16 = 0xff2d;
+const BELL: u16 = 0xff3a;
+
+pub const CHECKSUM_SEED: u8 = 0xff;
+
+// Half cycle widths in microseconds
+const HEADER_US: f32 = 650.0;
+const SYNC_FIRST_US: f32 = 200.0;
+const SYNC_SECOND_US: f32 = 250.0;
+const ZERO_US: f32 = 250.0;
+const ONE_US: f32 = 500.0;
+const GAP_US: f32 = 500_000.0;
+
+// Half cycle limits in microseconds
+const HEADER_MIN_US: f32 = 500.0;
+const HEADER_MAX_US: f32 = 900.0;
+const SYNC_MAX_US: f32 = 400.0;
+const BIT_MAX_US: f32 = 600.0;
+const MIN_HEADER_HALF_CYCLES: usize = 32;
+
+// Full cycle threshold between a 0 bit (500 us) and a 1 bit (1000 us)
+const BIT_THRESHOLD_US: f32 = 750.0;
+
+/// Length of the header tone of the generated records (6 seconds). The monitor READ
+/// waits 3.5 seconds before looking for the sync bit
+pub const DEFAULT_HEADER_HALF_CYCLES: usize = 9240;
+
+const CT2_MAGIC: &[u8] = b"CT2\x01";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapeRecord {
+    /// Length of the header tone in half cycles
+    pub header: usize,
+    pub data: Vec<u8>,
+    pub checksum: u8,
+    /// Sample position after the checksum
+    pub end: usize,
+}
+
+impl TapeRecord {
+    pub fn new(data: &[u8]) -> Self {
+        Self {
+            header: DEFAULT_HEADER_HALF_CYCLES,
+            data: data.to_vec(),
+            checksum: checksum(data),
+            end: 0,
+        }
+    }
+
+    pub fn is_checksum_valid(&self) -> bool {
+        checksum(&self.data) == self.checksum
+    }
+}
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(CHECKSUM_SEED, |acc, value| acc ^ value)
+}
+
+struct HalfCycles<'a> {
+    samples: &'a [u8],
+    pos: usize,
+    level: bool,
+    us_per_sample: f32,
+}
+
+impl<'a> HalfCycles<'a> {
+    fn new(samples: &'a [u8], pos: usize, sample_rate: f32) -> Self {
+        Self {
+            samples,
+            pos,
+            level: samples.get(pos).is_some_and(|&value| value >= 128),
+            us_per_sample: 1_000_000.0 / sample_rate,
+        }
+    }
+
+    // Width of the half cycle that ends at the next edge
+    fn next(&mut self) -> Option<f32> {
+        let offset = self
+            .samples
+            .get(self.pos..)?
+            .iter()
+            .position(|&value| (value >= 128) != self.level)?;
+        self.pos += offset;
+        self.level = !self.level;
+        Some(offset as f32 * self.us_per_sample)
+    }
+
+    // Skip the header tone and the sync bit. Returns the length of the header tone
+    fn find_header(&mut self) -> Option<usize> {
+        let mut header = 0;
+        loop {
+            let width = self.next()?;
+            if (HEADER_MIN_US..=HEADER_MAX_US).contains(&width) {
+                header += 1;
+            } else if width < SYNC_MAX_US && header >= MIN_HEADER_HALF_CYCLES {
+                self.next()?;
+                return Some(header);
+            } else {
+                header = 0;
+            }
+        }
+    }
+
+    // The level does not change after the last half cycle of a recorded tape, so the
+    // second half cycle of the last bit is assumed to be as long as the first one
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut value = 0u8;
+        for _ in 0..8 {
+            let first = self.next().filter(|&width| width <= BIT_MAX_US)?;
+            let second = self
+                .next()
+                .filter(|&width| width <= BIT_MAX_US)
+                .unwrap_or(first);
+            value = (value << 1) | (first + second > BIT_THRESHOLD_US) as u8;
+        }
+        Some(value)
+    }
+}
+
+/// Decode the record of `count` bytes that starts after the position `pos` of the
+/// tape samples. The samples are high when the value is at least 128
+pub fn decode_record(
+    samples: &[u8],
+    pos: usize,
+    count: usize,
+    sample_rate: f32,
+) -> Option<TapeRecord> {
+    let mut half_cycles = HalfCycles::new(samples, pos, sample_rate);
+    let header = half_cycles.find_header()?;
+
+    let mut data = Vec::with_capacity(count + 1);
+    for _ in 0..=count {
+        data.push(half_cycles.read_byte()?);
+    }
+
+    let checksum = data.pop()?;
+    Some(TapeRecord {
+        header,
+        data,
+        checksum,
+        end: half_cycles.pos,
+    })
+}
+
+/// Decode all the records of the tape samples. The length of each record is the number
+/// of bytes until the end of the data bits
+pub fn decode_records(samples: &[u8], sample_rate: f32) -> Vec<TapeRecord> {
+    let mut half_cycles = HalfCycles::new(samples, 0, sample_rate);
+    let mut records = Vec::new();
+
+    while let Some(header) = half_cycles.find_header() {
+        let mut data = Vec::new();
+        let mut end = half_cycles.pos;
+        while let Some(value) = half_cycles.read_byte() {
+            data.push(value);
+            end = half_cycles.pos;
+        }
+
+        if data.len() >= 2
+            && let Some(checksum) = data.pop()
+        {
+            records.push(TapeRecord {
+                header,
+                data,
+                checksum,
+                end,
+            });
+        }
+    }
+    records
+}
+
+// Half cycles that are not part of the header tone, sync bit and data of a record
+const SLACK_HALF_CYCLES: usize = 64;
+
+/// Check that the records contain all the half cycles of the tape samples, so that the
+/// tape can be regenerated from the records without losing non-standard data (for e.g.
+/// the data of a custom loader)
+pub fn records_cover_samples(samples: &[u8], records: &[TapeRecord]) -> bool {
+    let edges = samples
+        .windows(2)
+        .filter(|pair| (pair[0] >= 128) != (pair[1] >= 128))
+        .count();
+    let used: usize = records
+        .iter()
+        .map(|record| record.header + 2 + (record.data.len() + 1) * 16)
+        .sum();
+    edges <= used + SLACK_HALF_CYCLES * (records.len() + 1)
+}
+
+struct Encoder {
+    samples: Vec<u8>,
+    level: bool,
+    time_us: f64,
+    us_per_sample: f64,
+}
+
+impl Encoder {
+    fn half_cycle(&mut self, us: f32) {
+        self.time_us += us as f64;
+        let end = (self.time_us / self.us_per_sample).round() as usize;
+        let value = if self.level {
+            TAPE_HIGH_LEVEL
+        } else {
+            TAPE_LOW_LEVEL
+        };
+        self.samples.resize(end, value);
+        self.level = !self.level;
+    }
+}
+
+/// Encode the records to tape samples and update the end position of each record
+pub fn encode_records(records: &mut [TapeRecord], sample_rate: f32) -> Vec<u8> {
+    let mut encoder = Encoder {
+        samples: Vec::new(),
+        level: false,
+        time_us: 0.0,
+        us_per_sample: 1_000_000.0 / sample_rate as f64,
+    };
+
+    for record in records {
+        encoder.half_cycle(GAP_US);
+        for _ in 0..record.header {
+            encoder.half_cycle(HEADER_US);
+        }
+        encoder.half_cycle(SYNC_FIRST_US);
+        encoder.half_cycle(SYNC_SECOND_US);
+
+        for value in record.data.iter().chain([&record.checksum]) {
+            for bit in (0..8).rev() {
+                let us = if value & (1 << bit) != 0 {
+                    ONE_US
+                } else {
+                    ZERO_US
+                };
+                encoder.half_cycle(us);
+                encoder.half_cycle(us);
+            }
+        }
+        record.end = encoder.samples.len();
+    }
+    encoder.half_cycle(GAP_US);
+    encoder.samples
+}
+
+/*
+CT2 compact tape
+
+The CT2 file is the container of this emulator to store the decoded records with the
+length of their header tone, so that the tape can be loaded fast and played back
+again. It is not a format used by other emulators or tools.
+
+    Offset 0          "CT2" and the version 1
+    Each record       u16 LE  Length of the header tone in half cycles
+                      u16 LE  Length of the data
+                      Data
+                      u8      Checksum as read from the tape
+*/
+
+pub fn records_to_ct2(records: &[TapeRecord]) -> io::Result<Vec<u8>> {
+    let mut output = CT2_MAGIC.to_vec();
+    for record in records {
+        let Ok(len) = u16::try_from(record.data.len()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Tape record is too long for CT2",
+            ));
+        };
+        let header = record.header.min(u16::MAX as usize) as u16;
+        output.extend_from_slice(&header.to_le_bytes());
+        output.extend_from_slice(&len.to_le_bytes());
+        output.extend_from_slice(&record.data);
+        output.push(record.checksum);
+    }
+    Ok(output)
+}
+
+pub fn ct2_to_records(data: &[u8]) -> io::Result<Vec<TapeRecord>> {
+    let invalid = |s: &str| io::Error::new(io::ErrorKind::InvalidInput, s.to_string());
+
+    let Some(mut data) = data.strip_prefix(CT2_MAGIC) else {
+        return Err(invalid("Invalid CT2 file - Missing CT2 header"));
+    };
+
+    let mut records = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 4 {
+            return Err(invalid("Invalid CT2 file - Truncated record header"));
+        }
+        let header = u16::from_le_bytes([data[0], data[1]]) as usize;
+        let len = u16::from_le_bytes([data[2], data[3]]) as usize;
+        if data.len() < 4 + len + 1 {
+            return Err(invalid("Invalid CT2 file - Truncated record data"));
+        }
+        records.push(TapeRecord {
+            header,
+            data: data[4..4 + len].to_vec(),
+            checksum: data[4 + len],
+            end: 0,
+        });
+        data = &data[4 + len + 1..];
+    }
+    Ok(records)
+}
+
+fn read_u16(cpu: &mut CPU, addr: u16) -> u16 {
+    let lo = cpu.bus.unclocked_addr_read(addr);
+    let hi = cpu.bus.unclocked_addr_read(addr.wrapping_add(1));
+    u16::from_le_bytes([lo, hi])
+}
+
+fn write_u16(cpu: &mut CPU, addr: u16, value: u16) {
+    let [lo, hi] = value.to_le_bytes();
+    cpu.bus.unclocked_addr_write(addr, lo);
+    cpu.bus.unclocked_addr_write(addr.wrapping_add(1), hi);
+}
+
+pub fn trap_tape_read(cpu: &mut CPU) -> bool {
+    if !cpu.bus.audio.is_fast_tape() {
+        return false;
+    }
+
+    let enhanced_rom = if has_signature(cpu, &READ_SIGNATURE) {
+        false
+    } else if has_signature(cpu, &ENHANCED_READ_SIGNATURE) {
+        true
+    } else {
+        return false;
+    };
+
+    // READ stores at least one byte even when A2 is less than A1
+    let start = read_u16(cpu, A1);
+    let end = read_u16(cpu, A2);
+    let count = end.saturating_sub(start) as usize + 1;
+
+    let Some((data, tape_checksum)) = cpu.bus.audio.read_tape_record(count) else {
+        return false;
+    };
+
+    for (i, &value) in data.iter().enumerate() {
+        cpu.bus
+            .unclocked_addr_write(start.wrapping_add(i as u16), value);
+    }
+
+    let sum = checksum(&data);
+    cpu.bus.unclocked_addr_write(CHKSUM, sum);
+    write_u16(cpu, A1, start.wrapping_add(count as u16));
+
+    // The registers after the CMP CHKSUM at the end of READ
+    cpu.register_a = tape_checksum;
+    cpu.register_x = 0;
+    cpu.register_y = 0;
+    let result = tape_checksum.wrapping_sub(sum);
+    cpu.status.set(CpuFlags::CARRY, tape_checksum >= sum);
+    cpu.status.set(CpuFlags::ZERO, result == 0);
+    cpu.status.set(CpuFlags::NEGATIVE, result & 0x80 != 0);
+    cpu.program_counter = if enhanced_rom {
+        ENHANCED_READ_RETURN
+    } else if tape_checksum == sum {
+        BELL
+    } else {
+        PRERR
+    };
+    true
+}
+
+fn has_signature(cpu: &mut CPU, signature: &[u8]) -> bool {
+    signature
+        .iter()
+        .enumerate()
+        .all(|(i, &value)| cpu.bus.unclocked_addr_read(TAPE_READ_ENTRY + i as u16) == value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::AUDIO_SAMPLE_RATE;
+    use crate::bus::Bus;
+
+    #[test]
+    fn decode_tape_record() {
+        let data = [0x12, 0x34, 0x56, 0x78, 0xff, 0x00];
+        let mut records = [TapeRecord::new(&data), TapeRecord::new(&data[..2])];
+        for record in &mut records {
+            record.header = 64;
+        }
+        let samples = encode_records(&mut records, AUDIO_SAMPLE_RATE);
+
+        let record = decode_record(&samples, 0, data.len(), AUDIO_SAMPLE_RATE).unwrap();
+        assert_eq!(record.data, data);
+        assert_eq!(record.checksum, checksum(&data));
+
+        let record = decode_record(&samples, record.end, 2, AUDIO_SAMPLE_RATE).unwrap();
+        assert_eq!(record.data, data[..2]);
+        assert_eq!(record.checksum, checksum(&data[..2]));
+        assert_eq!(
+            decode_record(&samples, record.end, 2, AUDIO_SAMPLE_RATE),
+            None
+        );
+    }
+
+    #[test]
+    fn decode_all_records_and_ct2() {
+        let mut records = vec![
+            TapeRecord::new(&[0x10, 0x00, 0x00]),
+            TapeRecord::new(&[0x5a; 17]),
+        ];
+        records[0].header = 100;
+        records[1].header = 200;
+        records[1].checksum ^= 0xff;
+        let mut samples = encode_records(&mut records, AUDIO_SAMPLE_RATE);
+
+        // The level does not change after the end of a recording
+        samples.truncate(records[1].end);
+
+        let decoded = decode_records(&samples, AUDIO_SAMPLE_RATE);
+        assert_eq!(decoded.len(), 2);
+        for (record, expected) in decoded.iter().zip(&records) {
+            assert_eq!(record.header, expected.header);
+            assert_eq!(record.data, expected.data);
+            assert_eq!(record.checksum, expected.checksum);
+        }
+        assert!(decoded[0].is_checksum_valid());
+        assert!(!decoded[1].is_checksum_valid());
+        assert!(records_cover_samples(&samples, &decoded));
+
+        let ct2 = records_to_ct2(&decoded).unwrap();
+        assert_eq!(ct2_to_records(&ct2).unwrap(), records_with_no_end(&decoded));
+        assert!(ct2_to_records(&ct2[..ct2.len() - 1]).is_err());
+
+        // A custom loader after the records
+        samples.extend([224, 32].repeat(200));
+        assert!(!records_cover_samples(&samples, &decoded));
+    }
+
+    fn records_with_no_end(records: &[TapeRecord]) -> Vec<TapeRecord> {
+        records
+            .iter()
+            .map(|record| TapeRecord {
+                end: 0,
+                ..record.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trap_monitor_read() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+        cpu.bus.audio.set_fast_tape(true);
+        cpu.bus.audio.load_tape_binary(&[0xa9, 0x01, 0x60]);
+
+        // The monitor READ entry without the ROM
+        cpu.load(&READ_SIGNATURE, TAPE_READ_ENTRY);
+        cpu.load(&[0x00, 0x03, 0x02, 0x03], A1);
+
+        cpu.program_counter = TAPE_READ_ENTRY;
+        assert!(trap_tape_read(&mut cpu));
+        assert_eq!(cpu.program_counter, BELL);
+        assert_eq!(cpu.bus.mem_read(0x300), 0xa9);
+        assert_eq!(cpu.bus.mem_read(0x302), 0x60);
+        assert_eq!(read_u16(&mut cpu, A1), 0x303);
+        assert_eq!(cpu.bus.mem_read(CHKSUM), checksum(&[0xa9, 0x01, 0x60]));
+
+        // No more record on the tape
+        assert!(!trap_tape_read(&mut cpu));
+    }
+
+    #[test]
+    fn trap_monitor_read_length_mismatch() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+        cpu.bus.audio.set_fast_tape(true);
+        cpu.bus.audio.load_tape_binary(&[0xa9, 0x01, 0x60]);
+        cpu.load(&READ_SIGNATURE, TAPE_READ_ENTRY);
+
+        // The byte after the data is read as the checksum
+        cpu.load(&[0x00, 0x03, 0x01, 0x03], A1);
+        cpu.program_counter = TAPE_READ_ENTRY;
+        assert!(trap_tape_read(&mut cpu));
+        assert_eq!(cpu.program_counter, PRERR);
+        assert_eq!(cpu.register_a, 0x60);
+        assert_eq!(cpu.bus.mem_read(0x301), 0x01);
+    }
+
+    #[test]
+    fn trap_enhanced_monitor_read() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.load(
+            include_bytes!("../../resource/Apple2e_Enhanced.rom"),
+            0xc000,
+        );
+        cpu.reset();
+        cpu.bus.audio.set_fast_tape(true);
+        cpu.bus.audio.load_tape_binary(&[0x12, 0x34]);
+        cpu.load(&[0x00, 0x08, 0x01, 0x08], A1);
+
+        cpu.program_counter = TAPE_READ_ENTRY;
+        assert!(trap_tape_read(&mut cpu));
+        assert_eq!(cpu.program_counter, ENHANCED_READ_RETURN);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+        assert_eq!(cpu.bus.mem_read(0x800), 0x12);
+        assert_eq!(cpu.bus.mem_read(0x801), 0x34);
+        assert_eq!(read_u16(&mut cpu, A1), 0x802);
+
+        // The ROM switches off the internal ROM and branches to BELL
+        cpu.step_with_callback(|_| {});
+        assert_eq!(cpu.program_counter, 0xff06);
+        cpu.step_with_callback(|_| {});
+        assert_eq!(cpu.program_counter, BELL);
+    }
+}
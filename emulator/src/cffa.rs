@@ -0,0 +1,642 @@
+use crate::bus::{Card, Tick};
+use crate::harddisk::{DeviceStatus, HD_MAX_UNITS, HardDisk, trap_rom};
+use crate::mmu::Mmu;
+use crate::video::Video;
+use std::io;
+use std::path::Path;
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+/*
+CFFA compact flash interface card
+
+    C0n0        (r/w) ATA data register high byte. Reading C0n8 latches the high byte of
+                      the data word here, writing C0n8 writes the latched high byte
+    C0n1        (w)   Set the CS mask (ignored)
+    C0n2        (w)   Clear the CS mask (ignored)
+    C0n3        (w)   Firmware trap: SmartPort call, the value is the stack pointer
+    C0n4        (w)   Firmware trap: ProDOS block call with the parameters at $42-$47
+                (r)   Error code of the last firmware call
+    C0n5        (r)   Low byte of the firmware call result (block count)
+    C0n6        (r)   ATA alternate status
+                (w)   ATA device control (Bit 2 = software reset)
+    C0n7        (r)   High byte of the firmware call result
+    C0n8        (r/w) ATA data register low byte
+    C0n9        (r)   ATA error
+                (w)   ATA features
+    C0nA        (r/w) ATA sector count
+    C0nB        (r/w) ATA LBA bits 0-7
+    C0nC        (r/w) ATA LBA bits 8-15
+    C0nD        (r/w) ATA LBA bits 16-23
+    C0nE        (r/w) ATA device/head: Bit 6 = LBA mode, Bit 4 = device, Bit 3-0 = LBA bits 24-27
+    C0nF        (r)   ATA status
+                (w)   ATA command
+
+ATA commands READ SECTORS ($20), WRITE SECTORS ($30) and IDENTIFY DEVICE ($EC) are
+supported in LBA mode. RECALIBRATE, VERIFY, INITIALIZE DEVICE PARAMETERS, SET FEATURES
+and the power management commands complete without doing anything.
+
+The partitions are laid out as the CFFA firmware does with 4 partitions per device.
+Partition 1 to 4 are on the master device and partition 5 to 8 are on the slave device,
+with partition n starting at LBA (n - 1) % 4 * 65536. Each partition is backed by its own
+hdv, 2mg or raw image file.
+
+The slot ROM boots from partition 1 and provides the ProDOS block driver and the SmartPort
+interface (including the extended calls) with one SmartPort unit per partition. The ProDOS
+units in the slot of the card are partition 1 and 2. When ProDOS remaps the units to
+another slot, they are partition 3 and 4.
+*/
+
+const ROM: [u8; 256] = trap_rom(
+    CFFA_SMARTPORT,
+    CFFA_PRODOS,
+    CFFA_RESULT_LOW,
+    CFFA_RESULT_HIGH,
+);
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const CFFA_MODEL: &str = "emu6502 CFFA";
+
+/// Number of partitions supported by the card
+pub const CFFA_MAX_PARTITIONS: usize = HD_MAX_UNITS;
+
+const PARTITIONS_PER_DEVICE: usize = 4;
+const PARTITION_BLOCKS: u32 = 0x10000;
+const SECTOR_SIZE: usize = 512;
+
+// Firmware traps
+const CFFA_SMARTPORT: u8 = 0x03;
+const CFFA_PRODOS: u8 = 0x04;
+const CFFA_RESULT_LOW: u8 = 0x05;
+const CFFA_RESULT_HIGH: u8 = 0x07;
+
+// ATA status register
+const STATUS_DRDY: u8 = 0x40;
+const STATUS_DSC: u8 = 0x10;
+const STATUS_DRQ: u8 = 0x08;
+const STATUS_ERR: u8 = 0x01;
+const STATUS_READY: u8 = STATUS_DRDY | STATUS_DSC;
+
+// ATA error register
+const ERROR_IDNF: u8 = 0x10;
+const ERROR_ABRT: u8 = 0x04;
+
+const DEVICE_LBA: u8 = 0x40;
+const DEVICE_SELECT: u8 = 0x10;
+const CONTROL_SRST: u8 = 0x04;
+
+const ATA_READ_SECTORS: u8 = 0x20;
+const ATA_READ_SECTORS_NO_RETRY: u8 = 0x21;
+const ATA_WRITE_SECTORS: u8 = 0x30;
+const ATA_WRITE_SECTORS_NO_RETRY: u8 = 0x31;
+const ATA_IDENTIFY_DEVICE: u8 = 0xec;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Cffa {
+    pub partitions: HardDisk,
+    error: u8,
+    sector_count: u8,
+    lba: [u8; 3],
+    device_head: u8,
+    status: u8,
+    command: u8,
+    buffer: Vec<u8>,
+    buffer_index: usize,
+    sectors_left: usize,
+    data_high: u8,
+    call_error: u8,
+    call_result: u16,
+}
+
+impl Cffa {
+    pub fn new() -> Self {
+        Cffa {
+            partitions: HardDisk::new(),
+            error: 0,
+            sector_count: 0,
+            lba: [0; 3],
+            device_head: 0,
+            status: STATUS_READY,
+            command: 0,
+            buffer: vec![0; SECTOR_SIZE],
+            buffer_index: SECTOR_SIZE,
+            sectors_left: 0,
+            data_high: 0,
+            call_error: 0,
+            call_result: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.partitions.reset();
+        self.ata_reset();
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.partitions.is_busy()
+    }
+
+    /// Load the image of the partition (0 to 7)
+    pub fn load_partition<P>(&mut self, partition: usize, filename_path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        if partition >= CFFA_MAX_PARTITIONS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid partition {}", partition + 1),
+            ));
+        }
+
+        let drive_selected = self.partitions.drive_selected();
+        self.partitions.drive_select(partition);
+        let result = self.partitions.load_hdv_2mg_file(&filename_path);
+        if result.is_ok() {
+            self.partitions.set_disk_filename(&filename_path);
+            self.partitions.set_loaded(true);
+        }
+        self.partitions.drive_select(drive_selected);
+        result
+    }
+
+    // Executing device diagnostic after reset leaves the ATA signature in the registers
+    fn ata_reset(&mut self) {
+        self.error = 0x01;
+        self.sector_count = 0x01;
+        self.lba = [0x01, 0x00, 0x00];
+        self.device_head = 0;
+        self.status = STATUS_READY;
+        self.command = 0;
+        self.buffer_index = SECTOR_SIZE;
+        self.sectors_left = 0;
+    }
+
+    fn device(&self) -> usize {
+        ((self.device_head & DEVICE_SELECT) >> 4) as usize
+    }
+
+    fn is_device_present(&self) -> bool {
+        let first = self.device() * PARTITIONS_PER_DEVICE;
+        (first..first + PARTITIONS_PER_DEVICE).any(|drive| self.partitions.is_loaded(drive))
+    }
+
+    fn lba(&self) -> u32 {
+        u32::from_le_bytes([
+            self.lba[0],
+            self.lba[1],
+            self.lba[2],
+            self.device_head & 0x0f,
+        ])
+    }
+
+    fn set_lba(&mut self, lba: u32) {
+        let [lba0, lba1, lba2, lba3] = lba.to_le_bytes();
+        self.lba = [lba0, lba1, lba2];
+        self.device_head = (self.device_head & 0xf0) | (lba3 & 0x0f);
+    }
+
+    // Returns the partition and the block of the sector
+    fn locate(&self, lba: u32) -> Option<(usize, u32)> {
+        let partition = (lba / PARTITION_BLOCKS) as usize;
+        let block = lba % PARTITION_BLOCKS;
+        if partition >= PARTITIONS_PER_DEVICE {
+            return None;
+        }
+
+        let drive = self.device() * PARTITIONS_PER_DEVICE + partition;
+        if (block as usize) < self.partitions.num_blocks(drive) {
+            Some((drive, block))
+        } else {
+            None
+        }
+    }
+
+    // Number of sectors up to the end of the last partition of the device
+    fn capacity(&self) -> u32 {
+        let first = self.device() * PARTITIONS_PER_DEVICE;
+        (0..PARTITIONS_PER_DEVICE)
+            .rev()
+            .map(|partition| (partition, self.partitions.num_blocks(first + partition)))
+            .find(|(_, blocks)| *blocks > 0)
+            .map_or(0, |(partition, blocks)| {
+                partition as u32 * PARTITION_BLOCKS + (blocks as u32).min(PARTITION_BLOCKS)
+            })
+    }
+
+    fn abort(&mut self, error: u8) {
+        self.error = error;
+        self.status = STATUS_READY | STATUS_ERR;
+        self.sectors_left = 0;
+        self.buffer_index = SECTOR_SIZE;
+    }
+
+    fn execute(&mut self, command: u8) {
+        if !self.is_device_present() {
+            return;
+        }
+
+        self.command = command;
+        self.error = 0;
+        self.status = STATUS_READY;
+        self.buffer_index = SECTOR_SIZE;
+        let count = if self.sector_count == 0 {
+            256
+        } else {
+            self.sector_count as usize
+        };
+
+        match command {
+            ATA_READ_SECTORS
+            | ATA_READ_SECTORS_NO_RETRY
+            | ATA_WRITE_SECTORS
+            | ATA_WRITE_SECTORS_NO_RETRY => {
+                if self.device_head & DEVICE_LBA == 0 {
+                    self.abort(ERROR_ABRT);
+                    return;
+                }
+
+                self.sectors_left = count;
+                if command & 0xf0 == ATA_READ_SECTORS {
+                    self.read_sector();
+                } else if self.locate(self.lba()).is_some() {
+                    self.buffer_index = 0;
+                    self.status = STATUS_READY | STATUS_DRQ;
+                } else {
+                    self.abort(ERROR_IDNF);
+                }
+            }
+
+            ATA_IDENTIFY_DEVICE => {
+                self.identify_device();
+                self.sectors_left = 1;
+                self.buffer_index = 0;
+                self.status = STATUS_READY | STATUS_DRQ;
+            }
+
+            // Recalibrate, verify, initialize device parameters, power management and
+            // set features
+            0x10..=0x1f | 0x40 | 0x41 | 0x91 | 0xe0..=0xe7 | 0xef => {}
+
+            _ => self.abort(ERROR_ABRT),
+        }
+    }
+
+    fn read_sector(&mut self) {
+        let lba = self.lba();
+        let data = self
+            .locate(lba)
+            .and_then(|(drive, block)| self.partitions.read_block(drive, block));
+
+        if let Some(data) = data {
+            self.buffer.copy_from_slice(data);
+            self.buffer_index = 0;
+            self.status = STATUS_READY | STATUS_DRQ;
+        } else {
+            self.abort(ERROR_IDNF);
+        }
+    }
+
+    fn write_sector(&mut self) {
+        let error = match self.locate(self.lba()) {
+            Some((drive, block)) => self.partitions.write_block(drive, block, &self.buffer),
+            None => DeviceStatus::DeviceIoError as u8,
+        };
+
+        if error == DeviceStatus::DeviceWriteProtected as u8 {
+            self.abort(ERROR_ABRT);
+        } else if error != DeviceStatus::DeviceOk as u8 {
+            self.abort(ERROR_IDNF);
+        } else {
+            self.next_sector();
+        }
+    }
+
+    fn next_sector(&mut self) {
+        self.sectors_left = self.sectors_left.saturating_sub(1);
+        if self.sectors_left == 0 {
+            self.status = STATUS_READY;
+            return;
+        }
+
+        self.set_lba(self.lba() + 1);
+        match self.command {
+            ATA_READ_SECTORS | ATA_READ_SECTORS_NO_RETRY => self.read_sector(),
+            _ => {
+                self.buffer_index = 0;
+                self.status = STATUS_READY | STATUS_DRQ;
+            }
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        if self.buffer_index >= SECTOR_SIZE || self.command & 0xf0 == ATA_WRITE_SECTORS {
+            return 0;
+        }
+
+        let value = self.buffer[self.buffer_index];
+        self.data_high = self.buffer[self.buffer_index + 1];
+        self.buffer_index += 2;
+
+        if self.buffer_index >= SECTOR_SIZE {
+            if self.command == ATA_IDENTIFY_DEVICE {
+                self.sectors_left = 0;
+                self.status = STATUS_READY;
+            } else {
+                self.next_sector();
+            }
+        }
+        value
+    }
+
+    fn write_data(&mut self, value: u8) {
+        if self.buffer_index >= SECTOR_SIZE || self.command & 0xf0 != ATA_WRITE_SECTORS {
+            return;
+        }
+
+        self.buffer[self.buffer_index] = value;
+        self.buffer[self.buffer_index + 1] = self.data_high;
+        self.buffer_index += 2;
+
+        if self.buffer_index >= SECTOR_SIZE {
+            self.write_sector();
+        }
+    }
+
+    fn identify_device(&mut self) {
+        let capacity = self.capacity();
+        let heads = 16;
+        let sectors = 63;
+        let cylinders = (capacity / (heads * sectors)).clamp(1, 16383);
+        let (major, minor) = VERSION.split_once('.').unwrap_or((VERSION, ""));
+        let revision = format!("{major}.{minor}");
+
+        let mut words = [0u16; SECTOR_SIZE / 2];
+        words[0] = 0x848a;
+        words[1] = cylinders as u16;
+        words[3] = heads as u16;
+        words[6] = sectors as u16;
+        words[47] = 0x8001;
+        words[49] = 0x0200;
+        words[53] = 0x0001;
+        words[54] = cylinders as u16;
+        words[55] = heads as u16;
+        words[56] = sectors as u16;
+        words[57] = capacity as u16;
+        words[58] = (capacity >> 16) as u16;
+        words[60] = capacity as u16;
+        words[61] = (capacity >> 16) as u16;
+
+        // ATA strings are padded with spaces and store the first character of each
+        // pair in the high byte
+        let mut set_string = |start: usize, len: usize, text: &str| {
+            let text = format!("{text:<width$}", width = len * 2);
+            for (i, pair) in text.as_bytes().chunks(2).take(len).enumerate() {
+                words[start + i] = u16::from_be_bytes([pair[0], pair[1]]);
+            }
+        };
+        set_string(10, 10, &format!("CFFA{}", self.device() + 1));
+        set_string(23, 4, &revision);
+        set_string(27, 20, CFFA_MODEL);
+
+        for (i, word) in words.iter().enumerate() {
+            self.buffer[i * 2..i * 2 + 2].copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    fn read_register(&mut self, reg: u8) -> u8 {
+        let selected = self.is_device_present();
+        match reg {
+            0x08 => self.read_data(),
+            0x09 => self.error,
+            0x0a => self.sector_count,
+            0x0b..=0x0d => self.lba[(reg - 0x0b) as usize],
+            0x0e => self.device_head,
+            0x06 | 0x0f if selected => self.status,
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, reg: u8, value: u8) {
+        match reg {
+            0x06 if value & CONTROL_SRST != 0 => self.ata_reset(),
+            0x08 => self.write_data(value),
+            0x0a => self.sector_count = value,
+            0x0b..=0x0d => self.lba[(reg - 0x0b) as usize] = value,
+            0x0e => self.device_head = value,
+            0x0f => self.execute(value),
+            _ => {}
+        }
+    }
+}
+
+impl Default for Cffa {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tick for Cffa {
+    fn tick(&mut self) {
+        self.partitions.tick();
+    }
+}
+
+impl Card for Cffa {
+    fn rom_access(&mut self, addr: u16, _value: u8, _write_flag: bool) -> u8 {
+        ROM[(addr & 0xff) as usize]
+    }
+
+    fn io_access(
+        &mut self,
+        mmu: &mut Mmu,
+        video: &mut Video,
+        addr: u16,
+        value: u8,
+        write_flag: bool,
+    ) -> u8 {
+        let slot = ((addr & 0x00ff) - 0x0080) >> 4;
+        let io_addr = ((addr & 0x00ff) - (slot << 4)) as u8 & 0x0f;
+        let io_base = 0xc080 | (slot << 4);
+
+        match io_addr {
+            0x00 => {
+                if write_flag {
+                    self.data_high = value;
+                }
+                self.data_high
+            }
+
+            CFFA_SMARTPORT if write_flag => {
+                self.call_error = self
+                    .partitions
+                    .firmware_smartport_call(mmu, video, io_base, value);
+                self.call_result = 0;
+                0
+            }
+
+            CFFA_PRODOS => {
+                if write_flag {
+                    (self.call_error, self.call_result) =
+                        self.partitions.firmware_prodos_call(mmu, video, io_base);
+                }
+                self.call_error
+            }
+
+            CFFA_RESULT_LOW if !write_flag => self.call_result as u8,
+            CFFA_RESULT_HIGH if !write_flag => (self.call_result >> 8) as u8,
+
+            0x06 | 0x08..=0x0f => {
+                if write_flag {
+                    self.write_register(io_addr, value);
+                    0
+                } else {
+                    self.read_register(io_addr)
+                }
+            }
+
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Bus, IODevice, Mem};
+    use crate::cpu::CPU;
+
+    // Partition with the block number in the first two bytes of each block
+    fn load_partition(cffa: &mut Cffa, partition: usize, blocks: usize) {
+        let mut data = vec![0u8; blocks * SECTOR_SIZE];
+        for (block, sector) in data.chunks_mut(SECTOR_SIZE).enumerate() {
+            sector[..2].copy_from_slice(&(block as u16).to_le_bytes());
+            sector[2] = partition as u8;
+        }
+        let disk = &mut cffa.partitions;
+        disk.drive_select(partition);
+        disk.load_hdv_2mg_array(&data, true, false).unwrap();
+        disk.set_loaded(true);
+        disk.drive_select(0);
+    }
+
+    fn cffa_cpu() -> CPU {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+        cpu.bus.register_device(IODevice::Cffa, 7);
+        cpu.bus.mem.intcxrom = false;
+        load_partition(&mut cpu.bus.cffa, 0, 16);
+        load_partition(&mut cpu.bus.cffa, 1, 8);
+        load_partition(&mut cpu.bus.cffa, 5, 4);
+
+        // IORTS
+        cpu.load(&[0x60], 0xff58);
+        cpu
+    }
+
+    #[test]
+    fn ata_read_and_write_sectors() {
+        let mut cpu = cffa_cpu();
+        let bus = &mut cpu.bus;
+
+        // Read LBA 65537 (partition 2 block 1) and LBA 65538
+        bus.unclocked_addr_write(0xc0fe, DEVICE_LBA);
+        bus.unclocked_addr_write(0xc0fa, 2);
+        bus.unclocked_addr_write(0xc0fb, 0x01);
+        bus.unclocked_addr_write(0xc0fc, 0x00);
+        bus.unclocked_addr_write(0xc0fd, 0x01);
+        bus.unclocked_addr_write(0xc0ff, ATA_READ_SECTORS);
+        for block in [1u8, 2] {
+            assert_eq!(bus.unclocked_addr_read(0xc0ff), STATUS_READY | STATUS_DRQ);
+            assert_eq!(bus.unclocked_addr_read(0xc0f8), block);
+            assert_eq!(bus.unclocked_addr_read(0xc0f0), 0);
+            assert_eq!(bus.unclocked_addr_read(0xc0f8), 1);
+            for _ in 2..SECTOR_SIZE / 2 {
+                bus.unclocked_addr_read(0xc0f8);
+            }
+        }
+        assert_eq!(bus.unclocked_addr_read(0xc0ff), STATUS_READY);
+
+        // Write LBA 65539 of the slave device (partition 6 block 3)
+        bus.unclocked_addr_write(0xc0fe, DEVICE_LBA | DEVICE_SELECT);
+        bus.unclocked_addr_write(0xc0fa, 1);
+        bus.unclocked_addr_write(0xc0fb, 3);
+        bus.unclocked_addr_write(0xc0fd, 1);
+        bus.unclocked_addr_write(0xc0ff, ATA_WRITE_SECTORS);
+        for i in 0..SECTOR_SIZE / 2 {
+            bus.unclocked_addr_write(0xc0f0, 0xa5);
+            bus.unclocked_addr_write(0xc0f8, i as u8);
+        }
+        assert_eq!(bus.unclocked_addr_read(0xc0ff), STATUS_READY);
+        let data = bus.cffa.partitions.read_block(5, 3).unwrap();
+        assert_eq!(&data[..4], &[0x00, 0xa5, 0x01, 0xa5]);
+
+        // Partition 7 is not loaded
+        bus.unclocked_addr_write(0xc0fd, 0x02);
+        bus.unclocked_addr_write(0xc0ff, ATA_READ_SECTORS);
+        assert_eq!(bus.unclocked_addr_read(0xc0ff), STATUS_READY | STATUS_ERR);
+        assert_eq!(bus.unclocked_addr_read(0xc0f9), ERROR_IDNF);
+    }
+
+    #[test]
+    fn ata_identify_device() {
+        let mut cpu = cffa_cpu();
+        let bus = &mut cpu.bus;
+
+        bus.unclocked_addr_write(0xc0fe, DEVICE_LBA);
+        bus.unclocked_addr_write(0xc0ff, ATA_IDENTIFY_DEVICE);
+        let mut words = vec![];
+        for _ in 0..SECTOR_SIZE / 2 {
+            let low = bus.unclocked_addr_read(0xc0f8);
+            let high = bus.unclocked_addr_read(0xc0f0);
+            words.push(u16::from_le_bytes([low, high]));
+        }
+        assert_eq!(words[0], 0x848a);
+        assert_eq!(words[27], u16::from_be_bytes(*b"em"));
+
+        // Partition 2 with 8 blocks is the last partition of the master device
+        assert_eq!((words[60], words[61]), (8, 1));
+
+        // The slave device is not present without any partition
+        bus.cffa.partitions.eject(5);
+        bus.unclocked_addr_write(0xc0fe, DEVICE_LBA | DEVICE_SELECT);
+        assert_eq!(bus.unclocked_addr_read(0xc0ff), 0);
+    }
+
+    #[test]
+    fn firmware_prodos_and_smartport() {
+        let mut cpu = cffa_cpu();
+
+        // ProDOS read block 5 of drive 2 (partition 2) to $2000, then
+        // SmartPort read block 3 of unit 6 to $3000
+        cpu.load(&[0x01, 0xf0, 0x00, 0x20, 0x05, 0x00], 0x42);
+        cpu.load(&[0x03, 0x06, 0x00, 0x30, 0x03, 0x00, 0x00], 0x380);
+        cpu.load(
+            &[
+                0x20, 0x0a, 0xc7, 0x85, 0x06, 0x86, 0x07, 0x84, 0x08, 0x20, 0x0d, 0xc7, 0x01, 0x80,
+                0x03, 0x85, 0x09, 0x00,
+            ],
+            0x300,
+        );
+        cpu.program_counter = 0x300;
+        cpu.run();
+
+        assert_eq!(cpu.bus.unclocked_addr_read(0x06), 0);
+        assert_eq!(cpu.bus.unclocked_addr_read(0x07), 8);
+        assert_eq!(cpu.bus.unclocked_addr_read(0x08), 0);
+        assert_eq!(cpu.bus.unclocked_addr_read(0x2000), 5);
+        assert_eq!(cpu.bus.unclocked_addr_read(0x2002), 1);
+
+        assert_eq!(cpu.bus.unclocked_addr_read(0x09), 0);
+        assert_eq!(cpu.bus.unclocked_addr_read(0x3000), 3);
+        assert_eq!(cpu.bus.unclocked_addr_read(0x3002), 5);
+
+        // Boot loads block 0 of partition 1 to $800 and runs it with X = $70
+        cpu.load(&[0xff], 0x802);
+        cpu.program_counter = 0xc700;
+        cpu.run();
+        assert_eq!(cpu.register_x, 0x70);
+        assert_eq!(cpu.bus.unclocked_addr_read(0x802), 0);
+    }
+}
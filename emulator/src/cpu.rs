@@ -1,5 +1,8 @@
 use crate::bus::Bus;
 use crate::bus::Mem;
+use crate::cassette::{self, TAPE_READ_ENTRY};
+use crate::disk::WozHardware;
+use crate::diskaccel::{self, PRODOS_DRIVER_ENTRY, RWTS_ENTRY};
 //use std::collections::HashMap;
 //use crate::trace::disassemble;
 //use crate::trace::trace;
@@ -1815,6 +1818,21 @@ impl CPU {
         self.bus.mem_read(0xfbb3) == 0x06 && self.bus.mem_read(0xfbc0) == 0x00
     }
 
+    /// Current machine type in WOZ compatible hardware format
+    pub fn get_woz_hardware(&self) -> WozHardware {
+        match self.bus.mem_read(0xfbb3) {
+            0x38 => WozHardware::APPLE2,
+            0xea => WozHardware::APPLE2_PLUS,
+            0x06 => match self.bus.mem_read(0xfbc0) {
+                0xea => WozHardware::APPLE2E,
+                0xe0 => WozHardware::APPLE2E_ENHANCED,
+                _ if self.bus.mem_read(0xfbbf) == 0x05 => WozHardware::APPLE2C_PLUS,
+                _ => WozHardware::APPLE2C,
+            },
+            _ => WozHardware::empty(),
+        }
+    }
+
     pub fn run(&mut self) {
         self.run_with_callback(|_| {});
     }
@@ -1891,7 +1909,22 @@ impl CPU {
         if !self.alt_cpu {
             callback(self);
 
+            if self.bus.is_high_level_disk()
+                && matches!(self.program_counter, RWTS_ENTRY | PRODOS_DRIVER_ENTRY)
+                && diskaccel::trap_disk_call(self)
+            {
+                return true;
+            }
+
+            if self.bus.audio.is_fast_tape()
+                && self.program_counter == TAPE_READ_ENTRY
+                && cassette::trap_tape_read(self)
+            {
+                return true;
+            }
+
             let program_counter_state = self.program_counter;
+            self.bus.pc = program_counter_state;
             let code = self.next_byte();
             //let opcode = opcodes::CPU_OPS_CODES[code as usize];
             let opcode = &OPCODES[code as usize];
@@ -989,7 +989,8 @@ impl CPU {
 
         callback(self);
 
-        let _program_counter_state = self.program_counter;
+        let program_counter_state = self.program_counter;
+        self.bus.pc = program_counter_state;
         let code = self.next_program_byte();
         let _opcode = &OPCODES[code as usize];
 
@@ -2350,7 +2351,7 @@ impl CPU {
         // Detect Trap Function (Ignore MVP and MVN)
         {
             #[cfg(test)]
-            if self.program_counter == _program_counter_state && code != 0x54 && code != 0x44 {
+            if self.program_counter == program_counter_state && code != 0x54 && code != 0x44 {
                 return false;
             }
         }
@@ -1,8 +1,17 @@
 use crate::bus::{Card, Tick};
+use crate::diskimage::{
+    DiskCopy42, IMG_2MG_FORMAT_DOS, IMG_2MG_FORMAT_NIB, IMG_2MG_FORMAT_PRODOS, Image2mg,
+    ImageHeader,
+};
+use crate::disklog::{DiskLog, DiskLogEvent};
 use crate::disksound::DiskSound;
+use crate::disktrack::{self, TrackAnalysis};
 use crate::mmu::Mmu;
+use crate::overlay::{self, DELTA_TMAP_INDEX, DeltaEntry};
 use crate::video::Video;
+use bitflags::bitflags;
 //use rand::prelude::*;
+use std::collections::VecDeque;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::prelude::*;
@@ -92,6 +101,234 @@ pub enum TrackType {
     Flux,
 }
 
+bitflags! {
+    /// Compatible hardware bits in the WOZ2 INFO chunk
+    #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+    pub struct WozHardware: u16 {
+        const APPLE2            = 0x0001;
+        const APPLE2_PLUS       = 0x0002;
+        const APPLE2E           = 0x0004;
+        const APPLE2C           = 0x0008;
+        const APPLE2E_ENHANCED  = 0x0010;
+        const APPLE2GS          = 0x0020;
+        const APPLE2C_PLUS      = 0x0040;
+        const APPLE3            = 0x0080;
+        const APPLE3_PLUS       = 0x0100;
+    }
+}
+
+// Machine names used in the requires_machine META field
+const WOZ_META_MACHINE: [(&str, WozHardware); 9] = [
+    ("2", WozHardware::APPLE2),
+    ("2+", WozHardware::APPLE2_PLUS),
+    ("2e", WozHardware::APPLE2E),
+    ("2c", WozHardware::APPLE2C),
+    ("2e+", WozHardware::APPLE2E_ENHANCED),
+    ("2gs", WozHardware::APPLE2GS),
+    ("2c+", WozHardware::APPLE2C_PLUS),
+    ("3", WozHardware::APPLE3),
+    ("3+", WozHardware::APPLE3_PLUS),
+];
+
+/// Metadata from the WOZ INFO and META chunks
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct WozMetadata {
+    pub info_version: u8,
+    pub creator: String,
+    pub disk_sides: u8,
+    pub boot_sector_format: u8,
+    pub compatible_hardware: u16,
+    pub required_ram: u16,
+    pub meta: Vec<(String, String)>,
+}
+
+impl WozMetadata {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.meta
+            .iter()
+            .find(|(k, v)| k == key && !v.is_empty())
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.get("title")
+    }
+
+    pub fn side(&self) -> Option<&str> {
+        self.get("side").or_else(|| self.get("side_name"))
+    }
+
+    /// Compatible hardware from the INFO chunk combined with the requires_machine
+    /// META field. Empty value means the compatibility is unknown
+    pub fn compatible_hardware(&self) -> WozHardware {
+        let mut hardware = WozHardware::from_bits_truncate(self.compatible_hardware);
+        if let Some(machines) = self.get("requires_machine") {
+            for machine in machines.split('|') {
+                if let Some((_, flag)) = WOZ_META_MACHINE
+                    .iter()
+                    .find(|(name, _)| machine.trim().eq_ignore_ascii_case(name))
+                {
+                    hardware |= *flag;
+                }
+            }
+        }
+        hardware
+    }
+
+    pub fn is_compatible(&self, machine: WozHardware) -> bool {
+        let hardware = self.compatible_hardware();
+        hardware.is_empty() || hardware.intersects(machine)
+    }
+
+    /// Minimum RAM required in KiB. Returns 0 if unknown
+    pub fn required_ram_kb(&self) -> u16 {
+        let meta_ram = self
+            .get("requires_ram")
+            .and_then(|value| {
+                let value = value.trim().trim_end_matches('+').to_ascii_uppercase();
+                if let Some(kb) = value.strip_suffix('K') {
+                    kb.parse::<f32>().ok()
+                } else if let Some(mb) = value.strip_suffix('M') {
+                    mb.parse::<f32>().ok().map(|mb| mb * 1024.0)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(0.0) as u16;
+        u16::max(self.required_ram, meta_ram)
+    }
+}
+
+fn parse_woz_meta(data: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter_map(|line| {
+            line.split_once('\t')
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Maximum number of latch samples kept for the disk inspector
+pub const LATCH_HISTORY_SIZE: usize = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LatchSample {
+    pub quarter_track: u8,
+    pub bit_position: usize,
+    pub latch: u8,
+}
+
+/// List of images for multi-disk software. Images inside a zip file are stored as
+/// "archive.zip#image"
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct DiskSet {
+    pub images: Vec<String>,
+    pub index: usize,
+}
+
+const DISK_SET_ZIP_SEPARATOR: char = '#';
+#[cfg(feature = "zip")]
+const DISK_SET_EXTENSIONS: [&str; 12] = [
+    "dsk", "do", "po", "nib", "woz", "2mg", "2img", "dc", "dc42", "dsk.gz", "po.gz", "woz.gz",
+];
+
+#[cfg(feature = "zip")]
+fn is_disk_set_image(name: &str) -> bool {
+    let name = name.to_lowercase();
+    DISK_SET_EXTENSIONS
+        .iter()
+        .any(|ext| name.ends_with(&format!(".{ext}")))
+}
+
+// Split "archive.zip#image" into the zip filename and the image name in the zip file
+fn split_zip_entry(filename: &str) -> Option<(&str, &str)> {
+    filename
+        .rsplit_once(DISK_SET_ZIP_SEPARATOR)
+        .filter(|(zip_name, _)| zip_name.to_lowercase().ends_with(".zip"))
+}
+
+const DISK_SET_DISK_KEYS: [&str; 3] = ["disk", "disc", "d"];
+const DISK_SET_SIDE_KEYS: [&str; 2] = ["side", "s"];
+
+// Number after the key at the start of a word in the lowercase name. The single letter
+// keys must be directly followed by the number (e.g. "s2")
+fn parse_number_after(name: &str, keys: &[&str]) -> Option<u32> {
+    for key in keys {
+        for (pos, _) in name.match_indices(key) {
+            if name[..pos]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_ascii_alphabetic())
+            {
+                continue;
+            }
+
+            let mut rest = &name[pos + key.len()..];
+            if key.len() > 1 {
+                rest = rest.trim_start_matches([' ', '_', '-', '.']);
+            }
+            let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+            if let Ok(value) = digits.parse() {
+                return Some(value);
+            }
+            // Side A, Side B ...
+            if *key == "side"
+                && let Some(c) = rest.chars().next()
+                && c.is_ascii_alphabetic()
+                && !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+            {
+                return Some((c as u8 - b'a' + 1) as u32);
+            }
+        }
+    }
+    None
+}
+
+/// Sort key for the disk set based on the disk and side. The side field of the WOZ
+/// META chunk (e.g. "Disk 1, Side A") is used first, then the filename.
+/// e.g. "Game (Disk 2 Side B).woz" returns (2, 2)
+pub fn disk_set_sort_key(name: &str, meta_side: Option<&str>) -> (u32, u32) {
+    let meta_side = meta_side.map(str::to_lowercase).unwrap_or_default();
+    let name = Path::new(name)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let parse = |keys: &[&str]| {
+        parse_number_after(&meta_side, keys)
+            .or_else(|| parse_number_after(&name, keys))
+            .unwrap_or(0)
+    };
+    (parse(&DISK_SET_DISK_KEYS), parse(&DISK_SET_SIDE_KEYS))
+}
+
+// Side field of the META chunk of the WOZ image, used to sort the disk set
+#[cfg(feature = "zip")]
+fn woz_meta_side(dsk: &[u8]) -> Option<String> {
+    if !dsk.starts_with(b"WOZ") {
+        return None;
+    }
+
+    let mut offset = 12;
+    while offset + 8 <= dsk.len() {
+        let chunk_id = u32::from_le_bytes(dsk[offset..offset + 4].try_into().ok()?);
+        let chunk_size = u32::from_le_bytes(dsk[offset + 4..offset + 8].try_into().ok()?);
+        offset += 8;
+        if chunk_id == WOZ_META_CHUNK {
+            let end = usize::min(offset + chunk_size as usize, dsk.len());
+            let metadata = WozMetadata {
+                meta: parse_woz_meta(&dsk[offset..end]),
+                ..Default::default()
+            };
+            return metadata.side().map(str::to_string);
+        }
+        offset = offset.saturating_add(chunk_size as usize);
+    }
+    None
+}
+
 #[derive(Debug)]
 #[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
 pub struct Disk {
@@ -144,6 +381,15 @@ pub struct Disk {
 
     #[cfg_attr(feature = "serde_support", serde(default))]
     revolution: usize,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    image_header: ImageHeader,
+
+    #[cfg_attr(feature = "serde_support", serde(skip))]
+    dirty_tracks: Vec<bool>,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    woz_metadata: Option<WozMetadata>,
 }
 
 #[derive(Debug)]
@@ -182,6 +428,21 @@ pub struct DiskDrive {
 
     #[cfg_attr(feature = "serde_support", serde(default))]
     disable_disk_jitter: bool,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    overlay: bool,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    disk_set: Vec<DiskSet>,
+
+    #[cfg_attr(feature = "serde_support", serde(skip))]
+    latch_history: Option<VecDeque<LatchSample>>,
+
+    #[cfg_attr(feature = "serde_support", serde(skip))]
+    access_log: Option<DiskLog>,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    high_level_disk: bool,
 }
 
 // Q0L: Phase 0 OFF
@@ -234,18 +495,18 @@ const LOC_DRIVEWRITEMODE: u8 = 0x8f;
 
 const _PHASE_DELTA: [[i16; 4]; 4] = [[0, 1, 2, -1], [-1, 0, 1, 2], [-2, -1, 0, 1], [1, -2, -1, 0]];
 
-const TRANSLATE_VALUE_6X2: [u8; 64] = [
+pub(crate) const TRANSLATE_VALUE_6X2: [u8; 64] = [
     0x96, 0x97, 0x9a, 0x9b, 0x9d, 0x9e, 0x9f, 0xa6, 0xa7, 0xab, 0xac, 0xad, 0xae, 0xaf, 0xb2, 0xb3,
     0xb4, 0xb5, 0xb6, 0xb7, 0xb9, 0xba, 0xbb, 0xbc, 0xbd, 0xbe, 0xbf, 0xcb, 0xcd, 0xce, 0xcf, 0xd3,
     0xd6, 0xd7, 0xd9, 0xda, 0xdb, 0xdc, 0xdd, 0xde, 0xdf, 0xe5, 0xe6, 0xe7, 0xe9, 0xea, 0xeb, 0xec,
     0xed, 0xee, 0xef, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe, 0xff,
 ];
 
-const DSK_DO: [u8; 16] = [
+pub(crate) const DSK_DO: [u8; 16] = [
     0x0, 0xd, 0xb, 0x9, 0x7, 0x5, 0x3, 0x1, 0xe, 0xc, 0xa, 0x8, 0x6, 0x4, 0x2, 0xf,
 ];
 
-const DSK_PO: [u8; 16] = [
+pub(crate) const DSK_PO: [u8; 16] = [
     0x0, 0x2, 0x4, 0x6, 0x8, 0xa, 0xc, 0xe, 0x1, 0x3, 0x5, 0x7, 0x9, 0xb, 0xd, 0xf,
 ];
 
@@ -276,6 +537,7 @@ const WOZ_INFO_CHUNK: u32 = 0x4F464E49;
 const WOZ_TMAP_CHUNK: u32 = 0x50414D54;
 const WOZ_TRKS_CHUNK: u32 = 0x534B5254;
 const WOZ_FLUX_CHUNK: u32 = 0x58554C46;
+const WOZ_META_CHUNK: u32 = 0x4154454D;
 
 /* motor position from the magnet state
    -1 means invalid, not supported
@@ -435,7 +697,51 @@ fn bits_write_sync(buf: &mut [u8], index: usize) -> usize {
     index + 2
 }
 
-fn encode_bits_for_track(data: &[u8], track: u8, sector_format_prodos: bool) -> (Vec<u8>, usize) {
+/// Encode the 256 bytes sector into 342 6x2 nibbles and the checksum nibble
+pub(crate) fn encode_6x2(data: &[u8]) -> [u8; 343] {
+    let mut nibbles = [0u8; 344];
+    let mut encoded_contents = [0u8; 343];
+    let ptr6 = 0x56;
+
+    let mut idx2: i8 = 0x55;
+    for idx6 in (0..=0x101).rev() {
+        let mut val6 = data[idx6 % 0x100];
+
+        if idx6 >= 0x100 {
+            val6 = 0;
+        }
+
+        let mut val2 = nibbles[idx2 as usize];
+
+        val2 = (val2 << 1) + (val6 & 1);
+        val6 >>= 1;
+        val2 = (val2 << 1) + (val6 & 1);
+        val6 >>= 1;
+
+        nibbles[ptr6 + idx6] = val6;
+        nibbles[idx2 as usize] = val2;
+
+        idx2 -= 1;
+        if idx2 < 0 {
+            idx2 = 0x55;
+        }
+    }
+
+    let mut last = 0;
+    for (i, item) in nibbles.iter().enumerate().take(0x156) {
+        let val = *item;
+        encoded_contents[i] = TRANSLATE_VALUE_6X2[(last ^ val) as usize];
+        last = val;
+    }
+    encoded_contents[342] = TRANSLATE_VALUE_6X2[last as usize];
+    encoded_contents
+}
+
+pub(crate) fn encode_bits_for_track(
+    data: &[u8],
+    track: u8,
+    sector_format_prodos: bool,
+) -> (Vec<u8>, usize) {
     let mut buf = vec![0u8; NIB_TRACK_SIZE];
     let mut bit_index = 0;
 
@@ -488,41 +794,8 @@ fn encode_bits_for_track(data: &[u8], track: u8, sector_format_prodos: bool) ->
         };
 
         // Finally, the actual contents! Encode the buffer, then write them.
-        let mut nibbles = [0u8; 344];
-        let mut encoded_contents = [0u8; 343];
-        let ptr6 = 0x56;
-
-        let mut idx2: i8 = 0x55;
-        for idx6 in (0..=0x101).rev() {
-            let mut val6 = data[logical_sector * BYTES_PER_SECTOR + idx6 % 0x100];
-
-            if idx6 >= 0x100 {
-                val6 = 0;
-            }
-
-            let mut val2 = nibbles[idx2 as usize];
-
-            val2 = (val2 << 1) + (val6 & 1);
-            val6 >>= 1;
-            val2 = (val2 << 1) + (val6 & 1);
-            val6 >>= 1;
-
-            nibbles[ptr6 + idx6] = val6;
-            nibbles[idx2 as usize] = val2;
-
-            idx2 -= 1;
-            if idx2 < 0 {
-                idx2 = 0x55;
-            }
-        }
-
-        let mut last = 0;
-        for (i, item) in nibbles.iter().enumerate().take(0x156) {
-            let val = *item;
-            encoded_contents[i] = TRANSLATE_VALUE_6X2[(last ^ val) as usize];
-            last = val;
-        }
-        encoded_contents[342] = TRANSLATE_VALUE_6X2[last as usize];
+        let offset = logical_sector * BYTES_PER_SECTOR;
+        let encoded_contents = encode_6x2(&data[offset..offset + BYTES_PER_SECTOR]);
         for item in encoded_contents {
             bit_index = bits_write_byte(&mut buf, bit_index, item);
         }
@@ -611,7 +884,9 @@ fn save_dsk_woz_to_disk(disk: &Disk) -> io::Result<()> {
         if let Some(file_stem) = path.file_stem() {
             let stem_path = Path::new(file_stem);
             if let Some(path_ext) = path.extension() {
-                if check_file_extension(path, path_ext, stem_path, "dsk")
+                if disk.image_header != ImageHeader::None {
+                    convert_woz_to_image(disk)?;
+                } else if check_file_extension(path, path_ext, stem_path, "dsk")
                     || check_file_extension(path, path_ext, stem_path, "do")
                     || check_file_extension(path, path_ext, stem_path, "po")
                 {
@@ -628,6 +903,38 @@ fn save_dsk_woz_to_disk(disk: &Disk) -> io::Result<()> {
     Ok(())
 }
 
+fn mark_track_dirty(disk: &mut Disk, track: usize) {
+    if disk.dirty_tracks.len() < DSK_TRACK_SIZE {
+        disk.dirty_tracks.resize(DSK_TRACK_SIZE, false);
+    }
+    disk.dirty_tracks[track] = true;
+}
+
+// Append the track map and the tracks modified since the last save to the overlay file
+fn save_disk_overlay(disk: &mut Disk) -> io::Result<()> {
+    if let Some(filename) = &disk.filename {
+        let mut entries = vec![DeltaEntry {
+            index: DELTA_TMAP_INDEX,
+            bits: 0,
+            data: disk.tmap_data.clone(),
+        }];
+
+        for (track, dirty) in disk.dirty_tracks.iter().enumerate() {
+            if *dirty {
+                entries.push(DeltaEntry {
+                    index: track as u32,
+                    bits: disk.raw_track_bits[track] as u32,
+                    data: disk.raw_track_data[track].clone(),
+                });
+            }
+        }
+
+        overlay::append_delta(filename, &entries)?;
+        disk.dirty_tracks.fill(false);
+    }
+    Ok(())
+}
+
 fn _remove_unused_disk_tracks(disk: &mut Disk) {
     for qt in 0..160 {
         let tmap_track = disk.tmap_data[qt] as usize;
@@ -1032,6 +1339,15 @@ fn create_woz2_trk(dsk: &[u8], woz_offset: usize, disk: &Disk, newdsk: &mut Vec<
 
 // This functions assumes that the woz data comes originally from dsk / po
 fn convert_woz_to_dsk(disk: &Disk) -> io::Result<()> {
+    let data = decode_woz_to_dsk(disk);
+
+    // Write to new file
+    write_disk_content_to_disk(disk, &data)?;
+
+    Ok(())
+}
+
+fn decode_woz_to_dsk(disk: &Disk) -> Vec<u8> {
     let no_of_tracks: usize = disk.track_size;
     let mut data = vec![0u8; 16 * 256 * no_of_tracks];
 
@@ -1060,13 +1376,34 @@ fn convert_woz_to_dsk(disk: &Disk) -> io::Result<()> {
         }
     }
 
+    data
+}
+
+fn convert_woz_to_nib(disk: &Disk) -> io::Result<()> {
+    let data = decode_woz_to_nib(disk);
+
     // Write to new file
     write_disk_content_to_disk(disk, &data)?;
 
     Ok(())
 }
 
-fn convert_woz_to_nib(disk: &Disk) -> io::Result<()> {
+// The 2mg and DiskCopy 4.2 header and metadata are written back together with the
+// disk data. The DiskCopy 4.2 checksum is recalculated
+fn convert_woz_to_image(disk: &Disk) -> io::Result<()> {
+    let data = match &disk.image_header {
+        ImageHeader::Img2mg(header) if header.format == IMG_2MG_FORMAT_NIB => {
+            decode_woz_to_nib(disk)
+        }
+        _ => decode_woz_to_dsk(disk),
+    };
+
+    write_disk_content_to_disk(disk, &disk.image_header.build(&data))?;
+
+    Ok(())
+}
+
+fn decode_woz_to_nib(disk: &Disk) -> Vec<u8> {
     let no_of_tracks: usize = disk.track_size;
     let mut data = vec![0u8; NIB_TRACK_SIZE * no_of_tracks];
 
@@ -1146,10 +1483,7 @@ fn convert_woz_to_nib(disk: &Disk) -> io::Result<()> {
         data[offset..offset + NIB_TRACK_SIZE].copy_from_slice(&nib_track[0..NIB_TRACK_SIZE]);
     }
 
-    // Write to new file
-    write_disk_content_to_disk(disk, &data)?;
-
-    Ok(())
+    data
 }
 
 fn write_woz_u16(dsk: &mut Vec<u8>, value: u16) {
@@ -1381,6 +1715,11 @@ impl DiskDrive {
             disk_sound: DiskSound::default(),
             exact_write: false,
             disable_disk_jitter: false,
+            overlay: false,
+            disk_set: vec![DiskSet::default(), DiskSet::default()],
+            latch_history: None,
+            access_log: None,
+            high_level_disk: false,
         }
     }
 
@@ -1396,6 +1735,9 @@ impl DiskDrive {
 
     pub fn motor_status(&mut self, flag: bool) {
         if flag {
+            if !self.drive[self.drive_select].motor_status {
+                self.log_event(DiskLogEvent::MotorOn);
+            }
             self.drive[self.drive_select].motor_status = true;
             if self.pending_ticks > 0 {
                 self.pending_ticks = 0;
@@ -1406,6 +1748,7 @@ impl DiskDrive {
             } else {
                 // Set the motor off immediately if the IWM timer of 1 second is off
                 self.drive[self.drive_select].motor_status = false;
+                self.log_event(DiskLogEvent::MotorOff);
             }
         }
 
@@ -1424,7 +1767,11 @@ impl DiskDrive {
 
     pub fn drive_select(&mut self, drive: usize) {
         let motor_status = self.drive[self.drive_select].motor_status;
+        let changed = self.drive_select != drive;
         self.drive_select = drive;
+        if changed {
+            self.log_event(DiskLogEvent::DriveSelect);
+        }
         self.drive[self.drive_select].motor_status = motor_status;
         self.drive[(self.drive_select + 1) % 2].motor_status = false;
     }
@@ -1444,9 +1791,14 @@ impl DiskDrive {
         self.drive.push(disk);
         let disk = &mut self.drive[self.drive_select];
         disk.track = track;
+        self.disk_set.swap(0, 1);
     }
 
     fn set_phase(&mut self, phase: usize, flag: bool) {
+        self.log_event(DiskLogEvent::Phase {
+            phase: phase as u8,
+            on: flag,
+        });
         if flag {
             self.phase |= 1 << phase;
         } else {
@@ -1481,6 +1833,7 @@ impl DiskDrive {
             //  Set the stepper sound
             if disk.track != old_track {
                 self.disk_sound.set_stepper_sample();
+                self.log_event(DiskLogEvent::Track);
             }
         }
     }
@@ -1544,6 +1897,136 @@ impl DiskDrive {
         result
     }
 
+    /// Return the quarter track and the bit position of the head of the drive
+    pub fn get_head_position(&self, drive: usize) -> (usize, usize) {
+        let disk = &self.drive[drive];
+        (disk.track as usize, disk.head * 8 + disk.head_bit)
+    }
+
+    /// Return the track type, tmap index and bit count of each quarter track of the drive
+    pub fn get_quarter_track_info(&self, drive: usize) -> Vec<(usize, u8, TrackType, usize)> {
+        let disk = &self.drive[drive];
+        let mut result = Vec::new();
+        if !disk.loaded {
+            return result;
+        }
+
+        for (qt, &tmap_track) in disk.tmap_data.iter().enumerate() {
+            if tmap_track == 255 {
+                continue;
+            }
+            let track_type = disk.trackmap[tmap_track as usize];
+            let bits = if track_type == TrackType::Flux {
+                let track = &disk.raw_track_data[tmap_track as usize];
+                let count = disk.raw_track_bits[tmap_track as usize].min(track.len());
+                disktrack::flux_to_bitstream(track, count).1
+            } else {
+                disk.raw_track_bits[tmap_track as usize]
+            };
+            result.push((qt, tmap_track, track_type, bits));
+        }
+        result
+    }
+
+    /// Decode the bitstream of the quarter track of the drive
+    pub fn inspect_track(&self, drive: usize, quarter_track: usize) -> Option<TrackAnalysis> {
+        let disk = &self.drive[drive];
+        if !disk.loaded {
+            return None;
+        }
+
+        let tmap_track = *disk.tmap_data.get(quarter_track)?;
+        if tmap_track == 255 {
+            return None;
+        }
+
+        let track = &disk.raw_track_data[tmap_track as usize];
+        let track_bits = disk.raw_track_bits[tmap_track as usize];
+        if disk.trackmap[tmap_track as usize] == TrackType::Flux {
+            let (data, bit_count) =
+                disktrack::flux_to_bitstream(track, track_bits.min(track.len()));
+            Some(disktrack::analyze_track(&data, bit_count))
+        } else {
+            Some(disktrack::analyze_track(track, track_bits))
+        }
+    }
+
+    /// Enable or disable the recording of the data latch values read from the disk
+    pub fn set_latch_history(&mut self, flag: bool) {
+        if flag {
+            if self.latch_history.is_none() {
+                self.latch_history = Some(VecDeque::with_capacity(LATCH_HISTORY_SIZE));
+            }
+        } else {
+            self.latch_history = None;
+        }
+    }
+
+    pub fn get_latch_history(&self) -> Option<&VecDeque<LatchSample>> {
+        self.latch_history.as_ref()
+    }
+
+    /// Enable or disable the disk access log. Disabling the log discards the entries
+    pub fn set_access_log(&mut self, flag: bool) {
+        if flag {
+            if self.access_log.is_none() {
+                self.access_log = Some(DiskLog::new());
+            }
+        } else {
+            self.access_log = None;
+        }
+    }
+
+    pub fn get_access_log(&self) -> Option<&DiskLog> {
+        self.access_log.as_ref()
+    }
+
+    pub fn clear_access_log(&mut self) {
+        if let Some(log) = self.access_log.as_mut() {
+            log.clear();
+        }
+    }
+
+    pub fn set_log_context(&mut self, cycle: usize, pc: u16) {
+        if let Some(log) = self.access_log.as_mut() {
+            log.set_context(cycle, pc);
+        }
+    }
+
+    fn log_event(&mut self, event: DiskLogEvent) {
+        if let Some(log) = self.access_log.as_mut() {
+            let track = self.drive[self.drive_select].track;
+            log.record(self.drive_select, track, event);
+        }
+    }
+
+    fn record_latch(&mut self) {
+        if self.latch & 0x80 == 0 || self.prev_latch & 0x80 != 0 {
+            return;
+        }
+
+        if let Some(log) = self.access_log.as_mut() {
+            let track = self.drive[self.drive_select].track;
+            log.record_read(self.drive_select, track, self.latch);
+        }
+
+        if let Some(history) = self.latch_history.as_mut() {
+            let disk = &self.drive[self.drive_select];
+            if history.len() >= LATCH_HISTORY_SIZE {
+                history.pop_front();
+            }
+            history.push_back(LatchSample {
+                quarter_track: disk.track,
+                bit_position: disk.head * 8 + disk.head_bit,
+                latch: self.latch,
+            });
+        }
+    }
+
+    pub fn get_woz_metadata(&self, drive: usize) -> Option<&WozMetadata> {
+        self.drive[drive].woz_metadata.as_ref()
+    }
+
     pub fn get_random_one_rate(&self) -> f32 {
         self.random_one_rate
     }
@@ -1644,6 +2127,13 @@ impl DiskDrive {
         let filename = filename_path.as_ref();
         let mut dsk = self.read_and_decompress_file(filename)?;
 
+        // DiskCopy 4.2 images may use the .dsk extension
+        if DiskCopy42::is_dc42(&dsk) {
+            let metadata = std::fs::metadata(filename)?;
+            let write_protect = metadata.permissions().readonly();
+            return self.load_2mg_dc42_array_to_woz(&dsk, write_protect);
+        }
+
         if !(DSK_36_40_SIZE.contains(&dsk.len())
             || (dsk.len() >= DSK_IMAGE_SIZE - 255 && dsk.len() <= DSK_IMAGE_SIZE + 255))
         {
@@ -1673,6 +2163,70 @@ impl DiskDrive {
         )
     }
 
+    fn convert_2mg_dc42_to_woz<P>(&mut self, filename_path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let filename = filename_path.as_ref();
+        let dsk = self.read_and_decompress_file(filename)?;
+        let metadata = std::fs::metadata(filename)?;
+        let write_protect = metadata.permissions().readonly();
+        self.load_2mg_dc42_array_to_woz(&dsk, write_protect)
+    }
+
+    pub fn load_2mg_dc42_array_to_woz(
+        &mut self,
+        dsk: &[u8],
+        write_protect: bool,
+    ) -> io::Result<()> {
+        let (image_header, offset, len) = ImageHeader::parse(dsk)?;
+        let disk_type = match &image_header {
+            ImageHeader::None => {
+                return Err(std::io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Invalid 2mg/dc42 file",
+                ));
+            }
+            ImageHeader::Img2mg(header) if header.format == IMG_2MG_FORMAT_DOS => DiskType::Dsk,
+            ImageHeader::Img2mg(header) if header.format == IMG_2MG_FORMAT_NIB => DiskType::Nib,
+            _ => DiskType::Po,
+        };
+
+        let data = &dsk[offset..offset + len];
+        let valid_size = if disk_type == DiskType::Nib {
+            len == NIB_IMAGE_SIZE || len == NIB40_IMAGE_SIZE
+        } else {
+            len == DSK_IMAGE_SIZE || DSK_36_40_SIZE.contains(&len)
+        };
+
+        if !valid_size {
+            return Err(std::io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only 5.25 disk image is supported",
+            ));
+        }
+
+        let write_protect = write_protect || image_header.is_write_protected();
+        if disk_type == DiskType::Nib {
+            self.load_dsk_po_nib_array_to_woz(
+                data,
+                disk_type,
+                write_protect,
+                Self::convert_nib_track_to_woz,
+            )?;
+        } else {
+            self.load_dsk_po_nib_array_to_woz(
+                data,
+                disk_type,
+                write_protect,
+                Self::convert_dsk_po_track_to_woz,
+            )?;
+        }
+
+        self.drive[self.drive_select].image_header = image_header;
+        Ok(())
+    }
+
     fn check_dos_disk_in_prodos_order(&self, image: &[u8]) -> bool {
         let mut count = 0;
         let mut dos_match = true;
@@ -1867,6 +2421,8 @@ impl DiskDrive {
         disk.optimal_timing = 32;
         disk.po_mode = po_mode;
         disk.write_protect = write_protect;
+        disk.image_header = ImageHeader::None;
+        disk.woz_metadata = None;
         disk.last_track = 0;
         disk.disk_rom13 = false;
 
@@ -1993,6 +2549,8 @@ impl DiskDrive {
             ));
         }
 
+        self.drive[self.drive_select].woz_metadata = None;
+
         let woz1 = header == WOZ_WOZ1_HEADER;
 
         // Check the CRC32 of the woz2 file
@@ -2070,6 +2628,15 @@ impl DiskDrive {
                     woz_offset += chunk_size as usize;
                 }
 
+                // META
+                WOZ_META_CHUNK => {
+                    let end = usize::min(woz_offset + chunk_size as usize, dsk.len());
+                    let disk = &mut self.drive[self.drive_select];
+                    disk.woz_metadata.get_or_insert_default().meta =
+                        parse_woz_meta(&dsk[woz_offset..end]);
+                    woz_offset += chunk_size as usize;
+                }
+
                 // FLUX
                 WOZ_FLUX_CHUNK => {
                     // Only handle FLUX Chunk if the version is greater than 2
@@ -2101,6 +2668,8 @@ impl DiskDrive {
 
         //eprintln!("Tmap = {:02X?}", disk.tmap_data);
 
+        self.drive[self.drive_select].image_header = ImageHeader::None;
+
         //let disk = &mut self.drive[self.drive_select];
         //expand_unused_disk_tracks(disk);
 
@@ -2164,6 +2733,28 @@ impl DiskDrive {
         // Clear the last disk track
         disk.last_track = 0;
 
+        // Keep the INFO metadata. Version 2 INFO includes the compatible hardware
+        // and the required RAM
+        let mut metadata = WozMetadata {
+            info_version: dsk[offset],
+            creator: String::from_utf8_lossy(&dsk[offset + 5..offset + 37])
+                .trim_end()
+                .to_string(),
+            ..Default::default()
+        };
+
+        if dsk[offset] >= 2 {
+            metadata.disk_sides = dsk[offset + 37];
+            metadata.boot_sector_format = dsk[offset + 38];
+            metadata.compatible_hardware = u16::from_le_bytes([dsk[offset + 40], dsk[offset + 41]]);
+            metadata.required_ram = u16::from_le_bytes([dsk[offset + 42], dsk[offset + 43]]);
+        }
+
+        if let Some(previous) = disk.woz_metadata.take() {
+            metadata.meta = previous.meta;
+        }
+        disk.woz_metadata = Some(metadata);
+
         Ok(())
     }
 
@@ -2344,6 +2935,12 @@ impl DiskDrive {
         disk.po_mode = false;
         disk.last_track = 0;
         disk.disk_rom13 = false;
+        disk.image_header = ImageHeader::None;
+        disk.woz_metadata = None;
+
+        if let Some(disk_set) = self.disk_set.get_mut(drive_select) {
+            *disk_set = DiskSet::default();
+        }
 
         disk.raw_track_data = vec![vec![0u8; NOMINAL_USABLE_BYTES_TRACK_SIZE]; DSK_TRACK_SIZE];
         disk.raw_track_bits = vec![0; DSK_TRACK_SIZE];
@@ -2355,11 +2952,26 @@ impl DiskDrive {
         P: AsRef<Path>,
     {
         let filename = file_path.as_ref();
+        self.load_disk_image_file(filename)?;
+
+        let disk = &mut self.drive[self.drive_select];
+        disk.dirty_tracks = vec![false; DSK_TRACK_SIZE];
+
+        if self.overlay {
+            self.apply_overlay(filename)?;
+        }
+        Ok(())
+    }
+
+    fn load_disk_image_file(&mut self, filename: &Path) -> io::Result<()> {
+        if let Some((zip_name, image_name)) = split_zip_entry(&filename.display().to_string()) {
+            return self.load_zip_entry(Path::new(zip_name), image_name);
+        }
 
         if filename.extension().is_none() {
             return Err(std::io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "Invalid dsk/po/nib/woz file",
+                "Invalid dsk/po/nib/woz/2mg/dc file",
             ));
         }
 
@@ -2378,16 +2990,390 @@ impl DiskDrive {
                     return self.convert_nib_to_woz(filename);
                 } else if check_file_extension(filename, filename_ext, stem_path, "woz") {
                     return self.load_woz_file(filename);
+                } else if check_file_extension(filename, filename_ext, stem_path, "2mg")
+                    || check_file_extension(filename, filename_ext, stem_path, "2img")
+                    || check_file_extension(filename, filename_ext, stem_path, "dc")
+                    || check_file_extension(filename, filename_ext, stem_path, "dc42")
+                {
+                    return self.convert_2mg_dc42_to_woz(filename);
                 }
             }
         }
 
         Err(std::io::Error::new(
             io::ErrorKind::InvalidInput,
-            "Invalid dsk/po/nib/woz file",
+            "Invalid dsk/po/nib/woz/2mg/dc file",
+        ))
+    }
+
+    #[cfg(feature = "zip")]
+    fn load_zip_entry(&mut self, zip_path: &Path, image_name: &str) -> io::Result<()> {
+        let file = File::open(zip_path)?;
+        let mut archive = ZipArchive::new(file)?;
+        let mut data = Vec::new();
+        archive.by_name(image_name)?.read_to_end(&mut data)?;
+
+        #[cfg(feature = "flate")]
+        if image_name.to_lowercase().ends_with(".gz") {
+            data = decompress_array_gz(&data)?;
+        }
+
+        // The image in the zip file is not written back
+        let write_protect = true;
+        let name = image_name
+            .to_lowercase()
+            .trim_end_matches(".gz")
+            .to_string();
+
+        if name.ends_with(".woz") {
+            self.load_woz_array(&data, write_protect)
+        } else if name.ends_with(".nib") {
+            self.load_nib_array_to_woz(&data, write_protect)
+        } else if name.ends_with(".2mg")
+            || name.ends_with(".2img")
+            || name.ends_with(".dc")
+            || name.ends_with(".dc42")
+            || DiskCopy42::is_dc42(&data)
+        {
+            self.load_2mg_dc42_array_to_woz(&data, write_protect)
+        } else {
+            self.load_dsk_po_array_to_woz(&data, name.ends_with(".po"), write_protect)
+        }
+    }
+
+    #[cfg(not(feature = "zip"))]
+    fn load_zip_entry(&mut self, _zip_path: &Path, _image_name: &str) -> io::Result<()> {
+        Err(std::io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Zip support is not enabled",
+        ))
+    }
+
+    /// Check whether the file is a disk set (m3u playlist or zip with several images)
+    pub fn is_disk_set<P>(file_path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        let path = file_path.as_ref();
+        match path.extension() {
+            Some(ext) if check_extension(ext, "m3u") => true,
+            #[cfg(feature = "zip")]
+            Some(ext) if check_extension(ext, "zip") => Self::read_zip_disk_set(path)
+                .map(|images| images.len() > 1)
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    #[cfg(feature = "zip")]
+    fn read_zip_disk_set(path: &Path) -> io::Result<Vec<String>> {
+        let file = File::open(path)?;
+        let mut archive = ZipArchive::new(file)?;
+        let mut images = Vec::new();
+        for i in 0..archive.len() {
+            let mut item = archive.by_index(i)?;
+            if !item.is_file() || !is_disk_set_image(item.name()) {
+                continue;
+            }
+
+            let name = item.name().to_string();
+            let lowercase_name = name.to_lowercase();
+            let mut side = None;
+            if lowercase_name.ends_with(".woz") || lowercase_name.ends_with(".woz.gz") {
+                let mut data = Vec::new();
+                item.read_to_end(&mut data)?;
+                #[cfg(feature = "flate")]
+                if lowercase_name.ends_with(".gz") {
+                    data = decompress_array_gz(&data)?;
+                }
+                side = woz_meta_side(&data);
+            }
+            let key = disk_set_sort_key(&name, side.as_deref());
+            images.push((key, name));
+        }
+        images.sort();
+
+        let zip_name = path.display().to_string();
+        Ok(images
+            .into_iter()
+            .map(|(_, image)| format!("{zip_name}{DISK_SET_ZIP_SEPARATOR}{image}"))
+            .collect())
+    }
+
+    fn read_m3u_disk_set(path: &Path) -> io::Result<Vec<String>> {
+        let content = std::fs::read_to_string(path)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        Ok(content
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let image = Path::new(line);
+                if image.is_absolute() {
+                    image.display().to_string()
+                } else {
+                    base.join(image).display().to_string()
+                }
+            })
+            .collect())
+    }
+
+    /// Load the m3u playlist or zip file as disk set in the selected drive and mount
+    /// the first image of the set
+    pub fn load_disk_set<P>(&mut self, file_path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = self.absolute_path(file_path)?;
+        let images = match path.extension() {
+            Some(ext) if check_extension(ext, "m3u") => Self::read_m3u_disk_set(&path)?,
+            #[cfg(feature = "zip")]
+            Some(ext) if check_extension(ext, "zip") => Self::read_zip_disk_set(&path)?,
+            _ => Vec::new(),
+        };
+
+        if images.is_empty() {
+            return Err(std::io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No disk image found in disk set",
+            ));
+        }
+
+        let drive = self.drive_select;
+        self.mount_disk_set_image(drive, &images[0])?;
+        self.disk_set[drive] = DiskSet { images, index: 0 };
+        Ok(())
+    }
+
+    fn mount_disk_set_image(&mut self, drive: usize, image: &str) -> io::Result<()> {
+        let drive_selected = self.drive_select;
+        self.drive_select = drive;
+        let result = self.load_disk_image(image);
+        if result.is_ok() {
+            self.set_disk_filename(image);
+            self.set_loaded(true);
+        }
+        self.drive_select = drive_selected;
+        result
+    }
+
+    pub fn get_disk_set(&self, drive: usize) -> Option<&DiskSet> {
+        self.disk_set
+            .get(drive)
+            .filter(|set| !set.images.is_empty())
+    }
+
+    pub fn is_in_disk_set(&self, drive: usize, filename: &str) -> bool {
+        self.get_disk_set(drive)
+            .is_some_and(|set| set.images.iter().any(|image| image == filename))
+    }
+
+    pub fn clear_disk_set(&mut self, drive: usize) {
+        if let Some(disk_set) = self.disk_set.get_mut(drive) {
+            *disk_set = DiskSet::default();
+        }
+    }
+
+    /// Label of the mounted image in the disk set. The WOZ META side is used if
+    /// available, otherwise the image filename is used
+    pub fn get_disk_set_label(&self, drive: usize) -> Option<String> {
+        let disk_set = self.get_disk_set(drive)?;
+        let label = if let Some(side) = self.get_woz_metadata(drive).and_then(|m| m.side()) {
+            side.to_string()
+        } else {
+            let image = &disk_set.images[disk_set.index];
+            let name = image
+                .rsplit_once(DISK_SET_ZIP_SEPARATOR)
+                .map_or(image.as_str(), |(_, name)| name);
+            Path::new(name)
+                .file_name()
+                .map_or(name.to_string(), |n| n.to_string_lossy().to_string())
+        };
+        Some(format!(
+            "{} ({}/{})",
+            label,
+            disk_set.index + 1,
+            disk_set.images.len()
         ))
     }
 
+    fn change_disk_in_set(&mut self, drive: usize, forward: bool) -> io::Result<()> {
+        let Some(disk_set) = self.get_disk_set(drive) else {
+            return Ok(());
+        };
+
+        let count = disk_set.images.len();
+        let index = if forward {
+            (disk_set.index + 1) % count
+        } else {
+            (disk_set.index + count - 1) % count
+        };
+        let image = disk_set.images[index].clone();
+
+        // Save the current disk before swapping it out
+        let disk = &mut self.drive[drive];
+        if disk.modified {
+            if self.overlay {
+                save_disk_overlay(disk)?;
+            } else if self.enable_save {
+                save_dsk_woz_to_disk(disk)?;
+            }
+            disk.modified = false;
+        }
+
+        self.mount_disk_set_image(drive, &image)?;
+        self.disk_set[drive].index = index;
+        Ok(())
+    }
+
+    pub fn next_disk(&mut self, drive: usize) -> io::Result<()> {
+        self.change_disk_in_set(drive, true)
+    }
+
+    pub fn prev_disk(&mut self, drive: usize) -> io::Result<()> {
+        self.change_disk_in_set(drive, false)
+    }
+
+    /// When overlay mode is enabled, writes are stored in a sidecar delta file and the
+    /// original image is not modified until the overlay is committed
+    pub fn set_overlay_mode(&mut self, state: bool) {
+        self.overlay = state;
+    }
+
+    pub fn is_overlay_mode(&self) -> bool {
+        self.overlay
+    }
+
+    pub fn has_overlay(&self, drive: usize) -> bool {
+        self.drive[drive]
+            .filename
+            .as_ref()
+            .is_some_and(overlay::has_delta)
+    }
+
+    fn apply_overlay(&mut self, filename: &Path) -> io::Result<()> {
+        let entries = overlay::read_delta(filename)?;
+        let disk = &mut self.drive[self.drive_select];
+
+        for entry in entries {
+            let track = entry.index as usize;
+            if entry.index == DELTA_TMAP_INDEX {
+                if entry.data.len() == WOZ_TMAP_SIZE {
+                    disk.tmap_data.copy_from_slice(&entry.data);
+                }
+            } else if track < DSK_TRACK_SIZE && entry.bits as usize <= entry.data.len() * 8 {
+                disk.raw_track_data[track] = entry.data;
+                disk.raw_track_bits[track] = entry.bits as usize;
+                if disk.trackmap[track] == TrackType::None {
+                    disk.trackmap[track] = TrackType::Tmap;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the current content of the drive back to the original image and remove
+    /// the overlay file
+    pub fn commit_overlay(&mut self, drive: usize) -> io::Result<()> {
+        let disk = &mut self.drive[drive];
+        if disk.write_protect {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Drive is write protected",
+            ));
+        }
+
+        if let Some(filename) = &disk.filename {
+            save_dsk_woz_to_disk(disk)?;
+            overlay::remove_delta(filename)?;
+            disk.modified = false;
+            disk.dirty_tracks.fill(false);
+        }
+        Ok(())
+    }
+
+    /// Discard the overlay file and reload the original image
+    pub fn revert_overlay(&mut self, drive: usize) -> io::Result<()> {
+        let Some(filename) = self.drive[drive].filename.clone() else {
+            return Ok(());
+        };
+
+        overlay::remove_delta(&filename)?;
+        self.drive[drive].modified = false;
+
+        let drive_selected = self.drive_select;
+        self.drive_select = drive;
+        let result = self.load_disk_image(&filename);
+        self.drive_select = drive_selected;
+        result
+    }
+
+    /// Export the current content of the drive (including the overlay) as a new image.
+    /// The image format is determined by the extension. Exporting to WOZ requires the
+    /// mounted image to be a WOZ image
+    pub fn export_disk_image<P>(&mut self, drive: usize, file_path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = file_path.as_ref();
+        let disk = &mut self.drive[drive];
+
+        let (Some(original), Some(file_stem), Some(path_ext)) =
+            (disk.filename.clone(), path.file_stem(), path.extension())
+        else {
+            return Err(std::io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unable to export disk image",
+            ));
+        };
+
+        let stem_path = Path::new(file_stem);
+        let image_header = if check_file_extension(path, path_ext, stem_path, "2mg")
+            || check_file_extension(path, path_ext, stem_path, "2img")
+        {
+            if let ImageHeader::Img2mg(header) = &disk.image_header {
+                ImageHeader::Img2mg(header.clone())
+            } else {
+                let format = if disk.po_mode {
+                    IMG_2MG_FORMAT_PRODOS
+                } else {
+                    IMG_2MG_FORMAT_DOS
+                };
+                ImageHeader::Img2mg(Image2mg::new(format))
+            }
+        } else if check_file_extension(path, path_ext, stem_path, "dc")
+            || check_file_extension(path, path_ext, stem_path, "dc42")
+        {
+            if let ImageHeader::Dc42(header) = &disk.image_header {
+                ImageHeader::Dc42(header.clone())
+            } else {
+                let name = stem_path.display().to_string();
+                ImageHeader::Dc42(DiskCopy42::new(&name, disk.track_size * 16 * 256)?)
+            }
+        } else {
+            ImageHeader::None
+        };
+
+        // The 5.25 DiskCopy 4.2 image is read back in ProDOS order
+        let po_mode = disk.po_mode || matches!(image_header, ImageHeader::Dc42(_));
+
+        // The WOZ and zip writer uses the structure of the existing file
+        std::fs::copy(&original, path)?;
+
+        let filename = disk.filename.replace(path.display().to_string());
+        let header = std::mem::replace(&mut disk.image_header, image_header);
+        let po_mode = std::mem::replace(&mut disk.po_mode, po_mode);
+        let result = save_dsk_woz_to_disk(disk);
+        disk.filename = filename;
+        disk.image_header = header;
+        disk.po_mode = po_mode;
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(path);
+        }
+        result
+    }
+
     /// Read the flux data. The value in the flux data, is the the number of ticks since
     /// the previous flux transition in 125us.
     /// The read pulse is valid for 0.5 microsecond (4 cycles, 1 LSS sequencer clock)
@@ -2575,6 +3561,105 @@ impl DiskDrive {
         self.disable_fast_disk
     }
 
+    pub fn set_high_level_disk(&mut self, state: bool) {
+        self.high_level_disk = state;
+    }
+
+    pub fn is_high_level_disk(&self) -> bool {
+        self.high_level_disk
+    }
+
+    pub fn is_drive_write_protected(&self, drive: usize) -> bool {
+        self.drive[drive].write_protect
+    }
+
+    fn sector_track(&self, drive: usize, track: u8) -> Option<usize> {
+        let disk = &self.drive[drive];
+        if !disk.loaded || disk.disk_rom13 {
+            return None;
+        }
+
+        let tmap_track = *disk.tmap_data.get(track as usize * 4)?;
+        if tmap_track == 255 || disk.trackmap[tmap_track as usize] == TrackType::Flux {
+            return None;
+        }
+        Some(tmap_track as usize)
+    }
+
+    /// Read the physical sector of the 16 sector track. Returns the volume number and
+    /// the sector data, or None if the sector can not be decoded
+    pub fn read_physical_sector(
+        &mut self,
+        drive: usize,
+        track: u8,
+        sector: u8,
+    ) -> Option<(u8, [u8; 256])> {
+        let tmap_track = self.sector_track(drive, track)?;
+        let disk = &self.drive[drive];
+        let track_data = &disk.raw_track_data[tmap_track];
+        let track_bits = disk.raw_track_bits[tmap_track];
+        let result = disktrack::read_sector(track_data, track_bits, track, sector)?;
+
+        self.seek_track(drive, track);
+        self.log_event(DiskLogEvent::SectorRead { track, sector });
+        Some((result.volume, result.data))
+    }
+
+    /// Write the physical sector of the 16 sector track when its volume number matches
+    /// the expected volume (0 matches any volume). Returns the volume number found, or
+    /// None if the sector can not be decoded
+    pub fn write_physical_sector(
+        &mut self,
+        drive: usize,
+        track: u8,
+        sector: u8,
+        expected_volume: u8,
+        data: &[u8; 256],
+    ) -> Option<u8> {
+        let tmap_track = self.sector_track(drive, track)?;
+        let disk = &mut self.drive[drive];
+        let track_bits = disk.raw_track_bits[tmap_track];
+        let track_data = &mut disk.raw_track_data[tmap_track];
+        let result = disktrack::read_sector(track_data, track_bits, track, sector)?;
+        if expected_volume != 0 && expected_volume != result.volume {
+            return Some(result.volume);
+        }
+
+        disktrack::write_sector(track_data, track_bits, result.data_offset, data);
+        disk.modified = true;
+        mark_track_dirty(disk, tmap_track);
+
+        self.seek_track(drive, track);
+        self.log_event(DiskLogEvent::SectorWrite { track, sector });
+        Some(result.volume)
+    }
+
+    /// Move the head to the track and leave the motor running for the motor off delay,
+    /// as after a normal disk access. The modified disk is saved when the motor stops
+    fn seek_track(&mut self, drive: usize, track: u8) {
+        if self.drive_select != drive {
+            self.drive[self.drive_select].motor_status = false;
+            self.drive_select(drive);
+        }
+
+        let disk = &mut self.drive[drive];
+        disk.last_track = disk.track;
+        disk.track = track * 4;
+        self.phase = 0;
+
+        let tmap_track = disk.tmap_data[disk.track as usize];
+        let track_bits = if tmap_track == 255 {
+            NOMINAL_USABLE_BITS_TRACK_SIZE
+        } else {
+            disk.raw_track_bits[tmap_track as usize]
+        };
+        Self::update_position_if_track_changed(disk, track_bits);
+        disk.last_track = disk.track;
+
+        self.motor_status(true);
+        self.motor_status(false);
+    }
+
     pub fn is_normal_disk(&self) -> bool {
         if self.disable_fast_disk || (!self.is_motor_on() || self.is_motor_off_pending()) {
             true
@@ -2645,6 +3730,7 @@ impl DiskDrive {
 
                 track[disk.head] = value;
                 disk.modified = true;
+                mark_track_dirty(disk, tmap_track as usize);
             }
 
             /*
@@ -2726,13 +3812,19 @@ impl Tick for DiskDrive {
             if self.pending_ticks == 0 {
                 self.fast_disk_timer = 0;
                 self.reset_disk_sound_sample();
+                self.log_event(DiskLogEvent::MotorOff);
                 for drive in 0..self.drive.len() {
                     let disk = &mut self.drive[drive];
                     disk.motor_status = false;
 
                     // Check for modified flag, if it is modified needs to save back the file
                     if disk.modified {
-                        if self.enable_save {
+                        if self.overlay {
+                            let save_status = save_disk_overlay(disk);
+                            if save_status.is_err() {
+                                eprintln!("Unable to save disk overlay = {save_status:?}");
+                            }
+                        } else if self.enable_save {
                             let save_status = save_dsk_woz_to_disk(disk);
                             if save_status.is_err() {
                                 eprintln!("Unable to save disk = {save_status:?}");
@@ -2752,6 +3844,7 @@ impl Tick for DiskDrive {
         self.move_head_woz();
         self.step_lss();
         self.pulse = 0;
+        self.record_latch();
     }
 }
 
@@ -2781,6 +3874,9 @@ impl Disk {
             mc3470_read_pulse: 0,
             flux_weakbit: 0,
             revolution: 0,
+            image_header: ImageHeader::None,
+            dirty_tracks: vec![false; DSK_TRACK_SIZE],
+            woz_metadata: None,
         }
     }
 }
@@ -2905,6 +4001,12 @@ impl Card for DiskDrive {
             self.iwm_mode = value;
         } else if mode == 3 {
             self.bus = value;
+            if self.is_motor_on()
+                && let Some(log) = self.access_log.as_mut()
+            {
+                let track = self.drive[self.drive_select].track;
+                log.record_write(self.drive_select, track, value);
+            }
         }
         return_value
     }
@@ -2929,3 +4031,143 @@ fn default_tmap_data() -> Vec<u8> {
 fn default_trackmap() -> Vec<TrackType> {
     vec![TrackType::None; WOZ_TMAP_SIZE]
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn woz_meta_requirements() {
+        let meta = b"title\tTest Disk\nside\tDisk 1, Side A\nrequires_ram\t128K\nrequires_machine\t2e|2e+|2c\nnotes\tMouse recommended\n";
+        let metadata = WozMetadata {
+            compatible_hardware: WozHardware::APPLE2GS.bits(),
+            required_ram: 64,
+            meta: parse_woz_meta(meta),
+            ..Default::default()
+        };
+
+        assert_eq!(metadata.title(), Some("Test Disk"));
+        assert_eq!(metadata.side(), Some("Disk 1, Side A"));
+        assert_eq!(metadata.required_ram_kb(), 128);
+        assert!(metadata.is_compatible(WozHardware::APPLE2E_ENHANCED));
+        assert!(metadata.is_compatible(WozHardware::APPLE2GS));
+        assert!(!metadata.is_compatible(WozHardware::APPLE2_PLUS));
+    }
+
+    #[test]
+    fn disk_set_naming() {
+        assert_eq!(disk_set_sort_key("Game (Disk 2 Side B).woz", None), (2, 2));
+        assert_eq!(disk_set_sort_key("dir/game_side_a.dsk", None), (0, 1));
+        assert_eq!(disk_set_sort_key("Game - Disk1.po", None), (1, 0));
+        assert_eq!(disk_set_sort_key("game_s2.dsk", None), (0, 2));
+        assert_eq!(disk_set_sort_key("Game.dsk", None), (0, 0));
+        assert_eq!(disk_set_sort_key("Dungeon Side 2.dsk", None), (0, 2));
+        assert_eq!(disk_set_sort_key("Kids 2 - Towns 3.dsk", None), (0, 0));
+        assert_eq!(disk_set_sort_key("Game d 2.dsk", None), (0, 0));
+
+        // The side from the WOZ META chunk comes first
+        assert_eq!(
+            disk_set_sort_key("Game Disk 1.woz", Some("Disk 2, Side B")),
+            (2, 2)
+        );
+        assert_eq!(disk_set_sort_key("Game Disk 3.woz", Some("Side A")), (3, 1));
+
+        let mut names = vec!["Game Disk 2.dsk", "Game Disk 10.dsk", "Game Disk 1.dsk"];
+        names.sort_by_key(|name| disk_set_sort_key(name, None));
+        assert_eq!(
+            names,
+            vec!["Game Disk 1.dsk", "Game Disk 2.dsk", "Game Disk 10.dsk"]
+        );
+        assert_eq!(
+            split_zip_entry("/tmp/game.zip#disk1.dsk"),
+            Some(("/tmp/game.zip", "disk1.dsk"))
+        );
+        assert_eq!(split_zip_entry("/tmp/game#1.dsk"), None);
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn disk_set_woz_meta_side() {
+        let meta = b"title\tGame\nside\tDisk 2, Side A\n";
+        let mut woz = b"WOZ2\xff\x0a\x0d\x0a\0\0\0\0".to_vec();
+        woz.extend_from_slice(&[b'I', b'N', b'F', b'O', 2, 0, 0, 0, 0, 0]);
+        woz.extend_from_slice(&WOZ_META_CHUNK.to_le_bytes());
+        woz.extend_from_slice(&(meta.len() as u32).to_le_bytes());
+        woz.extend_from_slice(meta);
+
+        assert_eq!(woz_meta_side(&woz).as_deref(), Some("Disk 2, Side A"));
+        assert_eq!(woz_meta_side(&woz[..woz.len() - meta.len()]), None);
+        assert_eq!(woz_meta_side(b"not a woz"), None);
+    }
+
+    #[test]
+    fn export_dsk_to_dc42() {
+        let dir = std::env::temp_dir();
+        let image = dir.join(format!("emu6502_disk_{}.dsk", std::process::id()));
+        let export = dir.join(format!("emu6502_disk_{}.dc42", std::process::id()));
+        let data: Vec<u8> = (0..DSK_IMAGE_SIZE)
+            .map(|i| (i / 256) as u8 ^ (i as u8))
+            .collect();
+        std::fs::write(&image, &data).unwrap();
+
+        let mut disk = DiskDrive::default();
+        disk.set_disk_filename(&image);
+        disk.load_disk_image(&image).unwrap();
+        disk.set_loaded(true);
+        disk.export_disk_image(0, &export).unwrap();
+        let (header, _, len) = DiskCopy42::parse(&std::fs::read(&export).unwrap()).unwrap();
+        assert_eq!(header.encoding, 0x00);
+        assert_eq!(len, DSK_IMAGE_SIZE);
+
+        let mut exported = DiskDrive::default();
+        exported.load_disk_image(&export).unwrap();
+        exported.set_loaded(true);
+        for track in 0..35 {
+            for sector in 0..16 {
+                let expected = disk.read_physical_sector(0, track, sector);
+                assert!(expected.is_some());
+                assert_eq!(exported.read_physical_sector(0, track, sector), expected);
+            }
+        }
+
+        std::fs::remove_file(&image).unwrap();
+        std::fs::remove_file(&export).unwrap();
+    }
+
+    #[test]
+    fn commit_overlay_write_protected() {
+        let image =
+            std::env::temp_dir().join(format!("emu6502_overlay_{}.dsk", std::process::id()));
+        std::fs::write(&image, vec![0x5a; DSK_IMAGE_SIZE]).unwrap();
+
+        let mut disk = DiskDrive::default();
+        disk.set_overlay_mode(true);
+        disk.set_disk_filename(&image);
+        disk.load_disk_image(&image).unwrap();
+        disk.set_loaded(true);
+        assert!(
+            disk.write_physical_sector(0, 0, 0, 0, &[0x11; 256])
+                .is_some()
+        );
+
+        disk.drive[0].write_protect = true;
+        assert!(disk.commit_overlay(0).is_err());
+        assert_eq!(std::fs::read(&image).unwrap(), vec![0x5a; DSK_IMAGE_SIZE]);
+
+        disk.drive[0].write_protect = false;
+        disk.commit_overlay(0).unwrap();
+        assert_eq!(std::fs::read(&image).unwrap()[..256], [0x11; 256]);
+
+        std::fs::remove_file(&image).unwrap();
+    }
+
+    #[test]
+    fn woz_meta_unknown_hardware() {
+        let metadata = WozMetadata {
+            meta: parse_woz_meta(b"requires_ram\t1.25M\n"),
+            ..Default::default()
+        };
+        assert!(metadata.is_compatible(WozHardware::APPLE2));
+        assert_eq!(metadata.required_ram_kb(), 1280);
+    }
+}
@@ -0,0 +1,364 @@
+use crate::bus::{IODevice, Mem};
+use crate::cpu::{CPU, CpuFlags};
+use crate::disk::{DSK_DO, DSK_PO, DiskDrive};
+
+/*
+High-level Disk II acceleration
+
+The calls to the DOS 3.3 RWTS and the ProDOS Disk II driver are trapped at their entry
+point and serviced directly from the disk image instead of running the bitstream through
+the LSS. The call returns to the caller with the same register and memory effects.
+
+    DOS 3.3 RWTS      $BD00, A/Y points to the IOB
+                          IOB+0  Table type (1)
+                          IOB+1  Slot * 16        IOB+8  Buffer pointer
+                          IOB+2  Drive            IOB+C  Command (1 = read, 2 = write)
+                          IOB+3  Volume expected  IOB+D  Return code
+                          IOB+4  Track            IOB+E  Volume found
+                          IOB+5  Sector           IOB+F  Slot * 16 last accessed
+                                                  IOB+10 Drive last accessed
+
+    ProDOS driver     $D000 in the language card bank 1 when the device vector in
+                      $BF10-$BF2F points to it
+                          $42    Command (0 = status, 1 = read, 2 = write)
+                          $43    Unit number (DSSS0000)
+                          $44    Buffer pointer
+                          $46    Block number
+
+The call falls back to the LSS emulation when the sector can not be decoded (for e.g.
+non-standard formats, 13 sector disks and flux tracks) and for the seek and format
+commands.
+*/
+
+pub const RWTS_ENTRY: u16 = 0xbd00;
+pub const PRODOS_DRIVER_ENTRY: u16 = 0xd000;
+
+// STY $48, STA $49
+pub const RWTS_SIGNATURE: [u8; 4] = [0x84, 0x48, 0x85, 0x49];
+
+const RWTS_WRITE_PROTECTED: u8 = 0x10;
+const RWTS_VOLUME_MISMATCH: u8 = 0x20;
+const RWTS_DRIVE1_TRACK: u16 = 0x478;
+const RWTS_DRIVE2_TRACK: u16 = 0x4f8;
+
+const PRODOS_MLI: u16 = 0xbf00;
+const PRODOS_DEVADR: u16 = 0xbf10;
+const PRODOS_WRITE_PROTECTED: u8 = 0x2b;
+const PRODOS_BLOCKS: u16 = 280;
+
+const TRACKS: u8 = 35;
+
+fn read_u16(cpu: &mut CPU, addr: u16) -> u16 {
+    let lo = cpu.bus.unclocked_addr_read(addr);
+    let hi = cpu.bus.unclocked_addr_read(addr.wrapping_add(1));
+    u16::from_le_bytes([lo, hi])
+}
+
+fn disk_controller(cpu: &mut CPU, slot: usize) -> Option<&mut DiskDrive> {
+    if slot == 0 || slot >= cpu.bus.io_slot.len() || cpu.bus.io_slot[slot] != IODevice::Disk {
+        return None;
+    }
+
+    let disk = if cpu.bus.disk_controller_index(slot) == 0 {
+        &mut cpu.bus.disk
+    } else {
+        &mut cpu.bus.disk2
+    };
+    disk.is_high_level_disk().then_some(disk)
+}
+
+fn return_from_call(cpu: &mut CPU, error: u8) {
+    let sp = cpu.stack_pointer;
+    let lo = cpu
+        .bus
+        .unclocked_addr_read(0x100 + sp.wrapping_add(1) as u16);
+    let hi = cpu
+        .bus
+        .unclocked_addr_read(0x100 + sp.wrapping_add(2) as u16);
+    cpu.stack_pointer = sp.wrapping_add(2);
+    cpu.program_counter = u16::from_le_bytes([lo, hi]).wrapping_add(1);
+    cpu.register_a = error;
+    cpu.status.set(CpuFlags::CARRY, error != 0);
+    cpu.status.set(CpuFlags::ZERO, error == 0);
+    cpu.status.set(CpuFlags::NEGATIVE, error & 0x80 != 0);
+}
+
+fn read_buffer(cpu: &mut CPU, addr: u16, data: &mut [u8]) {
+    for (i, item) in data.iter_mut().enumerate() {
+        *item = cpu.bus.unclocked_addr_read(addr.wrapping_add(i as u16));
+    }
+}
+
+fn write_buffer(cpu: &mut CPU, addr: u16, data: &[u8]) {
+    for (i, &item) in data.iter().enumerate() {
+        cpu.bus
+            .unclocked_addr_write(addr.wrapping_add(i as u16), item);
+    }
+}
+
+fn trap_rwts(cpu: &mut CPU) -> bool {
+    for (i, &value) in RWTS_SIGNATURE.iter().enumerate() {
+        if cpu.bus.unclocked_addr_read(RWTS_ENTRY + i as u16) != value {
+            return false;
+        }
+    }
+
+    let iob = u16::from_le_bytes([cpu.register_y, cpu.register_a]);
+    let mut param = [0u8; 0x0d];
+    read_buffer(cpu, iob, &mut param);
+
+    let slot16 = param[1];
+    let slot = (slot16 >> 4) as usize & 7;
+    let expected_volume = param[3];
+    let track = param[4];
+    let sector = param[5];
+    let buffer = u16::from_le_bytes([param[8], param[9]]);
+    let command = param[0x0c];
+    if param[0] != 1
+        || !(1..=2).contains(&command)
+        || !(1..=2).contains(&param[2])
+        || track >= TRACKS
+        || sector >= 16
+    {
+        return false;
+    }
+    let drive = (param[2] - 1) as usize;
+    let physical_sector = DSK_DO[sector as usize];
+
+    let mut data = [0u8; 256];
+    if command == 2 {
+        read_buffer(cpu, buffer, &mut data);
+    }
+    let Some(disk) = disk_controller(cpu, slot) else {
+        return false;
+    };
+    let volume_mismatch = |volume| expected_volume != 0 && expected_volume != volume;
+    let (volume, error) = if command == 1 || disk.is_drive_write_protected(drive) {
+        let Some((volume, sector_data)) = disk.read_physical_sector(drive, track, physical_sector)
+        else {
+            return false;
+        };
+        if volume_mismatch(volume) {
+            (volume, RWTS_VOLUME_MISMATCH)
+        } else if command == 2 {
+            (volume, RWTS_WRITE_PROTECTED)
+        } else {
+            write_buffer(cpu, buffer, &sector_data);
+            (volume, 0)
+        }
+    } else {
+        let Some(volume) =
+            disk.write_physical_sector(drive, track, physical_sector, expected_volume, &data)
+        else {
+            return false;
+        };
+        if volume_mismatch(volume) {
+            (volume, RWTS_VOLUME_MISMATCH)
+        } else {
+            (volume, 0)
+        }
+    };
+
+    let track_table = if drive == 0 {
+        RWTS_DRIVE1_TRACK
+    } else {
+        RWTS_DRIVE2_TRACK
+    };
+    cpu.bus
+        .unclocked_addr_write(track_table + slot as u16, track * 2);
+    cpu.bus.unclocked_addr_write(iob + 0x0d, error);
+    cpu.bus.unclocked_addr_write(iob + 0x0e, volume);
+    cpu.bus.unclocked_addr_write(iob + 0x0f, slot16);
+    cpu.bus.unclocked_addr_write(iob + 0x10, drive as u8 + 1);
+    return_from_call(cpu, error);
+    true
+}
+
+fn trap_prodos(cpu: &mut CPU) -> bool {
+    // $D000 is the Applesoft ROM unless the language card bank 1 is read enabled
+    if !cpu.bus.mem.readbsr || !cpu.bus.mem.bank1 {
+        return false;
+    }
+
+    if cpu.bus.unclocked_addr_read(PRODOS_MLI) != 0x4c {
+        return false;
+    }
+
+    let unit = cpu.bus.unclocked_addr_read(0x43);
+    let slot = (unit >> 4) as usize & 7;
+    let drive = (unit >> 7) as usize;
+    let devadr = PRODOS_DEVADR + (slot as u16) * 2 + (drive as u16) * 16;
+    if read_u16(cpu, devadr) != PRODOS_DRIVER_ENTRY {
+        return false;
+    }
+
+    let command = cpu.bus.unclocked_addr_read(0x42);
+    let buffer = read_u16(cpu, 0x44);
+    let block = read_u16(cpu, 0x46);
+    if block >= PRODOS_BLOCKS {
+        return false;
+    }
+    let track = (block / 8) as u8;
+    let sectors = [
+        DSK_PO[(block % 8) as usize * 2],
+        DSK_PO[(block % 8) as usize * 2 + 1],
+    ];
+
+    let Some(disk) = disk_controller(cpu, slot) else {
+        return false;
+    };
+    if !disk.is_loaded(drive) {
+        return false;
+    }
+
+    let error = match command {
+        0 => {
+            let write_protected = disk.is_drive_write_protected(drive);
+            cpu.register_x = (PRODOS_BLOCKS & 0xff) as u8;
+            cpu.register_y = (PRODOS_BLOCKS >> 8) as u8;
+            if write_protected {
+                PRODOS_WRITE_PROTECTED
+            } else {
+                0
+            }
+        }
+        1 => {
+            let mut data = [0u8; 512];
+            for (i, &sector) in sectors.iter().enumerate() {
+                let Some((_, sector_data)) = disk.read_physical_sector(drive, track, sector) else {
+                    return false;
+                };
+                data[i * 256..(i + 1) * 256].copy_from_slice(&sector_data);
+            }
+            write_buffer(cpu, buffer, &data);
+            0
+        }
+        2 if disk.is_drive_write_protected(drive) => PRODOS_WRITE_PROTECTED,
+        2 => {
+            let mut data = [0u8; 512];
+            read_buffer(cpu, buffer, &mut data);
+            let Some(disk) = disk_controller(cpu, slot) else {
+                return false;
+            };
+            // The whole block is written again by the driver when a sector is not found
+            for (i, &sector) in sectors.iter().enumerate() {
+                let mut sector_data = [0u8; 256];
+                sector_data.copy_from_slice(&data[i * 256..(i + 1) * 256]);
+                if disk
+                    .write_physical_sector(drive, track, sector, 0, &sector_data)
+                    .is_none()
+                {
+                    return false;
+                }
+            }
+            0
+        }
+        _ => return false,
+    };
+
+    return_from_call(cpu, error);
+    true
+}
+
+/// Service the RWTS or ProDOS driver call at the program counter. Returns false if the
+/// call is not handled and the code should continue to run
+pub fn trap_disk_call(cpu: &mut CPU) -> bool {
+    match cpu.program_counter {
+        RWTS_ENTRY => trap_rwts(cpu),
+        PRODOS_DRIVER_ENTRY => trap_prodos(cpu),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    fn setup_cpu() -> CPU {
+        let mut bus = Bus::default();
+        bus.io_slot[6] = IODevice::Disk;
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+
+        let mut dsk = vec![0u8; 35 * 16 * 256];
+        for (i, item) in dsk.iter_mut().enumerate() {
+            *item = (i / 256) as u8;
+        }
+        cpu.bus
+            .disk
+            .load_dsk_po_array_to_woz(&dsk, false, false)
+            .unwrap();
+        cpu.bus.disk.set_loaded(true);
+        cpu.bus.disk.set_high_level_disk(true);
+        cpu
+    }
+
+    #[test]
+    fn rwts_read_and_write() {
+        let mut cpu = setup_cpu();
+        cpu.load(&RWTS_SIGNATURE, RWTS_ENTRY);
+
+        // Read track 17 sector 3 to $2000
+        let iob = [0x01, 0x60, 0x01, 0x00, 17, 3, 0, 0, 0x00, 0x20, 0, 0, 0x01];
+        cpu.load(&iob, 0x300);
+        let code = [
+            0xa9, 0x03, // LDA #$03
+            0xa0, 0x00, // LDY #$00
+            0x20, 0x00, 0xbd, // JSR $BD00
+            0x00, // END
+        ];
+        cpu.load_and_run_offset(&code, 0x1000, 0x1000);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+        assert_eq!(cpu.bus.mem_read(0x2000), (17 * 16 + 3) as u8);
+        assert_eq!(cpu.bus.mem_read(0x30d), 0);
+        assert_eq!(cpu.bus.mem_read(0x30e), 254);
+
+        // Write sector 4 and volume mismatch
+        cpu.load(&[0x5a; 256], 0x2000);
+        cpu.load(&[17, 4, 0, 0, 0x00, 0x20, 0, 0, 0x02], 0x304);
+        cpu.load_and_run_offset(&code, 0x1000, 0x1000);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+        let (_, data) = cpu.bus.disk.read_physical_sector(0, 17, DSK_DO[4]).unwrap();
+        assert_eq!(data, [0x5a; 256]);
+
+        cpu.load(&[0xa5; 256], 0x2000);
+        cpu.load(&[0x10], 0x303);
+        cpu.load_and_run_offset(&code, 0x1000, 0x1000);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert_eq!(cpu.register_a, RWTS_VOLUME_MISMATCH);
+        assert_eq!(cpu.bus.mem_read(0x30e), 254);
+        let (_, data) = cpu.bus.disk.read_physical_sector(0, 17, DSK_DO[4]).unwrap();
+        assert_eq!(data, [0x5a; 256]);
+    }
+
+    #[test]
+    fn prodos_read_block() {
+        let mut cpu = setup_cpu();
+        cpu.load(&[0x4c], PRODOS_MLI);
+        cpu.load(&[0x00, 0xd0], PRODOS_DEVADR + 12);
+
+        // $D000 is the Applesoft ROM when the language card is not read enabled
+        cpu.load(&[0x01, 0x60, 0x00, 0x20, 13, 0], 0x42);
+        cpu.program_counter = PRODOS_DRIVER_ENTRY;
+        assert!(!trap_disk_call(&mut cpu));
+
+        cpu.bus.mem.readbsr = true;
+        cpu.bus.mem.bank1 = true;
+
+        // Read block 13 (track 1) to $2000
+        cpu.load(&[0x01, 0x60, 0x00, 0x20, 13, 0], 0x42);
+        let code = [
+            0x20, 0x00, 0xd0, // JSR $D000
+            0x00, // END
+        ];
+        cpu.load_and_run_offset(&code, 0x1000, 0x1000);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+        for (i, &sector) in [DSK_PO[10], DSK_PO[11]].iter().enumerate() {
+            let (_, data) = cpu.bus.disk.read_physical_sector(0, 1, sector).unwrap();
+            assert_eq!(cpu.bus.mem_read(0x2000 + i as u16 * 256), data[0]);
+        }
+        assert_ne!(cpu.bus.mem_read(0x2000), cpu.bus.mem_read(0x2100));
+    }
+}
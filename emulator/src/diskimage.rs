@@ -0,0 +1,459 @@
+use std::io;
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+/*
+2IMG header (all values are little endian)
+https://apple2.org.za/gswv/a2zine/Docs/DiskImage_2MG_Info.txt
+
+    00  "2IMG" magic
+    04  Creator id (e.g. "CTKG", "WOOF", "!nfc")
+    08  Header size ($40)
+    0A  Version (1)
+    0C  Image format ($00 = DOS order, $01 = ProDOS order, $02 = NIB)
+    10  Flags : b31 = write protected, b8 = volume number valid, b7-b0 = volume number
+    14  Number of 512 bytes blocks (ProDOS order only)
+    18  Offset to disk data
+    1C  Length of disk data
+    20  Offset to comment
+    24  Length of comment
+    28  Offset to creator data
+    2C  Length of creator data
+    30  Reserved (must be 0)
+
+DiskCopy 4.2 header (all values are big endian)
+https://www.discferret.com/wiki/Apple_DiskCopy_4.2
+
+    00  Disk name as Pascal string (63 chars max)
+    40  Length of disk data
+    44  Length of tag data
+    48  Checksum of disk data
+    4C  Checksum of tag data
+    50  Disk encoding ($00 = 400K, $01 = 800K, $02 = 720K, $03 = 1440K)
+    51  Format byte ($12 = 400K, $22 = 800K Mac, $24 = 800K Apple II)
+    52  Magic ($0100)
+    54  Disk data followed by tag data
+
+DiskCopy 4.2 has no encoding for the 5.25 disks. The 5.25 disks (35 to 40 tracks) are
+written with the 400K encoding and format, and the data in ProDOS order.
+*/
+
+const IMG_2MG_MAGIC: u32 = 0x474d4932;
+const IMG_2MG_HEADER_SIZE: usize = 0x40;
+const IMG_2MG_VERSION: u16 = 1;
+const IMG_2MG_CREATOR: [u8; 4] = *b"EM65";
+const IMG_2MG_FLAG_LOCKED: u32 = 0x80000000;
+const IMG_2MG_FLAG_VOLUME_VALID: u32 = 0x00000100;
+
+const DC42_HEADER_SIZE: usize = 0x54;
+const DC42_MAGIC: u16 = 0x0100;
+const DC42_NAME_SIZE: usize = 63;
+const DC42_TAG_CHECKSUM_SKIP: usize = 12;
+
+pub const IMG_2MG_FORMAT_DOS: u32 = 0;
+pub const IMG_2MG_FORMAT_PRODOS: u32 = 1;
+pub const IMG_2MG_FORMAT_NIB: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Image2mg {
+    pub creator: [u8; 4],
+    pub version: u16,
+    pub format: u32,
+    pub flags: u32,
+    pub comment: Vec<u8>,
+    pub creator_data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct DiskCopy42 {
+    pub name: Vec<u8>,
+    pub encoding: u8,
+    pub format: u8,
+    pub tag_data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum ImageHeader {
+    #[default]
+    None,
+    Img2mg(Image2mg),
+    Dc42(DiskCopy42),
+}
+
+fn read_le_u16(dsk: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([dsk[offset], dsk[offset + 1]])
+}
+
+fn read_le_u32(dsk: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        dsk[offset],
+        dsk[offset + 1],
+        dsk[offset + 2],
+        dsk[offset + 3],
+    ])
+}
+
+fn read_be_u16(dsk: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([dsk[offset], dsk[offset + 1]])
+}
+
+fn read_be_u32(dsk: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        dsk[offset],
+        dsk[offset + 1],
+        dsk[offset + 2],
+        dsk[offset + 3],
+    ])
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl Image2mg {
+    pub fn new(format: u32) -> Self {
+        Image2mg {
+            creator: IMG_2MG_CREATOR,
+            version: IMG_2MG_VERSION,
+            format,
+            flags: 0,
+            comment: Vec::new(),
+            creator_data: Vec::new(),
+        }
+    }
+
+    pub fn is_write_protected(&self) -> bool {
+        self.flags & IMG_2MG_FLAG_LOCKED != 0
+    }
+
+    pub fn set_write_protected(&mut self, state: bool) {
+        if state {
+            self.flags |= IMG_2MG_FLAG_LOCKED
+        } else {
+            self.flags &= !IMG_2MG_FLAG_LOCKED
+        }
+    }
+
+    pub fn volume_number(&self) -> Option<u8> {
+        if self.flags & IMG_2MG_FLAG_VOLUME_VALID != 0 {
+            Some((self.flags & 0xff) as u8)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_volume_number(&mut self, volume: Option<u8>) {
+        self.flags &= !(IMG_2MG_FLAG_VOLUME_VALID | 0xff);
+        if let Some(value) = volume {
+            self.flags |= IMG_2MG_FLAG_VOLUME_VALID | value as u32;
+        }
+    }
+
+    /// Parse the 2IMG header and return the metadata with the offset and length of
+    /// the disk data
+    pub fn parse(dsk: &[u8]) -> io::Result<(Self, usize, usize)> {
+        if dsk.len() < IMG_2MG_HEADER_SIZE || read_le_u32(dsk, 0) != IMG_2MG_MAGIC {
+            return Err(invalid_input("Invalid 2mg file"));
+        }
+
+        let header_size = read_le_u16(dsk, 0x08) as usize;
+        let offset = read_le_u32(dsk, 0x18) as usize;
+        let len = read_le_u32(dsk, 0x1c) as usize;
+        let comment_offset = read_le_u32(dsk, 0x20) as usize;
+        let comment_len = read_le_u32(dsk, 0x24) as usize;
+        let creator_offset = read_le_u32(dsk, 0x28) as usize;
+        let creator_len = read_le_u32(dsk, 0x2c) as usize;
+
+        let section = |start: usize, len: usize| -> io::Result<Vec<u8>> {
+            if len == 0 {
+                return Ok(Vec::new());
+            }
+            match start.checked_add(len) {
+                Some(end) if start >= header_size && end <= dsk.len() => {
+                    Ok(dsk[start..end].to_vec())
+                }
+                _ => Err(invalid_input("Invalid 2mg file - Len error")),
+            }
+        };
+
+        if header_size < IMG_2MG_HEADER_SIZE || offset < header_size {
+            return Err(invalid_input("Invalid 2mg file - Header error"));
+        }
+
+        if offset.checked_add(len).is_none_or(|end| end > dsk.len()) {
+            return Err(invalid_input("Invalid 2mg file - Len error"));
+        }
+
+        let header = Image2mg {
+            creator: [dsk[4], dsk[5], dsk[6], dsk[7]],
+            version: read_le_u16(dsk, 0x0a),
+            format: read_le_u32(dsk, 0x0c),
+            flags: read_le_u32(dsk, 0x10),
+            comment: section(comment_offset, comment_len)?,
+            creator_data: section(creator_offset, creator_len)?,
+        };
+
+        Ok((header, offset, len))
+    }
+
+    /// Create the 2IMG file with the disk data, followed by the comment and the creator
+    /// data. The offsets are recalculated from the current content
+    pub fn build(&self, data: &[u8]) -> Vec<u8> {
+        let mut dsk = Vec::with_capacity(
+            IMG_2MG_HEADER_SIZE + data.len() + self.comment.len() + self.creator_data.len(),
+        );
+
+        let data_offset = IMG_2MG_HEADER_SIZE;
+        let comment_offset = data_offset + data.len();
+        let creator_offset = comment_offset + self.comment.len();
+        let blocks = if self.format == IMG_2MG_FORMAT_PRODOS {
+            data.len() / 512
+        } else {
+            0
+        };
+
+        let offset_or_zero = |offset: usize, len: usize| if len > 0 { offset } else { 0 };
+
+        dsk.extend_from_slice(&IMG_2MG_MAGIC.to_le_bytes());
+        dsk.extend_from_slice(&self.creator);
+        dsk.extend_from_slice(&(IMG_2MG_HEADER_SIZE as u16).to_le_bytes());
+        dsk.extend_from_slice(&self.version.to_le_bytes());
+        dsk.extend_from_slice(&self.format.to_le_bytes());
+        dsk.extend_from_slice(&self.flags.to_le_bytes());
+        dsk.extend_from_slice(&(blocks as u32).to_le_bytes());
+        dsk.extend_from_slice(&(data_offset as u32).to_le_bytes());
+        dsk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        dsk.extend_from_slice(
+            &(offset_or_zero(comment_offset, self.comment.len()) as u32).to_le_bytes(),
+        );
+        dsk.extend_from_slice(&(self.comment.len() as u32).to_le_bytes());
+        dsk.extend_from_slice(
+            &(offset_or_zero(creator_offset, self.creator_data.len()) as u32).to_le_bytes(),
+        );
+        dsk.extend_from_slice(&(self.creator_data.len() as u32).to_le_bytes());
+        dsk.resize(IMG_2MG_HEADER_SIZE, 0);
+
+        dsk.extend_from_slice(data);
+        dsk.extend_from_slice(&self.comment);
+        dsk.extend_from_slice(&self.creator_data);
+        dsk
+    }
+}
+
+/// DiskCopy 4.2 checksum. Each big endian word is added to the sum and the sum is
+/// rotated right by one bit
+pub fn dc42_checksum(data: &[u8]) -> u32 {
+    let mut sum: u32 = 0;
+    for word in data.chunks(2) {
+        let value = if word.len() == 2 {
+            u16::from_be_bytes([word[0], word[1]])
+        } else {
+            (word[0] as u16) << 8
+        };
+        sum = sum.wrapping_add(value as u32).rotate_right(1);
+    }
+    sum
+}
+
+fn dc42_tag_checksum(tag_data: &[u8]) -> u32 {
+    if tag_data.len() > DC42_TAG_CHECKSUM_SKIP {
+        dc42_checksum(&tag_data[DC42_TAG_CHECKSUM_SKIP..])
+    } else {
+        0
+    }
+}
+
+impl DiskCopy42 {
+    /// Create the header for the disk data. The encoding and format are determined by the
+    /// size of the data, only the 5.25, 400K, 800K, 720K and 1440K disks are supported
+    pub fn new(name: &str, data_len: usize) -> io::Result<Self> {
+        let (encoding, format) = match data_len {
+            143360 | 147456 | 151552 | 155648 | 159744 | 163840 => (0x00, 0x12),
+            409600 => (0x00, 0x12),
+            819200 => (0x01, 0x24),
+            737280 => (0x02, 0x22),
+            1474560 => (0x03, 0x22),
+            _ => return Err(invalid_input("Disk size is not supported by DiskCopy 4.2")),
+        };
+
+        let mut name = name.as_bytes().to_vec();
+        name.truncate(DC42_NAME_SIZE);
+
+        Ok(DiskCopy42 {
+            name,
+            encoding,
+            format,
+            tag_data: Vec::new(),
+        })
+    }
+
+    /// Check if the array contains a DiskCopy 4.2 image. Used to detect DiskCopy images
+    /// that uses the generic .dsk extension
+    pub fn is_dc42(dsk: &[u8]) -> bool {
+        if dsk.len() < DC42_HEADER_SIZE
+            || read_be_u16(dsk, 0x52) != DC42_MAGIC
+            || dsk[0] as usize > DC42_NAME_SIZE
+        {
+            return false;
+        }
+
+        let data_len = read_be_u32(dsk, 0x40) as usize;
+        let tag_len = read_be_u32(dsk, 0x44) as usize;
+        DC42_HEADER_SIZE + data_len + tag_len == dsk.len()
+    }
+
+    /// Parse the DiskCopy 4.2 header and return the metadata with the offset and length
+    /// of the disk data
+    pub fn parse(dsk: &[u8]) -> io::Result<(Self, usize, usize)> {
+        if !Self::is_dc42(dsk) {
+            return Err(invalid_input("Invalid DiskCopy 4.2 file"));
+        }
+
+        let name_len = dsk[0] as usize;
+        let data_len = read_be_u32(dsk, 0x40) as usize;
+        let tag_len = read_be_u32(dsk, 0x44) as usize;
+        let tag_offset = DC42_HEADER_SIZE + data_len;
+
+        if read_be_u32(dsk, 0x48) != dc42_checksum(&dsk[DC42_HEADER_SIZE..tag_offset]) {
+            return Err(invalid_input("DiskCopy 4.2 data checksum error"));
+        }
+
+        let header = DiskCopy42 {
+            name: dsk[1..1 + name_len].to_vec(),
+            encoding: dsk[0x50],
+            format: dsk[0x51],
+            tag_data: dsk[tag_offset..tag_offset + tag_len].to_vec(),
+        };
+
+        Ok((header, DC42_HEADER_SIZE, data_len))
+    }
+
+    /// Create the DiskCopy 4.2 file with updated data and tag checksums
+    pub fn build(&self, data: &[u8]) -> Vec<u8> {
+        let mut dsk = Vec::with_capacity(DC42_HEADER_SIZE + data.len() + self.tag_data.len());
+        let name_len = self.name.len().min(DC42_NAME_SIZE);
+
+        dsk.push(name_len as u8);
+        dsk.extend_from_slice(&self.name[..name_len]);
+        dsk.resize(0x40, 0);
+        dsk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        dsk.extend_from_slice(&(self.tag_data.len() as u32).to_be_bytes());
+        dsk.extend_from_slice(&dc42_checksum(data).to_be_bytes());
+        dsk.extend_from_slice(&dc42_tag_checksum(&self.tag_data).to_be_bytes());
+        dsk.push(self.encoding);
+        dsk.push(self.format);
+        dsk.extend_from_slice(&DC42_MAGIC.to_be_bytes());
+        dsk.extend_from_slice(data);
+        dsk.extend_from_slice(&self.tag_data);
+        dsk
+    }
+
+    /// Offset and content of the data checksum field after the disk data is modified
+    pub fn checksum_field(data: &[u8]) -> (usize, [u8; 4]) {
+        (0x48, dc42_checksum(data).to_be_bytes())
+    }
+}
+
+impl ImageHeader {
+    /// Detect and parse the image header. Returns ImageHeader::None with the full array
+    /// when there is no 2IMG or DiskCopy 4.2 header
+    pub fn parse(dsk: &[u8]) -> io::Result<(Self, usize, usize)> {
+        if dsk.len() >= 4 && read_le_u32(dsk, 0) == IMG_2MG_MAGIC {
+            let (header, offset, len) = Image2mg::parse(dsk)?;
+            Ok((ImageHeader::Img2mg(header), offset, len))
+        } else if DiskCopy42::is_dc42(dsk) {
+            let (header, offset, len) = DiskCopy42::parse(dsk)?;
+            Ok((ImageHeader::Dc42(header), offset, len))
+        } else {
+            Ok((ImageHeader::None, 0, dsk.len()))
+        }
+    }
+
+    pub fn is_write_protected(&self) -> bool {
+        match self {
+            ImageHeader::Img2mg(header) => header.is_write_protected(),
+            _ => false,
+        }
+    }
+
+    /// Wrap the disk data with the header
+    pub fn build(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ImageHeader::None => data.to_vec(),
+            ImageHeader::Img2mg(header) => header.build(data),
+            ImageHeader::Dc42(header) => header.build(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn img_2mg_round_trip() {
+        let mut header = Image2mg::new(IMG_2MG_FORMAT_PRODOS);
+        header.creator = *b"WOOF";
+        header.comment = b"Test comment".to_vec();
+        header.creator_data = vec![1, 2, 3];
+        header.set_volume_number(Some(254));
+        header.set_write_protected(true);
+
+        let data: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+        let dsk = header.build(&data);
+        assert_eq!(read_le_u32(&dsk, 0x14), 2, "Block count should be 2");
+
+        let (parsed, offset, len) = Image2mg::parse(&dsk).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(&dsk[offset..offset + len], &data[..]);
+        assert_eq!(parsed.volume_number(), Some(254));
+        assert!(parsed.is_write_protected());
+    }
+
+    #[test]
+    fn img_2mg_invalid() {
+        let mut dsk = Image2mg::new(IMG_2MG_FORMAT_PRODOS).build(&[0u8; 512]);
+        dsk.truncate(0x100);
+        assert!(Image2mg::parse(&dsk).is_err(), "Truncated data should fail");
+        assert!(
+            Image2mg::parse(&[0x32]).is_err(),
+            "Short header should fail"
+        );
+    }
+
+    #[test]
+    fn dc42_checksum_value() {
+        assert_eq!(dc42_checksum(&[0x00, 0x01]), 0x80000000);
+        assert_eq!(dc42_checksum(&[0x00, 0x01, 0x00, 0x01]), 0xc0000000);
+    }
+
+    #[test]
+    fn dc42_round_trip() {
+        assert!(DiskCopy42::new("ProDOS", 32 * 1024 * 1024).is_err());
+
+        let mut header = DiskCopy42::new("ProDOS", 819200).unwrap();
+        header.tag_data = vec![0x55; 24];
+        let data: Vec<u8> = (0..819200).map(|i| (i * 7) as u8).collect();
+        let mut dsk = header.build(&data);
+
+        assert!(DiskCopy42::is_dc42(&dsk));
+        let (parsed, offset, len) = DiskCopy42::parse(&dsk).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(parsed.format, 0x24);
+        assert_eq!(&dsk[offset..offset + len], &data[..]);
+
+        dsk[offset] ^= 0xff;
+        assert!(DiskCopy42::parse(&dsk).is_err(), "Checksum should fail");
+
+        let (field, value) = DiskCopy42::checksum_field(&dsk[offset..offset + len]);
+        dsk[field..field + 4].copy_from_slice(&value);
+        assert!(
+            DiskCopy42::parse(&dsk).is_ok(),
+            "Updated checksum should pass"
+        );
+    }
+}
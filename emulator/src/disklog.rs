@@ -0,0 +1,426 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/*
+Disk access log
+
+Records the Disk II activity with the CPU cycle and the program counter of the
+instruction that caused it. Address and data fields are detected from the nibbles
+read from the data latch and the nibbles written to the data register.
+
+The log can be exported as CSV or JSON. The CSV columns are
+
+    cycle,pc,drive,quarter_track,event,phase,volume,track,sector
+
+Columns that are not used by the event are left empty.
+*/
+
+/// Maximum number of entries kept in the log. Later events are dropped
+pub const DISK_LOG_MAX_ENTRIES: usize = 1 << 20;
+
+const ADDRESS_FIELD_LEN: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiskLogEvent {
+    DriveSelect,
+    Phase { phase: u8, on: bool },
+    MotorOn,
+    MotorOff,
+    Track,
+    AddressRead { volume: u8, track: u8, sector: u8 },
+    AddressWrite { volume: u8, track: u8, sector: u8 },
+    SectorRead { track: u8, sector: u8 },
+    SectorWrite { track: u8, sector: u8 },
+}
+
+impl DiskLogEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiskLogEvent::DriveSelect => "drive_select",
+            DiskLogEvent::Phase { .. } => "phase",
+            DiskLogEvent::MotorOn => "motor_on",
+            DiskLogEvent::MotorOff => "motor_off",
+            DiskLogEvent::Track => "track",
+            DiskLogEvent::AddressRead { .. } => "address_read",
+            DiskLogEvent::AddressWrite { .. } => "address_write",
+            DiskLogEvent::SectorRead { .. } => "sector_read",
+            DiskLogEvent::SectorWrite { .. } => "sector_write",
+        }
+    }
+
+    fn phase(&self) -> Option<(u8, bool)> {
+        match *self {
+            DiskLogEvent::Phase { phase, on } => Some((phase, on)),
+            _ => None,
+        }
+    }
+
+    fn volume(&self) -> Option<u8> {
+        match *self {
+            DiskLogEvent::AddressRead { volume, .. }
+            | DiskLogEvent::AddressWrite { volume, .. } => Some(volume),
+            _ => None,
+        }
+    }
+
+    fn track_sector(&self) -> Option<(u8, u8)> {
+        match *self {
+            DiskLogEvent::AddressRead { track, sector, .. }
+            | DiskLogEvent::AddressWrite { track, sector, .. }
+            | DiskLogEvent::SectorRead { track, sector }
+            | DiskLogEvent::SectorWrite { track, sector } => Some((track, sector)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DiskLogEntry {
+    pub cycle: usize,
+    pub pc: u16,
+    pub drive: usize,
+    pub quarter_track: u8,
+    pub event: DiskLogEvent,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Field {
+    Address { volume: u8, track: u8, sector: u8 },
+    Data,
+}
+
+/// Detect the address and data field prologues in a nibble stream
+#[derive(Debug, Clone, Default)]
+struct FieldDecoder {
+    history: u32,
+    address: Option<Vec<u8>>,
+}
+
+impl FieldDecoder {
+    fn decode_4x4(odd: u8, even: u8) -> u8 {
+        ((odd << 1) | 0x1) & even
+    }
+
+    fn feed(&mut self, value: u8) -> Option<Field> {
+        self.history = ((self.history << 8) | value as u32) & 0xffffff;
+
+        if let Some(address) = self.address.as_mut() {
+            address.push(value);
+            if address.len() < ADDRESS_FIELD_LEN {
+                return None;
+            }
+            let field = Field::Address {
+                volume: Self::decode_4x4(address[0], address[1]),
+                track: Self::decode_4x4(address[2], address[3]),
+                sector: Self::decode_4x4(address[4], address[5]),
+            };
+            self.address = None;
+            return Some(field);
+        }
+
+        match self.history {
+            0xd5aa96 | 0xd5aab5 => {
+                self.address = Some(Vec::with_capacity(ADDRESS_FIELD_LEN));
+                None
+            }
+            0xd5aaad => Some(Field::Data),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiskLog {
+    entries: Vec<DiskLogEntry>,
+    cycle: usize,
+    pc: u16,
+    read_decoder: FieldDecoder,
+    write_decoder: FieldDecoder,
+    last_address: Option<(u8, u8)>,
+}
+
+impl DiskLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the CPU cycle and program counter used for the following events
+    pub fn set_context(&mut self, cycle: usize, pc: u16) {
+        self.cycle = cycle;
+        self.pc = pc;
+    }
+
+    pub fn entries(&self) -> &[DiskLogEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.read_decoder = FieldDecoder::default();
+        self.write_decoder = FieldDecoder::default();
+        self.last_address = None;
+    }
+
+    pub fn record(&mut self, drive: usize, quarter_track: u8, event: DiskLogEvent) {
+        if self.entries.len() < DISK_LOG_MAX_ENTRIES {
+            self.entries.push(DiskLogEntry {
+                cycle: self.cycle,
+                pc: self.pc,
+                drive,
+                quarter_track,
+                event,
+            });
+        }
+    }
+
+    /// Record the nibble read from the data latch
+    pub fn record_read(&mut self, drive: usize, quarter_track: u8, value: u8) {
+        match self.read_decoder.feed(value) {
+            Some(Field::Address {
+                volume,
+                track,
+                sector,
+            }) => {
+                self.last_address = Some((track, sector));
+                let event = DiskLogEvent::AddressRead {
+                    volume,
+                    track,
+                    sector,
+                };
+                self.record(drive, quarter_track, event);
+            }
+            Some(Field::Data) => {
+                if let Some((track, sector)) = self.last_address.take() {
+                    let event = DiskLogEvent::SectorRead { track, sector };
+                    self.record(drive, quarter_track, event);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Record the nibble written to the data register
+    pub fn record_write(&mut self, drive: usize, quarter_track: u8, value: u8) {
+        match self.write_decoder.feed(value) {
+            Some(Field::Address {
+                volume,
+                track,
+                sector,
+            }) => {
+                self.last_address = Some((track, sector));
+                let event = DiskLogEvent::AddressWrite {
+                    volume,
+                    track,
+                    sector,
+                };
+                self.record(drive, quarter_track, event);
+            }
+            Some(Field::Data) => {
+                if let Some((track, sector)) = self.last_address.take() {
+                    let event = DiskLogEvent::SectorWrite { track, sector };
+                    self.record(drive, quarter_track, event);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Merge the entries of another log, adding drive_offset to the drive number
+    pub fn merge(&mut self, other: &DiskLog, drive_offset: usize) {
+        self.entries
+            .extend(other.entries.iter().map(|entry| DiskLogEntry {
+                drive: entry.drive + drive_offset,
+                ..*entry
+            }));
+        self.entries.sort_by_key(|entry| entry.cycle);
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut output =
+            String::from("cycle,pc,drive,quarter_track,event,phase,volume,track,sector\n");
+        for entry in &self.entries {
+            let phase = entry.event.phase().map_or(String::new(), |(phase, on)| {
+                format!("{phase}{}", if on { "+" } else { "-" })
+            });
+            let volume = entry
+                .event
+                .volume()
+                .map_or(String::new(), |v| v.to_string());
+            let (track, sector) = entry
+                .event
+                .track_sector()
+                .map_or((String::new(), String::new()), |(t, s)| {
+                    (t.to_string(), s.to_string())
+                });
+            output.push_str(&format!(
+                "{},{:04X},{},{},{},{},{},{},{}\n",
+                entry.cycle,
+                entry.pc,
+                entry.drive + 1,
+                entry.quarter_track,
+                entry.event.name(),
+                phase,
+                volume,
+                track,
+                sector
+            ));
+        }
+        output
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut output = String::from("[\n");
+        for (i, entry) in self.entries.iter().enumerate() {
+            output.push_str(&format!(
+                "  {{\"cycle\": {}, \"pc\": \"{:04X}\", \"drive\": {}, \"quarter_track\": {}, \"event\": \"{}\"",
+                entry.cycle,
+                entry.pc,
+                entry.drive + 1,
+                entry.quarter_track,
+                entry.event.name()
+            ));
+            if let Some((phase, on)) = entry.event.phase() {
+                output.push_str(&format!(", \"phase\": {phase}, \"on\": {on}"));
+            }
+            if let Some(volume) = entry.event.volume() {
+                output.push_str(&format!(", \"volume\": {volume}"));
+            }
+            if let Some((track, sector)) = entry.event.track_sector() {
+                output.push_str(&format!(", \"track\": {track}, \"sector\": {sector}"));
+            }
+            output.push('}');
+            if i + 1 < self.entries.len() {
+                output.push(',');
+            }
+            output.push('\n');
+        }
+        output.push_str("]\n");
+        output
+    }
+
+    /// Save the log. The log is saved as JSON if the file extension is json, otherwise
+    /// it is saved as CSV
+    pub fn save<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let output = if is_json {
+            self.to_json()
+        } else {
+            self.to_csv()
+        };
+        let mut file = File::create(path)?;
+        file.write_all(output.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode_4x4(value: u8) -> [u8; 2] {
+        [(value >> 1) | 0xaa, value | 0xaa]
+    }
+
+    fn sector_nibbles(track: u8, sector: u8) -> Vec<u8> {
+        let mut nibbles = vec![0xff, 0xff, 0xd5, 0xaa, 0x96];
+        for value in [254, track, sector, 254 ^ track ^ sector] {
+            nibbles.extend_from_slice(&encode_4x4(value));
+        }
+        nibbles.extend_from_slice(&[0xde, 0xaa, 0xeb, 0xff, 0xd5, 0xaa, 0xad, 0x96]);
+        nibbles
+    }
+
+    #[test]
+    fn detect_sector_read_and_write() {
+        let mut log = DiskLog::new();
+        log.set_context(100, 0xc65c);
+        log.record(0, 0, DiskLogEvent::MotorOn);
+        for value in sector_nibbles(0, 5) {
+            log.record_read(0, 0, value);
+        }
+
+        log.set_context(200, 0xb82a);
+        for value in [0xff, 0xd5, 0xaa, 0xad, 0x96] {
+            log.record_write(0, 0, value);
+        }
+
+        // Data field without the address field is not logged
+        for value in [0xd5, 0xaa, 0xad] {
+            log.record_read(0, 0, value);
+        }
+
+        let events: Vec<DiskLogEvent> = log.entries().iter().map(|e| e.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                DiskLogEvent::MotorOn,
+                DiskLogEvent::AddressRead {
+                    volume: 254,
+                    track: 0,
+                    sector: 5
+                },
+                DiskLogEvent::SectorRead {
+                    track: 0,
+                    sector: 5
+                },
+            ]
+        );
+
+        // RWTS reads the address field and switches to write mode for the data field
+        for value in sector_nibbles(0, 6).into_iter().take(13) {
+            log.record_read(0, 0, value);
+        }
+        for value in [0xd5, 0xaa, 0xad] {
+            log.record_write(0, 0, value);
+        }
+        assert_eq!(
+            log.entries().last().unwrap().event,
+            DiskLogEvent::SectorWrite {
+                track: 0,
+                sector: 6
+            }
+        );
+    }
+
+    #[test]
+    fn export_csv_and_json() {
+        let mut log = DiskLog::new();
+        log.set_context(10, 0x0801);
+        log.record(0, 2, DiskLogEvent::Phase { phase: 1, on: true });
+
+        let mut other = DiskLog::new();
+        other.set_context(5, 0x0900);
+        other.record(
+            1,
+            4,
+            DiskLogEvent::SectorRead {
+                track: 1,
+                sector: 2,
+            },
+        );
+        log.merge(&other, 2);
+
+        let csv = log.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[1], "5,0900,4,4,sector_read,,,1,2");
+        assert_eq!(lines[2], "10,0801,1,2,phase,1+,,,");
+
+        let json = log.to_json();
+        assert!(json.contains("\"event\": \"phase\", \"phase\": 1, \"on\": true}"));
+        assert!(json.contains("\"track\": 1, \"sector\": 2}"));
+    }
+}
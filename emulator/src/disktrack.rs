@@ -0,0 +1,583 @@
+use crate::disk::{TRANSLATE_VALUE_6X2, encode_6x2};
+
+/*
+Track bitstream analysis used by the disk inspector
+
+The track is decoded like the Disk II controller: the bitstream is framed into nibbles
+(a nibble starts with the first 1 bit), then the nibbles are scanned for
+
+    Sync gaps       4 or more consecutive $FF nibbles
+    Address fields  D5 AA 96 (16 sector) or D5 AA B5 (13 sector), 4x4 encoded volume,
+                    track, sector and checksum, followed by the DE AA epilogue
+    Data fields     D5 AA AD, 6x2 (342 nibbles) or 5x3 (410 nibbles) encoded data,
+                    checksum, followed by the DE AA epilogue
+    Weak bits       3 or more consecutive 0 bits. The MC3470 returns random bits for
+                    these regions
+
+Fields that wrap around the end of the track are decoded by reading past the end
+of the track from the start of the track.
+*/
+
+const SYNC_MIN_COUNT: usize = 4;
+const WEAK_ZERO_BITS: usize = 3;
+const WEAK_MERGE_BITS: usize = 8;
+
+// Extra bits read after the end of track to decode the field that wraps around
+const WRAP_BITS: usize = 420 * 10;
+
+// Number of nibbles searched for the data prologue after the address field
+const DATA_PROLOGUE_SEARCH: usize = 32;
+
+// Flux timing is in 125ns ticks. Each bit cell is 4us
+const FLUX_TICKS_PER_BIT: usize = 32;
+
+const TRANSLATE_VALUE_5X3: [u8; 32] = [
+    0xab, 0xad, 0xae, 0xaf, 0xb5, 0xb6, 0xb7, 0xba, 0xbb, 0xbd, 0xbe, 0xbf, 0xd6, 0xd7, 0xda, 0xdb,
+    0xdd, 0xde, 0xdf, 0xea, 0xeb, 0xed, 0xee, 0xef, 0xf5, 0xf6, 0xf7, 0xfa, 0xfb, 0xfd, 0xfe, 0xff,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SectorEncoding {
+    FiveAndThree,
+    SixAndTwo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitRegion {
+    pub bit_offset: usize,
+    pub bit_len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncGap {
+    pub bit_offset: usize,
+    pub bit_len: usize,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressField {
+    pub bit_offset: usize,
+    pub bit_len: usize,
+    pub encoding: SectorEncoding,
+    pub volume: u8,
+    pub track: u8,
+    pub sector: u8,
+    pub checksum_ok: bool,
+    pub epilogue_ok: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataField {
+    pub bit_offset: usize,
+    pub bit_len: usize,
+    pub encoding: SectorEncoding,
+    /// Sector of the preceding address field
+    pub sector: Option<u8>,
+    pub checksum_ok: bool,
+    pub epilogue_ok: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackAnalysis {
+    pub bit_count: usize,
+    pub nibble_count: usize,
+    pub sync_gaps: Vec<SyncGap>,
+    pub address_fields: Vec<AddressField>,
+    pub data_fields: Vec<DataField>,
+    pub weak_regions: Vec<BitRegion>,
+}
+
+impl TrackAnalysis {
+    pub fn bad_checksum_count(&self) -> usize {
+        self.address_fields
+            .iter()
+            .filter(|field| !field.checksum_ok)
+            .count()
+            + self
+                .data_fields
+                .iter()
+                .filter(|field| !field.checksum_ok)
+                .count()
+    }
+
+    /// Sectors with valid address and data field
+    pub fn readable_sectors(&self) -> Vec<u8> {
+        let mut sectors: Vec<u8> = self
+            .data_fields
+            .iter()
+            .filter(|field| field.checksum_ok)
+            .filter_map(|field| field.sector)
+            .collect();
+        sectors.sort_unstable();
+        sectors.dedup();
+        sectors
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Nibble {
+    start: usize,
+    end: usize,
+    value: u8,
+}
+
+fn read_bit(track: &[u8], bit_count: usize, pos: usize) -> bool {
+    let pos = pos % bit_count;
+    track[pos / 8] & (0x80 >> (pos % 8)) != 0
+}
+
+fn read_nibbles(track: &[u8], bit_count: usize) -> Vec<Nibble> {
+    let limit = bit_count + WRAP_BITS;
+    let mut nibbles = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        while pos < limit && !read_bit(track, bit_count, pos) {
+            pos += 1;
+        }
+
+        if pos + 8 > limit {
+            break;
+        }
+
+        let start = pos;
+        let mut value = 0;
+        for _ in 0..8 {
+            value = (value << 1) | read_bit(track, bit_count, pos) as u8;
+            pos += 1;
+        }
+        nibbles.push(Nibble {
+            start,
+            end: pos,
+            value,
+        });
+    }
+    nibbles
+}
+
+fn decode_4x4(nibbles: &[Nibble]) -> u8 {
+    ((nibbles[0].value << 1) | 0x1) & nibbles[1].value
+}
+
+fn detranslate(table: &[u8], value: u8) -> u8 {
+    table.iter().position(|&item| item == value).unwrap_or(0) as u8
+}
+
+fn has_epilogue(nibbles: &[Nibble]) -> bool {
+    nibbles.len() >= 2 && nibbles[0].value == 0xde && nibbles[1].value == 0xaa
+}
+
+fn find_weak_regions(track: &[u8], bit_count: usize) -> Vec<BitRegion> {
+    let mut regions: Vec<BitRegion> = Vec::new();
+    let mut zero_start = 0;
+    let mut zero_count = 0;
+
+    for pos in 0..=bit_count {
+        if pos < bit_count && !read_bit(track, bit_count, pos) {
+            if zero_count == 0 {
+                zero_start = pos;
+            }
+            zero_count += 1;
+            continue;
+        }
+
+        if zero_count >= WEAK_ZERO_BITS {
+            match regions.last_mut() {
+                Some(region)
+                    if region.bit_offset + region.bit_len + WEAK_MERGE_BITS >= zero_start =>
+                {
+                    region.bit_len = zero_start + zero_count - region.bit_offset;
+                }
+                _ => regions.push(BitRegion {
+                    bit_offset: zero_start,
+                    bit_len: zero_count,
+                }),
+            }
+        }
+        zero_count = 0;
+    }
+    regions
+}
+
+/// Analyze the bitstream of a track. The track data is the WOZ bitstream with the most
+/// significant bit first
+pub fn analyze_track(track: &[u8], bit_count: usize) -> TrackAnalysis {
+    let bit_count = bit_count.min(track.len() * 8);
+    let mut analysis = TrackAnalysis {
+        bit_count,
+        ..Default::default()
+    };
+
+    if bit_count == 0 {
+        return analysis;
+    }
+
+    let nibbles = read_nibbles(track, bit_count);
+    analysis.nibble_count = nibbles.iter().filter(|n| n.start < bit_count).count();
+    analysis.weak_regions = find_weak_regions(track, bit_count);
+
+    let mut last_address: Option<(SectorEncoding, u8)> = None;
+    let mut i = 0;
+    while i < nibbles.len() && nibbles[i].start < bit_count {
+        let remaining = &nibbles[i..];
+
+        // Sync gap
+        if remaining[0].value == 0xff {
+            let count = remaining.iter().take_while(|n| n.value == 0xff).count();
+            if count >= SYNC_MIN_COUNT {
+                let last = &remaining[count - 1];
+                analysis.sync_gaps.push(SyncGap {
+                    bit_offset: remaining[0].start,
+                    bit_len: last.end - remaining[0].start,
+                    count,
+                });
+            }
+            i += count;
+            continue;
+        }
+
+        if remaining.len() < 3 || remaining[0].value != 0xd5 || remaining[1].value != 0xaa {
+            i += 1;
+            continue;
+        }
+
+        match remaining[2].value {
+            0x96 | 0xb5 if remaining.len() >= 13 => {
+                let encoding = if remaining[2].value == 0x96 {
+                    SectorEncoding::SixAndTwo
+                } else {
+                    SectorEncoding::FiveAndThree
+                };
+                let volume = decode_4x4(&remaining[3..5]);
+                let track_no = decode_4x4(&remaining[5..7]);
+                let sector = decode_4x4(&remaining[7..9]);
+                let checksum = decode_4x4(&remaining[9..11]);
+                let checksum_ok = checksum == volume ^ track_no ^ sector;
+
+                analysis.address_fields.push(AddressField {
+                    bit_offset: remaining[0].start,
+                    bit_len: remaining[12].end - remaining[0].start,
+                    encoding,
+                    volume,
+                    track: track_no,
+                    sector,
+                    checksum_ok,
+                    epilogue_ok: has_epilogue(&remaining[11..]),
+                });
+                last_address = checksum_ok.then_some((encoding, sector));
+                i += 11;
+            }
+            0xad => {
+                let encoding = last_address.map_or(SectorEncoding::SixAndTwo, |(e, _)| e);
+                let (table, data_len): (&[u8], usize) = match encoding {
+                    SectorEncoding::SixAndTwo => (&TRANSLATE_VALUE_6X2, 342),
+                    SectorEncoding::FiveAndThree => (&TRANSLATE_VALUE_5X3, 410),
+                };
+
+                // Data nibbles and checksum nibble after the prologue
+                if remaining.len() < 3 + data_len + 1 {
+                    break;
+                }
+
+                let mut last = 0;
+                for nibble in &remaining[3..3 + data_len + 1] {
+                    last ^= detranslate(table, nibble.value);
+                }
+                let end = 3 + data_len + 1;
+
+                analysis.data_fields.push(DataField {
+                    bit_offset: remaining[0].start,
+                    bit_len: remaining[end - 1].end - remaining[0].start,
+                    encoding,
+                    sector: last_address.map(|(_, sector)| sector),
+                    checksum_ok: last == 0,
+                    epilogue_ok: has_epilogue(&remaining[end..]),
+                });
+                last_address = None;
+                i += end;
+            }
+            _ => i += 1,
+        }
+    }
+
+    analysis
+}
+
+/// Decoded 16 sector data field. The data offset is the bit position of the first
+/// data nibble after the prologue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectorData {
+    pub volume: u8,
+    pub data_offset: usize,
+    pub data: [u8; 256],
+}
+
+fn decode_6x2(nibbles: &[Nibble]) -> Option<[u8; 256]> {
+    let detranslate = |nibble: &Nibble| {
+        TRANSLATE_VALUE_6X2
+            .iter()
+            .position(|&item| item == nibble.value)
+            .map(|value| value as u8)
+    };
+
+    let mut last = 0;
+    let mut aux = [0u8; 0x56];
+    let mut data = [0u8; 256];
+    for j in (0..0x56).rev() {
+        last ^= detranslate(&nibbles[0x55 - j])?;
+        aux[j] = last;
+    }
+    for (i, item) in data.iter_mut().enumerate() {
+        last ^= detranslate(&nibbles[0x56 + i])?;
+        *item = last;
+    }
+    if detranslate(&nibbles[342])? != last {
+        return None;
+    }
+
+    let mut j = 0x55;
+    for item in &mut data {
+        let mut val = aux[j];
+        let mut val2 = (*item << 1) + (val & 1);
+        val >>= 1;
+        val2 = (val2 << 1) + (val & 1);
+        *item = val2;
+        aux[j] = val >> 1;
+        j = if j == 0 { 0x55 } else { j - 1 };
+    }
+    Some(data)
+}
+
+/// Find and decode the 16 sector physical sector of the track. Returns None if the
+/// sector is not found or the checksum is bad
+pub fn read_sector(track: &[u8], bit_count: usize, track_no: u8, sector: u8) -> Option<SectorData> {
+    let bit_count = bit_count.min(track.len() * 8);
+    if bit_count == 0 {
+        return None;
+    }
+
+    let nibbles = read_nibbles(track, bit_count);
+    let mut i = 0;
+    while i + 11 < nibbles.len() && nibbles[i].start < bit_count {
+        let remaining = &nibbles[i..];
+        if remaining[0].value != 0xd5 || remaining[1].value != 0xaa || remaining[2].value != 0x96 {
+            i += 1;
+            continue;
+        }
+
+        let volume = decode_4x4(&remaining[3..5]);
+        let address_track = decode_4x4(&remaining[5..7]);
+        let address_sector = decode_4x4(&remaining[7..9]);
+        let checksum = decode_4x4(&remaining[9..11]);
+        i += 11;
+
+        if checksum != volume ^ address_track ^ address_sector
+            || address_track != track_no
+            || address_sector != sector
+        {
+            continue;
+        }
+
+        let remaining = &nibbles[i..];
+        let prologue = remaining
+            .windows(3)
+            .take(DATA_PROLOGUE_SEARCH)
+            .position(|n| n[0].value == 0xd5 && n[1].value == 0xaa && n[2].value == 0xad)?;
+        let field = remaining.get(prologue + 3..prologue + 3 + 343)?;
+        let data = decode_6x2(field)?;
+        return Some(SectorData {
+            volume,
+            data_offset: field[0].start % bit_count,
+            data,
+        });
+    }
+    None
+}
+
+/// Write the 16 sector data field and the epilogue at the data offset returned by
+/// read_sector
+pub fn write_sector(track: &mut [u8], bit_count: usize, data_offset: usize, data: &[u8; 256]) {
+    let bit_count = bit_count.min(track.len() * 8);
+    let mut pos = data_offset;
+    for value in encode_6x2(data).into_iter().chain([0xde, 0xaa, 0xeb]) {
+        for bit in (0..8).rev() {
+            let index = pos % bit_count;
+            let mask = 0x80 >> (index % 8);
+            if value & (1 << bit) != 0 {
+                track[index / 8] |= mask;
+            } else {
+                track[index / 8] &= !mask;
+            }
+            pos += 1;
+        }
+    }
+}
+
+/// Convert the WOZ flux track to bitstream. Each flux value is the number of 125ns
+/// ticks since the previous flux transition
+pub fn flux_to_bitstream(flux: &[u8], flux_count: usize) -> (Vec<u8>, usize) {
+    let mut data = Vec::new();
+    let mut bit_count: usize = 0;
+    let mut ticks = 0;
+
+    let mut push_bit = |value: bool| {
+        if bit_count.is_multiple_of(8) {
+            data.push(0);
+        }
+        if value {
+            data[bit_count / 8] |= 0x80 >> (bit_count % 8);
+        }
+        bit_count += 1;
+    };
+
+    for &value in flux.iter().take(flux_count) {
+        ticks += value as usize;
+        if value == 255 {
+            continue;
+        }
+
+        let cells = ((ticks + FLUX_TICKS_PER_BIT / 2) / FLUX_TICKS_PER_BIT).max(1);
+        for _ in 1..cells {
+            push_bit(false);
+        }
+        push_bit(true);
+        ticks = 0;
+    }
+
+    (data, bit_count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct TrackBuilder {
+        data: Vec<u8>,
+        bit_count: usize,
+    }
+
+    impl TrackBuilder {
+        fn new() -> Self {
+            TrackBuilder {
+                data: Vec::new(),
+                bit_count: 0,
+            }
+        }
+
+        fn bits(&mut self, value: u8, count: usize) {
+            for i in (8 - count..8).rev() {
+                if self.bit_count.is_multiple_of(8) {
+                    self.data.push(0);
+                }
+                if value & (1 << i) != 0 {
+                    self.data[self.bit_count / 8] |= 0x80 >> (self.bit_count % 8);
+                }
+                self.bit_count += 1;
+            }
+        }
+
+        fn nibbles(&mut self, values: &[u8]) {
+            values.iter().for_each(|&value| self.bits(value, 8));
+        }
+
+        fn sync(&mut self, count: usize) {
+            for _ in 0..count {
+                self.bits(0xff, 8);
+                self.bits(0, 2);
+            }
+        }
+
+        fn encode_4x4(&mut self, value: u8) {
+            self.nibbles(&[(value >> 1) | 0xaa, value | 0xaa]);
+        }
+
+        fn sector(&mut self, track: u8, sector: u8, corrupt: bool) {
+            self.sync(8);
+            self.nibbles(&[0xd5, 0xaa, 0x96]);
+            for value in [254, track, sector, 254 ^ track ^ sector] {
+                self.encode_4x4(value);
+            }
+            self.nibbles(&[0xde, 0xaa, 0xeb]);
+            self.sync(6);
+            self.nibbles(&[0xd5, 0xaa, 0xad]);
+
+            // All zero data encodes to 0x96
+            let mut data = vec![0x96; 343];
+            if corrupt {
+                data[10] = 0x97;
+            }
+            self.nibbles(&data);
+            self.nibbles(&[0xde, 0xaa, 0xeb]);
+        }
+    }
+
+    #[test]
+    fn analyze_sectors() {
+        let mut track = TrackBuilder::new();
+        track.sector(17, 0, false);
+        track.sector(17, 1, true);
+        track.sync(16);
+        let analysis = analyze_track(&track.data, track.bit_count);
+
+        assert_eq!(analysis.address_fields.len(), 2);
+        assert_eq!(analysis.address_fields[1].track, 17);
+        assert_eq!(analysis.address_fields[1].sector, 1);
+        assert!(
+            analysis
+                .address_fields
+                .iter()
+                .all(|f| f.checksum_ok && f.epilogue_ok)
+        );
+
+        assert_eq!(analysis.data_fields.len(), 2);
+        assert_eq!(analysis.data_fields[0].sector, Some(0));
+        assert!(analysis.data_fields[0].checksum_ok);
+        assert!(!analysis.data_fields[1].checksum_ok);
+        assert_eq!(analysis.bad_checksum_count(), 1);
+        assert_eq!(analysis.readable_sectors(), vec![0]);
+        assert!(analysis.sync_gaps.iter().any(|gap| gap.count >= 16));
+        assert!(analysis.weak_regions.is_empty());
+    }
+
+    #[test]
+    fn read_and_write_sector() {
+        let mut data = [0u8; 256 * 16];
+        for (i, item) in data.iter_mut().enumerate() {
+            *item = (i / 256 + i) as u8;
+        }
+        let (mut track, bit_count) = crate::disk::encode_bits_for_track(&data, 3, false);
+
+        // Physical sector 1 contains DOS 3.3 logical sector 7
+        let sector = read_sector(&track, bit_count, 3, 1).unwrap();
+        assert_eq!(sector.volume, 254);
+        assert_eq!(&sector.data[..], &data[7 * 256..8 * 256]);
+        assert!(read_sector(&track, bit_count, 4, 1).is_none());
+
+        let new_data = [0x5a; 256];
+        write_sector(&mut track, bit_count, sector.data_offset, &new_data);
+        assert_eq!(read_sector(&track, bit_count, 3, 1).unwrap().data, new_data);
+        assert_eq!(
+            read_sector(&track, bit_count, 3, 2).unwrap().data[..],
+            data[14 * 256..15 * 256]
+        );
+        assert_eq!(analyze_track(&track, bit_count).bad_checksum_count(), 0);
+    }
+
+    #[test]
+    fn weak_bits_and_flux() {
+        let mut track = TrackBuilder::new();
+        track.sync(4);
+        track.bits(0, 8);
+        track.bits(0, 4);
+        track.sync(4);
+        let analysis = analyze_track(&track.data, track.bit_count);
+        assert_eq!(analysis.weak_regions.len(), 1);
+        assert_eq!(analysis.weak_regions[0].bit_len, 14);
+
+        // 1, 2, 3 and 8 bit cells (using the 255 continuation)
+        let (data, bit_count) = flux_to_bitstream(&[32, 64, 96, 255, 1], 5);
+        assert_eq!(bit_count, 14);
+        assert_eq!(data, vec![0b1010_0100, 0b0000_0100]);
+    }
+}
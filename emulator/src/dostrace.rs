@@ -0,0 +1,610 @@
+use crate::bus::Mem;
+use crate::cpu::{CPU, CpuFlags};
+use crate::diskaccel::{RWTS_ENTRY, RWTS_SIGNATURE};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/*
+DOS 3.3 file manager and RWTS call tracer
+
+The tracer is called before each instruction (for e.g. from the step_with_callback
+callback) and detects the entry of the file manager and the RWTS.
+
+    File manager      JMP target of the $03D6 vector ($AAFD)
+                      The parameter list is located with the $03DC routine ($B5BB)
+                          +0  Opcode            +2-9  Parameters
+                          +1  Subcode           +A    Return code
+    RWTS              $BD00, A/Y points to the IOB
+                          +1  Slot * 16         +8    Buffer pointer
+                          +2  Drive             +C    Command
+                          +3  Volume expected   +D    Return code
+                          +4  Track             +E    Volume found
+                          +5  Sector
+
+The file manager calls the RWTS, so the calls are nested. A call completes when the
+program counter returns to the caller with the stack pointer of the caller. The DOS
+command (for e.g. BLOAD) is recorded when the file manager is called by DOS itself.
+*/
+
+const FM_VECTOR: u16 = 0x3d6;
+const LOCATE_FM_PARAM: u16 = 0x3dc;
+const FM_PARAM_LIST: u16 = 0xb5bb;
+const FM_RETURN_CODE: u16 = 0x0a;
+const FM_FILENAME_LEN: u16 = 30;
+
+const RWTS_RETURN_CODE: u16 = 0x0d;
+
+const DOS_START: u16 = 0x9d00;
+const DOS_COMMAND_INDEX: u16 = 0xaa5f;
+
+const JMP: u8 = 0x4c;
+
+/// Maximum number of calls kept in the trace. Older calls are dropped
+pub const DOS_TRACE_MAX_CALLS: usize = 100_000;
+
+const DOS_COMMANDS: [&str; 28] = [
+    "INIT", "LOAD", "SAVE", "RUN", "CHAIN", "DELETE", "LOCK", "UNLOCK", "CLOSE", "READ", "EXEC",
+    "WRITE", "POSITION", "OPEN", "APPEND", "RENAME", "CATALOG", "MON", "NOMON", "PR#", "IN#",
+    "MAXFILES", "FP", "INT", "BSAVE", "BLOAD", "BRUN", "VERIFY",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DosCallKind {
+    FileManager,
+    Rwts,
+}
+
+pub fn fm_command_name(command: u8) -> Option<&'static str> {
+    let name = match command {
+        1 => "OPEN",
+        2 => "CLOSE",
+        3 => "READ",
+        4 => "WRITE",
+        5 => "DELETE",
+        6 => "CATALOG",
+        7 => "LOCK",
+        8 => "UNLOCK",
+        9 => "RENAME",
+        10 => "POSITION",
+        11 => "INIT",
+        12 => "VERIFY",
+        _ => return None,
+    };
+    Some(name)
+}
+
+pub fn fm_error_name(error: u8) -> &'static str {
+    match error {
+        0 => "No error",
+        1 => "Language not available",
+        2 | 3 => "Range error",
+        4 => "Write protected",
+        5 => "End of data",
+        6 => "File not found",
+        7 => "Volume mismatch",
+        8 => "I/O error",
+        9 => "Disk full",
+        10 => "File locked",
+        _ => "Unknown error",
+    }
+}
+
+pub fn rwts_command_name(command: u8) -> Option<&'static str> {
+    let name = match command {
+        0 => "SEEK",
+        1 => "READ",
+        2 => "WRITE",
+        4 => "FORMAT",
+        _ => return None,
+    };
+    Some(name)
+}
+
+pub fn rwts_error_name(error: u8) -> &'static str {
+    match error {
+        0x00 => "No error",
+        0x08 => "Init error",
+        0x10 => "Write protected",
+        0x20 => "Volume mismatch",
+        0x40 => "Drive error",
+        0x80 => "Read error",
+        _ => "Unknown error",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DosResult {
+    pub cycle: usize,
+    pub error: u8,
+    /// Decoded output parameters
+    pub outputs: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DosCall {
+    pub cycle: usize,
+    pub pc: u16,
+    pub kind: DosCallKind,
+    pub command: u8,
+    pub param_list: u16,
+    /// DOS command that made the file manager call
+    pub dos_command: Option<&'static str>,
+    /// Decoded input parameters
+    pub inputs: String,
+    pub result: Option<DosResult>,
+}
+
+impl DosCall {
+    pub fn to_text(&self) -> String {
+        let (kind, name) = match self.kind {
+            DosCallKind::FileManager => ("FM", fm_command_name(self.command)),
+            DosCallKind::Rwts => ("RWTS", rwts_command_name(self.command)),
+        };
+        let name = name.map_or(format!("${:02X}", self.command), |name| name.to_string());
+        let mut output = format!(
+            "CYC:{:>12} PC:${:04X} {kind} {name} (${:04X})",
+            self.cycle, self.pc, self.param_list
+        );
+        if let Some(dos_command) = self.dos_command {
+            output.push_str(&format!(" [{dos_command}]"));
+        }
+        if !self.inputs.is_empty() {
+            output.push(' ');
+            output.push_str(&self.inputs);
+        }
+        if let Some(result) = &self.result {
+            output.push_str(" ->");
+            if !result.outputs.is_empty() {
+                output.push(' ');
+                output.push_str(&result.outputs);
+            }
+            if result.error == 0 {
+                output.push_str(" OK");
+            } else {
+                let error_name = match self.kind {
+                    DosCallKind::FileManager => fm_error_name(result.error),
+                    DosCallKind::Rwts => rwts_error_name(result.error),
+                };
+                output.push_str(&format!(" ERR ${:02X} {error_name}", result.error));
+            }
+            output.push_str(&format!(" ({} cycles)", result.cycle - self.cycle));
+        }
+        output
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingCall {
+    index: usize,
+    return_pc: u16,
+    stack_pointer: u8,
+}
+
+#[derive(Debug, Default)]
+pub struct DosTracer {
+    calls: VecDeque<DosCall>,
+    pending: Vec<PendingCall>,
+    echo: bool,
+    recording: bool,
+}
+
+fn read_u16(cpu: &mut CPU, addr: u16) -> u16 {
+    let lo = cpu.bus.unclocked_addr_read(addr);
+    let hi = cpu.bus.unclocked_addr_read(addr.wrapping_add(1));
+    u16::from_le_bytes([lo, hi])
+}
+
+fn read_filename(cpu: &mut CPU, addr: u16) -> String {
+    let name: String = (0..FM_FILENAME_LEN)
+        .map(|i| (cpu.bus.unclocked_addr_read(addr.wrapping_add(i)) & 0x7f) as char)
+        .collect();
+    format!("\"{}\"", name.trim_end())
+}
+
+fn fm_entry(cpu: &mut CPU) -> Option<u16> {
+    (cpu.bus.unclocked_addr_read(FM_VECTOR) == JMP)
+        .then(|| read_u16(cpu, FM_VECTOR.wrapping_add(1)))
+}
+
+// The $03DC routine returns the parameter list address in A (high) and Y (low). It is
+// either LDA #hi / LDY #lo or LDA addr / LDY addr
+fn fm_param_list(cpu: &mut CPU) -> u16 {
+    let mut code = [0u8; 6];
+    for (i, value) in code.iter_mut().enumerate() {
+        *value = cpu.bus.unclocked_addr_read(LOCATE_FM_PARAM + i as u16);
+    }
+
+    match code {
+        [0xa9, hi, 0xa0, lo, ..] => u16::from_le_bytes([lo, hi]),
+        [0xad, a_lo, a_hi, 0xac, y_lo, y_hi] => {
+            let hi = cpu
+                .bus
+                .unclocked_addr_read(u16::from_le_bytes([a_lo, a_hi]));
+            let lo = cpu
+                .bus
+                .unclocked_addr_read(u16::from_le_bytes([y_lo, y_hi]));
+            u16::from_le_bytes([lo, hi])
+        }
+        _ => FM_PARAM_LIST,
+    }
+}
+
+fn decode_fm_inputs(cpu: &mut CPU, command: u8, p: u16) -> String {
+    let byte = |cpu: &mut CPU, offset: u16| cpu.bus.unclocked_addr_read(p.wrapping_add(offset));
+    let location = |cpu: &mut CPU| {
+        format!(
+            "volume={} drive={} slot={}",
+            byte(cpu, 4),
+            byte(cpu, 5),
+            byte(cpu, 6)
+        )
+    };
+    match command {
+        1 => {
+            let name = read_u16(cpu, p + 8);
+            format!(
+                "name={} length={} type=${:02X} {}",
+                read_filename(cpu, name),
+                read_u16(cpu, p + 2),
+                byte(cpu, 7),
+                location(cpu)
+            )
+        }
+        3 | 4 => {
+            let subcode = byte(cpu, 1);
+            let position = if subcode >= 3 {
+                format!(
+                    " record={} offset={}",
+                    read_u16(cpu, p + 2),
+                    read_u16(cpu, p + 4)
+                )
+            } else {
+                String::new()
+            };
+            match subcode {
+                1 | 3 if command == 4 => format!("byte=${:02X}{position}", byte(cpu, 8)),
+                1 | 3 => format!("byte{position}"),
+                2 | 4 => format!(
+                    "range=${:04X}-${:04X} length={}{position}",
+                    read_u16(cpu, p + 8),
+                    read_u16(cpu, p + 8).wrapping_add(read_u16(cpu, p + 6).wrapping_sub(1)),
+                    read_u16(cpu, p + 6)
+                ),
+                _ => format!("subcode={subcode}"),
+            }
+        }
+        5 | 7 | 8 | 12 => {
+            let name = read_u16(cpu, p + 8);
+            format!("name={} {}", read_filename(cpu, name), location(cpu))
+        }
+        6 => location(cpu),
+        9 => {
+            let name = read_u16(cpu, p + 8);
+            let new_name = read_u16(cpu, p + 2);
+            format!(
+                "name={} new_name={} {}",
+                read_filename(cpu, name),
+                read_filename(cpu, new_name),
+                location(cpu)
+            )
+        }
+        10 => format!(
+            "record={} offset={}",
+            read_u16(cpu, p + 2),
+            read_u16(cpu, p + 4)
+        ),
+        11 => format!("dos_page=${:02X} {}", byte(cpu, 2), location(cpu)),
+        _ => String::new(),
+    }
+}
+
+fn decode_fm_outputs(cpu: &mut CPU, command: u8, p: u16) -> String {
+    let subcode = cpu.bus.unclocked_addr_read(p.wrapping_add(1));
+    match command {
+        3 if subcode == 1 || subcode == 3 => {
+            format!("byte=${:02X}", cpu.bus.unclocked_addr_read(p + 8))
+        }
+        _ => String::new(),
+    }
+}
+
+fn decode_rwts_inputs(cpu: &mut CPU, p: u16) -> String {
+    let byte = |cpu: &mut CPU, offset: u16| cpu.bus.unclocked_addr_read(p.wrapping_add(offset));
+    format!(
+        "slot={} drive={} volume={} track={} sector={} buffer=${:04X}",
+        byte(cpu, 1) >> 4,
+        byte(cpu, 2),
+        byte(cpu, 3),
+        byte(cpu, 4),
+        byte(cpu, 5),
+        read_u16(cpu, p + 8)
+    )
+}
+
+impl DosTracer {
+    pub fn new() -> Self {
+        Self {
+            recording: true,
+            ..Default::default()
+        }
+    }
+
+    /// Start or stop recording the calls. The recorded calls are kept
+    pub fn set_recording(&mut self, flag: bool) {
+        self.recording = flag;
+        if !flag {
+            self.pending.clear();
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Print each call to stderr when it returns
+    pub fn set_echo(&mut self, flag: bool) {
+        self.echo = flag
+    }
+
+    pub fn calls(&self) -> &VecDeque<DosCall> {
+        &self.calls
+    }
+
+    pub fn clear(&mut self) {
+        self.calls.clear();
+        self.pending.clear();
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut output = String::new();
+        for call in &self.calls {
+            output.push_str(&call.to_text());
+            output.push('\n');
+        }
+        output
+    }
+
+    pub fn save<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_text().as_bytes())
+    }
+
+    fn complete_call(&mut self, cpu: &mut CPU, pending: PendingCall) {
+        let call = &self.calls[pending.index];
+        let (kind, command, param_list) = (call.kind, call.command, call.param_list);
+        let carry = cpu.status.contains(CpuFlags::CARRY);
+        let (error, outputs) = match kind {
+            DosCallKind::FileManager => {
+                let error = cpu
+                    .bus
+                    .unclocked_addr_read(param_list.wrapping_add(FM_RETURN_CODE));
+                if carry {
+                    (error, String::new())
+                } else {
+                    (0, decode_fm_outputs(cpu, command, param_list))
+                }
+            }
+            DosCallKind::Rwts => {
+                let error = cpu
+                    .bus
+                    .unclocked_addr_read(param_list.wrapping_add(RWTS_RETURN_CODE));
+                if carry {
+                    (error, String::new())
+                } else {
+                    let volume = cpu.bus.unclocked_addr_read(param_list + 0x0e);
+                    (0, format!("volume={volume}"))
+                }
+            }
+        };
+
+        let call = &mut self.calls[pending.index];
+        call.result = Some(DosResult {
+            cycle: cpu.bus.get_cycles(),
+            error,
+            outputs,
+        });
+        if self.echo {
+            eprintln!("{}", call.to_text());
+        }
+    }
+
+    fn add_call(&mut self, cpu: &mut CPU, mut call: DosCall) {
+        // The return address of the caller is on the top of the stack
+        let sp = cpu.stack_pointer;
+        let lo = cpu
+            .bus
+            .unclocked_addr_read(0x100 + sp.wrapping_add(1) as u16);
+        let hi = cpu
+            .bus
+            .unclocked_addr_read(0x100 + sp.wrapping_add(2) as u16);
+        let return_pc = u16::from_le_bytes([lo, hi]).wrapping_add(1);
+        call.pc = return_pc.wrapping_sub(3);
+
+        if call.kind == DosCallKind::FileManager && call.pc >= DOS_START {
+            let index = cpu.bus.unclocked_addr_read(DOS_COMMAND_INDEX) as usize / 2;
+            call.dos_command = DOS_COMMANDS.get(index).copied();
+        }
+
+        if self.calls.len() >= DOS_TRACE_MAX_CALLS {
+            self.calls.pop_front();
+            for pending in &mut self.pending {
+                pending.index = pending.index.saturating_sub(1);
+            }
+        }
+        self.calls.push_back(call);
+        self.pending.push(PendingCall {
+            index: self.calls.len() - 1,
+            return_pc,
+            stack_pointer: sp.wrapping_add(2),
+        });
+    }
+
+    /// Trace the instruction at the program counter
+    pub fn step(&mut self, cpu: &mut CPU) {
+        if !self.recording {
+            return;
+        }
+
+        let pc = cpu.program_counter;
+
+        // Drop the calls that are unwound without returning (for e.g. an error exit that
+        // restores the stack pointer)
+        while let Some(pending) = self.pending.last().copied() {
+            if pc == pending.return_pc && cpu.stack_pointer == pending.stack_pointer {
+                self.pending.pop();
+                self.complete_call(cpu, pending);
+            } else if cpu.stack_pointer > pending.stack_pointer {
+                self.pending.pop();
+            } else {
+                break;
+            }
+        }
+
+        if pc == RWTS_ENTRY {
+            let signature_found = RWTS_SIGNATURE
+                .iter()
+                .enumerate()
+                .all(|(i, &value)| cpu.bus.unclocked_addr_read(pc + i as u16) == value);
+
+            if signature_found {
+                let iob = u16::from_le_bytes([cpu.register_y, cpu.register_a]);
+                let call = DosCall {
+                    cycle: cpu.bus.get_cycles(),
+                    pc: 0,
+                    kind: DosCallKind::Rwts,
+                    command: cpu.bus.unclocked_addr_read(iob.wrapping_add(0x0c)),
+                    param_list: iob,
+                    dos_command: None,
+                    inputs: decode_rwts_inputs(cpu, iob),
+                    result: None,
+                };
+                self.add_call(cpu, call);
+            }
+        } else if pc >= DOS_START && fm_entry(cpu) == Some(pc) {
+            let param_list = fm_param_list(cpu);
+            let command = cpu.bus.unclocked_addr_read(param_list);
+            let call = DosCall {
+                cycle: cpu.bus.get_cycles(),
+                pc: 0,
+                kind: DosCallKind::FileManager,
+                command,
+                param_list,
+                dos_command: None,
+                inputs: decode_fm_inputs(cpu, command, param_list),
+                result: None,
+            };
+            self.add_call(cpu, call);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn trace_fm_and_rwts_calls() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+
+        // File manager vector and parameter list locator
+        cpu.load(&[0x4c, 0xfd, 0xaa], FM_VECTOR);
+        cpu.load(&[0xa9, 0xb5, 0xa0, 0xbb, 0x60], LOCATE_FM_PARAM);
+
+        // File manager that reads track 17 sector 0 and returns write protected
+        let fm = [
+            0xa9, 0xb7, // LDA #$B7
+            0xa0, 0xe8, // LDY #$E8
+            0x20, 0x00, 0xbd, // JSR $BD00
+            0xa9, 0x04, // LDA #$04
+            0x8d, 0xc5, 0xb5, // STA $B5C5
+            0x38, // SEC
+            0x60, // RTS
+        ];
+        cpu.load(&fm, 0xaafd);
+
+        // RWTS that returns without error
+        let rwts = [
+            0x84, 0x48, // STY $48
+            0x85, 0x49, // STA $49
+            0xa9, 0xfe, // LDA #$FE
+            0xa0, 0x0e, // LDY #$0E
+            0x91, 0x48, // STA ($48),Y
+            0x18, // CLC
+            0x60, // RTS
+        ];
+        cpu.load(&rwts, RWTS_ENTRY);
+
+        // IOB and WRITE range parameter list
+        cpu.load(
+            &[0x01, 0x60, 0x01, 0x00, 0x11, 0x00, 0xfb, 0xb7, 0x00, 0x20],
+            0xb7e8,
+        );
+        cpu.load(&[0x01], 0xb7f4);
+        cpu.load(
+            &[0x04, 0x02, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x20],
+            FM_PARAM_LIST,
+        );
+
+        let code = [
+            0x20, 0xd6, 0x03, // JSR $03D6
+            0xea, // NOP
+            0x00, // END
+        ];
+        cpu.load(&code, 0x1000);
+        cpu.program_counter = 0x1000;
+
+        let mut tracer = DosTracer::new();
+        cpu.run_with_callback(|cpu| tracer.step(cpu));
+
+        let calls = tracer.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].kind, DosCallKind::FileManager);
+        assert_eq!(calls[0].pc, 0x1000);
+        assert_eq!(calls[0].inputs, "range=$2000-$200F length=16");
+        assert_eq!(calls[0].result.as_ref().unwrap().error, 4);
+
+        assert_eq!(calls[1].kind, DosCallKind::Rwts);
+        assert_eq!(calls[1].pc, 0xab01);
+        assert_eq!(
+            calls[1].to_text().split_once(" RWTS").unwrap().1,
+            format!(
+                " READ ($B7E8) slot=6 drive=1 volume=0 track=17 sector=0 buffer=$2000 -> volume=254 OK ({} cycles)",
+                calls[1].result.as_ref().unwrap().cycle - calls[1].cycle
+            )
+        );
+        assert!(
+            tracer.to_text().contains(
+                "FM WRITE ($B5BB) range=$2000-$200F length=16 -> ERR $04 Write protected"
+            )
+        );
+    }
+
+    #[test]
+    fn recording() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+        cpu.load(&RWTS_SIGNATURE, RWTS_ENTRY);
+        cpu.program_counter = RWTS_ENTRY;
+
+        let mut tracer = DosTracer::new();
+        tracer.step(&mut cpu);
+        assert_eq!(tracer.calls().len(), 1);
+
+        // The calls are kept when the recording is stopped
+        tracer.set_recording(false);
+        tracer.step(&mut cpu);
+        assert!(!tracer.is_recording());
+        assert_eq!(tracer.calls().len(), 1);
+
+        tracer.set_recording(true);
+        tracer.step(&mut cpu);
+        assert_eq!(tracer.calls().len(), 2);
+    }
+}
@@ -1,5 +1,7 @@
 use crate::bus::{Card, Tick};
+use crate::diskimage::{DiskCopy42, IMG_2MG_FORMAT_PRODOS, Image2mg, ImageHeader};
 use crate::mmu::Mmu;
+use crate::overlay::{self, DELTA_FORMAT_INDEX, DELTA_WRITE_PROTECT_INDEX, DeltaEntry};
 use crate::video::Video;
 use std::ffi::OsStr;
 use std::fs::OpenOptions;
@@ -10,29 +12,112 @@ use std::path::PathBuf;
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
 
-// Fifo index hard disk firmware
+// Fifo index hard disk firmware. SmartPort calls (including extended calls) pass the
+// parameter list to the card, which decodes it and executes the command
 const ROM: [u8; 256] = [
     0xa9, 0x20, 0xa9, 0x00, 0xc9, 0x03, 0xa9, 0x00, 0x90, 0x40, 0x38, 0xb0, 0x01, 0x18, 0x08, 0x78,
     0xa5, 0x00, 0xa2, 0x60, 0x86, 0x00, 0x20, 0x00, 0x00, 0x85, 0x00, 0xba, 0xbd, 0x00, 0x01, 0x0a,
     0x0a, 0x0a, 0x0a, 0x8d, 0x78, 0x04, 0x28, 0xb0, 0x55, 0xa5, 0x3c, 0x48, 0xa5, 0x3d, 0x48, 0xbd,
-    0x02, 0x01, 0x85, 0x3c, 0x69, 0x03, 0x9d, 0x02, 0x01, 0xbd, 0x03, 0x01, 0x85, 0x3d, 0x69, 0x00,
-    0x9d, 0x03, 0x01, 0xa0, 0x01, 0xd0, 0x65, 0x4c, 0xba, 0xfa, 0x2c, 0x61, 0xc0, 0x30, 0xf8, 0x20,
+    0x02, 0x01, 0x85, 0x3c, 0xbd, 0x03, 0x01, 0x85, 0x3d, 0xa0, 0x01, 0xd0, 0x6f, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x4c, 0xba, 0xfa, 0x2c, 0x61, 0xc0, 0x30, 0xf8, 0x20,
     0x58, 0xff, 0xba, 0xbd, 0x00, 0x01, 0x0a, 0x0a, 0x0a, 0x0a, 0x8d, 0x78, 0x04, 0xaa, 0x9d, 0x83,
     0xc0, 0xa9, 0x00, 0x9d, 0x82, 0xc0, 0xbd, 0x80, 0xc0, 0x4a, 0xb0, 0xdb, 0xa9, 0x01, 0x85, 0x42,
     0x86, 0x43, 0xa9, 0x00, 0x85, 0x44, 0x85, 0x46, 0x85, 0x47, 0xa9, 0x08, 0x85, 0x45, 0x08, 0xae,
     0x78, 0x04, 0xa0, 0x00, 0xb9, 0x42, 0x00, 0x9d, 0x89, 0xc0, 0xc8, 0xc0, 0x06, 0x90, 0xf5, 0xbd,
     0x80, 0xc0, 0x30, 0xfb, 0x28, 0xb0, 0x06, 0x4a, 0xb0, 0xad, 0x4c, 0x01, 0x08, 0x4a, 0xa4, 0x42,
-    0xd0, 0x09, 0x48, 0xbc, 0x8a, 0xc0, 0xbd, 0x89, 0xc0, 0xaa, 0x68, 0x60, 0xb1, 0x3c, 0xc9, 0x03,
-    0xf0, 0x02, 0xb0, 0x34, 0xae, 0x78, 0x04, 0x9d, 0x8a, 0xc0, 0xc8, 0xb1, 0x3c, 0x48, 0xc8, 0xb1,
-    0x3c, 0x85, 0x3d, 0x68, 0x85, 0x3c, 0xa0, 0x00, 0xb1, 0x3c, 0xb0, 0x06, 0xc9, 0x03, 0xf0, 0x06,
-    0xd0, 0x19, 0xc9, 0x01, 0xd0, 0x15, 0xc8, 0xb1, 0x3c, 0x9d, 0x8a, 0xc0, 0xc0, 0x06, 0x90, 0xf6,
-    0xbd, 0x80, 0xc0, 0x30, 0xfb, 0x4a, 0xaa, 0x2c, 0xa2, 0x01, 0x2c, 0xa2, 0x04, 0x68, 0x85, 0x3d,
-    0x68, 0x85, 0x3c, 0x8a, 0xa2, 0x00, 0xa0, 0x02, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x0a,
+    0xd0, 0x09, 0x48, 0xbc, 0x8a, 0xc0, 0xbd, 0x89, 0xc0, 0xaa, 0x68, 0x60, 0xb1, 0x3c, 0x29, 0x40,
+    0xf0, 0x02, 0xa9, 0x02, 0x69, 0x03, 0x7d, 0x02, 0x01, 0x9d, 0x02, 0x01, 0x90, 0x03, 0xfe, 0x03,
+    0x01, 0xae, 0x78, 0x04, 0xb1, 0x3c, 0x9d, 0x8b, 0xc0, 0xc8, 0xb1, 0x3c, 0x9d, 0x8c, 0xc0, 0xc8,
+    0xb1, 0x3c, 0x9d, 0x8d, 0xc0, 0xbd, 0x8e, 0xc0, 0xaa, 0x68, 0x85, 0x3d, 0x68, 0x85, 0x3c, 0x8a,
+    0xc9, 0x01, 0xa2, 0x00, 0xa0, 0x02, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0xff, 0x0a,
 ];
 
+/*
+Firmware of the cards that service the ProDOS and SmartPort calls through trap registers
+(CFFA and SCSI). The trap register n is accessed as $C080+n,Y with Y = slot * 16
+
+    Cn00    LDA #$20 / LDA #$00 / CMP #$03 / LDA #$00  ProDOS and SmartPort signature
+    Cn08    BCC BOOT
+    Cn0A    SEC                 ProDOS entry ($CnFF)
+    Cn0B    BCS COMMON
+    Cn0D    CLC                 SmartPort entry (ProDOS entry + 3)
+    Cn0E    PHP / SEI / JSR IORTS / TSX / LDA $0100,X / ASL x4 / TAY / PLP
+    Cn1D    BCS PRODOS
+    Cn1F    TXA / STA SMARTPORT,Y  SmartPort trap with the stack pointer
+    Cn23    BCC RESULT
+    Cn25    STA PRODOS,Y        ProDOS block call trap with the parameters at $42-$47
+    Cn28    LDX RESULT_LOW,Y / LDA PRODOS,Y / PHA / LDA RESULT_HIGH,Y / TAY / PLA
+    Cn34    CMP #$01 / RTS      Carry set when the error code is not 0
+    Cn37    BOOT: read block 0 of unit slot * 16 to $0800 with the ProDOS trap
+    Cn5D    LDX $43 / JMP $0801
+    Cn62    JMP $FABA           Boot the next slot
+
+    CnFB    $80                 SmartPort ID (extended calls supported)
+    CnFC    $0000               Block count from the status call
+    CnFE    $BF                 Status byte
+    CnFF    $0A                 Low byte of the ProDOS entry
+*/
+const TRAP_ROM: [u8; 0x65] = [
+    0xa9, 0x20, 0xa9, 0x00, 0xc9, 0x03, 0xa9, 0x00, 0x90, 0x2d, 0x38, 0xb0, 0x01, 0x18, 0x08, 0x78,
+    0x20, 0x58, 0xff, 0xba, 0xbd, 0x00, 0x01, 0x0a, 0x0a, 0x0a, 0x0a, 0xa8, 0x28, 0xb0, 0x06, 0x8a,
+    0x99, 0x80, 0xc0, 0x90, 0x03, 0x99, 0x80, 0xc0, 0xbe, 0x80, 0xc0, 0xb9, 0x80, 0xc0, 0x48, 0xb9,
+    0x80, 0xc0, 0xa8, 0x68, 0xc9, 0x01, 0x60, 0x20, 0x58, 0xff, 0xba, 0xbd, 0x00, 0x01, 0x0a, 0x0a,
+    0x0a, 0x0a, 0xa8, 0x85, 0x43, 0xa9, 0x01, 0x85, 0x42, 0xa9, 0x00, 0x85, 0x44, 0x85, 0x46, 0x85,
+    0x47, 0xa9, 0x08, 0x85, 0x45, 0x99, 0x80, 0xc0, 0xb9, 0x80, 0xc0, 0xd0, 0x05, 0xa6, 0x43, 0x4c,
+    0x01, 0x08, 0x4c, 0xba, 0xfa,
+];
+
+// Offsets of the low byte of the trap register addresses in TRAP_ROM
+const TRAP_ROM_SMARTPORT: [usize; 1] = [0x21];
+const TRAP_ROM_PRODOS: [usize; 4] = [0x26, 0x2c, 0x56, 0x59];
+const TRAP_ROM_RESULT_LOW: [usize; 1] = [0x29];
+const TRAP_ROM_RESULT_HIGH: [usize; 1] = [0x30];
+
+/// Build the slot ROM of a card using the trap registers. The ProDOS register returns
+/// the error code, the result registers return the block count
+pub(crate) const fn trap_rom(
+    smartport: u8,
+    prodos: u8,
+    result_low: u8,
+    result_high: u8,
+) -> [u8; 256] {
+    let mut rom = [0u8; 256];
+    let mut i = 0;
+    while i < TRAP_ROM.len() {
+        rom[i] = TRAP_ROM[i];
+        i += 1;
+    }
+
+    let registers: [(&[usize], u8); 4] = [
+        (&TRAP_ROM_SMARTPORT, smartport),
+        (&TRAP_ROM_PRODOS, prodos),
+        (&TRAP_ROM_RESULT_LOW, result_low),
+        (&TRAP_ROM_RESULT_HIGH, result_high),
+    ];
+    let mut r = 0;
+    while r < registers.len() {
+        let (offsets, register) = registers[r];
+        let mut j = 0;
+        while j < offsets.len() {
+            rom[offsets[j]] = 0x80 | register;
+            j += 1;
+        }
+        r += 1;
+    }
+
+    rom[0xfb] = 0x80;
+    rom[0xfe] = 0xbf;
+    rom[0xff] = 0x0a;
+    rom
+}
+
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const HD_ID_STRING: &str = "emu6502 SP";
 
+/// Number of SmartPort units supported by the controller
+pub const HD_MAX_UNITS: usize = 8;
+
 const DISK_BLOCK_MAX: u32 = 0x007fffff;
 const HD_BLOCK_SIZE: usize = 512;
 const CYCLES_FOR_RW_BLOCK: usize = HD_BLOCK_SIZE;
@@ -51,6 +136,24 @@ const SMARTPORT_CMD_BUSY_STATUS: u8 = 0xbf;
 const SMARTPORT_STATUS: u8 = 0x00;
 const SMARTPORT_STATUS_GETDIB: u8 = 0x03;
 
+// SmartPort commands in the parameter list. Extended commands have bit 6 set
+const SP_CALL_STATUS: u8 = 0x00;
+const SP_CALL_READBLOCK: u8 = 0x01;
+const SP_CALL_WRITEBLOCK: u8 = 0x02;
+const SP_CALL_FORMAT: u8 = 0x03;
+const SP_CALL_CONTROL: u8 = 0x04;
+const SP_CALL_INIT: u8 = 0x05;
+const SP_CALL_EXTENDED: u8 = 0x40;
+
+const SP_ERROR_BAD_COMMAND: u8 = 0x01;
+const SP_ERROR_BAD_PCOUNT: u8 = 0x04;
+
+// Registers used to execute the firmware calls trapped by other mass storage cards
+const HD_EXECUTE: u16 = 0x00;
+const HD_COMMAND: u16 = 0x02;
+const HD_SP_COMMAND: u16 = 0x0b;
+const HD_SP_EXECUTE: u16 = 0x0e;
+
 /*
 Memory map for hard disk (derived from AppleWin)
 https://github.com/AppleWin/AppleWin/blob/master/source/Harddisk.cpp
@@ -97,6 +200,16 @@ Memory map SmartPort device (IO addr + s*$10):
     C08A	(r)   HIGH BYTE OF DISK IMAGE SIZE IN BLOCKS
     C089	(w)   a 6-deep FIFO to write: command, unitNum, memPtr(2), blockNum(2)
     C08A	(w)   a 7-deep FIFO to write: command, unitNum, memPtr(2), blockNum(3); first byte gets OR'd with $80 (ie. to indicate it's an SP command)
+    C08B	(r/w) SMARTPORT CALL COMMAND : standard ($00-$05) or extended ($40-$45) command
+    C08C	(r/w) LOW BYTE OF SMARTPORT CALL PARAMETER LIST
+    C08D	(r/w) HIGH BYTE OF SMARTPORT CALL PARAMETER LIST
+    C08E	(r)   EXECUTE SMARTPORT CALL AND RETURN ERROR CODE
+
+Block mode unit number DSSS0000 selects drive 1/2 with D. If SSS is not the slot of the
+card (ProDOS remapped SmartPort units), drive 3/4 is selected.
+
+SmartPort mode supports up to 8 units. The extended calls use 4-byte buffer pointer and
+block number. Only the lower 16 bits of the buffer and 24 bits of the block are used.
 
 */
 
@@ -112,6 +225,10 @@ struct Disk {
     #[cfg_attr(feature = "serde_support", educe(Debug(ignore)))]
     raw_data: Vec<u8>,
 
+    #[cfg_attr(feature = "serde_support", serde(skip))]
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    image_header: ImageHeader,
+
     write_protect: bool,
     filename: Option<String>,
     loaded: bool,
@@ -121,12 +238,17 @@ struct Disk {
     mem_block: u16,
     disk_block: u32,
     busy_cycle: usize,
+
+    // Set when the disk is inserted or ejected, cleared after SmartPort status is read
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    disk_switched: bool,
 }
 
 impl Disk {
     pub fn new() -> Self {
         Disk {
             raw_data: vec![0u8; 0],
+            image_header: ImageHeader::None,
             write_protect: false,
             filename: None,
             loaded: false,
@@ -136,6 +258,7 @@ impl Disk {
             mem_block: 0,
             disk_block: 0,
             busy_cycle: 0,
+            disk_switched: false,
         }
     }
 }
@@ -163,6 +286,15 @@ pub struct HardDisk {
 
     #[cfg_attr(feature = "serde_support", serde(default))]
     fifo_index: u8,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    overlay: bool,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    sp_command: u8,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    sp_param_list: u16,
 }
 
 #[repr(u8)]
@@ -177,7 +309,7 @@ pub enum DeviceStatus {
 
 impl HardDisk {
     pub fn new() -> Self {
-        let drive = vec![Disk::default(), Disk::default()];
+        let drive = (0..HD_MAX_UNITS).map(|_| Disk::default()).collect();
         HardDisk {
             drive,
             drive_select: 0,
@@ -187,6 +319,9 @@ impl HardDisk {
             status_code: 0,
             smartport: false,
             fifo_index: 0,
+            overlay: false,
+            sp_command: 0,
+            sp_param_list: 0,
         }
     }
 
@@ -216,22 +351,104 @@ impl HardDisk {
     }
 
     pub fn get_disk_filename(&self, drive: usize) -> Option<String> {
-        let disk = &self.drive[drive];
-        disk.filename.to_owned()
+        self.drive
+            .get(drive)
+            .and_then(|disk| disk.filename.to_owned())
     }
 
     pub fn set_enable_save_disk(&mut self, value: bool) {
         self.enable_save = value;
     }
 
+    /// When overlay mode is enabled, writes are stored in a sidecar delta file and the
+    /// original image is not modified until the overlay is committed
+    pub fn set_overlay_mode(&mut self, state: bool) {
+        self.overlay = state;
+    }
+
+    pub fn is_overlay_mode(&self) -> bool {
+        self.overlay
+    }
+
+    pub fn has_overlay(&self, drive: usize) -> bool {
+        self.drive[drive]
+            .filename
+            .as_ref()
+            .is_some_and(overlay::has_delta)
+    }
+
+    /// Write the current content of the drive back to the original image and remove
+    /// the overlay file
+    pub fn commit_overlay(&mut self, drive: usize) -> io::Result<()> {
+        let disk = &mut self.drive[drive];
+        if disk.write_protect {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Drive is write protected",
+            ));
+        }
+
+        if let Some(filename) = &disk.filename {
+            std::fs::write(filename, &disk.raw_data)?;
+            overlay::remove_delta(filename)?;
+        }
+        Ok(())
+    }
+
+    /// Discard the overlay file and reload the original image
+    pub fn revert_overlay(&mut self, drive: usize) -> io::Result<()> {
+        let Some(filename) = self.drive[drive].filename.clone() else {
+            return Ok(());
+        };
+
+        overlay::remove_delta(&filename)?;
+
+        let drive_selected = self.drive_select;
+        self.drive_select = drive;
+        let result = self.load_hdv_2mg_file(&filename);
+        self.drive_select = drive_selected;
+        result
+    }
+
+    fn apply_overlay<P>(&mut self, filename_path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let entries = overlay::read_delta(filename_path)?;
+        let disk = &mut self.drive[self.drive_select];
+
+        for entry in entries {
+            match entry.index {
+                DELTA_FORMAT_INDEX => {
+                    disk.raw_data[disk.offset..disk.offset + disk.data_len].fill(0);
+                }
+                DELTA_WRITE_PROTECT_INDEX => {
+                    if let Some(&state) = entry.data.first() {
+                        Self::update_write_protect(disk, state != 0);
+                    }
+                }
+                _ => {
+                    let block_offset = entry.index as usize * HD_BLOCK_SIZE;
+                    if entry.data.len() == HD_BLOCK_SIZE
+                        && block_offset + HD_BLOCK_SIZE <= disk.data_len
+                    {
+                        let start = disk.offset + block_offset;
+                        disk.raw_data[start..start + HD_BLOCK_SIZE].copy_from_slice(&entry.data);
+                    }
+                }
+            }
+        }
+        Self::update_dc42_checksum(disk);
+        Ok(())
+    }
+
     pub fn set_loaded(&mut self, state: bool) {
         let disk = &mut self.drive[self.drive_select];
         disk.loaded = state;
     }
 
     pub fn is_loaded(&self, drive: usize) -> bool {
-        let disk = &self.drive[drive];
-        disk.loaded
+        self.drive.get(drive).is_some_and(|disk| disk.loaded)
     }
 
     pub fn set_smartport(&mut self, state: bool) {
@@ -243,37 +460,224 @@ impl HardDisk {
     }
 
     pub fn drive_select(&mut self, drive: usize) {
-        self.drive_select = drive;
+        // Save state from older version only contains two drives
+        if drive >= self.drive.len() && drive < HD_MAX_UNITS {
+            self.drive.resize_with(HD_MAX_UNITS, Disk::default);
+        }
+        self.drive_select = drive % self.drive.len();
+    }
+
+    /// Number of units in the SmartPort controller
+    pub fn num_units(&self) -> usize {
+        self.drive.len()
     }
 
     pub fn drive_selected(&self) -> usize {
         self.drive_select
     }
 
+    /// Number of blocks in the drive, or 0 if no disk is loaded
+    pub fn num_blocks(&self, drive: usize) -> usize {
+        self.drive
+            .get(drive)
+            .filter(|disk| disk.loaded)
+            .map_or(0, |disk| disk.data_len / HD_BLOCK_SIZE)
+    }
+
+    /// ProDOS error code of the last command of the selected drive
+    pub(crate) fn error(&self) -> u8 {
+        self.drive[self.drive_select].error
+    }
+
+    /// Executes the ProDOS block call with the parameters at $42-$47 through the
+    /// registers at io_base. Returns the error code and the number of blocks of the drive
+    pub(crate) fn firmware_prodos_call(
+        &mut self,
+        mmu: &mut Mmu,
+        video: &mut Video,
+        io_base: u16,
+    ) -> (u8, u16) {
+        for (i, addr) in (0x42..0x48).enumerate() {
+            let value = mmu.unclocked_addr_read(addr);
+            self.io_access(mmu, video, io_base + HD_COMMAND + i as u16, value, true);
+        }
+        self.io_access(mmu, video, io_base + HD_EXECUTE, 0, false);
+
+        let blocks = self.num_blocks(self.drive_select).min(0xffff) as u16;
+        (self.error(), blocks)
+    }
+
+    /// Executes the SmartPort call of the JSR to the SmartPort entry. sp is the stack
+    /// pointer in the entry. The command and the parameter list follow the JSR, the
+    /// return address on the stack is updated to skip over them. Returns the error code
+    pub(crate) fn firmware_smartport_call(
+        &mut self,
+        mmu: &mut Mmu,
+        video: &mut Video,
+        io_base: u16,
+        sp: u8,
+    ) -> u8 {
+        let stack_addr = |offset: u8| 0x100 | sp.wrapping_add(offset) as u16;
+        let return_addr = u16::from_le_bytes([
+            mmu.unclocked_addr_read(stack_addr(2)),
+            mmu.unclocked_addr_read(stack_addr(3)),
+        ]);
+
+        let command = mmu.unclocked_addr_read(return_addr.wrapping_add(1));
+        let param_size = if command & SP_CALL_EXTENDED != 0 {
+            4
+        } else {
+            2
+        };
+        for i in 0..3 {
+            let value = mmu.unclocked_addr_read(return_addr.wrapping_add(1 + i));
+            self.io_access(mmu, video, io_base + HD_SP_COMMAND + i, value, true);
+        }
+
+        let [low, high] = return_addr.wrapping_add(1 + param_size).to_le_bytes();
+        mmu.unclocked_addr_write(stack_addr(2), low);
+        mmu.unclocked_addr_write(stack_addr(3), high);
+
+        self.io_access(mmu, video, io_base + HD_SP_EXECUTE, 0, false)
+    }
+
     pub fn eject(&mut self, drive_select: usize) {
-        let disk = &mut self.drive[drive_select];
+        let Some(disk) = self.drive.get_mut(drive_select) else {
+            return;
+        };
+        disk.disk_switched = disk.loaded;
         disk.loaded = false;
         disk.write_protect = false;
         disk.filename = None;
         disk.raw_data = vec![0u8; 0];
+        disk.image_header = ImageHeader::None;
+        disk.offset = 0;
         disk.data_len = 0;
         disk.error = 0;
     }
 
+    pub fn get_image_header(&self, drive: usize) -> &ImageHeader {
+        &self.drive[drive].image_header
+    }
+
+    pub fn is_write_protected(&self, drive: usize) -> bool {
+        self.drive[drive].write_protect
+    }
+
+    /// Set the write protect state of the drive. For 2mg image, the locked flag in
+    /// the header is updated and written back to the image. In overlay mode, the state
+    /// is stored in the overlay file instead
+    pub fn set_write_protect(&mut self, drive: usize, state: bool) -> io::Result<()> {
+        let enable_save = self.enable_save;
+        let disk = &mut self.drive[drive];
+        let flags = Self::update_write_protect(disk, state);
+
+        if self.overlay {
+            if let Some(filename) = &disk.filename {
+                let entry = DeltaEntry {
+                    index: DELTA_WRITE_PROTECT_INDEX,
+                    bits: 0,
+                    data: vec![state as u8],
+                };
+                overlay::append_delta(filename, &[entry])?;
+            }
+        } else if enable_save
+            && let Some(flags) = flags
+            && let Some(filename) = &disk.filename
+        {
+            let mut f = OpenOptions::new().write(true).open(filename)?;
+            f.seek(SeekFrom::Start(0x10))?;
+            f.write_all(&flags)?;
+        }
+        Ok(())
+    }
+
+    // Returns the updated flags of the 2mg header
+    fn update_write_protect(disk: &mut Disk, state: bool) -> Option<[u8; 4]> {
+        disk.write_protect = state;
+        if let ImageHeader::Img2mg(header) = &mut disk.image_header {
+            header.set_write_protected(state);
+            let flags = header.flags.to_le_bytes();
+            disk.raw_data[0x10..0x14].copy_from_slice(&flags);
+            Some(flags)
+        } else {
+            None
+        }
+    }
+
+    /// Save the drive content to a new image. The image format is determined by the
+    /// extension (2mg, dc/dc42 or raw hdv/po). The metadata of the loaded image is
+    /// preserved when saving to the same format
+    pub fn save_disk_image_as<P>(&self, drive: usize, filename_path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let filename = filename_path.as_ref();
+        let disk = &self.drive[drive];
+
+        if !disk.loaded || disk.data_len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No disk loaded in drive",
+            ));
+        }
+
+        let data = &disk.raw_data[disk.offset..disk.offset + disk.data_len];
+        let header = match filename.extension() {
+            Some(ext)
+                if ext.eq_ignore_ascii_case(OsStr::new("2mg"))
+                    || ext.eq_ignore_ascii_case(OsStr::new("2img")) =>
+            {
+                if let ImageHeader::Img2mg(header) = &disk.image_header {
+                    ImageHeader::Img2mg(header.clone())
+                } else {
+                    let mut header = Image2mg::new(IMG_2MG_FORMAT_PRODOS);
+                    header.set_write_protected(disk.write_protect);
+                    ImageHeader::Img2mg(header)
+                }
+            }
+            Some(ext)
+                if ext.eq_ignore_ascii_case(OsStr::new("dc"))
+                    || ext.eq_ignore_ascii_case(OsStr::new("dc42")) =>
+            {
+                if let ImageHeader::Dc42(header) = &disk.image_header {
+                    ImageHeader::Dc42(header.clone())
+                } else {
+                    let name = filename
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    ImageHeader::Dc42(DiskCopy42::new(&name, data.len())?)
+                }
+            }
+            _ => ImageHeader::None,
+        };
+
+        std::fs::write(filename, header.build(data))
+    }
+
     pub fn load_hdv_2mg_file<P>(&mut self, filename_path: P) -> io::Result<()>
     where
         P: AsRef<Path>,
     {
         let filename = filename_path.as_ref();
         let hdv_mode = if let Some(extension) = filename.extension() {
-            !extension.eq_ignore_ascii_case(OsStr::new("2mg"))
+            !(extension.eq_ignore_ascii_case(OsStr::new("2mg"))
+                || extension.eq_ignore_ascii_case(OsStr::new("2img"))
+                || extension.eq_ignore_ascii_case(OsStr::new("dc"))
+                || extension.eq_ignore_ascii_case(OsStr::new("dc42")))
         } else {
             true
         };
         let dsk = std::fs::read(filename)?;
         let metadata = std::fs::metadata(filename)?;
         let write_protect = metadata.permissions().readonly();
-        self.load_hdv_2mg_array(&dsk, hdv_mode, write_protect)
+        self.load_hdv_2mg_array(&dsk, hdv_mode, write_protect)?;
+
+        if self.overlay {
+            self.apply_overlay(filename)?;
+        }
+        Ok(())
     }
 
     pub fn load_hdv_2mg_array(
@@ -282,20 +686,22 @@ impl HardDisk {
         hdv_mode: bool,
         write_protect: bool,
     ) -> io::Result<()> {
+        // DiskCopy 4.2 images may use the generic .dsk / .hdv extension
+        let (image_header, offset, data_len) = if !hdv_mode || DiskCopy42::is_dc42(dsk) {
+            parse_image_header(dsk)?
+        } else {
+            (ImageHeader::None, 0, dsk.len())
+        };
+
         let disk = &mut self.drive[self.drive_select];
         disk.raw_data = vec![0; dsk.len()];
         disk.raw_data[..].copy_from_slice(dsk);
-        disk.offset = 0;
+        disk.disk_switched = true;
         disk.error = 0;
-        disk.data_len = dsk.len();
-        disk.write_protect = write_protect;
-
-        if !hdv_mode {
-            (disk.offset, disk.data_len) = parse_2mg_array(dsk)?;
-            if dsk.len() >= 0x14 && dsk[0x13] & 0x80 > 0 {
-                disk.write_protect = true
-            }
-        }
+        disk.offset = offset;
+        disk.data_len = data_len;
+        disk.write_protect = write_protect || image_header.is_write_protected();
+        disk.image_header = image_header;
         Ok(())
     }
 
@@ -444,6 +850,12 @@ impl HardDisk {
                         general_status |= 1 << 2;
                     }
 
+                    // Report the disk switched once after hot-swapping the disk
+                    if disk.disk_switched {
+                        general_status |= 1;
+                        disk.disk_switched = false;
+                    }
+
                     Self::write_data_to_mmu(mmu, video, disk.mem_block, general_status);
                     Self::write_data_to_mmu(mmu, video, disk.mem_block + 1, low_size);
                     Self::write_data_to_mmu(mmu, video, disk.mem_block + 2, high_size);
@@ -517,17 +929,14 @@ impl HardDisk {
             disk.disk_block
         };
 
-        let block_offset = disk_block as usize * HD_BLOCK_SIZE;
-        let start = block_offset + disk.offset;
-        let end = block_offset + disk.offset + HD_BLOCK_SIZE;
-
-        //eprintln!("Reading ${:04x} ${:04x} ${:04x}", block_offset, start, end);
+        let mem_block = disk.mem_block;
 
-        if block_offset + HD_BLOCK_SIZE <= disk.data_len {
+        if let Some(data) = self.read_block(self.drive_select, disk_block) {
             let mut buf = [0u8; HD_BLOCK_SIZE];
-            buf[..].copy_from_slice(&disk.raw_data[start..end]);
+            buf[..].copy_from_slice(data);
+            let disk = &mut self.drive[self.drive_select];
             for (i, data) in buf.iter().enumerate() {
-                let addr = disk.mem_block.wrapping_add(i as u16);
+                let addr = mem_block.wrapping_add(i as u16);
 
                 if (0xc000..=0xcfff).contains(&addr) {
                     disk.error = DeviceStatus::DeviceIoError as u8;
@@ -538,10 +947,22 @@ impl HardDisk {
             disk.error = DeviceStatus::DeviceOk as u8;
             disk.busy_cycle = CYCLES_FOR_RW_BLOCK;
         } else {
-            disk.error = DeviceStatus::DeviceIoError as u8;
+            self.drive[self.drive_select].error = DeviceStatus::DeviceIoError as u8;
         }
     }
 
+    /// Returns the data of the block in the drive, or None if the block is outside of
+    /// the disk image
+    pub(crate) fn read_block(&self, drive: usize, disk_block: u32) -> Option<&[u8]> {
+        let disk = self.drive.get(drive)?;
+        let block_offset = disk_block as usize * HD_BLOCK_SIZE;
+        if block_offset + HD_BLOCK_SIZE > disk.data_len {
+            return None;
+        }
+        let start = block_offset + disk.offset;
+        Some(&disk.raw_data[start..start + HD_BLOCK_SIZE])
+    }
+
     fn block_cmd_write(&mut self, mmu: &mut Mmu, _video: &mut Video) {
         let disk = &mut self.drive[self.drive_select];
         if disk.write_protect {
@@ -555,15 +976,6 @@ impl HardDisk {
             disk.disk_block
         };
 
-        let block_offset = disk_block as usize * HD_BLOCK_SIZE;
-        let start = block_offset + disk.offset;
-        let end = block_offset + disk.offset + HD_BLOCK_SIZE;
-
-        if block_offset + HD_BLOCK_SIZE > disk.data_len {
-            disk.error = DeviceStatus::DeviceIoError as u8;
-            return;
-        }
-
         let mut buf = [0u8; HD_BLOCK_SIZE];
 
         for (i, item) in buf.iter_mut().enumerate() {
@@ -575,7 +987,41 @@ impl HardDisk {
             *item = mmu.unclocked_addr_read(addr);
         }
 
-        if self.enable_save {
+        let error = self.write_block(self.drive_select, disk_block, &buf);
+        self.drive[self.drive_select].error = error;
+    }
+
+    /// Writes the block to the drive and saves it to the image file or the overlay.
+    /// Returns the ProDOS error code
+    pub(crate) fn write_block(&mut self, drive: usize, disk_block: u32, buf: &[u8]) -> u8 {
+        let Some(disk) = self.drive.get_mut(drive) else {
+            return DeviceStatus::DeviceNotConnected as u8;
+        };
+        if disk.write_protect {
+            return DeviceStatus::DeviceWriteProtected as u8;
+        }
+
+        let block_offset = disk_block as usize * HD_BLOCK_SIZE;
+        let start = block_offset + disk.offset;
+        let end = block_offset + disk.offset + HD_BLOCK_SIZE;
+
+        if buf.len() != HD_BLOCK_SIZE || block_offset + HD_BLOCK_SIZE > disk.data_len {
+            return DeviceStatus::DeviceIoError as u8;
+        }
+
+        if self.overlay {
+            // Store the block in the overlay file and keep the original image intact
+            if let Some(filename) = &disk.filename {
+                let entry = DeltaEntry {
+                    index: disk_block,
+                    bits: 0,
+                    data: buf.to_vec(),
+                };
+                if overlay::append_delta(filename, &[entry]).is_err() {
+                    return DeviceStatus::DeviceIoError as u8;
+                }
+            }
+        } else if self.enable_save {
             // Try to write the block to disk
             // If failed, don't update the memory copy
             if let Some(filename) = &disk.filename {
@@ -585,8 +1031,7 @@ impl HardDisk {
                         || end as u64 > metadata.len()
                         || metadata.len() == 0
                     {
-                        disk.error = DeviceStatus::DeviceIoError as u8;
-                        return;
+                        return DeviceStatus::DeviceIoError as u8;
                     }
                 }
 
@@ -594,23 +1039,53 @@ impl HardDisk {
                     Ok(mut f) => {
                         let result = f
                             .seek(SeekFrom::Start(start as u64))
-                            .and_then(|_| f.write_all(&buf));
+                            .and_then(|_| f.write_all(buf));
                         if result.is_err() {
-                            disk.error = DeviceStatus::DeviceIoError as u8;
-                            return;
+                            return DeviceStatus::DeviceIoError as u8;
                         }
                     }
                     _ => {
                         eprintln!("Unable to open {filename}");
-                        disk.error = DeviceStatus::DeviceIoError as u8;
-                        return;
+                        return DeviceStatus::DeviceIoError as u8;
                     }
                 }
             }
         }
 
-        disk.error = DeviceStatus::DeviceOk as u8;
-        disk.raw_data[start..end].copy_from_slice(&buf);
+        disk.raw_data[start..end].copy_from_slice(buf);
+        if self.enable_save && !self.overlay {
+            if Self::save_dc42_checksum(disk).is_err() {
+                return DeviceStatus::DeviceIoError as u8;
+            }
+        } else {
+            Self::update_dc42_checksum(disk);
+        }
+        DeviceStatus::DeviceOk as u8
+    }
+
+    // DiskCopy 4.2 stores the checksum of the whole disk data in the header. It is
+    // updated after each write so that the image file is always valid
+    fn update_dc42_checksum(disk: &mut Disk) -> Option<(usize, [u8; 4])> {
+        if !matches!(disk.image_header, ImageHeader::Dc42(_)) {
+            return None;
+        }
+
+        let (field, value) =
+            DiskCopy42::checksum_field(&disk.raw_data[disk.offset..disk.offset + disk.data_len]);
+        disk.raw_data[field..field + value.len()].copy_from_slice(&value);
+        Some((field, value))
+    }
+
+    // Update the DiskCopy 4.2 checksum and write it to the image file
+    fn save_dc42_checksum(disk: &mut Disk) -> io::Result<()> {
+        if let Some((field, value)) = Self::update_dc42_checksum(disk)
+            && let Some(filename) = &disk.filename
+        {
+            let mut f = OpenOptions::new().write(true).open(filename)?;
+            f.seek(SeekFrom::Start(field as u64))?;
+            f.write_all(&value)?;
+        }
+        Ok(())
     }
 
     fn block_cmd_format(&mut self) {
@@ -625,11 +1100,23 @@ impl HardDisk {
             return;
         }
 
-        for i in 0..disk.raw_data.len() {
-            disk.raw_data[i] = 0;
-        }
+        // Only clear the disk data to keep the image header and metadata intact
+        disk.raw_data[disk.offset..disk.offset + disk.data_len].fill(0);
+        Self::update_dc42_checksum(disk);
 
-        if self.enable_save
+        if self.overlay {
+            if let Some(filename) = &disk.filename {
+                let entry = DeltaEntry {
+                    index: DELTA_FORMAT_INDEX,
+                    bits: 0,
+                    data: Vec::new(),
+                };
+                if overlay::append_delta(filename, &[entry]).is_err() {
+                    disk.error = DeviceStatus::DeviceIoError as u8;
+                    return;
+                }
+            }
+        } else if self.enable_save
             && let Some(filename) = &disk.filename
         {
             match OpenOptions::new().write(true).open(filename) {
@@ -696,6 +1183,85 @@ impl HardDisk {
 
         self.block_cmd_status()
     }
+
+    // Decode the SmartPort parameter list in memory and execute the command. Returns the
+    // SmartPort error code
+    fn smartport_call(&mut self, mmu: &mut Mmu, video: &mut Video) -> u8 {
+        let param_list = self.sp_param_list;
+        let read_param = |offset: u16| mmu.unclocked_addr_read(param_list.wrapping_add(offset));
+        let read_param_u16 =
+            |offset: u16| u16::from_le_bytes([read_param(offset), read_param(offset + 1)]);
+
+        let extended = self.sp_command & SP_CALL_EXTENDED != 0;
+        let call = self.sp_command & !SP_CALL_EXTENDED;
+        let param_count = read_param(0);
+        let unit = read_param(1);
+        let buffer = read_param_u16(2);
+
+        // Extended calls use 4-byte buffer pointer
+        let param_offset = if extended { 6 } else { 4 };
+
+        let expected_count = match call {
+            SP_CALL_STATUS | SP_CALL_READBLOCK | SP_CALL_WRITEBLOCK | SP_CALL_CONTROL => 3,
+            SP_CALL_FORMAT | SP_CALL_INIT => 1,
+            _ => return SP_ERROR_BAD_COMMAND,
+        };
+
+        if param_count != expected_count {
+            return SP_ERROR_BAD_PCOUNT;
+        }
+
+        // Only status and init calls are supported for the controller (unit 0)
+        if unit as usize > self.drive.len()
+            || (unit == 0 && call != SP_CALL_STATUS && call != SP_CALL_INIT)
+        {
+            return DeviceStatus::DeviceNotConnected as u8;
+        }
+
+        let block = u32::from_le_bytes([
+            read_param(param_offset),
+            read_param(param_offset + 1),
+            read_param(param_offset + 2),
+            if extended {
+                read_param(param_offset + 3)
+            } else {
+                0
+            },
+        ]);
+        let code = read_param(param_offset);
+
+        match call {
+            SP_CALL_INIT => {
+                self.reset();
+                return DeviceStatus::DeviceOk as u8;
+            }
+            SP_CALL_CONTROL => {
+                // Only the reset control code is supported
+                return if code == 0 {
+                    DeviceStatus::DeviceOk as u8
+                } else {
+                    DeviceStatus::DeviceBadControl as u8
+                };
+            }
+            _ => {}
+        }
+
+        self.command = SMARTPORT_CMD_STATUS | call;
+        self.unit_num = unit;
+        self.drive_select = if unit == 0 { 0 } else { unit as usize - 1 };
+
+        let disk = &mut self.drive[self.drive_select];
+        disk.mem_block = buffer;
+        match call {
+            SP_CALL_STATUS => self.status_code = code,
+            SP_CALL_READBLOCK | SP_CALL_WRITEBLOCK => disk.disk_block = block,
+            _ => {}
+        }
+
+        self.block_cmd_execute(mmu, video);
+        self.command = SMARTPORT_CMD_BUSY_STATUS;
+        self.drive[self.drive_select].error
+    }
 }
 
 impl Tick for HardDisk {
@@ -707,52 +1273,34 @@ impl Tick for HardDisk {
     }
 }
 
-fn read_dsk_u32(dsk: &[u8], offset: usize) -> u32 {
-    dsk[offset] as u32
-        + (dsk[offset + 1] as u32) * 256
-        + (dsk[offset + 2] as u32) * 65536
-        + (dsk[offset + 3] as u32) * 16777216
-}
+fn parse_image_header(dsk: &[u8]) -> io::Result<(ImageHeader, usize, usize)> {
+    let (image_header, offset, len) = ImageHeader::parse(dsk)?;
 
-fn parse_2mg_array(dsk: &[u8]) -> io::Result<(usize, usize)> {
-    if read_dsk_u32(dsk, 0) != 0x474d4932 || dsk.len() < 0x40 {
-        return Err(std::io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "Invalid 2mg file",
-        ));
-    }
-
-    let format = read_dsk_u32(dsk, 0x0c);
-    let blocks = read_dsk_u32(dsk, 0x14);
-    let offset = read_dsk_u32(dsk, 0x18);
-    let len = read_dsk_u32(dsk, 0x1c);
-    let comment_len = read_dsk_u32(dsk, 0x24);
-    let creator_len = read_dsk_u32(dsk, 0x28);
-
-    if dsk.len() != (offset + len + comment_len + creator_len) as usize
-        || !len.is_multiple_of(HD_BLOCK_SIZE as u32)
-    {
+    if !len.is_multiple_of(HD_BLOCK_SIZE) {
         return Err(std::io::Error::new(
             io::ErrorKind::InvalidInput,
-            "Invalid 2mg file - Len error",
+            "Disk image length is not a multiple of block size",
         ));
     }
 
-    if format != 1 {
-        return Err(std::io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "Only 2mg Prodos format is supported",
-        ));
-    }
+    if let ImageHeader::Img2mg(header) = &image_header {
+        if header.format != IMG_2MG_FORMAT_PRODOS {
+            return Err(std::io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only 2mg Prodos format is supported",
+            ));
+        }
 
-    if blocks * HD_BLOCK_SIZE as u32 != len {
-        return Err(std::io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "2mg blocks does not match disk data length",
-        ));
+        let blocks = u32::from_le_bytes([dsk[0x14], dsk[0x15], dsk[0x16], dsk[0x17]]) as usize;
+        if blocks * HD_BLOCK_SIZE != len {
+            return Err(std::io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "2mg blocks does not match disk data length",
+            ));
+        }
     }
 
-    Ok((offset as usize, len as usize))
+    Ok((image_header, offset, len))
 }
 
 impl Default for HardDisk {
@@ -837,7 +1385,11 @@ impl Card for HardDisk {
             0x3 => {
                 if write_flag {
                     if self.command & 0x80 == 0 {
-                        self.drive_select = (value >> 7) as usize % self.drive.len();
+                        let mut drive = (value >> 7) as usize;
+                        if ((value >> 4) & 0x7) as usize != slot {
+                            drive += 2;
+                        }
+                        self.drive_select = drive % self.drive.len();
                     } else if value & 0xf == 0 {
                         self.drive_select = 0;
                     } else {
@@ -920,8 +1472,203 @@ impl Card for HardDisk {
             // High Disk Len block
             0xa => self.high_disk_block_size(),
 
+            // SmartPort call command
+            0xb => {
+                if write_flag {
+                    self.sp_command = value;
+                }
+                self.sp_command
+            }
+
+            // SmartPort call parameter list
+            0xc => {
+                if write_flag {
+                    self.sp_param_list = self.sp_param_list & 0xff00 | value as u16;
+                }
+                (self.sp_param_list & 0xff) as u8
+            }
+
+            0xd => {
+                if write_flag {
+                    self.sp_param_list = self.sp_param_list & 0x00ff | ((value as u16) << 8);
+                }
+                (self.sp_param_list >> 8) as u8
+            }
+
+            // Execute SmartPort call
+            0xe => {
+                if write_flag {
+                    value
+                } else {
+                    self.smartport_call(mmu, video)
+                }
+            }
+
             // Return floating bus value
             _ => value,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Bus, IODevice};
+    use crate::cpu::{CPU, CpuFlags};
+
+    fn smartport_cpu() -> CPU {
+        let mut bus = Bus::default();
+        bus.io_slot[7] = IODevice::HardDisk;
+        bus.harddisk.set_smartport(true);
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn smartport_extended_read_block() {
+        let mut cpu = smartport_cpu();
+
+        // Unit 5 with 3 blocks, block 2 filled with 0xa5
+        let mut data = vec![0u8; 512 * 3];
+        data[1024..].fill(0xa5);
+        let harddisk = &mut cpu.bus.harddisk;
+        harddisk.drive_select(4);
+        harddisk.load_hdv_2mg_array(&data, true, false).unwrap();
+        harddisk.set_loaded(true);
+
+        let code = [
+            0x20, 0x0d, 0xc7, // JSR $C70D
+            0x41, // Extended read block
+            0x00, 0x11, 0x00, 0x00, // Parameter list at $1100
+            0x8d, 0x00, 0x03, // STA $0300
+            0x00, // END
+        ];
+        let param_list = [
+            0x03, // Parameter count
+            0x05, // Unit 5
+            0x00, 0x20, 0x00, 0x00, // Buffer at $2000
+            0x02, 0x00, 0x00, 0x00, // Block 2
+        ];
+        for (i, value) in param_list.iter().enumerate() {
+            cpu.bus.mem.unclocked_addr_write(0x1100 + i as u16, *value);
+        }
+
+        cpu.bus.mem.unclocked_addr_write(0x300, 0xff);
+        cpu.load_and_run_offset(&code, 0x1000, 0x1000);
+        // Returns after the parameter list and runs the STA sentinel
+        assert_eq!(cpu.program_counter, 0x100c);
+        assert_eq!(cpu.bus.mem.unclocked_addr_read(0x300), 0);
+        assert_eq!((cpu.register_x, cpu.register_y), (0x00, 0x02));
+        assert_eq!(cpu.register_a, 0, "Extended read should return no error");
+        assert!(
+            !cpu.status.contains(CpuFlags::CARRY),
+            "Carry should be clear on success"
+        );
+        assert_eq!(cpu.bus.mem.unclocked_addr_read(0x2000), 0xa5);
+        assert_eq!(cpu.bus.mem.unclocked_addr_read(0x21ff), 0xa5);
+    }
+
+    #[test]
+    fn smartport_write_protected_unit() {
+        let mut cpu = smartport_cpu();
+
+        let data = vec![0u8; 512 * 2];
+        let harddisk = &mut cpu.bus.harddisk;
+        harddisk.drive_select(7);
+        harddisk.load_hdv_2mg_array(&data, true, true).unwrap();
+        harddisk.set_loaded(true);
+
+        let code = [
+            0x20, 0x0d, 0xc7, // JSR $C70D
+            0x02, // Write block
+            0x00, 0x11, // Parameter list at $1100
+            0x8d, 0x00, 0x03, // STA $0300
+            0x00, // END
+        ];
+        let param_list = [
+            0x03, // Parameter count
+            0x08, // Unit 8
+            0x00, 0x20, // Buffer at $2000
+            0x01, 0x00, 0x00, // Block 1
+        ];
+        for (i, value) in param_list.iter().enumerate() {
+            cpu.bus.mem.unclocked_addr_write(0x1100 + i as u16, *value);
+        }
+
+        cpu.load_and_run_offset(&code, 0x1000, 0x1000);
+        // Returns after the parameter list and runs the STA sentinel
+        assert_eq!(cpu.program_counter, 0x100a);
+        assert_eq!(cpu.bus.mem.unclocked_addr_read(0x300), 0x2b);
+        assert_eq!((cpu.register_x, cpu.register_y), (0x00, 0x02));
+        assert_eq!(cpu.register_a, 0x2b, "Write protected error expected");
+        assert!(
+            cpu.status.contains(CpuFlags::CARRY),
+            "Carry should be set on error"
+        );
+    }
+
+    #[test]
+    fn overlay_format_and_write_protect() {
+        let image = std::env::temp_dir().join(format!("emu6502_hd_{}.po", std::process::id()));
+        std::fs::write(&image, vec![0x5a; HD_BLOCK_SIZE * 4]).unwrap();
+        overlay::remove_delta(&image).unwrap();
+
+        let mut harddisk = HardDisk::new();
+        harddisk.set_overlay_mode(true);
+        harddisk.set_disk_filename(&image);
+        harddisk.load_hdv_2mg_file(&image).unwrap();
+
+        harddisk.block_cmd_format();
+        assert_eq!(
+            harddisk.write_block(0, 1, &[0x11; HD_BLOCK_SIZE]),
+            DeviceStatus::DeviceOk as u8
+        );
+        harddisk.set_write_protect(0, true).unwrap();
+        assert!(harddisk.commit_overlay(0).is_err());
+
+        // The format is a single entry
+        let entries = overlay::read_delta(&image).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        harddisk.load_hdv_2mg_file(&image).unwrap();
+        assert!(harddisk.is_write_protected(0));
+        assert_eq!(harddisk.read_block(0, 0).unwrap(), [0; HD_BLOCK_SIZE]);
+        assert_eq!(harddisk.read_block(0, 1).unwrap(), [0x11; HD_BLOCK_SIZE]);
+
+        harddisk.set_write_protect(0, false).unwrap();
+        harddisk.commit_overlay(0).unwrap();
+        assert!(!harddisk.has_overlay(0));
+        let data = std::fs::read(&image).unwrap();
+        assert_eq!(
+            data[HD_BLOCK_SIZE..HD_BLOCK_SIZE * 2],
+            [0x11; HD_BLOCK_SIZE]
+        );
+        assert_eq!(data[HD_BLOCK_SIZE * 3], 0);
+
+        std::fs::remove_file(&image).unwrap();
+    }
+
+    #[test]
+    fn dc42_checksum_on_write() {
+        let image = std::env::temp_dir().join(format!("emu6502_hd_{}.dc", std::process::id()));
+        let header = DiskCopy42::new("Test", 819200).unwrap();
+        std::fs::write(&image, header.build(&vec![0; 819200])).unwrap();
+
+        let mut harddisk = HardDisk::new();
+        harddisk.set_enable_save_disk(true);
+        harddisk.set_disk_filename(&image);
+        harddisk.load_hdv_2mg_file(&image).unwrap();
+        assert_eq!(
+            harddisk.write_block(0, 5, &[0x11; HD_BLOCK_SIZE]),
+            DeviceStatus::DeviceOk as u8
+        );
+
+        // The image file is valid while the disk is still in the drive
+        let data = std::fs::read(&image).unwrap();
+        let (_, offset, _) = DiskCopy42::parse(&data).unwrap();
+        assert_eq!(data[offset + HD_BLOCK_SIZE * 5], 0x11);
+
+        std::fs::remove_file(&image).unwrap();
+    }
+}
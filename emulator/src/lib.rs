@@ -1,19 +1,38 @@
+pub mod applesoft;
 pub mod audio;
 pub mod bus;
+pub mod cassette;
+pub mod cffa;
 pub mod cpu;
 pub mod disk;
+pub mod diskaccel;
+pub mod diskimage;
+pub mod disklog;
 pub mod disksound;
+pub mod disktrack;
+pub mod dostrace;
 pub mod harddisk;
+pub mod loader;
 pub mod marshal;
+pub mod mlitrace;
 pub mod mmu;
 pub mod mockingboard;
+#[cfg(not(target_os = "wasi"))]
+pub mod modem;
 pub mod mouse;
 pub mod network;
 pub mod noslotclock;
 pub mod ntsc;
+pub mod overlay;
 pub mod parallel;
+pub mod printer;
 pub mod ramfactor;
+pub mod scsi;
+pub mod serial;
+pub mod thunderclock;
 pub mod trace;
+pub mod usernet;
+pub mod uthernet;
 pub mod video;
 pub mod videoterm;
 pub mod vidhd;
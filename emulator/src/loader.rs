@@ -0,0 +1,628 @@
+use crate::bus::Mem;
+use crate::cpu::CPU;
+use std::io;
+use std::path::Path;
+
+/*
+Load host files directly into memory
+
+The file type and the aux type (load address) of a host file are taken from
+    - the AppleSingle header of the file
+    - the AppleDouble header file (._name) next to the file
+    - the #TTAAAA suffix of the file name (for e.g. HELLO#FC0801 or GAME#062000)
+    - the file extension (.bas for Applesoft, .int for Integer BASIC)
+
+    Applesoft         The program is stored from TXTTAB ($67) and the links of the lines
+                      are rebuilt. VARTAB ($69), ARYTAB ($6B), STREND ($6D) and PRGEND
+                      ($AF) are set to the end of the program and FRETOP ($6F) to
+                      MEMSIZE ($73), which is the same as a CLEAR.
+    Integer BASIC     The program is stored below HIMEM ($4C) and PP ($CA) points to the
+                      start of the program. PV ($CC) is set to LOMEM ($4A).
+
+Applesoft programs in plain text are tokenized. Integer BASIC programs in plain text are
+returned as text to be typed into the interpreter.
+*/
+
+pub const FILE_TYPE_TXT: u8 = 0x04;
+pub const FILE_TYPE_BIN: u8 = 0x06;
+pub const FILE_TYPE_INT: u8 = 0xfa;
+pub const FILE_TYPE_BAS: u8 = 0xfc;
+pub const FILE_TYPE_SYS: u8 = 0xff;
+
+const SYS_ADDRESS: u16 = 0x2000;
+
+const APPLE_SINGLE_MAGIC: u32 = 0x0005_1600;
+const APPLE_DOUBLE_MAGIC: u32 = 0x0005_1607;
+const ENTRY_DATA_FORK: u32 = 1;
+const ENTRY_REAL_NAME: u32 = 3;
+const ENTRY_PRODOS_INFO: u32 = 11;
+
+pub(crate) const TXTTAB: u16 = 0x67;
+pub(crate) const VARTAB: u16 = 0x69;
+pub(crate) const ARYTAB: u16 = 0x6b;
+pub(crate) const STREND: u16 = 0x6d;
+const FRETOP: u16 = 0x6f;
+const MEMSIZE: u16 = 0x73;
+pub(crate) const PRGEND: u16 = 0xaf;
+const APPLESOFT_START: u16 = 0x801;
+const APPLESOFT_END: u16 = 0x9600;
+const MAX_LINE_NUMBER: u32 = 63999;
+
+// JSR SETPTRS, JMP NEWSTT
+const APPLESOFT_RUN: u16 = 0xd566;
+const APPLESOFT_RUN_SIGNATURE: [u8; 6] = [0x20, 0x65, 0xd6, 0x4c, 0xd2, 0xd7];
+
+const INT_LOMEM: u16 = 0x4a;
+const INT_HIMEM: u16 = 0x4c;
+const INT_PP: u16 = 0xca;
+const INT_PV: u16 = 0xcc;
+
+pub(crate) const TOKEN_DATA: u8 = 0x83;
+pub(crate) const TOKEN_REM: u8 = 0xb2;
+const TOKEN_PRINT: u8 = 0xba;
+const TOKEN_AT: u8 = 0xc5;
+const TOKEN_ATN: u8 = 0xe1;
+
+pub(crate) const APPLESOFT_TOKENS: [&str; 107] = [
+    "END", "FOR", "NEXT", "DATA", "INPUT", "DEL", "DIM", "READ", "GR", "TEXT", "PR#", "IN#",
+    "CALL", "PLOT", "HLIN", "VLIN", "HGR2", "HGR", "HCOLOR=", "HPLOT", "DRAW", "XDRAW", "HTAB",
+    "HOME", "ROT=", "SCALE=", "SHLOAD", "TRACE", "NOTRACE", "NORMAL", "INVERSE", "FLASH", "COLOR=",
+    "POP", "VTAB", "HIMEM:", "LOMEM:", "ONERR", "RESUME", "RECALL", "STORE", "SPEED=", "LET",
+    "GOTO", "RUN", "IF", "RESTORE", "&", "GOSUB", "RETURN", "REM", "STOP", "ON", "WAIT", "LOAD",
+    "SAVE", "DEF", "POKE", "PRINT", "CONT", "LIST", "CLEAR", "GET", "NEW", "TAB(", "TO", "FN",
+    "SPC(", "THEN", "AT", "NOT", "STEP", "+", "-", "*", "/", "^", "AND", "OR", ">", "=", "<",
+    "SGN", "INT", "ABS", "USR", "FRE", "SCRN(", "PDL", "POS", "SQR", "RND", "LOG", "EXP", "COS",
+    "SIN", "TAN", "ATN", "PEEK", "LEN", "STR$", "VAL", "ASC", "CHR$", "LEFT$", "RIGHT$", "MID$",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostFile {
+    pub name: String,
+    pub file_type: Option<u8>,
+    pub aux_type: Option<u16>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadedProgram {
+    Binary(u16),
+    Applesoft,
+    IntegerBasic,
+    /// Integer BASIC program in plain text to be typed into the interpreter
+    IntegerBasicText(String),
+}
+
+fn invalid_input(s: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, s.to_string())
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+type Entries<'a> = Vec<(u32, &'a [u8])>;
+
+// Returns the entries (id, data) of an AppleSingle or AppleDouble file
+fn apple_single_entries(data: &[u8]) -> Option<(u32, Entries<'_>)> {
+    let magic = be_u32(data, 0)?;
+    if magic != APPLE_SINGLE_MAGIC && magic != APPLE_DOUBLE_MAGIC {
+        return None;
+    }
+
+    let count = be_u16(data, 24)? as usize;
+    let mut entries = Vec::with_capacity(count);
+    for i in 0..count {
+        let offset = 26 + i * 12;
+        let id = be_u32(data, offset)?;
+        let start = be_u32(data, offset + 4)? as usize;
+        let len = be_u32(data, offset + 8)? as usize;
+        entries.push((id, data.get(start..start + len)?));
+    }
+    Some((magic, entries))
+}
+
+// The file type and the aux type from the name suffix #TTAAAA
+fn parse_type_suffix(name: &str) -> Option<(&str, u8, u16)> {
+    let (base, suffix) = name.rsplit_once('#')?;
+    if suffix.len() != 6 || !suffix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let file_type = u8::from_str_radix(&suffix[..2], 16).ok()?;
+    let aux_type = u16::from_str_radix(&suffix[2..], 16).ok()?;
+    Some((base, file_type, aux_type))
+}
+
+impl HostFile {
+    pub fn from_bytes(name: &str, data: &[u8]) -> io::Result<Self> {
+        let mut file = HostFile {
+            name: name.to_string(),
+            file_type: None,
+            aux_type: None,
+            data: data.to_vec(),
+        };
+
+        if let Some((base, file_type, aux_type)) = parse_type_suffix(name) {
+            file.name = base.to_string();
+            file.file_type = Some(file_type);
+            file.aux_type = Some(aux_type);
+        }
+
+        if let Some((magic, entries)) = apple_single_entries(data) {
+            if magic == APPLE_DOUBLE_MAGIC {
+                return Err(invalid_input(
+                    "AppleDouble header file does not contain the data fork",
+                ));
+            }
+            file.data.clear();
+            file.apply_entries(&entries);
+        }
+
+        Ok(file)
+    }
+
+    fn apply_entries(&mut self, entries: &[(u32, &[u8])]) {
+        for &(id, entry) in entries {
+            match id {
+                ENTRY_DATA_FORK => self.data = entry.to_vec(),
+                ENTRY_REAL_NAME => self.name = String::from_utf8_lossy(entry).to_string(),
+                ENTRY_PRODOS_INFO => {
+                    if let (Some(file_type), Some(aux_type)) = (be_u16(entry, 2), be_u32(entry, 4))
+                    {
+                        self.file_type = Some(file_type as u8);
+                        self.aux_type = Some(aux_type as u16);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Read the host file. The AppleDouble header file ._name is used when it exists
+    pub fn from_file<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        // Load the data fork when the AppleDouble header file is selected
+        if let Some(data_name) = name.strip_prefix("._") {
+            let mut file = Self::from_file(path.with_file_name(data_name))?;
+            if file.file_type.is_none() {
+                let header = std::fs::read(path)?;
+                if let Some((_, entries)) = apple_single_entries(&header) {
+                    let data = std::mem::take(&mut file.data);
+                    file.apply_entries(&entries);
+                    file.data = data;
+                }
+            }
+            return Ok(file);
+        }
+
+        let data = std::fs::read(path)?;
+        let mut file = Self::from_bytes(&name, &data)?;
+
+        let header_path = path.with_file_name(format!("._{name}"));
+        if file.file_type.is_none()
+            && let Ok(header) = std::fs::read(header_path)
+            && let Some((APPLE_DOUBLE_MAGIC, entries)) = apple_single_entries(&header)
+        {
+            let data = std::mem::take(&mut file.data);
+            file.apply_entries(&entries);
+            file.data = data;
+        }
+        Ok(file)
+    }
+
+    fn extension(&self) -> Option<String> {
+        Path::new(&self.name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+    }
+}
+
+fn is_text(data: &[u8]) -> bool {
+    !data.is_empty()
+        && data.iter().all(|&value| {
+            value == b'\r' || value == b'\n' || value == b'\t' || (0x20..0x7f).contains(&value)
+        })
+        && data
+            .iter()
+            .find(|value| !value.is_ascii_whitespace())
+            .is_some_and(|value| value.is_ascii_digit())
+}
+
+pub(crate) fn read_u16(cpu: &mut CPU, addr: u16) -> u16 {
+    let lo = cpu.bus.unclocked_addr_read(addr);
+    let hi = cpu.bus.unclocked_addr_read(addr.wrapping_add(1));
+    u16::from_le_bytes([lo, hi])
+}
+
+fn write_u16(cpu: &mut CPU, addr: u16, value: u16) {
+    let [lo, hi] = value.to_le_bytes();
+    cpu.bus.unclocked_addr_write(addr, lo);
+    cpu.bus.unclocked_addr_write(addr.wrapping_add(1), hi);
+}
+
+/// Store the binary in memory at the address
+pub fn load_binary(cpu: &mut CPU, data: &[u8], addr: u16) -> io::Result<()> {
+    if addr as usize + data.len() > 0x10000 {
+        return Err(invalid_input("Binary does not fit in memory"));
+    }
+    for (i, &value) in data.iter().enumerate() {
+        cpu.bus.unclocked_addr_write(addr + i as u16, value);
+    }
+    Ok(())
+}
+
+fn match_token(line: &[u8], pos: usize) -> Option<(u8, usize)> {
+    for (index, token) in APPLESOFT_TOKENS.iter().enumerate() {
+        // The spaces inside a keyword are ignored
+        let mut i = pos;
+        let mut matched = true;
+        for (j, &expected) in token.as_bytes().iter().enumerate() {
+            while j > 0 && line.get(i) == Some(&b' ') {
+                i += 1;
+            }
+            if line.get(i) != Some(&expected) {
+                matched = false;
+                break;
+            }
+            i += 1;
+        }
+        if matched {
+            return Some((0x80 + index as u8, i - pos));
+        }
+    }
+    None
+}
+
+// Tokenize the statements of a line the same way as the PARSE routine of Applesoft.
+// The lowercase letters are converted to uppercase except in the strings, REM and
+// DATA, as done by the input routine of the enhanced Apple //e
+fn tokenize_statements(line: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut data = false;
+    let mut i = 0;
+    while i < line.len() {
+        let value = line[i];
+        if value == b' ' && !data {
+            i += 1;
+            continue;
+        }
+
+        if value == b'"' {
+            output.push(value);
+            i += 1;
+            while i < line.len() {
+                output.push(line[i]);
+                i += 1;
+                if line[i - 1] == b'"' {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        if data {
+            data = value != b':';
+            output.push(value);
+            i += 1;
+            continue;
+        }
+
+        let value = value.to_ascii_uppercase();
+        if value == b'?' {
+            output.push(TOKEN_PRINT);
+            i += 1;
+            continue;
+        }
+
+        if (b'0'..=b';').contains(&value) {
+            output.push(value);
+            i += 1;
+            continue;
+        }
+
+        let upper = line[i..].to_ascii_uppercase();
+        match match_token(&upper, 0) {
+            // ATN and A TO are not AT
+            Some((TOKEN_AT, len)) if upper.get(len) == Some(&b'N') => {
+                output.push(TOKEN_ATN);
+                i += len + 1;
+            }
+            Some((TOKEN_AT, len)) if upper.get(len) == Some(&b'O') => {
+                output.push(value);
+                i += 1;
+            }
+            Some((token, len)) => {
+                output.push(token);
+                i += len;
+                if token == TOKEN_DATA {
+                    data = true;
+                } else if token == TOKEN_REM {
+                    output.extend_from_slice(&line[i..]);
+                    break;
+                }
+            }
+            None => {
+                output.push(value);
+                i += 1;
+            }
+        }
+    }
+    output
+}
+
+/// Tokenize the Applesoft program in plain text. The links of the lines are set for
+/// the program stored at `start`
+pub fn tokenize_applesoft(text: &str, start: u16) -> io::Result<Vec<u8>> {
+    let mut lines = std::collections::BTreeMap::new();
+    for line in text.lines() {
+        let line = line.trim_start();
+        if line.is_empty() {
+            continue;
+        }
+        let digits = line.bytes().take_while(|c| c.is_ascii_digit()).count();
+        let number = line[..digits]
+            .parse::<u32>()
+            .ok()
+            .filter(|&number| number <= MAX_LINE_NUMBER)
+            .ok_or_else(|| invalid_input(&format!("Invalid line number: {line}")))?;
+        let statements = tokenize_statements(&line.as_bytes()[digits..]);
+        if statements.contains(&0) || statements.len() > 239 {
+            return Err(invalid_input(&format!("Invalid line: {line}")));
+        }
+        lines.insert(number as u16, statements);
+    }
+
+    let mut program = Vec::new();
+    for (number, statements) in lines {
+        let next = start as usize + program.len() + 4 + statements.len() + 1;
+        program.extend_from_slice(&(next as u16).to_le_bytes());
+        program.extend_from_slice(&number.to_le_bytes());
+        program.extend_from_slice(&statements);
+        program.push(0);
+    }
+    program.extend_from_slice(&[0, 0]);
+    Ok(program)
+}
+
+// Rebuild the links of the lines for the program stored at `start`. Returns the length
+// of the program including the end marker
+fn relink_applesoft(program: &mut [u8], start: u16) -> io::Result<usize> {
+    let mut pos = 0;
+    loop {
+        let link = program
+            .get(pos..pos + 2)
+            .ok_or_else(|| invalid_input("Invalid Applesoft program"))?;
+        if link[1] == 0 {
+            return Ok(pos + 2);
+        }
+
+        let end = program
+            .get(pos + 4..)
+            .and_then(|line| line.iter().position(|&value| value == 0))
+            .ok_or_else(|| invalid_input("Invalid Applesoft program"))?;
+        let next = pos + 4 + end + 1;
+        let [lo, hi] = start.wrapping_add(next as u16).to_le_bytes();
+        program[pos] = lo;
+        program[pos + 1] = hi;
+        pos = next;
+    }
+}
+
+/// Store the tokenized Applesoft program from TXTTAB and set the Applesoft pointers
+pub fn load_applesoft(cpu: &mut CPU, program: &[u8]) -> io::Result<()> {
+    let start = match read_u16(cpu, TXTTAB) {
+        0 => APPLESOFT_START,
+        start => start,
+    };
+    let memsize = match read_u16(cpu, MEMSIZE) {
+        0 => APPLESOFT_END,
+        memsize => memsize,
+    };
+
+    let mut program = program.to_vec();
+    let len = relink_applesoft(&mut program, start)?;
+    let end = start as usize + len;
+    if end >= memsize as usize {
+        return Err(invalid_input("Applesoft program is too large"));
+    }
+
+    cpu.bus.unclocked_addr_write(start - 1, 0);
+    load_binary(cpu, &program[..len], start)?;
+
+    let end = end as u16;
+    write_u16(cpu, TXTTAB, start);
+    for addr in [VARTAB, ARYTAB, STREND, PRGEND] {
+        write_u16(cpu, addr, end);
+    }
+    write_u16(cpu, FRETOP, memsize);
+    write_u16(cpu, MEMSIZE, memsize);
+    Ok(())
+}
+
+/// Store the tokenized Integer BASIC program below HIMEM
+pub fn load_integer_basic(cpu: &mut CPU, program: &[u8]) -> io::Result<()> {
+    let himem = read_u16(cpu, INT_HIMEM);
+    let lomem = read_u16(cpu, INT_LOMEM);
+    if himem == 0 || himem <= lomem {
+        return Err(invalid_input("Integer BASIC is not initialized"));
+    }
+
+    let start = (himem as usize)
+        .checked_sub(program.len())
+        .filter(|&start| start >= lomem as usize)
+        .ok_or_else(|| invalid_input("Integer BASIC program is too large"))?;
+    load_binary(cpu, program, start as u16)?;
+    write_u16(cpu, INT_PP, start as u16);
+    write_u16(cpu, INT_PV, lomem);
+    Ok(())
+}
+
+/// Run the Applesoft program from the first line. Returns false when Applesoft is not
+/// in the ROM
+pub fn run_applesoft(cpu: &mut CPU) -> bool {
+    let signature_found = APPLESOFT_RUN_SIGNATURE
+        .iter()
+        .enumerate()
+        .all(|(i, &value)| cpu.bus.unclocked_addr_read(APPLESOFT_RUN + i as u16) == value);
+    if signature_found {
+        cpu.program_counter = APPLESOFT_RUN;
+    }
+    signature_found
+}
+
+/// Load the host file into memory. `addr` overrides the load address of a binary
+pub fn load_host_file(
+    cpu: &mut CPU,
+    file: &HostFile,
+    addr: Option<u16>,
+) -> io::Result<LoadedProgram> {
+    let extension = file.extension();
+    let file_type = file.file_type.or(match extension.as_deref() {
+        Some("bas") => Some(FILE_TYPE_BAS),
+        Some("int") => Some(FILE_TYPE_INT),
+        Some("txt") => Some(FILE_TYPE_TXT),
+        _ => None,
+    });
+
+    match file_type {
+        Some(FILE_TYPE_BAS) | Some(FILE_TYPE_TXT) if is_text(&file.data) => {
+            let text = String::from_utf8_lossy(&file.data);
+            let start = match read_u16(cpu, TXTTAB) {
+                0 => APPLESOFT_START,
+                start => start,
+            };
+            let program = tokenize_applesoft(&text, start)?;
+            load_applesoft(cpu, &program)?;
+            Ok(LoadedProgram::Applesoft)
+        }
+        Some(FILE_TYPE_BAS) => {
+            load_applesoft(cpu, &file.data)?;
+            Ok(LoadedProgram::Applesoft)
+        }
+        Some(FILE_TYPE_INT) if is_text(&file.data) => {
+            let text = String::from_utf8_lossy(&file.data).replace("\r\n", "\r");
+            Ok(LoadedProgram::IntegerBasicText(text.replace('\n', "\r")))
+        }
+        Some(FILE_TYPE_INT) => {
+            load_integer_basic(cpu, &file.data)?;
+            Ok(LoadedProgram::IntegerBasic)
+        }
+        _ => {
+            let addr = addr
+                .or(file.aux_type.filter(|_| file_type != Some(FILE_TYPE_SYS)))
+                .or((file_type == Some(FILE_TYPE_SYS)).then_some(SYS_ADDRESS))
+                .ok_or_else(|| {
+                    invalid_input(
+                        "Unknown load address. Use an AppleSingle file or the #06AAAA file name suffix",
+                    )
+                })?;
+            load_binary(cpu, &file.data, addr)?;
+            Ok(LoadedProgram::Binary(addr))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn tokenize_applesoft_program() {
+        let text = "20 PRINT \"HI THERE\":GOTO 10\n10 A = ATN(1) : FOR I=1 TO 10\r\n30 DATA A B, 1\n40 REM a b\n50 ?SCORE";
+        let program = tokenize_applesoft(text, 0x801).unwrap();
+        let expected = [
+            0x14, 0x08, 0x0a, 0x00, b'A', 0xd0, 0xe1, b'(', b'1', b')', b':', 0x81, b'I', 0xd0,
+            b'1', 0xc1, b'1', b'0', 0x00, //
+            0x28, 0x08, 0x14, 0x00, 0xba, b'"', b'H', b'I', b' ', b'T', b'H', b'E', b'R', b'E',
+            b'"', b':', 0xab, b'1', b'0', 0x00, //
+            0x35, 0x08, 0x1e, 0x00, 0x83, b' ', b'A', b' ', b'B', b',', b' ', b'1', 0x00, //
+            0x3f, 0x08, 0x28, 0x00, 0xb2, b' ', b'a', b' ', b'b', 0x00, //
+            0x49, 0x08, 0x32, 0x00, 0xba, b'S', b'C', 0xce, b'E', 0x00, //
+            0x00, 0x00,
+        ];
+        assert_eq!(program, expected);
+        assert!(tokenize_applesoft("PRINT", 0x801).is_err());
+    }
+
+    #[test]
+    fn tokenize_applesoft_lowercase() {
+        // Same as the lines typed in the enhanced Apple //e
+        let text = "10 print \"Hi\":rem Ab\n20 data Xy,\"q\":score=atn(1)\n30 ?hello";
+        let program = tokenize_applesoft(text, 0x801).unwrap();
+        let expected = [
+            0x10, 0x08, 0x0a, 0x00, 0xba, b'"', b'H', b'i', b'"', b':', 0xb2, b' ', b'A', b'b',
+            0x00, //
+            0x27, 0x08, 0x14, 0x00, 0x83, b' ', b'X', b'y', b',', b'"', b'q', b'"', b':', b'S',
+            b'C', 0xce, b'E', 0xd0, 0xe1, b'(', b'1', b')', 0x00, //
+            0x32, 0x08, 0x1e, 0x00, 0xba, b'H', b'E', b'L', b'L', b'O', 0x00, //
+            0x00, 0x00,
+        ];
+        assert_eq!(program, expected);
+    }
+
+    #[test]
+    fn load_applesoft_program() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+        write_u16(&mut cpu, TXTTAB, 0x801);
+        write_u16(&mut cpu, MEMSIZE, 0x9600);
+
+        let file = HostFile::from_bytes("HELLO#FC0801", b"10 HOME\n20 END\n").unwrap();
+        assert_eq!(file.name, "HELLO");
+        assert_eq!(
+            load_host_file(&mut cpu, &file, None).unwrap(),
+            LoadedProgram::Applesoft
+        );
+        assert_eq!(read_u16(&mut cpu, TXTTAB), 0x801);
+        assert_eq!(read_u16(&mut cpu, VARTAB), 0x80f);
+        assert_eq!(read_u16(&mut cpu, FRETOP), 0x9600);
+        assert_eq!(cpu.bus.mem_read(0x805), 0x97);
+        assert_eq!(read_u16(&mut cpu, 0x807), 0x80d);
+    }
+
+    #[test]
+    fn load_apple_single_binary() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+
+        let mut data = vec![0x00, 0x05, 0x16, 0x00, 0x00, 0x02, 0x00, 0x00];
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&2u16.to_be_bytes());
+        for (id, offset, len) in [(1u32, 50u32, 3u32), (11, 53, 8)] {
+            data.extend_from_slice(&id.to_be_bytes());
+            data.extend_from_slice(&offset.to_be_bytes());
+            data.extend_from_slice(&len.to_be_bytes());
+        }
+        data.extend_from_slice(&[0xa9, 0x01, 0x60]);
+        data.extend_from_slice(&[0x00, 0xc3, 0x00, 0x06, 0x00, 0x00, 0x03, 0x00]);
+
+        let file = HostFile::from_bytes("TEST", &data).unwrap();
+        assert_eq!(file.file_type, Some(FILE_TYPE_BIN));
+        assert_eq!(file.aux_type, Some(0x300));
+        assert_eq!(
+            load_host_file(&mut cpu, &file, None).unwrap(),
+            LoadedProgram::Binary(0x300)
+        );
+        assert_eq!(cpu.bus.mem_read(0x301), 0x01);
+
+        let raw = HostFile::from_bytes("RAW.BIN", &[0xea]).unwrap();
+        assert!(load_host_file(&mut cpu, &raw, None).is_err());
+        assert!(load_host_file(&mut cpu, &raw, Some(0x6000)).is_ok());
+    }
+}
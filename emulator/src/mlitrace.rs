@@ -0,0 +1,515 @@
+use crate::bus::Mem;
+use crate::cpu::{CPU, CpuFlags};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/*
+ProDOS MLI call tracer
+
+The tracer is called before each instruction (for e.g. from the step_with_callback
+callback). A call is detected when the instruction is JSR $BF00. The call is followed by
+the command byte and the pointer to the parameter list
+
+    JSR $BF00
+    DB  command
+    DW  parameter list
+
+The MLI returns to the instruction after the parameter list pointer with the error code
+in A and the carry set on error. The input parameters are decoded when the call is made
+and the output parameters are decoded when the call returns.
+*/
+
+const MLI_ENTRY: u16 = 0xbf00;
+const JSR: u8 = 0x20;
+
+/// Maximum number of calls kept in the trace. Older calls are dropped
+pub const MLI_TRACE_MAX_CALLS: usize = 100_000;
+
+const MAX_PATHNAME_LEN: u8 = 64;
+
+pub fn mli_command_name(command: u8) -> Option<&'static str> {
+    let name = match command {
+        0x40 => "ALLOC_INTERRUPT",
+        0x41 => "DEALLOC_INTERRUPT",
+        0x65 => "QUIT",
+        0x80 => "READ_BLOCK",
+        0x81 => "WRITE_BLOCK",
+        0x82 => "GET_TIME",
+        0xc0 => "CREATE",
+        0xc1 => "DESTROY",
+        0xc2 => "RENAME",
+        0xc3 => "SET_FILE_INFO",
+        0xc4 => "GET_FILE_INFO",
+        0xc5 => "ON_LINE",
+        0xc6 => "SET_PREFIX",
+        0xc7 => "GET_PREFIX",
+        0xc8 => "OPEN",
+        0xc9 => "NEWLINE",
+        0xca => "READ",
+        0xcb => "WRITE",
+        0xcc => "CLOSE",
+        0xcd => "FLUSH",
+        0xce => "SET_MARK",
+        0xcf => "GET_MARK",
+        0xd0 => "SET_EOF",
+        0xd1 => "GET_EOF",
+        0xd2 => "SET_BUF",
+        0xd3 => "GET_BUF",
+        _ => return None,
+    };
+    Some(name)
+}
+
+pub fn mli_error_name(error: u8) -> &'static str {
+    match error {
+        0x00 => "No error",
+        0x01 => "Bad system call number",
+        0x04 => "Bad system call parameter count",
+        0x25 => "Interrupt table full",
+        0x27 => "I/O error",
+        0x28 => "No device connected",
+        0x2b => "Disk write protected",
+        0x2e => "Disk switched",
+        0x40 => "Invalid pathname",
+        0x42 => "Maximum number of files open",
+        0x43 => "Invalid reference number",
+        0x44 => "Directory not found",
+        0x45 => "Volume not found",
+        0x46 => "File not found",
+        0x47 => "Duplicate filename",
+        0x48 => "Volume full",
+        0x49 => "Volume directory full",
+        0x4a => "Incompatible file format",
+        0x4b => "Unsupported storage type",
+        0x4c => "End of file encountered",
+        0x4d => "Position out of range",
+        0x4e => "File access error",
+        0x50 => "File is open",
+        0x51 => "Directory structure damaged",
+        0x52 => "Not a ProDOS volume",
+        0x53 => "Invalid system call parameter",
+        0x55 => "Volume Control Block table full",
+        0x56 => "Bad buffer address",
+        0x57 => "Duplicate volume",
+        0x5a => "File structure damaged",
+        _ => "Unknown error",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MliResult {
+    pub cycle: usize,
+    pub error: u8,
+    /// Decoded output parameters
+    pub outputs: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MliCall {
+    pub cycle: usize,
+    pub pc: u16,
+    pub command: u8,
+    pub param_list: u16,
+    /// Decoded input parameters
+    pub inputs: String,
+    pub result: Option<MliResult>,
+}
+
+impl MliCall {
+    pub fn to_text(&self) -> String {
+        let name = mli_command_name(self.command)
+            .map_or(format!("${:02X}", self.command), |name| name.to_string());
+        let mut output = format!(
+            "CYC:{:>12} PC:${:04X} {name} (${:04X}) {}",
+            self.cycle, self.pc, self.param_list, self.inputs
+        );
+        if let Some(result) = &self.result {
+            output.push_str(" ->");
+            if !result.outputs.is_empty() {
+                output.push(' ');
+                output.push_str(&result.outputs);
+            }
+            if result.error == 0 {
+                output.push_str(" OK");
+            } else {
+                output.push_str(&format!(
+                    " ERR ${:02X} {}",
+                    result.error,
+                    mli_error_name(result.error)
+                ));
+            }
+            output.push_str(&format!(" ({} cycles)", result.cycle - self.cycle));
+        }
+        output
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingCall {
+    index: usize,
+    return_pc: u16,
+    stack_pointer: u8,
+}
+
+#[derive(Debug, Default)]
+pub struct MliTracer {
+    calls: VecDeque<MliCall>,
+    pending: Option<PendingCall>,
+    echo: bool,
+    recording: bool,
+}
+
+fn read_u16(cpu: &mut CPU, addr: u16) -> u16 {
+    let lo = cpu.bus.unclocked_addr_read(addr);
+    let hi = cpu.bus.unclocked_addr_read(addr.wrapping_add(1));
+    u16::from_le_bytes([lo, hi])
+}
+
+fn read_u24(cpu: &mut CPU, addr: u16) -> u32 {
+    read_u16(cpu, addr) as u32 | (cpu.bus.unclocked_addr_read(addr.wrapping_add(2)) as u32) << 16
+}
+
+fn read_pathname(cpu: &mut CPU, addr: u16) -> String {
+    let len = cpu.bus.unclocked_addr_read(addr).min(MAX_PATHNAME_LEN);
+    (0..len)
+        .map(|i| (cpu.bus.unclocked_addr_read(addr.wrapping_add(1 + i as u16)) & 0x7f) as char)
+        .collect()
+}
+
+fn pathname_param(cpu: &mut CPU, param_list: u16, offset: u16) -> String {
+    let ptr = read_u16(cpu, param_list.wrapping_add(offset));
+    format!("\"{}\"", read_pathname(cpu, ptr))
+}
+
+fn decode_inputs(cpu: &mut CPU, command: u8, p: u16) -> String {
+    let byte = |cpu: &mut CPU, offset: u16| cpu.bus.unclocked_addr_read(p.wrapping_add(offset));
+    match command {
+        0x40 => format!("code=${:04X}", read_u16(cpu, p.wrapping_add(2))),
+        0x41 => format!("int_num={}", byte(cpu, 1)),
+        0x65 => format!("type={}", byte(cpu, 1)),
+        0x80 | 0x81 => format!(
+            "unit=${:02X} buffer=${:04X} block={}",
+            byte(cpu, 1),
+            read_u16(cpu, p.wrapping_add(2)),
+            read_u16(cpu, p.wrapping_add(4))
+        ),
+        0xc0 => format!(
+            "path={} access=${:02X} type=${:02X} aux=${:04X} storage=${:02X}",
+            pathname_param(cpu, p, 1),
+            byte(cpu, 3),
+            byte(cpu, 4),
+            read_u16(cpu, p.wrapping_add(5)),
+            byte(cpu, 7)
+        ),
+        0xc1 | 0xc4 | 0xc6 => format!("path={}", pathname_param(cpu, p, 1)),
+        0xc2 => format!(
+            "path={} new_path={}",
+            pathname_param(cpu, p, 1),
+            pathname_param(cpu, p, 3)
+        ),
+        0xc3 => format!(
+            "path={} access=${:02X} type=${:02X} aux=${:04X}",
+            pathname_param(cpu, p, 1),
+            byte(cpu, 3),
+            byte(cpu, 4),
+            read_u16(cpu, p.wrapping_add(5))
+        ),
+        0xc5 => format!(
+            "unit=${:02X} buffer=${:04X}",
+            byte(cpu, 1),
+            read_u16(cpu, p.wrapping_add(2))
+        ),
+        0xc7 => format!("buffer=${:04X}", read_u16(cpu, p.wrapping_add(1))),
+        0xc8 => format!(
+            "path={} io_buffer=${:04X}",
+            pathname_param(cpu, p, 1),
+            read_u16(cpu, p.wrapping_add(3))
+        ),
+        0xc9 => format!(
+            "ref={} mask=${:02X} char=${:02X}",
+            byte(cpu, 1),
+            byte(cpu, 2),
+            byte(cpu, 3)
+        ),
+        0xca | 0xcb => format!(
+            "ref={} buffer=${:04X} request={}",
+            byte(cpu, 1),
+            read_u16(cpu, p.wrapping_add(2)),
+            read_u16(cpu, p.wrapping_add(4))
+        ),
+        0xcc | 0xcd | 0xcf | 0xd1 | 0xd3 => format!("ref={}", byte(cpu, 1)),
+        0xce => format!(
+            "ref={} mark={}",
+            byte(cpu, 1),
+            read_u24(cpu, p.wrapping_add(2))
+        ),
+        0xd0 => format!(
+            "ref={} eof={}",
+            byte(cpu, 1),
+            read_u24(cpu, p.wrapping_add(2))
+        ),
+        0xd2 => format!(
+            "ref={} io_buffer=${:04X}",
+            byte(cpu, 1),
+            read_u16(cpu, p.wrapping_add(2))
+        ),
+        _ => String::new(),
+    }
+}
+
+fn decode_outputs(cpu: &mut CPU, command: u8, p: u16) -> String {
+    let byte = |cpu: &mut CPU, offset: u16| cpu.bus.unclocked_addr_read(p.wrapping_add(offset));
+    match command {
+        0x40 => format!("int_num={}", byte(cpu, 1)),
+        0xc4 => format!(
+            "access=${:02X} type=${:02X} aux=${:04X} storage=${:02X} blocks={}",
+            byte(cpu, 3),
+            byte(cpu, 4),
+            read_u16(cpu, p.wrapping_add(5)),
+            byte(cpu, 7),
+            read_u16(cpu, p.wrapping_add(8))
+        ),
+        0xc5 => {
+            // The first volume name in the buffer
+            let buffer = read_u16(cpu, p.wrapping_add(2));
+            let value = cpu.bus.unclocked_addr_read(buffer);
+            let len = value & 0x0f;
+            let name: String = (1..=len as u16)
+                .map(|i| (cpu.bus.unclocked_addr_read(buffer.wrapping_add(i)) & 0x7f) as char)
+                .collect();
+            format!("unit=${:02X} volume=\"/{name}\"", value & 0xf0)
+        }
+        0xc7 => {
+            let buffer = read_u16(cpu, p.wrapping_add(1));
+            format!("prefix=\"{}\"", read_pathname(cpu, buffer))
+        }
+        0xc8 => format!("ref={}", byte(cpu, 5)),
+        0xca | 0xcb => format!("transferred={}", read_u16(cpu, p.wrapping_add(6))),
+        0xcf => format!("mark={}", read_u24(cpu, p.wrapping_add(2))),
+        0xd1 => format!("eof={}", read_u24(cpu, p.wrapping_add(2))),
+        0xd3 => format!("io_buffer=${:04X}", read_u16(cpu, p.wrapping_add(2))),
+        _ => String::new(),
+    }
+}
+
+impl MliTracer {
+    pub fn new() -> Self {
+        Self {
+            recording: true,
+            ..Default::default()
+        }
+    }
+
+    /// Start or stop recording the calls. The recorded calls are kept
+    pub fn set_recording(&mut self, flag: bool) {
+        self.recording = flag;
+        if !flag {
+            self.pending = None;
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Print each call to stderr when it returns
+    pub fn set_echo(&mut self, flag: bool) {
+        self.echo = flag
+    }
+
+    pub fn calls(&self) -> &VecDeque<MliCall> {
+        &self.calls
+    }
+
+    pub fn clear(&mut self) {
+        self.calls.clear();
+        self.pending = None;
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut output = String::new();
+        for call in &self.calls {
+            output.push_str(&call.to_text());
+            output.push('\n');
+        }
+        output
+    }
+
+    pub fn save<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_text().as_bytes())
+    }
+
+    /// Trace the instruction at the program counter
+    pub fn step(&mut self, cpu: &mut CPU) {
+        if !self.recording {
+            return;
+        }
+
+        let pc = cpu.program_counter;
+
+        if let Some(pending) = self.pending
+            && pc == pending.return_pc
+            && cpu.stack_pointer == pending.stack_pointer
+        {
+            self.pending = None;
+            let call = &self.calls[pending.index];
+            let (command, param_list) = (call.command, call.param_list);
+            let error = if cpu.status.contains(CpuFlags::CARRY) {
+                cpu.register_a
+            } else {
+                0
+            };
+            let outputs = if error == 0 {
+                decode_outputs(cpu, command, param_list)
+            } else {
+                String::new()
+            };
+
+            let call = &mut self.calls[pending.index];
+            call.result = Some(MliResult {
+                cycle: cpu.bus.get_cycles(),
+                error,
+                outputs,
+            });
+            if self.echo {
+                eprintln!("{}", call.to_text());
+            }
+        }
+
+        if cpu.bus.unclocked_addr_read(pc) != JSR || read_u16(cpu, pc.wrapping_add(1)) != MLI_ENTRY
+        {
+            return;
+        }
+
+        let command = cpu.bus.unclocked_addr_read(pc.wrapping_add(3));
+        let param_list = read_u16(cpu, pc.wrapping_add(4));
+        let inputs = decode_inputs(cpu, command, param_list);
+
+        if self.calls.len() >= MLI_TRACE_MAX_CALLS {
+            self.calls.pop_front();
+        }
+        self.calls.push_back(MliCall {
+            cycle: cpu.bus.get_cycles(),
+            pc,
+            command,
+            param_list,
+            inputs,
+            result: None,
+        });
+
+        // QUIT does not return
+        if command == 0x65 {
+            if self.echo {
+                eprintln!("{}", self.calls[self.calls.len() - 1].to_text());
+            }
+            self.pending = None;
+        } else {
+            self.pending = Some(PendingCall {
+                index: self.calls.len() - 1,
+                return_pc: pc.wrapping_add(6),
+                stack_pointer: cpu.stack_pointer,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn trace_open_call() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+
+        // Minimal MLI that returns the ref num 1 for OPEN and error $46 for other calls
+        let mli = [
+            0x68, // PLA
+            0x18, // CLC
+            0x69, 0x04, // ADC #$04
+            0x85, 0xfe, // STA $FE
+            0x68, // PLA
+            0x69, 0x00, // ADC #$00
+            0x85, 0xff, // STA $FF
+            0xad, 0x00, 0x03, // LDA $0300
+            0xc9, 0x03, // CMP #$03
+            0xd0, 0x0a, // BNE error
+            0xa9, 0x01, // LDA #$01
+            0x8d, 0x05, 0x03, // STA $0305
+            0xa9, 0x00, // LDA #$00
+            0x18, // CLC
+            0x90, 0x03, // BCC return
+            0xa9, 0x46, // error: LDA #$46
+            0x38, // SEC
+            0x6c, 0xfe, 0x00, // return: JMP ($00FE)
+        ];
+        cpu.load(&mli, MLI_ENTRY);
+
+        // OPEN parameter list with pathname /TEST/FILE
+        cpu.load(&[0x03, 0x10, 0x03, 0x00, 0x1c, 0x00], 0x300);
+        cpu.load(b"\x0a/TEST/FILE", 0x310);
+
+        let code = [
+            0x20, 0x00, 0xbf, 0xc8, 0x00, 0x03, // JSR $BF00 OPEN $0300
+            0xad, 0x05, 0x03, // LDA $0305
+            0x8d, 0x01, 0x03, // STA $0301
+            0xa9, 0x01, // LDA #$01
+            0x8d, 0x00, 0x03, // STA $0300
+            0x20, 0x00, 0xbf, 0xcc, 0x00, 0x03, // JSR $BF00 CLOSE $0300
+            0xea, // NOP
+            0x00, // END
+        ];
+        cpu.load(&code, 0x1000);
+        cpu.program_counter = 0x1000;
+
+        let mut tracer = MliTracer::new();
+        cpu.run_with_callback(|cpu| tracer.step(cpu));
+
+        let calls = tracer.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].inputs, "path=\"/TEST/FILE\" io_buffer=$1C00");
+        let result = calls[0].result.as_ref().unwrap();
+        assert_eq!(result.error, 0);
+        assert_eq!(result.outputs, "ref=1");
+        assert_eq!(calls[1].result.as_ref().unwrap().error, 0x46);
+        assert!(
+            tracer
+                .to_text()
+                .contains("CLOSE ($0300) ref=1 -> ERR $46 File not found")
+        );
+    }
+
+    #[test]
+    fn parameters_wrap_around_and_recording() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+
+        // The parameter list and the buffer at the end of the address space
+        assert!(decode_inputs(&mut cpu, 0xce, 0xfffe).starts_with("ref="));
+        cpu.load(&[0x00, 0x00, 0xff, 0xff], 0x300);
+        assert!(decode_outputs(&mut cpu, 0xc5, 0x300).starts_with("unit="));
+
+        cpu.load(&[0x20, 0x00, 0xbf, 0xcc, 0x00, 0x03], 0x1000);
+        cpu.program_counter = 0x1000;
+        let mut tracer = MliTracer::new();
+        tracer.step(&mut cpu);
+        assert_eq!(tracer.calls().len(), 1);
+
+        // The calls are kept when the recording is stopped
+        tracer.set_recording(false);
+        tracer.step(&mut cpu);
+        assert!(!tracer.is_recording());
+        assert_eq!(tracer.calls().len(), 1);
+
+        tracer.set_recording(true);
+        tracer.step(&mut cpu);
+        assert_eq!(tracer.calls().len(), 2);
+    }
+}
@@ -294,6 +294,12 @@ impl Mmu {
         }
     }
 
+    pub fn get_aux_size(&self) -> usize {
+        self.ext_aux_mem
+            .as_ref()
+            .map_or(1, |aux_mem| aux_mem.len() / 0x10000 + 1)
+    }
+
     pub fn mem_read(&self, addr: u16) -> u8 {
         if (0xc100..=0xffff).contains(&addr) {
             if !self.rom_bank {
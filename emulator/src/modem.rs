@@ -0,0 +1,684 @@
+use crate::serial::{connect_tcp_async, read_stream, write_stream};
+use std::collections::VecDeque;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+
+#[cfg(feature = "web_time")]
+use web_time::Instant;
+
+#[cfg(not(feature = "web_time"))]
+use std::time::Instant;
+
+/*
+Hayes compatible modem
+
+    ATDT host:port  Dial a TCP connection (ATDP, ATD are the same). The port defaults to 23
+                    and can also be separated from the host by a space. CONNECT or
+                    NO CARRIER is reported when the connection completes, and any
+                    character typed while dialing cancels the call
+    ATA             Answer the incoming connection on the listen socket
+    ATH             Hang up
+    ATO             Return to the online mode after the +++ escape
+    ATZ, AT&F       Reset to the default settings and hang up
+    ATEn ATQn ATVn  Command echo, quiet mode and verbose result codes
+    ATIn            Modem information
+    ATSn=v ATSn?    Set and query the S-registers
+    A/              Repeat the last command
+
+The other common commands (L, M, X, &C, &D, &K) are accepted and ignored.
+
+    S0   Rings to auto-answer (0 = Disabled)
+    S1   Ring count
+    S2   Escape character (Default is '+')
+    S3   Carriage return character
+    S4   Line feed character
+    S5   Backspace character
+    S12  Escape guard time in 1/50 second. The escape characters must follow each other
+         within the guard time, and the data must be idle for the guard time before
+         and after them
+*/
+
+const S_AUTO_ANSWER: usize = 0;
+const S_RING_COUNT: usize = 1;
+const S_ESCAPE: usize = 2;
+const S_CR: usize = 3;
+const S_LF: usize = 4;
+const S_BS: usize = 5;
+const S_GUARD_TIME: usize = 12;
+const NUM_REGISTERS: usize = 38;
+
+const DEFAULT_REGISTERS: [(usize, u8); 7] = [
+    (S_ESCAPE, b'+'),
+    (S_CR, 0x0d),
+    (S_LF, 0x0a),
+    (S_BS, 0x08),
+    (6, 2),
+    (7, 50),
+    (S_GUARD_TIME, 50),
+];
+
+const RING_INTERVAL: Duration = Duration::from_secs(3);
+const MAX_COMMAND_LENGTH: usize = 255;
+const DEFAULT_PORT: u16 = 23;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResultCode {
+    Ok = 0,
+    Connect = 1,
+    Ring = 2,
+    NoCarrier = 3,
+    Error = 4,
+}
+
+impl ResultCode {
+    fn text(self) -> &'static str {
+        match self {
+            ResultCode::Ok => "OK",
+            ResultCode::Connect => "CONNECT",
+            ResultCode::Ring => "RING",
+            ResultCode::NoCarrier => "NO CARRIER",
+            ResultCode::Error => "ERROR",
+        }
+    }
+}
+
+pub struct HayesModem {
+    registers: [u8; NUM_REGISTERS],
+    echo: bool,
+    quiet: bool,
+    verbose: bool,
+
+    listener: Option<TcpListener>,
+    incoming: Option<TcpStream>,
+    stream: Option<TcpStream>,
+    dialing: Option<Receiver<io::Result<TcpStream>>>,
+    online: bool,
+    outgoing: VecDeque<u8>,
+
+    command: Vec<u8>,
+    last_command: Vec<u8>,
+    escape_count: usize,
+    last_data: Instant,
+    last_ring: Option<Instant>,
+    // The time of the current poll
+    now: Instant,
+}
+
+impl HayesModem {
+    /// Create the modem. Incoming calls are accepted on the listen address
+    pub fn new(listen: Option<&str>) -> io::Result<Self> {
+        let listener = match listen {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Some(listener)
+            }
+            None => None,
+        };
+
+        let mut modem = HayesModem {
+            registers: [0; NUM_REGISTERS],
+            echo: true,
+            quiet: false,
+            verbose: true,
+            listener,
+            incoming: None,
+            stream: None,
+            dialing: None,
+            online: false,
+            outgoing: VecDeque::new(),
+            command: Vec::new(),
+            last_command: Vec::new(),
+            escape_count: 0,
+            last_data: Instant::now(),
+            last_ring: None,
+            now: Instant::now(),
+        };
+        modem.reset();
+        Ok(modem)
+    }
+
+    /// The address of the listen socket for incoming calls
+    pub fn local_addr(&self) -> Option<String> {
+        self.listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+            .map(|addr| addr.to_string())
+    }
+
+    /// True when a call is connected (Data carrier detect)
+    pub fn is_carrier(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// True when the data is passed to the remote side instead of the command interpreter
+    pub fn is_online(&self) -> bool {
+        self.online
+    }
+
+    fn reset(&mut self) {
+        self.hang_up();
+        self.registers = [0; NUM_REGISTERS];
+        for (reg, value) in DEFAULT_REGISTERS {
+            self.registers[reg] = value;
+        }
+        self.echo = true;
+        self.quiet = false;
+        self.verbose = true;
+    }
+
+    fn hang_up(&mut self) {
+        self.stream = None;
+        self.dialing = None;
+        self.incoming = None;
+        self.online = false;
+        self.outgoing.clear();
+        self.escape_count = 0;
+        self.last_ring = None;
+        self.registers[S_RING_COUNT] = 0;
+    }
+
+    fn guard_time(&self) -> Duration {
+        Duration::from_millis(self.registers[S_GUARD_TIME] as u64 * 20)
+    }
+
+    fn result(&self, code: ResultCode, receive: &mut VecDeque<u8>) {
+        if self.quiet {
+            return;
+        }
+
+        let cr = self.registers[S_CR];
+        let lf = self.registers[S_LF];
+        if self.verbose {
+            receive.extend([cr, lf]);
+            receive.extend(code.text().as_bytes());
+            receive.extend([cr, lf]);
+        } else {
+            receive.extend((code as u8).to_string().as_bytes());
+            receive.push_back(cr);
+        }
+    }
+
+    fn info(&self, text: &str, receive: &mut VecDeque<u8>) {
+        let cr = self.registers[S_CR];
+        let lf = self.registers[S_LF];
+        receive.extend([cr, lf]);
+        receive.extend(text.as_bytes());
+        receive.extend([cr, lf]);
+    }
+
+    /// Process the data from the computer in `transmit`, exchange the data with the
+    /// remote side and put the data and the result codes for the computer in `receive`
+    pub fn poll(&mut self, receive: &mut VecDeque<u8>, transmit: &mut VecDeque<u8>) {
+        self.poll_at(Instant::now(), receive, transmit);
+    }
+
+    fn poll_at(&mut self, now: Instant, receive: &mut VecDeque<u8>, transmit: &mut VecDeque<u8>) {
+        self.now = now;
+        if let Some(dialing) = &self.dialing {
+            match dialing.try_recv() {
+                Ok(result) => self.dial_result(result, receive),
+                Err(TryRecvError::Disconnected) => {
+                    self.dialing = None;
+                    self.result(ResultCode::NoCarrier, receive);
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+
+        while let Some(value) = transmit.pop_front() {
+            if self.online {
+                self.data_byte(value);
+            } else if self.dialing.is_some() {
+                self.hang_up();
+                self.result(ResultCode::NoCarrier, receive);
+            } else {
+                self.command_byte(value, receive);
+            }
+        }
+
+        let idle = now.saturating_duration_since(self.last_data);
+        if self.online && self.escape_count == 3 && idle >= self.guard_time() {
+            self.online = false;
+            self.escape_count = 0;
+            self.result(ResultCode::Ok, receive);
+        }
+
+        if let Some(stream) = &mut self.stream {
+            let connected = write_stream(stream, &mut self.outgoing)
+                && (!self.online || read_stream(stream, receive));
+            if !connected {
+                self.hang_up();
+                self.result(ResultCode::NoCarrier, receive);
+            }
+        }
+
+        self.poll_incoming(receive);
+    }
+
+    fn poll_incoming(&mut self, receive: &mut VecDeque<u8>) {
+        if self.stream.is_some() || self.dialing.is_some() {
+            return;
+        }
+
+        if self.incoming.is_none()
+            && let Some(listener) = &self.listener
+            && let Ok((stream, _)) = listener.accept()
+            && stream.set_nonblocking(true).is_ok()
+        {
+            let _ = stream.set_nodelay(true);
+            self.incoming = Some(stream);
+            self.registers[S_RING_COUNT] = 0;
+            self.last_ring = None;
+        }
+
+        if self.incoming.is_none()
+            || self
+                .last_ring
+                .is_some_and(|ring| self.now.saturating_duration_since(ring) < RING_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_ring = Some(self.now);
+        self.registers[S_RING_COUNT] = self.registers[S_RING_COUNT].saturating_add(1);
+        self.result(ResultCode::Ring, receive);
+
+        let auto_answer = self.registers[S_AUTO_ANSWER];
+        if auto_answer > 0 && self.registers[S_RING_COUNT] >= auto_answer {
+            let code = self.answer();
+            self.result(code, receive);
+        }
+    }
+
+    fn data_byte(&mut self, value: u8) {
+        // The escape sequence is three escape characters with the guard time before
+        // and after, and less than the guard time between them. The escape characters
+        // are still sent to the remote side
+        let guard_passed = self.now.saturating_duration_since(self.last_data) >= self.guard_time();
+        let escape = value == self.registers[S_ESCAPE];
+        self.escape_count = match self.escape_count {
+            0 if escape && guard_passed => 1,
+            1 | 2 if escape && !guard_passed => self.escape_count + 1,
+            _ if escape && guard_passed => 1,
+            _ => 0,
+        };
+        self.last_data = self.now;
+        self.outgoing.push_back(value);
+    }
+
+    fn command_byte(&mut self, value: u8, receive: &mut VecDeque<u8>) {
+        let value = value & 0x7f;
+        if self.echo {
+            receive.push_back(value);
+        }
+
+        if value == self.registers[S_CR] {
+            let line = std::mem::take(&mut self.command);
+            self.command_line(line, receive);
+        } else if value == self.registers[S_BS] {
+            self.command.pop();
+        } else if value == b'/' && self.command.eq_ignore_ascii_case(b"A") {
+            self.command.clear();
+            let line = self.last_command.clone();
+            self.command_line(line, receive);
+        } else if value >= 0x20 && self.command.len() < MAX_COMMAND_LENGTH {
+            self.command.push(value);
+        }
+    }
+
+    fn command_line(&mut self, line: Vec<u8>, receive: &mut VecDeque<u8>) {
+        let line = line.to_ascii_uppercase();
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return;
+        }
+
+        let Some(command) = line.strip_prefix(b"AT") else {
+            self.result(ResultCode::Error, receive);
+            return;
+        };
+        self.last_command = line.to_vec();
+
+        let code = self.execute(command, receive);
+
+        // The result of the dial command is reported when the call completes
+        if self.dialing.is_none() {
+            self.result(code, receive);
+        }
+    }
+
+    fn execute(&mut self, command: &[u8], receive: &mut VecDeque<u8>) -> ResultCode {
+        let mut index = 0;
+        while index < command.len() {
+            let ch = command[index];
+            index += 1;
+
+            match ch {
+                b' ' => {}
+                b'A' => return self.answer(),
+                b'D' => return self.dial(&command[index..]),
+                b'O' => {
+                    return if self.stream.is_some() {
+                        self.online = true;
+                        self.last_data = self.now;
+                        ResultCode::Connect
+                    } else {
+                        ResultCode::NoCarrier
+                    };
+                }
+                b'E' | b'Q' | b'V' | b'H' | b'Z' | b'I' => {
+                    let value = parse_number(command, &mut index).unwrap_or(0);
+                    match (ch, value) {
+                        (b'E', 0..=1) => self.echo = value == 1,
+                        (b'Q', 0..=1) => self.quiet = value == 1,
+                        (b'V', 0..=1) => self.verbose = value == 1,
+                        (b'H', 0) => self.hang_up(),
+                        (b'H', 1) => {}
+                        (b'Z', _) => self.reset(),
+                        (b'I', _) => self.info(&format!("emu6502 Hayes modem ({value})"), receive),
+                        _ => return ResultCode::Error,
+                    }
+                }
+                b'S' => {
+                    let Some(reg) = parse_number(command, &mut index) else {
+                        return ResultCode::Error;
+                    };
+                    if reg >= NUM_REGISTERS {
+                        return ResultCode::Error;
+                    }
+                    match command.get(index) {
+                        Some(b'=') => {
+                            index += 1;
+                            let value = parse_number(command, &mut index).unwrap_or(0);
+                            if value > 255 {
+                                return ResultCode::Error;
+                            }
+                            self.registers[reg] = value as u8;
+                        }
+                        Some(b'?') => {
+                            index += 1;
+                            self.info(&format!("{:03}", self.registers[reg]), receive);
+                        }
+                        _ => return ResultCode::Error,
+                    }
+                }
+                b'&' | b'\\' | b'%' => {
+                    let Some(&option) = command.get(index) else {
+                        return ResultCode::Error;
+                    };
+                    index += 1;
+                    parse_number(command, &mut index);
+                    if ch == b'&' && option == b'F' {
+                        self.reset();
+                    }
+                }
+                b'B' | b'C' | b'L' | b'M' | b'N' | b'P' | b'T' | b'W' | b'X' | b'Y' => {
+                    parse_number(command, &mut index);
+                }
+                _ => return ResultCode::Error,
+            }
+        }
+        ResultCode::Ok
+    }
+
+    fn answer(&mut self) -> ResultCode {
+        let Some(stream) = self.incoming.take() else {
+            return ResultCode::NoCarrier;
+        };
+        self.stream = Some(stream);
+        self.online = true;
+        self.escape_count = 0;
+        self.last_data = self.now;
+        self.last_ring = None;
+        ResultCode::Connect
+    }
+
+    fn dial(&mut self, number: &[u8]) -> ResultCode {
+        // Skip the tone or pulse dialing modifier
+        let number = number.strip_prefix(b"T").unwrap_or(number);
+        let number = number.strip_prefix(b"P").unwrap_or(number);
+        let number = String::from_utf8_lossy(number).trim().to_ascii_lowercase();
+        if number.is_empty() {
+            return ResultCode::Error;
+        }
+
+        let addr = match number.split_once([':', ' ']) {
+            Some((host, port)) => format!("{host}:{}", port.trim()),
+            None => format!("{number}:{DEFAULT_PORT}"),
+        };
+
+        self.hang_up();
+        self.dialing = Some(connect_tcp_async(&addr));
+        ResultCode::Ok
+    }
+
+    fn dial_result(&mut self, result: io::Result<TcpStream>, receive: &mut VecDeque<u8>) {
+        self.dialing = None;
+        match result {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                self.online = true;
+                self.escape_count = 0;
+                self.last_data = self.now;
+                self.result(ResultCode::Connect, receive);
+            }
+            Err(_) => self.result(ResultCode::NoCarrier, receive),
+        }
+    }
+}
+
+fn parse_number(command: &[u8], index: &mut usize) -> Option<usize> {
+    let start = *index;
+    while *index < command.len() && command[*index].is_ascii_digit() {
+        *index += 1;
+    }
+    if *index == start {
+        return None;
+    }
+    std::str::from_utf8(&command[start..*index])
+        .ok()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn send_at(modem: &mut HayesModem, now: Instant, data: &[u8]) -> String {
+        let mut receive = VecDeque::new();
+        let mut transmit: VecDeque<u8> = data.iter().copied().collect();
+        modem.poll_at(now, &mut receive, &mut transmit);
+        String::from_utf8_lossy(receive.make_contiguous()).into_owned()
+    }
+
+    fn send(modem: &mut HayesModem, data: &[u8]) -> String {
+        let now = modem.now;
+        send_at(modem, now, data)
+    }
+
+    // Wait for the dialing thread instead of polling
+    fn wait_dial(modem: &mut HayesModem) -> String {
+        let result = modem.dialing.take().unwrap().recv_timeout(TIMEOUT).unwrap();
+        let mut receive = VecDeque::new();
+        modem.dial_result(result, &mut receive);
+        String::from_utf8_lossy(receive.make_contiguous()).into_owned()
+    }
+
+    // Block until the data from the remote side arrives
+    fn receive(modem: &mut HayesModem) -> String {
+        let stream = modem.stream.as_ref().unwrap();
+        stream.set_nonblocking(false).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let text = send(modem, b"");
+        if let Some(stream) = &modem.stream {
+            stream.set_nonblocking(true).unwrap();
+        }
+        text
+    }
+
+    fn connect(modem: &mut HayesModem) -> TcpStream {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let dial = format!("ATDT 127.0.0.1:{port}\r");
+        assert_eq!(send(modem, dial.as_bytes()), "");
+        assert_eq!(wait_dial(modem), "\r\nCONNECT\r\n");
+        assert!(modem.is_carrier() && modem.is_online());
+
+        let (remote, _) = server.accept().unwrap();
+        remote.set_read_timeout(Some(TIMEOUT)).unwrap();
+        remote
+    }
+
+    #[test]
+    fn command_mode() {
+        let mut modem = HayesModem::new(None).unwrap();
+        assert_eq!(send(&mut modem, b"AT\r"), "AT\r\r\nOK\r\n");
+        assert_eq!(send(&mut modem, b"ATE0 S0=2\r"), "ATE0 S0=2\r\r\nOK\r\n");
+        assert_eq!(send(&mut modem, b"ATS0?\r"), "\r\n002\r\n\r\nOK\r\n");
+        assert_eq!(send(&mut modem, b"ATV0\r"), "0\r");
+        assert_eq!(send(&mut modem, b"ATJ\r"), "4\r");
+        assert_eq!(send(&mut modem, b"A/"), "4\r");
+        assert_eq!(send(&mut modem, b"ATO\r"), "3\r");
+        assert_eq!(send(&mut modem, b"ATZ\r"), "\r\nOK\r\n");
+        assert_eq!(send(&mut modem, b"AT\r"), "AT\r\r\nOK\r\n");
+    }
+
+    #[test]
+    fn dial_and_escape() {
+        let mut modem = HayesModem::new(None).unwrap();
+        send(&mut modem, b"ATE0S12=5\r");
+        let mut remote = connect(&mut modem);
+        let start = modem.now;
+        let guard = modem.guard_time();
+
+        send(&mut modem, b"HELLO");
+        let mut data = [0u8; 5];
+        remote.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"HELLO");
+
+        remote.write_all(b"WORLD").unwrap();
+        assert_eq!(receive(&mut modem), "WORLD");
+
+        // The escape characters are too far apart
+        let mut now = start + guard;
+        for _ in 0..3 {
+            send_at(&mut modem, now, b"+");
+            now += guard;
+        }
+        assert!(modem.is_online());
+
+        // No guard time before the escape characters
+        now += guard;
+        send_at(&mut modem, now, b"X+++");
+        assert_eq!(send_at(&mut modem, now + guard, b""), "");
+        assert!(modem.is_online());
+
+        // The data after the escape characters cancels the escape
+        now += guard * 2;
+        send_at(&mut modem, now, b"+++X");
+        assert_eq!(send_at(&mut modem, now + guard, b""), "");
+        assert!(modem.is_online());
+
+        // No guard time after the escape characters
+        now += guard * 2;
+        send_at(&mut modem, now, b"++");
+        send_at(&mut modem, now + guard / 2, b"+");
+        assert_eq!(send_at(&mut modem, now + guard, b""), "");
+        assert!(modem.is_online());
+
+        assert_eq!(send_at(&mut modem, now + guard * 2, b""), "\r\nOK\r\n");
+        assert!(modem.is_carrier() && !modem.is_online());
+
+        assert_eq!(send(&mut modem, b"ATO\r"), "\r\nCONNECT\r\n");
+        assert!(modem.is_online());
+        now += guard * 4;
+        send_at(&mut modem, now, b"+++");
+        assert_eq!(send_at(&mut modem, now + guard, b""), "\r\nOK\r\n");
+
+        assert_eq!(send(&mut modem, b"ATH\r"), "\r\nOK\r\n");
+        assert!(!modem.is_carrier());
+
+        // The remote side receives all the data
+        let mut data = Vec::new();
+        remote.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"+++X++++++X++++++");
+    }
+
+    #[test]
+    fn dial_failure_and_cancel() {
+        let mut modem = HayesModem::new(None).unwrap();
+        send(&mut modem, b"ATE0\r");
+
+        // Nothing listens on the port of a closed listener
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let dial = format!("ATD127.0.0.1:{port}\r");
+        assert_eq!(send(&mut modem, dial.as_bytes()), "");
+        assert_eq!(wait_dial(&mut modem), "\r\nNO CARRIER\r\n");
+        assert!(!modem.is_carrier());
+
+        // A key press while dialing hangs up
+        assert_eq!(send(&mut modem, dial.as_bytes()), "");
+        assert!(modem.dialing.is_some());
+        assert_eq!(send(&mut modem, b" "), "\r\nNO CARRIER\r\n");
+        assert!(modem.dialing.is_none());
+        assert_eq!(send(&mut modem, b"AT\r"), "\r\nOK\r\n");
+    }
+
+    #[test]
+    fn answer_incoming_call() {
+        let mut modem = HayesModem::new(Some("127.0.0.1:0")).unwrap();
+        send(&mut modem, b"ATE0\r");
+        let mut caller = TcpStream::connect(modem.local_addr().unwrap()).unwrap();
+        caller.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        // Accept the call without polling the listener
+        let (stream, _) = modem.listener.as_ref().unwrap().accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        modem.incoming = Some(stream);
+
+        let start = modem.now;
+        assert_eq!(send_at(&mut modem, start, b""), "\r\nRING\r\n");
+        assert_eq!(send_at(&mut modem, start + RING_INTERVAL / 2, b""), "");
+        assert_eq!(
+            send_at(&mut modem, start + RING_INTERVAL, b""),
+            "\r\nRING\r\n"
+        );
+        assert_eq!(send(&mut modem, b"ATS1?\r"), "\r\n002\r\n\r\nOK\r\n");
+        assert_eq!(send(&mut modem, b"ATA\r"), "\r\nCONNECT\r\n");
+
+        caller.write_all(b"BBS").unwrap();
+        assert_eq!(receive(&mut modem), "BBS");
+        send(&mut modem, b"Y");
+        let mut data = [0u8; 1];
+        caller.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"Y");
+
+        drop(caller);
+        assert_eq!(receive(&mut modem), "\r\nNO CARRIER\r\n");
+        assert!(!modem.is_carrier());
+    }
+
+    #[test]
+    fn auto_answer() {
+        let mut modem = HayesModem::new(Some("127.0.0.1:0")).unwrap();
+        send(&mut modem, b"ATE0S0=1\r");
+        let _caller = TcpStream::connect(modem.local_addr().unwrap()).unwrap();
+        let (stream, _) = modem.listener.as_ref().unwrap().accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        modem.incoming = Some(stream);
+
+        assert_eq!(send(&mut modem, b""), "\r\nRING\r\n\r\nCONNECT\r\n");
+        assert!(modem.is_carrier() && modem.is_online());
+    }
+}
@@ -1,5 +1,6 @@
 use crate::bus::Card;
 use crate::mmu::Mmu;
+use crate::usernet::UserNetwork;
 use crate::video::Video;
 use std::io::ErrorKind;
 use std::io::{Read, Write};
@@ -48,8 +49,8 @@ const W5100_SIPR0: usize = 0x0f;
 const W5100_SIPR1: usize = 0x10;
 const W5100_SIPR2: usize = 0x11;
 const W5100_SIPR3: usize = 0x12;
-const _W5100_IR: usize = 0x15;
-const _W5100_IMR: usize = 0x16;
+const W5100_IR: usize = 0x15;
+const W5100_IMR: usize = 0x16;
 const W5100_RTR0: usize = 0x17;
 const W5100_RTR1: usize = 0x18;
 const W5100_RCR: usize = 0x19;
@@ -70,10 +71,14 @@ const W5100_MR_IND: u8 = 0x01;
 const W5100_MR_AI: u8 = 0x02;
 const W5100_MR_RST: u8 = 0x80;
 
+// _W5100 interrupt register constants. Bit 0-3 are the interrupts of the 4 sockets
+const W5100_IR_SOCKETS: u8 = 0x0f;
+const W5100_IR_CLEAR_MASK: u8 = 0xe0;
+
 // _W5100 socket constants
 const W5100_SN_MR: usize = 0x00;
 const W5100_SN_CR: usize = 0x01;
-const _W5100_SN_IR: usize = 0x02;
+const W5100_SN_IR: usize = 0x02;
 const W5100_SN_SR: usize = 0x03;
 const W5100_SN_PORT0: usize = 0x04;
 const W5100_SN_PORT1: usize = 0x05;
@@ -109,6 +114,16 @@ const W5100_SN_DNS_NAME_BEGIN: usize = 0x2b;
 const W5100_SN_DNS_NAME_END: usize = 0xff;
 const W5100_SN_DNS_NAME_CPTY: usize = W5100_SN_DNS_NAME_END - W5100_SN_DNS_NAME_BEGIN;
 
+// _W5100 socket interrupt register constants
+const W5100_SN_IR_CON: u8 = 0x01;
+const W5100_SN_IR_DISCON: u8 = 0x02;
+const W5100_SN_IR_RECV: u8 = 0x04;
+const W5100_SN_IR_TIMEOUT: u8 = 0x08;
+const W5100_SN_IR_SEND_OK: u8 = 0x10;
+
+// Sockets are polled every 1000 cycles for the interrupts
+const POLL_INTERVAL: usize = 1000;
+
 // _W5100 socket mode register constants
 const W5100_SN_MR_PROTO_MASK: u8 = 0x0f;
 const _W5100_SN_MR_MF: u8 = 0x40;
@@ -174,7 +189,7 @@ macro_rules! u2_debug {
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "pcap")]
-struct PcapCapture(pcap::Capture<pcap::Active>);
+pub(crate) struct PcapCapture(pub(crate) pcap::Capture<pcap::Active>);
 
 #[cfg(feature = "pcap")]
 impl Debug for PcapCapture {
@@ -183,6 +198,149 @@ impl Debug for PcapCapture {
     }
 }
 
+#[cfg(target_os = "windows")]
+#[cfg(feature = "pcap")]
+fn is_pcap_available() -> bool {
+    *PCAP_LOADED.get_or_init(|| unsafe { libloading::Library::new("wpcap") }.is_ok())
+}
+
+/// Returns the names of the network interfaces that can be used for the raw ethernet
+/// frames of the network cards
+pub(crate) fn list_pcap_interfaces() -> Vec<String> {
+    let names: Vec<String>;
+    #[cfg(feature = "pcap")]
+    {
+        #[cfg(target_os = "windows")]
+        if is_pcap_available() {
+            names = list_pcap_device_names();
+        } else {
+            names = Vec::new();
+        }
+
+        #[cfg(not(target_os = "windows"))]
+        {
+            names = list_pcap_device_names();
+        }
+    }
+
+    #[cfg(not(feature = "pcap"))]
+    {
+        names = Vec::new();
+    }
+    names
+}
+
+/// Opens the non blocking capture of the interface for the raw ethernet frames. The
+/// default capture device of pcap is used when the interface is not provided or found
+#[cfg(feature = "pcap")]
+pub(crate) fn open_pcap_capture(interface: Option<&str>) -> Option<PcapCapture> {
+    #[cfg(target_os = "windows")]
+    if !is_pcap_available() {
+        return None;
+    }
+
+    let mut device = find_pcap_device(interface);
+
+    if device.is_none() {
+        // No interface provided or found, try to get default capture device from pcap
+        let result = pcap::Device::lookup();
+        if let Ok(Some(lookup_device)) = result {
+            device = Some(lookup_device)
+        } else if let Err(error) = result {
+            u2_debug!("Unable to lookup device: {:?}", error);
+        }
+    }
+
+    let device = device?;
+    u2_debug!("Using device name: {} desc: {:?}", device.name, device.desc);
+    let cap_active = get_pcap_device(&device)?;
+    match cap_active.setnonblock() {
+        Ok(nonblock_cap) => Some(PcapCapture(nonblock_cap)),
+        Err(error) => {
+            u2_debug!(
+                "Unable to set nonblock to device. Fall back to blocking mode: {:?}",
+                error
+            );
+            get_pcap_device(&device).map(PcapCapture)
+        }
+    }
+}
+
+#[cfg(feature = "pcap")]
+fn list_pcap_device_names() -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(devices) = pcap::Device::list() {
+        for device in devices {
+            let name = if let Some(desc) = device.desc {
+                format!("{} - {}", desc, device.name)
+            } else {
+                device.name.to_string()
+            };
+            names.push(name);
+        }
+    }
+    names
+}
+
+#[cfg(feature = "pcap")]
+fn find_pcap_device(interface: Option<&str>) -> Option<pcap::Device> {
+    // Attempt to find the device based on provided interface name or default to lookup
+    if let Some(name) = interface {
+        match pcap::Device::list() {
+            Ok(devices) => {
+                for device in devices {
+                    if device.name.to_lowercase().contains(&name.to_lowercase())
+                        || device
+                            .desc
+                            .as_ref()
+                            .is_some_and(|s| s.to_lowercase().contains(&name.to_lowercase()))
+                    {
+                        return Some(device);
+                    }
+                }
+                None
+            }
+            Err(error) => {
+                u2_debug!("Unable to list pcap devices: {:?}", error);
+                None
+            }
+        }
+    } else {
+        match pcap::Device::lookup() {
+            Ok(Some(device)) => Some(device),
+            Ok(None) => {
+                u2_debug!("No pcap device found.");
+                None
+            }
+            Err(error) => {
+                u2_debug!("Unable to lookup device: {:?}", error);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(feature = "pcap")]
+fn get_pcap_device(device: &pcap::Device) -> Option<pcap::Capture<pcap::Active>> {
+    let result = pcap::Capture::from_device(device.clone());
+    if let Ok(cap) = result {
+        let cap_result = cap.snaplen(1700).promisc(true).timeout(20).open();
+        if let Ok(cap) = cap_result {
+            Some(cap)
+        } else {
+            if let Err(error) = cap_result {
+                u2_debug!("Unable to get device: {:?}", error);
+            }
+            None
+        }
+    } else {
+        if let Err(error) = result {
+            u2_debug!("Unable to get device: {:?}", error);
+        }
+        None
+    }
+}
+
 #[derive(Debug, Default)]
 enum Proto {
     #[default]
@@ -190,6 +348,7 @@ enum Proto {
     Tcp(TcpStream),
     TcpListener(TcpListener),
     Udp(UdpSocket),
+    User(UserNetwork),
 
     #[cfg(feature = "pcap")]
     MacRaw(PcapCapture),
@@ -249,6 +408,21 @@ pub struct Uthernet2 {
     mem: Vec<u8>,
     socket: Vec<Socket>,
     interface: Option<String>,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    user_network: bool,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    irq_enabled: bool,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    irq_happen: Option<usize>,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    cycles: usize,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    next_poll: usize,
 }
 
 impl Default for Uthernet2 {
@@ -263,6 +437,11 @@ impl Default for Uthernet2 {
             mem: vec![0; 0x8000],
             socket,
             interface: None,
+            user_network: false,
+            irq_enabled: false,
+            irq_happen: None,
+            cycles: 0,
+            next_poll: 0,
         };
         instance.reset(true);
         instance
@@ -270,18 +449,6 @@ impl Default for Uthernet2 {
 }
 
 impl Uthernet2 {
-    #[cfg(target_os = "windows")]
-    #[cfg(feature = "pcap")]
-    fn check_pcap_availability() -> bool {
-        unsafe { libloading::Library::new("wpcap") }.is_ok()
-    }
-
-    #[cfg(target_os = "windows")]
-    #[cfg(feature = "pcap")]
-    fn is_pcap_available(&self) -> bool {
-        *PCAP_LOADED.get_or_init(Uthernet2::check_pcap_availability)
-    }
-
     pub fn new() -> Self {
         Uthernet2::default()
     }
@@ -293,6 +460,7 @@ impl Uthernet2 {
 
         self.mode = 0;
         self.mem = vec![0; 0x8000];
+        self.irq_happen = None;
 
         for socket in &mut self.socket {
             socket.clear_socket_handle();
@@ -324,35 +492,87 @@ impl Uthernet2 {
     }
 
     pub fn list_interfaces(&self) -> Vec<String> {
-        let names: Vec<String>;
-        #[cfg(feature = "pcap")]
-        {
-            #[cfg(target_os = "windows")]
-            if self.is_pcap_available() {
-                names = self.list_pcap_device_names();
-            } else {
-                names = Vec::new();
+        list_pcap_interfaces()
+    }
+
+    pub fn set_interface(&mut self, name: String) {
+        self.interface = Some(name);
+    }
+
+    pub fn get_interface(&self) -> Option<String> {
+        self.interface.clone()
+    }
+
+    /// Use the user-mode network for the MACRAW sockets instead of the pcap interface.
+    /// The user-mode network is also used when the pcap interface cannot be opened
+    pub fn set_user_network(&mut self, state: bool) {
+        self.user_network = state;
+    }
+
+    /// Connects the interrupt line of the card. The interrupts enabled in IMR are then
+    /// asserted through `Bus::irq`
+    pub fn set_irq_enabled(&mut self, state: bool) {
+        self.irq_enabled = state;
+        self.update_interrupt();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.irq_enabled && self.mem[W5100_IMR] & W5100_IR_SOCKETS != 0
+    }
+
+    /// Polls the sockets with enabled interrupts for received data and incoming
+    /// connections, so that the interrupts are raised without reading RX_RSR.
+    /// Called every cycle so that the interrupts raised by the register writes
+    /// are stamped with the current cycle
+    pub fn tick(&mut self, cycles: usize) {
+        self.cycles = cycles;
+
+        if !self.is_active() || cycles < self.next_poll {
+            return;
+        }
+        self.next_poll = cycles + POLL_INTERVAL;
+
+        for i in 0..self.socket.len() {
+            if self.mem[W5100_IMR] & (1 << i) == 0 {
+                continue;
             }
 
-            #[cfg(not(target_os = "windows"))]
-            {
-                names = self.list_pcap_device_names();
+            if self.get_socket_status(i) == W5100_SN_SR_SOCK_LISTEN {
+                self.listen_socket(i);
+            } else {
+                self.receive_one_packet(i);
             }
         }
+    }
 
-        #[cfg(not(feature = "pcap"))]
-        {
-            names = Vec::new();
-        }
-        names
+    pub fn poll_irq(&self) -> Option<usize> {
+        self.irq_happen
     }
 
-    pub fn set_interface(&mut self, name: String) {
-        self.interface = Some(name);
+    fn set_socket_interrupt(&mut self, i: usize, value: u8) {
+        let base_addr = self.get_base_socket_addr(i);
+        self.mem[base_addr + W5100_SN_IR] |= value;
+        self.update_interrupt();
     }
 
-    pub fn get_interface(&self) -> Option<String> {
-        self.interface.clone()
+    // The socket interrupts of IR follow SN_IR. The interrupt is asserted when an
+    // interrupt of IR is enabled in IMR
+    fn update_interrupt(&mut self) {
+        let mut sockets = 0;
+        for i in 0..self.socket.len() {
+            if self.mem[self.get_base_socket_addr(i) + W5100_SN_IR] != 0 {
+                sockets |= 1 << i;
+            }
+        }
+        self.mem[W5100_IR] = (self.mem[W5100_IR] & !W5100_IR_SOCKETS) | sockets;
+
+        if self.irq_enabled && self.mem[W5100_IR] & self.mem[W5100_IMR] != 0 {
+            if self.irq_happen.is_none() {
+                self.irq_happen = Some(self.cycles);
+            }
+        } else {
+            self.irq_happen = None;
+        }
     }
 
     fn auto_increment(&mut self) {
@@ -466,39 +686,24 @@ impl Uthernet2 {
                 }
             }
         } else if socket.status == W5100_SN_SR_SOCK_MACRAW {
-            #[cfg(feature = "pcap")]
             self.receive_one_raw_packet_from_socket(i)
         }
     }
 
-    #[cfg(feature = "pcap")]
     fn receive_one_raw_packet_from_socket(&mut self, i: usize) {
         let base_addr = self.get_base_socket_addr(i);
         let socket = &mut self.socket[i];
-        if let Proto::MacRaw(pcap_capture) = &mut socket.socket_handle {
-            match pcap_capture.0.next_packet() {
-                Ok(packet) => {
-                    let buffer = Vec::from(packet.data);
-
-                    if buffer.len() >= 6 {
-                        let mac = &self.mem[W5100_SHAR0..=W5100_SHAR5];
-                        let broadcast = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
-                        let mr = self.mem[base_addr + W5100_SN_MR];
-                        let accept_all = mr & _W5100_SN_MR_MF == 0;
-
-                        if accept_all || *mac == buffer[0..6] || broadcast == buffer[0..6] {
-                            let rsr = u16::from_be_bytes([
-                                self.mem[base_addr + W5100_SN_RX_RSR0],
-                                self.mem[base_addr + W5100_SN_RX_RSR1],
-                            ]) as usize;
-                            if rsr + 2 + buffer.len() < socket.receive_size {
-                                self.write_raw_data_for_protocol(i, &buffer);
-                            }
-                        }
-                    }
-                }
+        let buffer = match &mut socket.socket_handle {
+            Proto::User(network) => match network.receive() {
+                Some(frame) => frame,
+                None => return,
+            },
 
-                Err(pcap::Error::TimeoutExpired) => {}
+            #[cfg(feature = "pcap")]
+            Proto::MacRaw(pcap_capture) => match pcap_capture.0.next_packet() {
+                Ok(packet) => Vec::from(packet.data),
+
+                Err(pcap::Error::TimeoutExpired) => return,
 
                 Err(error) => {
                     u2_debug!(
@@ -506,6 +711,26 @@ impl Uthernet2 {
                         error
                     );
                     self.clear_socket(i);
+                    return;
+                }
+            },
+
+            _ => return,
+        };
+
+        if buffer.len() >= 6 {
+            let mac = &self.mem[W5100_SHAR0..=W5100_SHAR5];
+            let broadcast = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+            let mr = self.mem[base_addr + W5100_SN_MR];
+            let accept_all = mr & _W5100_SN_MR_MF == 0;
+
+            if accept_all || *mac == buffer[0..6] || broadcast == buffer[0..6] {
+                let rsr = u16::from_be_bytes([
+                    self.mem[base_addr + W5100_SN_RX_RSR0],
+                    self.mem[base_addr + W5100_SN_RX_RSR1],
+                ]) as usize;
+                if rsr + 2 + buffer.len() < self.socket[i].receive_size {
+                    self.write_raw_data_for_protocol(i, &buffer);
                 }
             }
         }
@@ -528,6 +753,7 @@ impl Uthernet2 {
                     Ok(size) => {
                         //u2_debug!("Read bytes received from peer = 0x{size:02X}");
                         if size == 0 {
+                            self.set_socket_interrupt(i, W5100_SN_IR_DISCON);
                             self.clear_socket(i);
                         } else {
                             self.write_data_for_protocol(i, &buffer[0..size]);
@@ -541,6 +767,7 @@ impl Uthernet2 {
                             "Read bytes received from peer ERROR {:?} - Closing socket",
                             error
                         );
+                        self.set_socket_interrupt(i, W5100_SN_IR_DISCON);
                         self.clear_socket(i);
                     }
                 }
@@ -609,9 +836,9 @@ impl Uthernet2 {
         let size = u16::to_be_bytes(rsr as u16);
         self.mem[base_addr + W5100_SN_RX_RSR0] = size[0];
         self.mem[base_addr + W5100_SN_RX_RSR1] = size[1];
+        self.set_socket_interrupt(i, W5100_SN_IR_RECV);
     }
 
-    #[cfg(feature = "pcap")]
     fn write_raw_data_for_protocol(&mut self, i: usize, data: &[u8]) {
         let base_addr = self.get_base_socket_addr(i);
         let socket = &mut self.socket[i];
@@ -638,6 +865,7 @@ impl Uthernet2 {
         let size = u16::to_be_bytes(rsr as u16);
         self.mem[base_addr + W5100_SN_RX_RSR0] = size[0];
         self.mem[base_addr + W5100_SN_RX_RSR1] = size[1];
+        self.set_socket_interrupt(i, W5100_SN_IR_RECV);
     }
 
     fn get_transmit_free_size_register(&self, i: usize, shift: usize) -> u8 {
@@ -707,6 +935,14 @@ impl Uthernet2 {
             W5100_SHAR0..=W5100_SHAR5 => self.mem[addr] = value,
             W5100_SIPR0..=W5100_SIPR3 => self.mem[addr] = value,
             W5100_SUBR0..=W5100_SUBR3 => self.mem[addr] = value,
+            W5100_IR => {
+                self.mem[addr] &= !(value & W5100_IR_CLEAR_MASK);
+                self.update_interrupt();
+            }
+            W5100_IMR => {
+                self.mem[addr] = value;
+                self.update_interrupt();
+            }
             _ => {}
         };
         //u2_debug!("Write to memory addr = 0x{addr:04X} value = 0x{value:02X}");
@@ -777,6 +1013,11 @@ impl Uthernet2 {
         match loc {
             W5100_SN_MR => self.set_socket_mode_register(unit, addr, value),
             W5100_SN_CR => self.set_command_register(unit, addr, value),
+            W5100_SN_IR => {
+                // Interrupts are cleared by writing 1
+                self.mem[addr] &= !value;
+                self.update_interrupt();
+            }
             W5100_SN_PORT0 | W5100_SN_PORT1 | W5100_SN_DPORT0 | W5100_SN_DPORT1 => {
                 self.mem[addr] = value
             }
@@ -825,7 +1066,11 @@ impl Uthernet2 {
             }
 
             W5100_SN_CR_CONNECT => self.connect_socket(i),
-            W5100_SN_CR_CLOSE | W5100_SN_CR_DISCONNECT => self.close_socket(i),
+            W5100_SN_CR_CLOSE => self.close_socket(i),
+            W5100_SN_CR_DISCONNECT => {
+                self.close_socket(i);
+                self.set_socket_interrupt(i, W5100_SN_IR_DISCON);
+            }
             W5100_SN_CR_SEND => self.send_data(i),
             W5100_SN_CR_RECV => self.update_rsr(i),
             _ => u2_debug!("Unknown Command received on #{i} Command: 0x{value:02X}"),
@@ -883,16 +1128,7 @@ impl Uthernet2 {
                 self.set_socket_status(i, W5100_SN_SR_SOCK_IPRAW)
             }
             W5100_SN_MR_MACRAW => {
-                #[cfg(feature = "pcap")]
-                {
-                    #[cfg(target_os = "windows")]
-                    if self.is_pcap_available() {
-                        self.assign_interface_to_raw_protocol(i);
-                    }
-
-                    #[cfg(not(target_os = "windows"))]
-                    self.assign_interface_to_raw_protocol(i);
-                }
+                self.assign_interface_to_raw_protocol(i);
 
                 self.set_socket_status(i, W5100_SN_SR_SOCK_MACRAW);
             }
@@ -937,115 +1173,17 @@ impl Uthernet2 {
         }
     }
 
-    #[cfg(feature = "pcap")]
     fn assign_interface_to_raw_protocol(&mut self, i: usize) {
-        let mut device = self.find_pcap_device();
-
-        if device.is_none() {
-            // No interface provided or found, try to get default capture device from pcap
-            let result = pcap::Device::lookup();
-            if let Ok(Some(lookup_device)) = result {
-                device = Some(lookup_device)
-            } else if let Err(error) = result {
-                u2_debug!("Unable to lookup device: {:?}", error);
-            }
-        }
-
-        let Some(device) = device else { return };
-        u2_debug!("Using device name: {} desc: {:?}", device.name, device.desc);
-        let Some(cap_active) = self.get_pcap_device(&device) else {
+        #[cfg(feature = "pcap")]
+        if !self.user_network
+            && let Some(capture) = open_pcap_capture(self.interface.as_deref())
+        {
+            self.socket[i].set_socket_handle(Proto::MacRaw(capture));
             return;
-        };
-        match cap_active.setnonblock() {
-            Ok(nonblock_cap) => {
-                self.socket[i].set_socket_handle(Proto::MacRaw(PcapCapture(nonblock_cap)));
-            }
-            Err(error) => {
-                u2_debug!(
-                    "Unable to set nonblock to device. Fall back to blocking mode: {:?}",
-                    error
-                );
-                let Some(cap_active) = self.get_pcap_device(&device) else {
-                    return;
-                };
-                self.socket[i].set_socket_handle(Proto::MacRaw(PcapCapture(cap_active)));
-            }
-        }
-    }
-
-    #[cfg(feature = "pcap")]
-    fn list_pcap_device_names(&self) -> Vec<String> {
-        let mut names = Vec::new();
-        if let Ok(devices) = pcap::Device::list() {
-            for device in devices {
-                let name = if let Some(desc) = device.desc {
-                    format!("{} - {}", desc, device.name)
-                } else {
-                    device.name.to_string()
-                };
-                names.push(name);
-            }
         }
-        names
-    }
 
-    #[cfg(feature = "pcap")]
-    fn find_pcap_device(&self) -> Option<pcap::Device> {
-        // Attempt to find the device based on provided interface name or default to lookup
-        if let Some(name) = &self.interface {
-            match pcap::Device::list() {
-                Ok(devices) => {
-                    for device in devices {
-                        if device.name.to_lowercase().contains(&name.to_lowercase())
-                            || device
-                                .desc
-                                .as_ref()
-                                .is_some_and(|s| s.to_lowercase().contains(&name.to_lowercase()))
-                        {
-                            return Some(device);
-                        }
-                    }
-                    None
-                }
-                Err(error) => {
-                    u2_debug!("Unable to list pcap devices: {:?}", error);
-                    None
-                }
-            }
-        } else {
-            match pcap::Device::lookup() {
-                Ok(Some(device)) => Some(device),
-                Ok(None) => {
-                    u2_debug!("No pcap device found.");
-                    None
-                }
-                Err(error) => {
-                    u2_debug!("Unable to lookup device: {:?}", error);
-                    None
-                }
-            }
-        }
-    }
-
-    #[cfg(feature = "pcap")]
-    fn get_pcap_device(&self, device: &pcap::Device) -> Option<pcap::Capture<pcap::Active>> {
-        let result = pcap::Capture::from_device(device.clone());
-        if let Ok(cap) = result {
-            let cap_result = cap.snaplen(1700).promisc(true).timeout(20).open();
-            if let Ok(cap) = cap_result {
-                Some(cap)
-            } else {
-                if let Err(error) = cap_result {
-                    u2_debug!("Unable to get device: {:?}", error);
-                }
-                None
-            }
-        } else {
-            if let Err(error) = result {
-                u2_debug!("Unable to get device: {:?}", error);
-            }
-            None
-        }
+        u2_debug!("Socket #{i} uses the user-mode network");
+        self.socket[i].set_socket_handle(Proto::User(UserNetwork::new()));
     }
 
     fn clear_socket_dest(&mut self, i: usize) {
@@ -1130,6 +1268,7 @@ impl Uthernet2 {
                 if stream.set_nonblocking(true).is_ok() {
                     self.socket[i].set_socket_handle(Proto::Tcp(stream));
                     self.set_socket_status(i, W5100_SN_SR_SOCK_ESTABLISHED);
+                    self.set_socket_interrupt(i, W5100_SN_IR_CON);
                 } else {
                     u2_debug!("Connect Socket on #{i} - Failed to set non-blocking");
                     self.clear_socket(i);
@@ -1138,6 +1277,7 @@ impl Uthernet2 {
             _ => {
                 u2_debug!("Connect Socket on #{i} to {sock_addr:?} FAILED");
                 self.clear_socket(i);
+                self.set_socket_interrupt(i, W5100_SN_IR_TIMEOUT);
             }
         }
     }
@@ -1202,6 +1342,7 @@ impl Uthernet2 {
                 match stream {
                     Ok(s) => {
                         self.set_socket_status(i, W5100_SN_SR_SOCK_ESTABLISHED);
+                        self.set_socket_interrupt(i, W5100_SN_IR_CON);
                         return Some(s);
                     }
                     Err(ref e) if e.kind() == ErrorKind::WouldBlock => return None,
@@ -1269,9 +1410,12 @@ impl Uthernet2 {
                 _ => u2_debug!("Send data Socket#{i} Unknown mode: 0x{:02X}", socket.status),
             }
         } else if socket.status == W5100_SN_SR_SOCK_MACRAW {
-            #[cfg(feature = "pcap")]
             self.send_raw_data_to_socket(i, &data);
         }
+
+        if self.get_socket_status(i) != W5100_SN_SR_CLOSED {
+            self.set_socket_interrupt(i, W5100_SN_IR_SEND_OK);
+        }
     }
 
     fn send_data_to_socket(&mut self, i: usize, data: &[u8]) {
@@ -1286,6 +1430,7 @@ impl Uthernet2 {
                         && !(matches!(error.kind(), ErrorKind::WouldBlock))
                     {
                         self.clear_socket(i);
+                        self.set_socket_interrupt(i, W5100_SN_IR_TIMEOUT);
                     }
                 }
                 _ => {
@@ -1314,16 +1459,22 @@ impl Uthernet2 {
         }
     }
 
-    #[cfg(feature = "pcap")]
     fn send_raw_data_to_socket(&mut self, i: usize, data: &[u8]) {
         let socket = &mut self.socket[i];
 
-        if let Proto::MacRaw(pcap_capture) = &mut socket.socket_handle {
-            let result = pcap_capture.0.sendpacket(data);
-            if let Err(error) = result {
-                u2_debug!("Send Raw data Socket#{i} Error: {:?}", error);
-                self.clear_socket(i);
+        match &mut socket.socket_handle {
+            Proto::User(network) => network.send(data),
+
+            #[cfg(feature = "pcap")]
+            Proto::MacRaw(pcap_capture) => {
+                let result = pcap_capture.0.sendpacket(data);
+                if let Err(error) = result {
+                    u2_debug!("Send Raw data Socket#{i} Error: {:?}", error);
+                    self.clear_socket(i);
+                }
             }
+
+            _ => {}
         }
     }
 
@@ -1356,6 +1507,11 @@ impl Uthernet2 {
         let rsr_to_update = u16::to_be_bytes(data_present as u16);
         self.mem[base_addr + W5100_SN_RX_RSR0] = rsr_to_update[0];
         self.mem[base_addr + W5100_SN_RX_RSR1] = rsr_to_update[1];
+
+        // RECV is raised again while data remains in the receive buffer
+        if data_present > 0 {
+            self.set_socket_interrupt(i, W5100_SN_IR_RECV);
+        }
     }
 }
 
@@ -1431,3 +1587,79 @@ impl Card for Uthernet2 {
         return_value as u8
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_socket(u2: &mut Uthernet2, loc: usize, value: u8) {
+        u2.write_value_at(W5100_S0_BASE + loc, value);
+    }
+
+    fn read_socket(u2: &mut Uthernet2, loc: usize) -> u8 {
+        u2.read_value_at(W5100_S0_BASE + loc)
+    }
+
+    // Poll with a blocking read so that the data sent by the peer is received
+    fn tick_blocking(u2: &mut Uthernet2, cycles: usize) {
+        if let Proto::Tcp(stream) = &u2.socket[0].socket_handle {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream.set_nonblocking(false).unwrap();
+        }
+        u2.tick(cycles);
+        if let Proto::Tcp(stream) = &u2.socket[0].socket_handle {
+            stream.set_nonblocking(true).unwrap();
+        }
+    }
+
+    #[test]
+    fn socket_interrupts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut u2 = Uthernet2::new();
+        u2.set_irq_enabled(true);
+        write_socket(&mut u2, W5100_SN_MR, W5100_SN_MR_TCP);
+        write_socket(&mut u2, W5100_SN_CR, W5100_SN_CR_OPEN);
+        for (i, value) in [127, 0, 0, 1].into_iter().enumerate() {
+            write_socket(&mut u2, W5100_SN_DIPR0 + i, value);
+        }
+        write_socket(&mut u2, W5100_SN_DPORT0, (port >> 8) as u8);
+        write_socket(&mut u2, W5100_SN_DPORT1, port as u8);
+        write_socket(&mut u2, W5100_SN_CR, W5100_SN_CR_CONNECT);
+
+        // Interrupts are tracked but not asserted until enabled in IMR
+        assert_eq!(read_socket(&mut u2, W5100_SN_IR), W5100_SN_IR_CON);
+        assert_eq!(u2.read_value_at(W5100_IR), 0x01);
+        assert_eq!(u2.poll_irq(), None);
+        u2.tick(1000);
+        u2.write_value_at(W5100_IMR, 0x01);
+        assert_eq!(u2.poll_irq(), Some(1000));
+
+        // Cleared by writing 1
+        write_socket(&mut u2, W5100_SN_IR, W5100_SN_IR_CON);
+        assert_eq!(u2.read_value_at(W5100_IR), 0x00);
+        assert_eq!(u2.poll_irq(), None);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"hi").unwrap();
+        tick_blocking(&mut u2, 1000 + POLL_INTERVAL);
+        assert_eq!(read_socket(&mut u2, W5100_SN_IR), W5100_SN_IR_RECV);
+        assert_eq!(read_socket(&mut u2, W5100_SN_RX_RSR1), 2);
+        assert!(u2.poll_irq().is_some());
+        write_socket(&mut u2, W5100_SN_IR, W5100_SN_IR_RECV);
+
+        write_socket(&mut u2, W5100_SN_TX_WR1, 3);
+        write_socket(&mut u2, W5100_SN_CR, W5100_SN_CR_SEND);
+        assert_eq!(read_socket(&mut u2, W5100_SN_IR), W5100_SN_IR_SEND_OK);
+        write_socket(&mut u2, W5100_SN_IR, W5100_SN_IR_SEND_OK);
+
+        drop(stream);
+        tick_blocking(&mut u2, 1000 + 2 * POLL_INTERVAL);
+        assert_eq!(read_socket(&mut u2, W5100_SN_IR), W5100_SN_IR_DISCON);
+        assert_eq!(read_socket(&mut u2, W5100_SN_SR), W5100_SN_SR_CLOSED);
+        assert!(u2.poll_irq().is_some());
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/*
+Copy-on-write overlay (delta) file
+
+The overlay file is stored next to the disk image with the ".delta" suffix appended
+to the image filename. Writes to the mounted image are appended to the overlay file
+and the original image is left untouched until the overlay is committed.
+
+The file is an append-only log. When replayed, a later entry replaces an earlier
+entry with the same index. A format entry discards the earlier entries of the blocks.
+
+    00  "E6DL" magic
+    04  Version (1)
+    08  Entries, each entry is
+            00  Index (block number for hard disk, track number for floppy)
+                FFFFFFFF  Track map of the floppy
+                FFFFFFFE  Format of the hard disk, all the blocks are cleared
+                FFFFFFFD  Write protect state of the hard disk (1 byte, 1 = protected)
+            04  Bit count (number of valid bits, only used for floppy track)
+            08  Length of data
+            0C  Data
+*/
+
+const DELTA_MAGIC: &[u8; 4] = b"E6DL";
+const DELTA_VERSION: u32 = 1;
+const DELTA_HEADER_SIZE: usize = 8;
+const DELTA_ENTRY_HEADER_SIZE: usize = 12;
+
+/// Index used by the floppy drive to store the track map
+pub const DELTA_TMAP_INDEX: u32 = 0xffffffff;
+
+/// Index used by the hard disk to record a format of the whole disk
+pub const DELTA_FORMAT_INDEX: u32 = 0xfffffffe;
+
+/// Index used by the hard disk to store the write protect state
+pub const DELTA_WRITE_PROTECT_INDEX: u32 = 0xfffffffd;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaEntry {
+    pub index: u32,
+    pub bits: u32,
+    pub data: Vec<u8>,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+pub fn delta_filename<P>(image_path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    let mut filename = image_path.as_ref().as_os_str().to_owned();
+    filename.push(".delta");
+    PathBuf::from(filename)
+}
+
+pub fn encode_delta_entry(entry: &DeltaEntry) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(DELTA_ENTRY_HEADER_SIZE + entry.data.len());
+    buf.extend_from_slice(&entry.index.to_le_bytes());
+    buf.extend_from_slice(&entry.bits.to_le_bytes());
+    buf.extend_from_slice(&(entry.data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&entry.data);
+    buf
+}
+
+pub fn decode_delta(data: &[u8]) -> io::Result<Vec<DeltaEntry>> {
+    if data.len() < DELTA_HEADER_SIZE
+        || &data[0..4] != DELTA_MAGIC
+        || read_u32(data, 4) != DELTA_VERSION
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid overlay file",
+        ));
+    }
+
+    // Position of the entry of each index in the entries
+    let mut entries: Vec<DeltaEntry> = Vec::new();
+    let mut positions = HashMap::new();
+    let mut offset = DELTA_HEADER_SIZE;
+
+    while offset + DELTA_ENTRY_HEADER_SIZE <= data.len() {
+        let index = read_u32(data, offset);
+        let bits = read_u32(data, offset + 4);
+        let len = read_u32(data, offset + 8) as usize;
+        let start = offset + DELTA_ENTRY_HEADER_SIZE;
+
+        // Ignore partially written entry at the end of file
+        if start + len > data.len() {
+            break;
+        }
+
+        let entry = DeltaEntry {
+            index,
+            bits,
+            data: data[start..start + len].to_vec(),
+        };
+
+        if index == DELTA_FORMAT_INDEX {
+            entries.retain(|e| e.index == DELTA_WRITE_PROTECT_INDEX);
+            positions = entries
+                .iter()
+                .enumerate()
+                .map(|(pos, e)| (e.index, pos))
+                .collect();
+        }
+
+        if let Some(&pos) = positions.get(&index) {
+            entries[pos] = entry;
+        } else {
+            positions.insert(index, entries.len());
+            entries.push(entry);
+        }
+        offset = start + len;
+    }
+
+    Ok(entries)
+}
+
+/// Read the overlay file for the image. Returns an empty list if there is no overlay
+pub fn read_delta<P>(image_path: P) -> io::Result<Vec<DeltaEntry>>
+where
+    P: AsRef<Path>,
+{
+    let path = delta_filename(image_path);
+    let mut data = Vec::new();
+    match File::open(&path) {
+        Ok(mut file) => {
+            file.read_to_end(&mut data)?;
+            decode_delta(&data)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn append_delta<P>(image_path: P, entries: &[DeltaEntry]) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let path = delta_filename(image_path);
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+    let mut buf = Vec::new();
+    if file.metadata()?.len() == 0 {
+        buf.extend_from_slice(DELTA_MAGIC);
+        buf.extend_from_slice(&DELTA_VERSION.to_le_bytes());
+    }
+
+    for entry in entries {
+        buf.extend_from_slice(&encode_delta_entry(entry));
+    }
+    file.write_all(&buf)
+}
+
+pub fn has_delta<P>(image_path: P) -> bool
+where
+    P: AsRef<Path>,
+{
+    delta_filename(image_path).exists()
+}
+
+pub fn remove_delta<P>(image_path: P) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    match std::fs::remove_file(delta_filename(image_path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(index: u32, value: u8) -> DeltaEntry {
+        DeltaEntry {
+            index,
+            bits: 0,
+            data: vec![value; 4],
+        }
+    }
+
+    #[test]
+    fn delta_filename_suffix() {
+        assert_eq!(
+            delta_filename("disk/test.po"),
+            PathBuf::from("disk/test.po.delta")
+        );
+    }
+
+    #[test]
+    fn decode_replaces_earlier_entries() {
+        let mut data = Vec::new();
+        data.extend_from_slice(DELTA_MAGIC);
+        data.extend_from_slice(&DELTA_VERSION.to_le_bytes());
+        data.extend_from_slice(&encode_delta_entry(&entry(1, 0x11)));
+        data.extend_from_slice(&encode_delta_entry(&entry(2, 0x22)));
+        data.extend_from_slice(&encode_delta_entry(&entry(1, 0x33)));
+
+        let entries = decode_delta(&data).unwrap();
+        assert_eq!(entries, vec![entry(1, 0x33), entry(2, 0x22)]);
+
+        // Truncated entry is ignored
+        data.truncate(data.len() - 1);
+        let entries = decode_delta(&data).unwrap();
+        assert_eq!(entries, vec![entry(1, 0x11), entry(2, 0x22)]);
+    }
+
+    #[test]
+    fn decode_format_discards_blocks() {
+        let format = DeltaEntry {
+            index: DELTA_FORMAT_INDEX,
+            bits: 0,
+            data: Vec::new(),
+        };
+        let mut data = Vec::new();
+        data.extend_from_slice(DELTA_MAGIC);
+        data.extend_from_slice(&DELTA_VERSION.to_le_bytes());
+        for entry in [
+            entry(1, 0x11),
+            entry(DELTA_WRITE_PROTECT_INDEX, 0x01),
+            format.clone(),
+            entry(2, 0x22),
+            format.clone(),
+            entry(3, 0x33),
+        ] {
+            data.extend_from_slice(&encode_delta_entry(&entry));
+        }
+
+        let entries = decode_delta(&data).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                entry(DELTA_WRITE_PROTECT_INDEX, 0x01),
+                format,
+                entry(3, 0x33)
+            ]
+        );
+    }
+
+    #[test]
+    fn append_and_read_delta() {
+        let image = std::env::temp_dir().join(format!("emu6502_overlay_{}.po", std::process::id()));
+        remove_delta(&image).unwrap();
+        assert!(!has_delta(&image));
+
+        append_delta(&image, &[entry(5, 0x55)]).unwrap();
+        append_delta(&image, &[entry(6, 0x66), entry(5, 0x77)]).unwrap();
+        assert!(has_delta(&image));
+
+        let entries = read_delta(&image).unwrap();
+        assert_eq!(entries, vec![entry(5, 0x77), entry(6, 0x66)]);
+
+        remove_delta(&image).unwrap();
+        assert!(read_delta(&image).unwrap().is_empty());
+    }
+}
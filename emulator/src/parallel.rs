@@ -1,13 +1,17 @@
 use crate::bus::Card;
 use crate::mmu::Mmu;
+use crate::printer::Printer;
 use crate::video::Video;
 
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug)]
 #[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
-pub struct ParallelCard {}
+pub struct ParallelCard {
+    #[cfg_attr(feature = "serde_support", serde(skip))]
+    pub printer: Printer,
+}
 
 const ROM: [u8; 256] = [
     0x18, 0xb0, 0x38, 0x48, 0x8a, 0x48, 0x98, 0x48, 0x08, 0x78, 0x20, 0x58, 0xff, 0xba, 0x68, 0x68,
@@ -30,7 +34,9 @@ const ROM: [u8; 256] = [
 
 impl ParallelCard {
     pub fn new() -> Self {
-        ParallelCard {}
+        ParallelCard {
+            printer: Printer::default(),
+        }
     }
 }
 
@@ -57,9 +63,7 @@ impl Card for ParallelCard {
         let io_addr = ((addr & 0x00ff) - ((slot as u16) << 4)) as u8;
         match io_addr {
             // Load output
-            0x80 if write_flag => {
-                eprint!("{}", (value & 0x7f) as char);
-            }
+            0x80 if write_flag => self.printer.write(value),
 
             // Send a strobe
             0x82 => {}
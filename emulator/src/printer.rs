@@ -0,0 +1,730 @@
+use crate::video::CHAR_APPLE2E_ROM;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::str::FromStr;
+
+/*
+Dot matrix printer
+
+The data sent to the printer is captured as is to a file. The data is also interpreted
+as Epson ESC/P or ImageWriter II control codes and the text and bit image graphics are
+rendered into letter size pages at 144 dpi.
+
+    Epson         CR LF FF BS HT VT SO SI DC2 DC4
+                  ESC @ 0 1 2 3 A J j        Reset and line spacing
+                  ESC K L Y Z *              Bit image (bit 7 is the top pin)
+                  ESC r                      Color
+                  ESC E F G H - W ! M P g l $ \ SO SI
+
+    ImageWriter   CR LF FF BS HT SO SI
+                  ESC c A B T f r            Reset and line spacing
+                  ESC G S g V F              Bit image (bit 0 is the top pin)
+                  ESC K                      Color
+                  ESC n N E e q Q p P        Pitch and graphics resolution
+                  ESC ! " X Y L R
+
+The other commands are skipped with their parameters.
+*/
+
+/// Resolution of the rendered pages in dots per inch
+pub const PAGE_DPI: usize = 144;
+pub const PAGE_WIDTH: usize = PAGE_DPI * 17 / 2;
+pub const PAGE_HEIGHT: usize = PAGE_DPI * 11;
+
+const PAGE_LENGTH: f32 = 11.0;
+const PRINT_WIDTH: f32 = 8.0;
+const PIN_SPACING: f32 = 1.0 / 72.0;
+
+const BS: u8 = 0x08;
+const HT: u8 = 0x09;
+const LF: u8 = 0x0a;
+const VT: u8 = 0x0b;
+const FF: u8 = 0x0c;
+const CR: u8 = 0x0d;
+const SO: u8 = 0x0e;
+const SI: u8 = 0x0f;
+const DC2: u8 = 0x12;
+const DC4: u8 = 0x14;
+const ESC: u8 = 0x1b;
+
+type Rgb = [u8; 3];
+
+const BLACK: Rgb = [0, 0, 0];
+const WHITE: Rgb = [0xff, 0xff, 0xff];
+const YELLOW: Rgb = [0xff, 0xea, 0x00];
+const MAGENTA: Rgb = [0xe4, 0x00, 0x7c];
+const CYAN: Rgb = [0x00, 0x9f, 0xe3];
+const VIOLET: Rgb = [0x7b, 0x3f, 0x98];
+const ORANGE: Rgb = [0xf3, 0x6f, 0x21];
+const GREEN: Rgb = [0x00, 0x96, 0x4b];
+
+const EPSON_COLORS: [Rgb; 7] = [BLACK, MAGENTA, CYAN, VIOLET, YELLOW, ORANGE, GREEN];
+const IMAGEWRITER_COLORS: [Rgb; 7] = [BLACK, YELLOW, MAGENTA, CYAN, ORANGE, GREEN, VIOLET];
+
+// Graphics resolution of ESC * m
+const EPSON_GRAPHICS_DPI: [f32; 8] = [60.0, 120.0, 120.0, 240.0, 80.0, 72.0, 90.0, 144.0];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrinterModel {
+    #[default]
+    Epson,
+    ImageWriter,
+}
+
+impl FromStr for PrinterModel {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "epson" => Ok(PrinterModel::Epson),
+            "imagewriter" => Ok(PrinterModel::ImageWriter),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid printer model: {s}"),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PrinterModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrinterModel::Epson => write!(f, "epson"),
+            PrinterModel::ImageWriter => write!(f, "imagewriter"),
+        }
+    }
+}
+
+/// A printed page of PAGE_WIDTH x PAGE_HEIGHT pixels. The page is run length encoded
+/// when it is ejected, as the printed pages are mostly blank
+pub struct PrinterPage {
+    pub width: usize,
+    pub height: usize,
+    runs: Vec<(u32, Rgb)>,
+}
+
+impl PrinterPage {
+    fn new(width: usize, height: usize, data: &[u8]) -> Self {
+        let mut runs: Vec<(u32, Rgb)> = Vec::new();
+        for pixel in data.chunks_exact(3) {
+            let rgb = [pixel[0], pixel[1], pixel[2]];
+            match runs.last_mut() {
+                Some((count, color)) if *color == rgb => *count += 1,
+                _ => runs.push((1, rgb)),
+            }
+        }
+        PrinterPage {
+            width,
+            height,
+            runs,
+        }
+    }
+
+    /// Returns the page in RGB format
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.width * self.height * 3);
+        for &(count, rgb) in &self.runs {
+            for _ in 0..count {
+                data.extend_from_slice(&rgb);
+            }
+        }
+        data
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Normal,
+    Escape,
+    Parameters(u8, usize),
+    TabList(u8),
+    Graphics(usize),
+    Skip(usize),
+}
+
+pub struct Printer {
+    model: PrinterModel,
+    capture_file: Option<String>,
+    capture: Option<BufWriter<File>>,
+
+    state: State,
+    params: Vec<u8>,
+
+    page: Vec<u8>,
+    pages: Vec<PrinterPage>,
+
+    // Print head position and margin in inches
+    x: f32,
+    y: f32,
+    left_margin: f32,
+    line_spacing: f32,
+    reverse_feed: bool,
+
+    pitch: f32,
+    graphics_dpi: f32,
+    double_width: bool,
+    double_width_line: bool,
+    bold: bool,
+    underline: bool,
+    color: Rgb,
+}
+
+impl fmt::Debug for Printer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Printer {}", self.model)
+    }
+}
+
+impl Printer {
+    pub fn new(model: PrinterModel) -> Self {
+        let mut printer = Printer {
+            model,
+            capture_file: None,
+            capture: None,
+            state: State::Normal,
+            params: Vec::new(),
+            page: Vec::new(),
+            pages: Vec::new(),
+            x: 0.0,
+            y: 0.0,
+            left_margin: 0.0,
+            line_spacing: 0.0,
+            reverse_feed: false,
+            pitch: 0.0,
+            graphics_dpi: 0.0,
+            double_width: false,
+            double_width_line: false,
+            bold: false,
+            underline: false,
+            color: BLACK,
+        };
+        printer.reset();
+        printer
+    }
+
+    /// Power on settings. The print head stays on the current line
+    fn reset(&mut self) {
+        self.state = State::Normal;
+        self.left_margin = 0.0;
+        self.x = 0.0;
+        self.line_spacing = 1.0 / 6.0;
+        self.reverse_feed = false;
+        self.pitch = 10.0;
+        self.graphics_dpi = 80.0;
+        self.double_width = false;
+        self.double_width_line = false;
+        self.bold = false;
+        self.underline = false;
+        self.color = BLACK;
+    }
+
+    pub fn model(&self) -> PrinterModel {
+        self.model
+    }
+
+    pub fn set_model(&mut self, model: PrinterModel) {
+        self.model = model;
+        self.reset();
+    }
+
+    /// Capture the printer data to the file. The file is opened for append when the
+    /// first byte is printed
+    pub fn set_capture_file(&mut self, path: Option<String>) {
+        self.flush();
+        self.capture = None;
+        self.capture_file = path;
+    }
+
+    pub fn capture_file(&self) -> Option<&str> {
+        self.capture_file.as_deref()
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(capture) = &mut self.capture {
+            let _ = capture.flush();
+        }
+    }
+
+    fn capture_byte(&mut self, value: u8) {
+        if self.capture.is_none()
+            && let Some(path) = &self.capture_file
+        {
+            match File::options().create(true).append(true).open(path) {
+                Ok(file) => self.capture = Some(BufWriter::new(file)),
+                Err(err) => {
+                    eprintln!("Unable to create printer capture {path} : {err}");
+                    self.capture_file = None;
+                }
+            }
+        }
+
+        if let Some(capture) = &mut self.capture
+            && capture.write_all(&[value]).is_err()
+        {
+            self.capture = None;
+            self.capture_file = None;
+        }
+    }
+
+    /// True when something has been printed on the current page
+    pub fn is_page_dirty(&self) -> bool {
+        !self.page.is_empty()
+    }
+
+    /// Feed out the current page if it is not blank
+    pub fn eject_page(&mut self) {
+        if self.page.is_empty() {
+            return;
+        }
+        let page = std::mem::take(&mut self.page);
+        self.pages
+            .push(PrinterPage::new(PAGE_WIDTH, PAGE_HEIGHT, &page));
+        self.flush();
+    }
+
+    pub fn has_pages(&self) -> bool {
+        !self.pages.is_empty()
+    }
+
+    /// Returns the completed pages
+    pub fn take_pages(&mut self) -> Vec<PrinterPage> {
+        std::mem::take(&mut self.pages)
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.capture_byte(value);
+
+        match self.state {
+            State::Normal => self.control(value & 0x7f),
+
+            State::Escape => {
+                let command = value & 0x7f;
+                let len = match self.model {
+                    PrinterModel::Epson => epson_parameters(command),
+                    PrinterModel::ImageWriter => imagewriter_parameters(command),
+                };
+                self.params.clear();
+                self.state = match (self.model, command) {
+                    (PrinterModel::Epson, b'B' | b'D') => State::TabList(0),
+                    (PrinterModel::ImageWriter, b'(' | b')' | b'u') => State::TabList(b'.'),
+                    _ if len > 0 => State::Parameters(command, len),
+                    _ => State::Normal,
+                };
+                if len == 0 {
+                    self.execute(command);
+                }
+            }
+
+            State::Parameters(command, len) => {
+                self.params.push(value);
+                if self.params.len() >= len {
+                    self.state = State::Normal;
+                    self.execute(command);
+                }
+            }
+
+            State::TabList(terminator) => {
+                if value & 0x7f == terminator {
+                    self.state = State::Normal;
+                }
+            }
+
+            State::Graphics(remaining) => {
+                self.graphics_column(value);
+                self.state = if remaining > 1 {
+                    State::Graphics(remaining - 1)
+                } else {
+                    State::Normal
+                };
+            }
+
+            State::Skip(remaining) => {
+                self.state = if remaining > 1 {
+                    State::Skip(remaining - 1)
+                } else {
+                    State::Normal
+                };
+            }
+        }
+    }
+
+    fn control(&mut self, value: u8) {
+        match value {
+            ESC => self.state = State::Escape,
+            CR => self.carriage_return(),
+            LF | VT => self.line_feed(),
+            FF => {
+                self.eject_page();
+                self.y = 0.0;
+                self.x = self.left_margin;
+            }
+            BS => self.x = (self.x - self.char_width()).max(self.left_margin),
+            HT => {
+                let column = ((self.x - self.left_margin) * self.pitch + 0.01) as usize;
+                self.x = self.left_margin + ((column / 8 + 1) * 8) as f32 / self.pitch;
+            }
+            SO if self.model == PrinterModel::ImageWriter => self.double_width = true,
+            SI if self.model == PrinterModel::ImageWriter => self.double_width = false,
+            SO => self.double_width_line = true,
+            DC4 => self.double_width_line = false,
+            SI => self.pitch = 17.16,
+            DC2 => self.pitch = 10.0,
+            0x20..=0x7e => self.print_char(value),
+            _ => {}
+        }
+    }
+
+    fn execute(&mut self, command: u8) {
+        match self.model {
+            PrinterModel::Epson => self.execute_epson(command),
+            PrinterModel::ImageWriter => self.execute_imagewriter(command),
+        }
+    }
+
+    fn execute_epson(&mut self, command: u8) {
+        let param = self.params.first().copied().unwrap_or(0);
+        let count = self.word_param(0);
+
+        match command {
+            b'@' => self.reset(),
+            b'0' => self.line_spacing = 1.0 / 8.0,
+            b'1' => self.line_spacing = 7.0 / 72.0,
+            b'2' => self.line_spacing = 1.0 / 6.0,
+            b'3' => self.line_spacing = param as f32 / 216.0,
+            b'A' => self.line_spacing = (param & 0x7f) as f32 / 72.0,
+            b'J' => self.advance(param as f32 / 216.0),
+            b'j' => self.advance(-(param as f32) / 216.0),
+            b'K' => self.start_graphics(60.0, count),
+            b'L' | b'Y' => self.start_graphics(120.0, count),
+            b'Z' => self.start_graphics(240.0, count),
+            b'*' => {
+                let count = self.word_param(1);
+                self.start_graphics(EPSON_GRAPHICS_DPI[(param & 0x07) as usize], count);
+            }
+            // 9-pin graphics are skipped
+            b'^' => {
+                let count = self.word_param(1);
+                if count > 0 {
+                    self.state = State::Skip(count * 2);
+                }
+            }
+            b'r' => self.color = EPSON_COLORS[(param & 0x07) as usize % EPSON_COLORS.len()],
+            b'E' | b'G' => self.bold = true,
+            b'F' | b'H' => self.bold = false,
+            b'-' => self.underline = param & 0x01 != 0,
+            b'W' => self.double_width = param & 0x01 != 0,
+            b'M' => self.pitch = 12.0,
+            b'P' => self.pitch = 10.0,
+            b'g' => self.pitch = 15.0,
+            SI => self.pitch = 17.16,
+            SO => self.double_width_line = true,
+            b'!' => {
+                self.pitch = match (param & 0x04 != 0, param & 0x01 != 0) {
+                    (true, true) => 20.0,
+                    (true, false) => 17.16,
+                    (false, true) => 12.0,
+                    (false, false) => 10.0,
+                };
+                self.bold = param & 0x18 != 0;
+                self.double_width = param & 0x20 != 0;
+                self.underline = param & 0x80 != 0;
+            }
+            b'l' => {
+                self.left_margin = param as f32 / self.pitch;
+                self.x = self.x.max(self.left_margin);
+            }
+            b'$' => self.x = self.left_margin + count as f32 / 60.0,
+            b'\\' => self.x = (self.x + count as i16 as f32 / 120.0).max(self.left_margin),
+            // Page length in inches when the first parameter is 0
+            b'C' if self.params.len() == 1 && param == 0 => {
+                self.state = State::Parameters(command, 2);
+            }
+            _ => {}
+        }
+    }
+
+    fn execute_imagewriter(&mut self, command: u8) {
+        let number = self
+            .params
+            .iter()
+            .take_while(|ch| ch.is_ascii_digit())
+            .fold(0usize, |acc, ch| acc * 10 + (ch - b'0') as usize);
+
+        match command {
+            b'c' => self.reset(),
+            b'n' => self.set_pitch(9.0, 72.0),
+            b'N' => self.set_pitch(10.0, 80.0),
+            b'E' => self.set_pitch(12.0, 96.0),
+            b'e' => self.set_pitch(13.4, 107.0),
+            b'q' => self.set_pitch(15.0, 120.0),
+            b'Q' => self.set_pitch(17.0, 136.0),
+            b'p' => self.set_pitch(14.4, 144.0),
+            b'P' => self.set_pitch(16.0, 160.0),
+            b'G' | b'S' => {
+                let dpi = self.graphics_dpi;
+                self.start_graphics(dpi, number);
+            }
+            b'g' => {
+                let dpi = self.graphics_dpi;
+                self.start_graphics(dpi, number * 8);
+            }
+            b'V' => {
+                let value = self.params[4];
+                for _ in 0..number {
+                    self.graphics_column(value);
+                }
+            }
+            b'R' => {
+                let value = self.params[3] & 0x7f;
+                for _ in 0..number {
+                    self.control(value);
+                }
+            }
+            b'A' => self.line_spacing = 1.0 / 6.0,
+            b'B' => self.line_spacing = 1.0 / 8.0,
+            b'T' => self.line_spacing = number as f32 / 144.0,
+            b'f' => self.reverse_feed = false,
+            b'r' => self.reverse_feed = true,
+            b'L' => {
+                self.left_margin = number as f32 / self.pitch;
+                self.x = self.x.max(self.left_margin);
+            }
+            b'F' => self.x = self.left_margin + number as f32 / self.graphics_dpi,
+            b'K' => {
+                let index = self.params[0].wrapping_sub(b'0') as usize;
+                if index < IMAGEWRITER_COLORS.len() {
+                    self.color = IMAGEWRITER_COLORS[index];
+                }
+            }
+            b'!' => self.bold = true,
+            b'"' => self.bold = false,
+            b'X' => self.underline = true,
+            b'Y' => self.underline = false,
+            _ => {}
+        }
+    }
+
+    fn word_param(&self, index: usize) -> usize {
+        let low = self.params.get(index).copied().unwrap_or(0) as usize;
+        let high = self.params.get(index + 1).copied().unwrap_or(0) as usize;
+        low | (high << 8)
+    }
+
+    fn set_pitch(&mut self, pitch: f32, graphics_dpi: f32) {
+        self.pitch = pitch;
+        self.graphics_dpi = graphics_dpi;
+    }
+
+    fn start_graphics(&mut self, dpi: f32, count: usize) {
+        self.graphics_dpi = dpi;
+        if count > 0 {
+            self.state = State::Graphics(count);
+        }
+    }
+
+    fn char_width(&self) -> f32 {
+        if self.double_width || self.double_width_line {
+            2.0 / self.pitch
+        } else {
+            1.0 / self.pitch
+        }
+    }
+
+    fn carriage_return(&mut self) {
+        self.x = self.left_margin;
+    }
+
+    fn line_feed(&mut self) {
+        self.double_width_line = false;
+        if self.reverse_feed {
+            self.advance(-self.line_spacing);
+        } else {
+            self.advance(self.line_spacing);
+        }
+    }
+
+    fn advance(&mut self, distance: f32) {
+        self.y = (self.y + distance).max(0.0);
+        while self.y >= PAGE_LENGTH - 0.001 {
+            self.eject_page();
+            self.y = (self.y - PAGE_LENGTH).max(0.0);
+        }
+    }
+
+    fn print_char(&mut self, ch: u8) {
+        let width = self.char_width();
+        if self.x + width > PRINT_WIDTH + 0.001 {
+            self.carriage_return();
+            self.line_feed();
+        }
+
+        // The 7x8 Apple IIe font is scaled to the character width
+        let dot_width = width / 7.0;
+        let (x, y) = (self.x, self.y);
+        for row in 0..8 {
+            let bitmap = CHAR_APPLE2E_ROM[(ch as usize | 0x80) * 8 + row];
+            for column in 0..7 {
+                if bitmap & (0x80 >> column) != 0 {
+                    let dot_x = x + column as f32 * dot_width;
+                    let dot_y = y + row as f32 * PIN_SPACING;
+                    self.dot(dot_x, dot_y, dot_width.max(PIN_SPACING));
+                    if self.bold {
+                        self.dot(dot_x + PIN_SPACING / 2.0, dot_y, dot_width.max(PIN_SPACING));
+                    }
+                }
+            }
+        }
+
+        if self.underline {
+            self.fill(x, y + 8.0 * PIN_SPACING, width, PIN_SPACING / 2.0);
+        }
+        self.x += width;
+    }
+
+    fn graphics_column(&mut self, value: u8) {
+        for pin in 0..8 {
+            let mask = match self.model {
+                PrinterModel::Epson => 0x80 >> pin,
+                PrinterModel::ImageWriter => 1 << pin,
+            };
+            if value & mask != 0 {
+                self.dot(self.x, self.y + pin as f32 * PIN_SPACING, PIN_SPACING);
+            }
+        }
+        self.x += 1.0 / self.graphics_dpi;
+    }
+
+    fn dot(&mut self, x: f32, y: f32, width: f32) {
+        self.fill(x, y, width, PIN_SPACING);
+    }
+
+    // Fill the rectangle in inches with the ribbon color. The ink is subtractive so
+    // overprinting with another color darkens the dots
+    fn fill(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        let dpi = PAGE_DPI as f32;
+        let x0 = (x * dpi).round().max(0.0) as usize;
+        let y0 = (y * dpi).round().max(0.0) as usize;
+        let x1 = (((x + width) * dpi).round() as usize).min(PAGE_WIDTH);
+        let y1 = (((y + height) * dpi).round() as usize).min(PAGE_HEIGHT);
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+
+        if self.page.is_empty() {
+            self.page = WHITE.repeat(PAGE_WIDTH * PAGE_HEIGHT);
+        }
+
+        for py in y0..y1 {
+            for px in x0..x1 {
+                let offset = (py * PAGE_WIDTH + px) * 3;
+                for (channel, ink) in self.page[offset..offset + 3].iter_mut().zip(self.color) {
+                    *channel = (*channel).min(ink);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Self::new(PrinterModel::default())
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn epson_parameters(command: u8) -> usize {
+    match command {
+        b'3' | b'A' | b'J' | b'j' | b'r' | b'-' | b'W' | b'l' | b'Q' | b'C' | b'N' | b'U'
+        | b'S' | b'x' | b'k' | b'R' | b't' | b'p' | b'!' | b'm' | b'I' | b's' | b'i' | b'a'
+        | b'w' => 1,
+        b'K' | b'L' | b'Y' | b'Z' | b'$' | b'\\' | b'e' | b'f' | b'?' => 2,
+        b'*' | b'^' => 3,
+        _ => 0,
+    }
+}
+
+fn imagewriter_parameters(command: u8) -> usize {
+    match command {
+        b'K' | b'l' | b'a' | b's' => 1,
+        b'T' | b'v' | b'Z' | b'D' => 2,
+        b'g' | b'L' => 3,
+        b'G' | b'S' | b'F' | b'H' | b'R' => 4,
+        b'V' => 5,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pixel(page: &PrinterPage, x: usize, y: usize) -> Rgb {
+        let data = page.to_rgb();
+        let offset = (y * page.width + x) * 3;
+        [data[offset], data[offset + 1], data[offset + 2]]
+    }
+
+    fn print(printer: &mut Printer, data: &[u8]) {
+        for &value in data {
+            printer.write(value);
+        }
+    }
+
+    #[test]
+    fn epson_bit_image() {
+        let mut printer = Printer::new(PrinterModel::Epson);
+
+        // Print Shop sets 7/72 inch spacing, the color and prints 120 dpi columns
+        print(&mut printer, &[ESC, b'A', 7, ESC, b'r', 2]);
+        print(
+            &mut printer,
+            &[ESC, b'L', 3, 0, 0x80, 0x00, 0x01, CR, LF, FF],
+        );
+
+        let pages = printer.take_pages();
+        assert_eq!(pages.len(), 1);
+        let page = &pages[0];
+        assert_eq!(pixel(page, 0, 0), CYAN);
+        assert_eq!(pixel(page, 0, 8), WHITE);
+        assert_eq!(pixel(page, 0, 14), WHITE);
+        assert_eq!(pixel(page, 3, 14), CYAN);
+        assert_eq!(pixel(page, 40, 0), WHITE);
+        assert!(!printer.has_pages());
+    }
+
+    #[test]
+    fn imagewriter_text_and_pages() {
+        let mut printer = Printer::new(PrinterModel::ImageWriter);
+
+        // Bit 0 is the top pin
+        print(&mut printer, b"\x1bG0001\x01\r\n");
+        for _ in 0..66 {
+            print(&mut printer, b"\x1bK2A\r\n");
+        }
+        printer.eject_page();
+
+        let pages = printer.take_pages();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pixel(&pages[0], 0, 0), BLACK);
+        assert_eq!(pixel(&pages[0], 0, 2), WHITE);
+        assert!(pages[1].to_rgb().chunks(3).any(|rgb| rgb == MAGENTA));
+    }
+
+    #[test]
+    fn capture_printer_data() {
+        let path = std::env::temp_dir().join(format!("emu6502_printer_{}.prn", std::process::id()));
+        let mut printer = Printer::default();
+        printer.set_capture_file(Some(path.to_string_lossy().into_owned()));
+        print(&mut printer, &[0xc1, 0x8d, 0x8a]);
+        printer.flush();
+
+        assert_eq!(std::fs::read(&path).unwrap(), [0xc1, 0x8d, 0x8a]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
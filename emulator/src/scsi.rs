@@ -0,0 +1,977 @@
+use crate::bus::{Card, Tick};
+use crate::harddisk::{DeviceStatus, HardDisk, trap_rom};
+use crate::mmu::Mmu;
+use crate::video::Video;
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+/*
+Apple High-Speed SCSI card (NCR 5380)
+
+    C0n0        (r)   NCR 5380 current SCSI data
+                (w)   NCR 5380 output data
+    C0n1        (r/w) NCR 5380 initiator command: Bit 7 = RST, Bit 6 = AIP (r),
+                      Bit 5 = LA (r), Bit 4 = ACK, Bit 3 = BSY, Bit 2 = SEL, Bit 1 = ATN,
+                      Bit 0 = assert data bus
+    C0n2        (r/w) NCR 5380 mode: Bit 1 = DMA mode, Bit 0 = arbitrate
+    C0n3        (r/w) NCR 5380 target command: Bit 2 = MSG, Bit 1 = C/D, Bit 0 = I/O
+    C0n4        (r)   NCR 5380 current SCSI bus status: Bit 7 = RST, Bit 6 = BSY,
+                      Bit 5 = REQ, Bit 4 = MSG, Bit 3 = C/D, Bit 2 = I/O, Bit 1 = SEL
+                (w)   NCR 5380 select enable (ignored)
+    C0n5        (r)   NCR 5380 bus and status: Bit 6 = DMA request, Bit 4 = IRQ,
+                      Bit 3 = phase match, Bit 1 = ATN, Bit 0 = ACK
+                (w)   NCR 5380 start DMA send
+    C0n6        (r)   NCR 5380 input data
+                (w)   NCR 5380 start DMA target receive (ignored)
+    C0n7        (r)   NCR 5380 reset parity / interrupt
+                (w)   NCR 5380 start DMA initiator receive
+    C0n8        (r/w) Pseudo DMA data. Each access transfers a byte with the REQ/ACK
+                      handshake in DMA mode
+    C0n9        (w)   Firmware trap: SmartPort call, the value is the stack pointer
+    C0nA        (w)   Firmware trap: ProDOS block call with the parameters at $42-$47
+                (r)   Error code of the last firmware call
+    C0nB        (r)   Low byte of the firmware call result (block count)
+    C0nC        (r)   High byte of the firmware call result
+    C0nE        (r)   Bit 7 = DMA request
+
+The card is SCSI ID 7. The targets are SCSI ID 0 to 6, each backed by an image file.
+Disk targets use 512 bytes blocks and CD-ROM targets are read only with 2048 bytes
+blocks backed by an ISO / HFS image.
+
+Arbitration is always won as soon as it is started. The targets support TEST UNIT READY,
+REZERO UNIT, REQUEST SENSE, READ(6), WRITE(6), SEEK(6), INQUIRY, MODE SENSE(6),
+START STOP UNIT, PREVENT ALLOW MEDIUM REMOVAL, READ CAPACITY, READ(10), WRITE(10),
+SEEK(10), VERIFY(10) and READ TOC (CD-ROM only). The message out bytes are ignored
+and the only message in byte is COMMAND COMPLETE.
+
+The slot ROM boots from SCSI ID 0 and provides the ProDOS block driver and the SmartPort
+interface (including the extended calls) with one SmartPort unit per SCSI ID, using
+512 bytes blocks for both disk and CD-ROM targets. The ProDOS units in the slot of the
+card are SCSI ID 0 and 1. When ProDOS remaps the units to another slot, they are
+SCSI ID 2 and 3.
+
+The slot ROM is not the Apple SCSI card ROM. It only has the ProDOS and SmartPort entry
+points and the boot code, the calls are serviced through the firmware trap registers.
+Software that looks for the Apple ROM signature or calls the other entry points of the
+Apple firmware doesn't see the card as an Apple SCSI card, it can still drive the
+NCR 5380 registers directly.
+*/
+
+const ROM: [u8; 256] = trap_rom(
+    SCSI_SMARTPORT,
+    SCSI_PRODOS,
+    SCSI_RESULT_LOW,
+    SCSI_RESULT_HIGH,
+);
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const SCSI_VENDOR: &str = "EMU6502";
+
+/// Number of SCSI targets supported by the card
+pub const SCSI_MAX_TARGETS: usize = 7;
+
+const BLOCK_SIZE: usize = 512;
+const CDROM_BLOCK_SIZE: usize = 2048;
+
+// Card registers
+const SCSI_DMA_DATA: u8 = 0x08;
+const SCSI_SMARTPORT: u8 = 0x09;
+const SCSI_PRODOS: u8 = 0x0a;
+const SCSI_RESULT_LOW: u8 = 0x0b;
+const SCSI_RESULT_HIGH: u8 = 0x0c;
+const SCSI_DRQ: u8 = 0x0e;
+
+// NCR 5380 initiator command register
+const ICR_RST: u8 = 0x80;
+const ICR_AIP: u8 = 0x40;
+const ICR_LA: u8 = 0x20;
+const ICR_ACK: u8 = 0x10;
+const ICR_BSY: u8 = 0x08;
+const ICR_SEL: u8 = 0x04;
+const ICR_ATN: u8 = 0x02;
+const ICR_DATA_BUS: u8 = 0x01;
+
+// NCR 5380 mode register
+const MODE_DMA: u8 = 0x02;
+const MODE_ARBITRATE: u8 = 0x01;
+
+// NCR 5380 current SCSI bus status register
+const CSBS_BSY: u8 = 0x40;
+const CSBS_REQ: u8 = 0x20;
+const CSBS_SEL: u8 = 0x02;
+
+// NCR 5380 bus and status register
+const BSR_DMA_REQUEST: u8 = 0x40;
+const BSR_IRQ: u8 = 0x10;
+const BSR_PHASE_MATCH: u8 = 0x08;
+const BSR_ATN: u8 = 0x02;
+const BSR_ACK: u8 = 0x01;
+
+const INITIATOR_ID: u8 = 0x80;
+
+const STATUS_GOOD: u8 = 0x00;
+const STATUS_CHECK_CONDITION: u8 = 0x02;
+const MESSAGE_COMMAND_COMPLETE: u8 = 0x00;
+
+const SCSI_TEST_UNIT_READY: u8 = 0x00;
+const SCSI_REZERO_UNIT: u8 = 0x01;
+const SCSI_REQUEST_SENSE: u8 = 0x03;
+const SCSI_READ_6: u8 = 0x08;
+const SCSI_WRITE_6: u8 = 0x0a;
+const SCSI_SEEK_6: u8 = 0x0b;
+const SCSI_INQUIRY: u8 = 0x12;
+const SCSI_MODE_SENSE_6: u8 = 0x1a;
+const SCSI_START_STOP_UNIT: u8 = 0x1b;
+const SCSI_PREVENT_ALLOW_REMOVAL: u8 = 0x1e;
+const SCSI_READ_CAPACITY: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const SCSI_WRITE_10: u8 = 0x2a;
+const SCSI_SEEK_10: u8 = 0x2b;
+const SCSI_VERIFY_10: u8 = 0x2f;
+const SCSI_READ_TOC: u8 = 0x43;
+
+// Sense key and additional sense code
+type Sense = (u8, u8);
+
+const SENSE_NONE: Sense = (0x00, 0x00);
+const SENSE_READ_ERROR: Sense = (0x03, 0x11);
+const SENSE_WRITE_ERROR: Sense = (0x03, 0x0c);
+const SENSE_INVALID_COMMAND: Sense = (0x05, 0x20);
+const SENSE_LBA_OUT_OF_RANGE: Sense = (0x05, 0x21);
+const SENSE_WRITE_PROTECTED: Sense = (0x07, 0x27);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum ScsiDeviceType {
+    #[default]
+    Disk,
+    CdRom,
+}
+
+impl ScsiDeviceType {
+    /// Returns CdRom for iso, cdr and toast images, otherwise Disk
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let is_cdrom = path.as_ref().extension().is_some_and(|extension| {
+            ["iso", "cdr", "toast"]
+                .iter()
+                .any(|ext| extension.eq_ignore_ascii_case(OsStr::new(ext)))
+        });
+
+        if is_cdrom {
+            ScsiDeviceType::CdRom
+        } else {
+            ScsiDeviceType::Disk
+        }
+    }
+
+    fn block_size(self) -> usize {
+        match self {
+            ScsiDeviceType::Disk => BLOCK_SIZE,
+            ScsiDeviceType::CdRom => CDROM_BLOCK_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+enum Phase {
+    BusFree,
+    DataOut,
+    DataIn,
+    Command,
+    Status,
+    MessageOut,
+    MessageIn,
+}
+
+impl Phase {
+    // MSG, C/D and I/O signals of the phase
+    fn signals(self) -> u8 {
+        match self {
+            Phase::BusFree | Phase::DataOut => 0x00,
+            Phase::DataIn => 0x01,
+            Phase::Command => 0x02,
+            Phase::Status => 0x03,
+            Phase::MessageOut => 0x06,
+            Phase::MessageIn => 0x07,
+        }
+    }
+
+    fn is_input(self) -> bool {
+        self.signals() & 0x01 != 0
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Scsi {
+    pub targets: HardDisk,
+    device_type: [ScsiDeviceType; SCSI_MAX_TARGETS],
+    sense: [Sense; SCSI_MAX_TARGETS],
+    output_data: u8,
+    initiator_command: u8,
+    mode: u8,
+    target_command: u8,
+    dma: bool,
+    target: Option<usize>,
+    phase: Phase,
+    req: bool,
+    cdb: Vec<u8>,
+    data: Vec<u8>,
+    data_index: usize,
+    lba: u32,
+    blocks_left: u32,
+    status: u8,
+    call_error: u8,
+    call_result: u16,
+}
+
+impl Scsi {
+    pub fn new() -> Self {
+        Scsi {
+            targets: HardDisk::new(),
+            device_type: [ScsiDeviceType::Disk; SCSI_MAX_TARGETS],
+            sense: [SENSE_NONE; SCSI_MAX_TARGETS],
+            output_data: 0,
+            initiator_command: 0,
+            mode: 0,
+            target_command: 0,
+            dma: false,
+            target: None,
+            phase: Phase::BusFree,
+            req: false,
+            cdb: Vec::new(),
+            data: Vec::new(),
+            data_index: 0,
+            lba: 0,
+            blocks_left: 0,
+            status: STATUS_GOOD,
+            call_error: 0,
+            call_result: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.targets.reset();
+        self.output_data = 0;
+        self.initiator_command = 0;
+        self.mode = 0;
+        self.target_command = 0;
+        self.bus_reset();
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.targets.is_busy()
+    }
+
+    pub fn device_type(&self, id: usize) -> ScsiDeviceType {
+        self.device_type[id]
+    }
+
+    /// Load the image of the SCSI target (ID 0 to 6). The device type is determined by
+    /// the extension of the image (see ScsiDeviceType::from_path)
+    pub fn load_target<P>(&mut self, id: usize, filename_path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        if id >= SCSI_MAX_TARGETS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid SCSI ID {id}"),
+            ));
+        }
+
+        let device_type = ScsiDeviceType::from_path(&filename_path);
+        let drive_selected = self.targets.drive_selected();
+        self.targets.drive_select(id);
+        let result = self
+            .targets
+            .load_hdv_2mg_file(&filename_path)
+            .and_then(|_| {
+                if device_type == ScsiDeviceType::CdRom {
+                    self.targets.set_write_protect(id, true)
+                } else {
+                    Ok(())
+                }
+            });
+        if result.is_ok() {
+            self.targets.set_disk_filename(&filename_path);
+            self.targets.set_loaded(true);
+            self.device_type[id] = device_type;
+            self.sense[id] = SENSE_NONE;
+        }
+        self.targets.drive_select(drive_selected);
+        result
+    }
+
+    fn bus_reset(&mut self) {
+        self.dma = false;
+        self.target = None;
+        self.phase = Phase::BusFree;
+        self.req = false;
+        self.cdb.clear();
+        self.data.clear();
+        self.blocks_left = 0;
+    }
+
+    fn is_phase_match(&self) -> bool {
+        self.phase != Phase::BusFree && self.target_command & 0x07 == self.phase.signals()
+    }
+
+    fn is_dma_request(&self) -> bool {
+        self.dma && self.req && self.is_phase_match()
+    }
+
+    // Data on the bus driven by the target or the initiator
+    fn bus_data(&self) -> u8 {
+        match self.phase {
+            Phase::DataIn => self.data.get(self.data_index).copied().unwrap_or(0),
+            Phase::Status => self.status,
+            Phase::MessageIn => MESSAGE_COMMAND_COMPLETE,
+            _ if self.initiator_command & ICR_DATA_BUS != 0 => self.output_data,
+            _ => 0,
+        }
+    }
+
+    // The target responds to the selection when SEL and its ID are asserted without BSY.
+    // The information transfer starts when the initiator releases SEL
+    fn update_selection(&mut self) {
+        let icr = self.initiator_command;
+        match self.target {
+            None if icr & (ICR_SEL | ICR_BSY | ICR_DATA_BUS) == ICR_SEL | ICR_DATA_BUS => {
+                let ids = self.output_data & !INITIATOR_ID;
+                self.target = (0..SCSI_MAX_TARGETS)
+                    .find(|&id| ids & (1 << id) != 0 && self.targets.is_loaded(id));
+            }
+            Some(_) if self.phase == Phase::BusFree && icr & ICR_SEL == 0 => {
+                self.phase = if icr & ICR_ATN != 0 {
+                    Phase::MessageOut
+                } else {
+                    Phase::Command
+                };
+                self.cdb.clear();
+                self.req = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn write_initiator_command(&mut self, value: u8) {
+        let previous = self.initiator_command;
+        self.initiator_command = value & !(ICR_AIP | ICR_LA);
+
+        if value & ICR_RST != 0 {
+            self.bus_reset();
+            return;
+        }
+
+        self.update_selection();
+        if self.target.is_none() || self.phase == Phase::BusFree {
+            return;
+        }
+
+        // The target completes the transfer of the byte when ACK is asserted and
+        // requests the next byte when ACK is released
+        let ack = value & ICR_ACK != 0;
+        if ack && previous & ICR_ACK == 0 && self.req {
+            self.acknowledge();
+        } else if !ack && previous & ICR_ACK != 0 {
+            self.req = self.phase != Phase::BusFree;
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.req = false;
+        match self.phase {
+            Phase::Command => {
+                self.cdb.push(self.output_data);
+                if self.cdb.len() >= command_length(self.cdb[0]) {
+                    self.execute_command();
+                }
+            }
+
+            Phase::DataIn => {
+                self.data_index += 1;
+                if self.data_index >= self.data.len() {
+                    self.next_data_in();
+                }
+            }
+
+            Phase::DataOut => {
+                self.data[self.data_index] = self.output_data;
+                self.data_index += 1;
+                if self.data_index >= self.data.len() {
+                    self.write_data_out();
+                }
+            }
+
+            Phase::Status => self.phase = Phase::MessageIn,
+
+            Phase::MessageIn => {
+                self.target = None;
+                self.phase = Phase::BusFree;
+            }
+
+            // The initiator releases ATN before the last message byte
+            Phase::MessageOut => {
+                if self.initiator_command & ICR_ATN == 0 {
+                    self.phase = Phase::Command;
+                    self.cdb.clear();
+                }
+            }
+
+            Phase::BusFree => {}
+        }
+    }
+
+    // Pseudo DMA transfer of a byte in the current phase
+    fn dma_transfer(&mut self, value: u8, write_flag: bool) -> u8 {
+        if !self.is_dma_request() || self.phase.is_input() == write_flag {
+            return self.bus_data();
+        }
+
+        let data = if write_flag {
+            self.output_data = value;
+            value
+        } else {
+            self.bus_data()
+        };
+        self.acknowledge();
+        self.req = self.phase != Phase::BusFree;
+        data
+    }
+
+    fn check_condition(&mut self, sense: Sense) {
+        if let Some(id) = self.target {
+            self.sense[id] = sense;
+        }
+        self.status = STATUS_CHECK_CONDITION;
+        self.phase = Phase::Status;
+    }
+
+    fn execute_command(&mut self) {
+        let Some(id) = self.target else {
+            return;
+        };
+
+        self.data.clear();
+        self.data_index = 0;
+        self.blocks_left = 0;
+        self.status = STATUS_GOOD;
+        self.phase = Phase::Status;
+
+        let cdb = std::mem::take(&mut self.cdb);
+        let result = match cdb[0] {
+            SCSI_TEST_UNIT_READY
+            | SCSI_REZERO_UNIT
+            | SCSI_SEEK_6
+            | SCSI_START_STOP_UNIT
+            | SCSI_PREVENT_ALLOW_REMOVAL
+            | SCSI_SEEK_10
+            | SCSI_VERIFY_10 => Ok(()),
+
+            SCSI_REQUEST_SENSE => {
+                let (key, asc) = std::mem::replace(&mut self.sense[id], SENSE_NONE);
+                let mut data = vec![0u8; 18];
+                data[0] = 0x70;
+                data[2] = key;
+                data[7] = 10;
+                data[12] = asc;
+                self.data_in(data, cdb[4] as usize);
+                Ok(())
+            }
+
+            SCSI_READ_6 | SCSI_WRITE_6 => {
+                let lba = u32::from_be_bytes([0, cdb[1] & 0x1f, cdb[2], cdb[3]]);
+                let blocks = if cdb[4] == 0 { 256 } else { cdb[4] as u32 };
+                if cdb[0] == SCSI_READ_6 {
+                    self.start_read(id, lba, blocks)
+                } else {
+                    self.start_write(id, lba, blocks)
+                }
+            }
+
+            SCSI_READ_10 | SCSI_WRITE_10 => {
+                let lba = u32::from_be_bytes([cdb[2], cdb[3], cdb[4], cdb[5]]);
+                let blocks = u16::from_be_bytes([cdb[7], cdb[8]]) as u32;
+                if cdb[0] == SCSI_READ_10 {
+                    self.start_read(id, lba, blocks)
+                } else {
+                    self.start_write(id, lba, blocks)
+                }
+            }
+
+            SCSI_INQUIRY => {
+                self.data_in(self.inquiry(id), cdb[4] as usize);
+                Ok(())
+            }
+
+            SCSI_MODE_SENSE_6 => {
+                let capacity = self.capacity(id).min(0xffffff);
+                let block_size = self.device_type[id].block_size() as u32;
+                let mut data = vec![11, 0, 0, 8];
+                if self.targets.is_write_protected(id) {
+                    data[2] = 0x80;
+                }
+                data.extend_from_slice(&capacity.to_be_bytes());
+                data.extend_from_slice(&block_size.to_be_bytes());
+                self.data_in(data, cdb[4] as usize);
+                Ok(())
+            }
+
+            SCSI_READ_CAPACITY => {
+                let last_lba = self.capacity(id).saturating_sub(1);
+                let block_size = self.device_type[id].block_size() as u32;
+                let mut data = last_lba.to_be_bytes().to_vec();
+                data.extend_from_slice(&block_size.to_be_bytes());
+                self.data_in(data, 8);
+                Ok(())
+            }
+
+            // Single data track followed by the lead-out in LBA format
+            SCSI_READ_TOC if self.device_type[id] == ScsiDeviceType::CdRom => {
+                let mut data = vec![0, 18, 1, 1];
+                data.extend_from_slice(&[0, 0x14, 1, 0, 0, 0, 0, 0]);
+                data.extend_from_slice(&[0, 0x14, 0xaa, 0]);
+                data.extend_from_slice(&self.capacity(id).to_be_bytes());
+                let allocation_length = u16::from_be_bytes([cdb[7], cdb[8]]) as usize;
+                self.data_in(data, allocation_length);
+                Ok(())
+            }
+
+            _ => Err(SENSE_INVALID_COMMAND),
+        };
+
+        match result {
+            Ok(()) => self.sense[id] = SENSE_NONE,
+            Err(sense) => self.check_condition(sense),
+        }
+    }
+
+    fn data_in(&mut self, mut data: Vec<u8>, allocation_length: usize) {
+        data.truncate(allocation_length);
+        if !data.is_empty() {
+            self.data = data;
+            self.phase = Phase::DataIn;
+        }
+    }
+
+    fn inquiry(&self, id: usize) -> Vec<u8> {
+        let (device_type, removable, product) = match self.device_type[id] {
+            ScsiDeviceType::Disk => (0x00, 0x00, "SCSI HARD DISK"),
+            ScsiDeviceType::CdRom => (0x05, 0x80, "SCSI CD-ROM"),
+        };
+
+        let mut data = vec![device_type, removable, 0x02, 0x02, 31, 0, 0, 0];
+        data.extend_from_slice(format!("{SCSI_VENDOR:<8}").as_bytes());
+        data.extend_from_slice(format!("{product:<16}").as_bytes());
+        data.extend_from_slice(format!("{VERSION:<4.4}").as_bytes());
+        data
+    }
+
+    // Number of blocks of the target
+    fn capacity(&self, id: usize) -> u32 {
+        let bytes = self.targets.num_blocks(id) * BLOCK_SIZE;
+        (bytes / self.device_type[id].block_size()) as u32
+    }
+
+    fn check_range(&self, id: usize, lba: u32, blocks: u32) -> Result<(), Sense> {
+        if lba as u64 + blocks as u64 > self.capacity(id) as u64 {
+            Err(SENSE_LBA_OUT_OF_RANGE)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn start_read(&mut self, id: usize, lba: u32, blocks: u32) -> Result<(), Sense> {
+        self.check_range(id, lba, blocks)?;
+        if blocks > 0 {
+            self.lba = lba;
+            self.blocks_left = blocks;
+            self.read_block(id)?;
+            self.phase = Phase::DataIn;
+        }
+        Ok(())
+    }
+
+    fn read_block(&mut self, id: usize) -> Result<(), Sense> {
+        let sectors = self.device_type[id].block_size() / BLOCK_SIZE;
+        let first = self.lba * sectors as u32;
+
+        self.data.clear();
+        for block in first..first + sectors as u32 {
+            let data = self.targets.read_block(id, block).ok_or(SENSE_READ_ERROR)?;
+            self.data.extend_from_slice(data);
+        }
+        self.data_index = 0;
+        self.lba += 1;
+        self.blocks_left -= 1;
+        Ok(())
+    }
+
+    fn next_data_in(&mut self) {
+        let Some(id) = self.target else {
+            return;
+        };
+
+        if self.blocks_left == 0 {
+            self.phase = Phase::Status;
+        } else if let Err(sense) = self.read_block(id) {
+            self.check_condition(sense);
+        }
+    }
+
+    fn start_write(&mut self, id: usize, lba: u32, blocks: u32) -> Result<(), Sense> {
+        if self.targets.is_write_protected(id) {
+            return Err(SENSE_WRITE_PROTECTED);
+        }
+
+        self.check_range(id, lba, blocks)?;
+        if blocks > 0 {
+            self.lba = lba;
+            self.blocks_left = blocks;
+            self.data = vec![0; self.device_type[id].block_size()];
+            self.data_index = 0;
+            self.phase = Phase::DataOut;
+        }
+        Ok(())
+    }
+
+    fn write_data_out(&mut self) {
+        let Some(id) = self.target else {
+            return;
+        };
+
+        let sectors = self.device_type[id].block_size() / BLOCK_SIZE;
+        let first = self.lba * sectors as u32;
+        for (i, sector) in self.data.chunks(BLOCK_SIZE).enumerate() {
+            let error = self.targets.write_block(id, first + i as u32, sector);
+            if error != DeviceStatus::DeviceOk as u8 {
+                self.check_condition(SENSE_WRITE_ERROR);
+                return;
+            }
+        }
+
+        self.lba += 1;
+        self.blocks_left -= 1;
+        self.data_index = 0;
+        if self.blocks_left == 0 {
+            self.phase = Phase::Status;
+        }
+    }
+
+    fn read_register(&mut self, reg: u8) -> u8 {
+        match reg {
+            0x00 | 0x06 => self.bus_data(),
+
+            0x01 => {
+                let arbitration = self.mode & MODE_ARBITRATE != 0 && self.target.is_none();
+                self.initiator_command | if arbitration { ICR_AIP } else { 0 }
+            }
+
+            0x02 => self.mode,
+            0x03 => self.target_command,
+
+            0x04 => {
+                let mut value = self.phase.signals() << 2;
+                if self.target.is_some() || self.initiator_command & ICR_BSY != 0 {
+                    value |= CSBS_BSY;
+                }
+                if self.req {
+                    value |= CSBS_REQ;
+                }
+                if self.initiator_command & ICR_SEL != 0 {
+                    value |= CSBS_SEL;
+                }
+                value
+            }
+
+            0x05 => {
+                let mut value = self.initiator_command & (BSR_ATN | BSR_ACK);
+                if self.is_phase_match() {
+                    value |= BSR_PHASE_MATCH;
+                } else if self.dma && self.req {
+                    value |= BSR_IRQ;
+                }
+                if self.is_dma_request() {
+                    value |= BSR_DMA_REQUEST;
+                }
+                value
+            }
+
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, reg: u8, value: u8) {
+        match reg {
+            0x00 => {
+                self.output_data = value;
+                self.update_selection();
+            }
+
+            0x01 => self.write_initiator_command(value),
+
+            0x02 => {
+                self.mode = value;
+                if value & MODE_DMA == 0 {
+                    self.dma = false;
+                }
+            }
+
+            0x03 => self.target_command = value,
+            0x05 | 0x07 => self.dma = self.mode & MODE_DMA != 0,
+            _ => {}
+        }
+    }
+}
+
+// Length of the command descriptor block from the group code of the operation code
+fn command_length(opcode: u8) -> usize {
+    match opcode >> 5 {
+        1 | 2 => 10,
+        5 => 12,
+        _ => 6,
+    }
+}
+
+impl Default for Scsi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tick for Scsi {
+    fn tick(&mut self) {
+        self.targets.tick();
+    }
+}
+
+impl Card for Scsi {
+    fn rom_access(&mut self, addr: u16, _value: u8, _write_flag: bool) -> u8 {
+        ROM[(addr & 0xff) as usize]
+    }
+
+    fn io_access(
+        &mut self,
+        mmu: &mut Mmu,
+        video: &mut Video,
+        addr: u16,
+        value: u8,
+        write_flag: bool,
+    ) -> u8 {
+        let slot = ((addr & 0x00ff) - 0x0080) >> 4;
+        let io_addr = ((addr & 0x00ff) - (slot << 4)) as u8 & 0x0f;
+        let io_base = 0xc080 | (slot << 4);
+
+        match io_addr {
+            0x00..=0x07 => {
+                if write_flag {
+                    self.write_register(io_addr, value);
+                    0
+                } else {
+                    self.read_register(io_addr)
+                }
+            }
+
+            SCSI_DMA_DATA => self.dma_transfer(value, write_flag),
+
+            SCSI_SMARTPORT if write_flag => {
+                self.call_error = self
+                    .targets
+                    .firmware_smartport_call(mmu, video, io_base, value);
+                self.call_result = 0;
+                0
+            }
+
+            SCSI_PRODOS => {
+                if write_flag {
+                    (self.call_error, self.call_result) =
+                        self.targets.firmware_prodos_call(mmu, video, io_base);
+                }
+                self.call_error
+            }
+
+            SCSI_RESULT_LOW if !write_flag => self.call_result as u8,
+            SCSI_RESULT_HIGH if !write_flag => (self.call_result >> 8) as u8,
+
+            SCSI_DRQ if !write_flag && self.is_dma_request() => 0x80,
+
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Bus, IODevice, Mem};
+    use crate::cpu::CPU;
+
+    // Target with the block number in the first two bytes of each 512 bytes block
+    fn load_target(scsi: &mut Scsi, id: usize, blocks: usize, device_type: ScsiDeviceType) {
+        let mut data = vec![0u8; blocks * BLOCK_SIZE];
+        for (block, sector) in data.chunks_mut(BLOCK_SIZE).enumerate() {
+            sector[..2].copy_from_slice(&(block as u16).to_le_bytes());
+            sector[2] = id as u8;
+        }
+        let disk = &mut scsi.targets;
+        disk.drive_select(id);
+        disk.load_hdv_2mg_array(&data, true, device_type == ScsiDeviceType::CdRom)
+            .unwrap();
+        disk.set_loaded(true);
+        disk.drive_select(0);
+        scsi.device_type[id] = device_type;
+    }
+
+    fn scsi_cpu() -> CPU {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+        cpu.bus.register_device(IODevice::Scsi, 7);
+        cpu.bus.mem.intcxrom = false;
+        load_target(&mut cpu.bus.scsi, 0, 16, ScsiDeviceType::Disk);
+        load_target(&mut cpu.bus.scsi, 3, 64, ScsiDeviceType::CdRom);
+
+        // IORTS
+        cpu.load(&[0x60], 0xff58);
+        cpu
+    }
+
+    // Arbitrate, select the target and send the command with the REQ / ACK handshake
+    fn send_command(bus: &mut Bus, id: u8, cdb: &[u8]) {
+        bus.unclocked_addr_write(0xc0f0, INITIATOR_ID);
+        bus.unclocked_addr_write(0xc0f2, MODE_ARBITRATE);
+        assert_ne!(bus.unclocked_addr_read(0xc0f1) & ICR_AIP, 0);
+        bus.unclocked_addr_write(0xc0f1, ICR_SEL | ICR_BSY);
+        bus.unclocked_addr_write(0xc0f0, INITIATOR_ID | (1 << id));
+        bus.unclocked_addr_write(0xc0f1, ICR_SEL | ICR_BSY | ICR_DATA_BUS);
+        bus.unclocked_addr_write(0xc0f2, 0);
+        bus.unclocked_addr_write(0xc0f1, ICR_SEL | ICR_DATA_BUS);
+        assert_ne!(bus.unclocked_addr_read(0xc0f4) & CSBS_BSY, 0);
+        bus.unclocked_addr_write(0xc0f1, 0);
+
+        bus.unclocked_addr_write(0xc0f3, Phase::Command.signals());
+        for &byte in cdb {
+            assert_eq!(bus.unclocked_addr_read(0xc0f4), 0x68);
+            bus.unclocked_addr_write(0xc0f0, byte);
+            bus.unclocked_addr_write(0xc0f1, ICR_DATA_BUS | ICR_ACK);
+            bus.unclocked_addr_write(0xc0f1, 0);
+        }
+    }
+
+    // Read the bytes of the input phase with the REQ / ACK handshake
+    fn receive(bus: &mut Bus, phase: Phase) -> Vec<u8> {
+        let mut data = vec![];
+        let signals = phase.signals() << 2;
+        while bus.unclocked_addr_read(0xc0f4) & 0x3c == CSBS_REQ | signals {
+            data.push(bus.unclocked_addr_read(0xc0f0));
+            bus.unclocked_addr_write(0xc0f1, ICR_ACK);
+            bus.unclocked_addr_write(0xc0f1, 0);
+        }
+        data
+    }
+
+    fn complete(bus: &mut Bus) -> u8 {
+        let status = receive(bus, Phase::Status);
+        assert_eq!(receive(bus, Phase::MessageIn), [MESSAGE_COMMAND_COMPLETE]);
+        assert_eq!(bus.unclocked_addr_read(0xc0f4), 0);
+        status[0]
+    }
+
+    #[test]
+    fn inquiry_and_read_capacity() {
+        let mut cpu = scsi_cpu();
+        let bus = &mut cpu.bus;
+
+        send_command(bus, 3, &[SCSI_INQUIRY, 0, 0, 0, 36, 0]);
+        let data = receive(bus, Phase::DataIn);
+        assert_eq!(data.len(), 36);
+        assert_eq!(&data[..2], &[0x05, 0x80]);
+        assert_eq!(&data[8..15], b"EMU6502");
+        assert_eq!(complete(bus), STATUS_GOOD);
+
+        // 64 blocks of 512 bytes are 16 CD-ROM blocks
+        send_command(bus, 3, &[SCSI_READ_CAPACITY, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(receive(bus, Phase::DataIn), [0, 0, 0, 15, 0, 0, 8, 0]);
+        assert_eq!(complete(bus), STATUS_GOOD);
+
+        // No target at SCSI ID 2
+        bus.unclocked_addr_write(0xc0f0, INITIATOR_ID | 0x04);
+        bus.unclocked_addr_write(0xc0f1, ICR_SEL | ICR_DATA_BUS);
+        assert_eq!(bus.unclocked_addr_read(0xc0f4) & CSBS_BSY, 0);
+        bus.unclocked_addr_write(0xc0f1, 0);
+    }
+
+    #[test]
+    fn pseudo_dma_read_and_write() {
+        let mut cpu = scsi_cpu();
+        let bus = &mut cpu.bus;
+
+        // READ(10) CD-ROM block 2 is 512 bytes block 8 to 11
+        send_command(bus, 3, &[SCSI_READ_10, 0, 0, 0, 0, 2, 0, 0, 1, 0]);
+        bus.unclocked_addr_write(0xc0f3, Phase::DataIn.signals());
+        bus.unclocked_addr_write(0xc0f2, MODE_DMA);
+        bus.unclocked_addr_write(0xc0f7, 0);
+        let mut data = vec![];
+        while bus.unclocked_addr_read(0xc0fe) & 0x80 != 0 {
+            data.push(bus.unclocked_addr_read(0xc0f8));
+        }
+        assert_eq!(data.len(), CDROM_BLOCK_SIZE);
+        assert_eq!(data[0], 8);
+        assert_eq!(data[3 * BLOCK_SIZE], 11);
+        assert_ne!(bus.unclocked_addr_read(0xc0f5) & BSR_IRQ, 0);
+        bus.unclocked_addr_write(0xc0f2, 0);
+        assert_eq!(complete(bus), STATUS_GOOD);
+
+        // WRITE(6) of 2 blocks to the disk at block 4
+        send_command(bus, 0, &[SCSI_WRITE_6, 0, 0, 4, 2, 0]);
+        bus.unclocked_addr_write(0xc0f3, Phase::DataOut.signals());
+        bus.unclocked_addr_write(0xc0f2, MODE_DMA);
+        bus.unclocked_addr_write(0xc0f1, ICR_DATA_BUS);
+        bus.unclocked_addr_write(0xc0f5, 0);
+        let mut count = 0;
+        while bus.unclocked_addr_read(0xc0fe) & 0x80 != 0 {
+            bus.unclocked_addr_write(0xc0f8, (count / BLOCK_SIZE) as u8 + 0xa0);
+            count += 1;
+        }
+        assert_eq!(count, 2 * BLOCK_SIZE);
+        bus.unclocked_addr_write(0xc0f1, 0);
+        bus.unclocked_addr_write(0xc0f2, 0);
+        assert_eq!(complete(bus), STATUS_GOOD);
+        assert_eq!(bus.scsi.targets.read_block(0, 5).unwrap()[0], 0xa1);
+
+        // The CD-ROM is read only
+        send_command(bus, 3, &[SCSI_WRITE_6, 0, 0, 0, 1, 0]);
+        assert_eq!(complete(bus), STATUS_CHECK_CONDITION);
+        send_command(bus, 3, &[SCSI_REQUEST_SENSE, 0, 0, 0, 18, 0]);
+        let sense = receive(bus, Phase::DataIn);
+        assert_eq!((sense[2], sense[12]), SENSE_WRITE_PROTECTED);
+        assert_eq!(complete(bus), STATUS_GOOD);
+    }
+
+    #[test]
+    fn firmware_prodos_and_boot() {
+        let mut cpu = scsi_cpu();
+
+        // ProDOS read block 5 of drive 1 (SCSI ID 0) to $2000
+        cpu.load(&[0x01, 0x70, 0x00, 0x20, 0x05, 0x00], 0x42);
+        cpu.load(&[0x20, 0x0a, 0xc7, 0x85, 0x06, 0x86, 0x07, 0x00], 0x300);
+        cpu.program_counter = 0x300;
+        cpu.run();
+
+        assert_eq!(cpu.bus.unclocked_addr_read(0x06), 0);
+        assert_eq!(cpu.bus.unclocked_addr_read(0x07), 16);
+        assert_eq!(cpu.bus.unclocked_addr_read(0x2000), 5);
+
+        // Boot loads block 0 of SCSI ID 0 to $800 and runs it with X = $70
+        cpu.load(&[0xff], 0x802);
+        cpu.program_counter = 0xc700;
+        cpu.run();
+        assert_eq!(cpu.register_x, 0x70);
+        assert_eq!(cpu.bus.unclocked_addr_read(0x802), 0);
+    }
+}
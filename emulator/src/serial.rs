@@ -0,0 +1,1142 @@
+use crate::audio::CPU_6502_MHZ;
+use crate::bus::Card;
+use crate::mmu::Mmu;
+use crate::video::Video;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::str::FromStr;
+
+#[cfg(not(target_os = "wasi"))]
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+#[cfg(not(target_os = "wasi"))]
+use std::sync::mpsc;
+
+#[cfg(not(target_os = "wasi"))]
+use std::time::Duration;
+
+#[cfg(not(target_os = "wasi"))]
+use crate::modem::HayesModem;
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+/*
+Super Serial Card
+
+    C081        (r)   DIP switch 1
+                      Bit 7-4  Baud rate (6551 control register value, 1110 = 9600)
+                      Bit 0    1 = Communications mode, 0 = Printer mode (echo to screen)
+    C082        (r)   DIP switch 2
+                      Bit 7    2 stop bits
+                      Bit 5    7 data bits
+                      Bit 3-2  Parity (00 = None, 01 = Odd, 11 = Even)
+                      Bit 1    Add line feed after carriage return
+                      Bit 0    Enable receive interrupts
+    C083-C087   (r/w) Firmware traps used by the slot ROM
+    C088        (r/w) 6551 ACIA Data
+    C089        (r/w) 6551 ACIA Status (write for programmed reset)
+    C08A        (r/w) 6551 ACIA Command
+    C08B        (r/w) 6551 ACIA Control
+
+The slot ROM has the Pascal 1.1 firmware signature and supports PR#n, IN#n and the
+Pascal 1.1 INIT, READ, WRITE and STATUS calls. The host side of the serial port can be
+a TCP client, a TCP server, a pseudo-terminal, a log file or a Hayes compatible modem.
+
+Apple //c serial ports
+
+    C098-C09B   (r/w) Port 1 (printer) 6551 ACIA Data, Status, Command, Control
+    C0A8-C0AB   (r/w) Port 2 (modem) 6551 ACIA Data, Status, Command, Control
+
+The registers are mirrored at C09C-C09F and C0AC-C0AF. The firmware is part of the
+//c ROM, so only the ACIAs are emulated.
+*/
+
+// 6551 status register
+const STATUS_IRQ: u8 = 0x80;
+const STATUS_DSR: u8 = 0x40;
+const STATUS_DCD: u8 = 0x20;
+const STATUS_TDRE: u8 = 0x10;
+const STATUS_RDRF: u8 = 0x08;
+const STATUS_OVERRUN: u8 = 0x04;
+
+// 6551 command register
+const COMMAND_DTR: u8 = 0x01;
+const COMMAND_RX_IRQ_DISABLED: u8 = 0x02;
+const COMMAND_TX_CONTROL: u8 = 0x0c;
+const COMMAND_TX_IRQ: u8 = 0x04;
+const COMMAND_ECHO: u8 = 0x10;
+
+// 6551 control register
+const CONTROL_BAUD: u8 = 0x0f;
+const CONTROL_RX_CLOCK: u8 = 0x10;
+const CONTROL_STOP_BITS: u8 = 0x80;
+
+const BAUD_RATES: [usize; 16] = [
+    115200, 50, 75, 110, 135, 150, 300, 600, 1200, 1800, 2400, 3600, 4800, 7200, 9600, 19200,
+];
+
+// DIP switches
+pub const SW1_COMMUNICATIONS: u8 = 0x01;
+pub const SW2_STOP_BITS: u8 = 0x80;
+pub const SW2_DATA_BITS: u8 = 0x20;
+pub const SW2_PARITY: u8 = 0x0c;
+pub const SW2_LINE_FEED: u8 = 0x02;
+pub const SW2_INTERRUPTS: u8 = 0x01;
+
+// 9600 baud, 8 data bits, 1 stop bit, no parity in communications mode
+const DEFAULT_DIP_SWITCH: [u8; 2] = [0xe0 | SW1_COMMUNICATIONS, 0x00];
+
+// Firmware traps
+const SSC_STATUS: u8 = 0x03;
+const SSC_OUTPUT: u8 = 0x04;
+const SSC_INPUT: u8 = 0x05;
+const SSC_ENTRY: u8 = 0x06;
+const SSC_INIT: u8 = 0x07;
+
+const CSWL: u16 = 0x36;
+const KSWL: u16 = 0x38;
+const DOS_CSWL: u16 = 0xaa53;
+const DOS_KSWL: u16 = 0xaa55;
+
+const ROM: [u8; 256] = [
+    0x2c, 0x58, 0xff, 0x70, 0x0c, 0x38, 0x90, 0x18, 0xb8, 0x50, 0x06, 0x01, 0x31, 0x65, 0x6b, 0x75,
+    0x7b, 0x48, 0x98, 0x48, 0x8a, 0x48, 0x08, 0x78, 0x20, 0x58, 0xff, 0xba, 0xbd, 0x00, 0x01, 0x0a,
+    0x0a, 0x0a, 0x0a, 0xa8, 0xbd, 0x01, 0x01, 0x99, 0x86, 0xc0, 0xb9, 0x86, 0xc0, 0xd0, 0x14, 0xbd,
+    0x04, 0x01, 0x99, 0x84, 0xc0, 0xb9, 0x84, 0xc0, 0x10, 0x24, 0x28, 0x68, 0xaa, 0x68, 0xa8, 0x68,
+    0x4c, 0xf0, 0xfd, 0xb9, 0x85, 0xc0, 0x30, 0x08, 0xad, 0x00, 0xc0, 0x10, 0xf6, 0x8d, 0x10, 0xc0,
+    0xba, 0x48, 0xbc, 0x03, 0x01, 0xbd, 0x04, 0x01, 0x91, 0x28, 0x68, 0x9d, 0x04, 0x01, 0x28, 0x68,
+    0xaa, 0x68, 0xa8, 0x68, 0x60, 0x99, 0x87, 0xc0, 0xa2, 0x00, 0x60, 0xb9, 0x85, 0xc0, 0x10, 0xfb,
+    0x29, 0x7f, 0xa2, 0x00, 0x60, 0x99, 0x84, 0xc0, 0xa2, 0x00, 0x60, 0x99, 0x83, 0xc0, 0xb9, 0x83,
+    0xc0, 0x0a, 0xa2, 0x00, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Host side of a serial port
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum SerialConnection {
+    #[default]
+    None,
+    /// Connect to host:port
+    TcpClient(String),
+    /// Listen on address:port and accept one connection at a time
+    TcpServer(String),
+    /// Pseudo-terminal. The path of the slave device is reported by the host
+    Pty,
+    /// Log the transmitted data to the file
+    File(String),
+    /// Hayes compatible modem. Incoming calls are accepted on the optional address:port
+    Modem(Option<String>),
+}
+
+impl FromStr for SerialConnection {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s.split_once(':').unwrap_or((s, ""));
+        match (kind.to_ascii_lowercase().as_str(), value) {
+            ("none", _) => Ok(SerialConnection::None),
+            ("pty", _) => Ok(SerialConnection::Pty),
+            ("tcp", addr) if !addr.is_empty() => Ok(SerialConnection::TcpClient(addr.to_string())),
+            ("listen", port) if !port.is_empty() && !port.contains(':') => {
+                Ok(SerialConnection::TcpServer(format!("127.0.0.1:{port}")))
+            }
+            ("listen", addr) if !addr.is_empty() => {
+                Ok(SerialConnection::TcpServer(addr.to_string()))
+            }
+            ("file", path) if !path.is_empty() => Ok(SerialConnection::File(path.to_string())),
+            ("modem", "") => Ok(SerialConnection::Modem(None)),
+            ("modem", port) if !port.contains(':') => {
+                Ok(SerialConnection::Modem(Some(format!("127.0.0.1:{port}"))))
+            }
+            ("modem", addr) => Ok(SerialConnection::Modem(Some(addr.to_string()))),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid serial connection: {s}"),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SerialConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerialConnection::None => write!(f, "none"),
+            SerialConnection::TcpClient(addr) => write!(f, "tcp:{addr}"),
+            SerialConnection::TcpServer(addr) => write!(f, "listen:{addr}"),
+            SerialConnection::Pty => write!(f, "pty"),
+            SerialConnection::File(path) => write!(f, "file:{path}"),
+            SerialConnection::Modem(None) => write!(f, "modem"),
+            SerialConnection::Modem(Some(addr)) => write!(f, "modem:{addr}"),
+        }
+    }
+}
+
+#[derive(Default)]
+enum Host {
+    #[default]
+    None,
+    // The connection is made on a worker thread
+    #[cfg(not(target_os = "wasi"))]
+    Connecting(mpsc::Receiver<io::Result<TcpStream>>),
+    #[cfg(not(target_os = "wasi"))]
+    Tcp(TcpStream),
+    #[cfg(not(target_os = "wasi"))]
+    Listener(TcpListener, Option<TcpStream>),
+    // The slave side is kept open so that the master does not report EIO when the
+    // terminal program closes the device
+    #[cfg(unix)]
+    Pty {
+        master: File,
+        _slave: File,
+    },
+    File(File),
+    #[cfg(not(target_os = "wasi"))]
+    Modem(Box<HayesModem>),
+    // The data stays in the buffers so that the tests do not depend on the network
+    #[cfg(test)]
+    Buffer,
+}
+
+#[derive(Default)]
+pub struct SerialHost {
+    connection: SerialConnection,
+    host: Host,
+    name: String,
+    receive_buffer: VecDeque<u8>,
+    transmit_buffer: VecDeque<u8>,
+}
+
+impl fmt::Debug for SerialHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SerialHost {}", self.name)
+    }
+}
+
+#[cfg(unix)]
+fn open_pty() -> io::Result<(File, File, String)> {
+    use std::ffi::CStr;
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    // SAFETY: The file descriptor returned by posix_openpt is owned by the File and
+    // ptsname returns a null terminated string that is copied before the next call
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let name = libc::ptsname(fd);
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let path = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let slave = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+
+        // Raw mode so that the data is passed unchanged
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut termios) == 0 {
+            libc::cfmakeraw(&mut termios);
+            libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+        }
+        Ok((master, slave, path))
+    }
+}
+
+#[cfg(not(target_os = "wasi"))]
+fn connect_tcp(addr: &str) -> io::Result<TcpStream> {
+    let sock_addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("Unable to resolve {addr}")))?;
+    let stream = TcpStream::connect_timeout(&sock_addr, Duration::from_secs(5))?;
+    stream.set_nonblocking(true)?;
+    let _ = stream.set_nodelay(true);
+    Ok(stream)
+}
+
+/// Connect on a worker thread so that the name lookup and the connection do not block
+/// the emulation. The result is sent to the returned channel
+#[cfg(not(target_os = "wasi"))]
+pub(crate) fn connect_tcp_async(addr: &str) -> mpsc::Receiver<io::Result<TcpStream>> {
+    let (sender, receiver) = mpsc::channel();
+    let addr = addr.to_owned();
+    std::thread::spawn(move || {
+        // The receiver is dropped when the call is cancelled
+        let _ = sender.send(connect_tcp(&addr));
+    });
+    receiver
+}
+
+// Returns false when the stream is closed
+pub(crate) fn read_stream<R: Read>(stream: &mut R, buffer: &mut VecDeque<u8>) -> bool {
+    let mut data = [0u8; 256];
+    match stream.read(&mut data) {
+        Ok(0) => false,
+        Ok(len) => {
+            buffer.extend(&data[..len]);
+            true
+        }
+        Err(e) => matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted),
+    }
+}
+
+// Returns false when the stream is closed
+pub(crate) fn write_stream<W: Write>(stream: &mut W, buffer: &mut VecDeque<u8>) -> bool {
+    while !buffer.is_empty() {
+        let (data, _) = buffer.as_slices();
+        match stream.write(data) {
+            Ok(0) => return false,
+            Ok(len) => {
+                buffer.drain(..len);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+impl SerialHost {
+    pub fn open(connection: &SerialConnection) -> io::Result<Self> {
+        let mut name = connection.to_string();
+        let host = match connection {
+            SerialConnection::None => Host::None,
+
+            #[cfg(not(target_os = "wasi"))]
+            SerialConnection::TcpClient(addr) => Host::Connecting(connect_tcp_async(addr)),
+
+            #[cfg(not(target_os = "wasi"))]
+            SerialConnection::TcpServer(addr) => {
+                let listener = TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                name = format!("listen:{}", listener.local_addr()?);
+                Host::Listener(listener, None)
+            }
+
+            #[cfg(unix)]
+            SerialConnection::Pty => {
+                let (master, slave, path) = open_pty()?;
+                name = path;
+                Host::Pty {
+                    master,
+                    _slave: slave,
+                }
+            }
+
+            #[cfg(not(target_os = "wasi"))]
+            SerialConnection::Modem(listen) => {
+                let modem = HayesModem::new(listen.as_deref())?;
+                if let Some(addr) = modem.local_addr() {
+                    name = format!("modem:{addr}");
+                }
+                Host::Modem(Box::new(modem))
+            }
+
+            SerialConnection::File(path) => Host::File(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?,
+            ),
+
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Serial connection {connection} is not supported"),
+                ));
+            }
+        };
+
+        Ok(SerialHost {
+            connection: connection.clone(),
+            host,
+            name,
+            ..Default::default()
+        })
+    }
+
+    pub fn connection(&self) -> &SerialConnection {
+        &self.connection
+    }
+
+    /// The connection string, or the device path of the pseudo-terminal
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(self.host, Host::None)
+    }
+
+    /// True when the remote side is connected (Data carrier detect)
+    pub fn is_carrier(&self) -> bool {
+        match &self.host {
+            Host::None => false,
+            #[cfg(not(target_os = "wasi"))]
+            Host::Connecting(_) => false,
+            #[cfg(not(target_os = "wasi"))]
+            Host::Listener(_, stream) => stream.is_some(),
+            #[cfg(not(target_os = "wasi"))]
+            Host::Modem(modem) => modem.is_carrier(),
+            _ => true,
+        }
+    }
+
+    /// Accept the pending connection, send the buffered data and receive the data
+    /// from the host
+    pub fn poll(&mut self) {
+        #[cfg(not(target_os = "wasi"))]
+        if let Host::Connecting(receiver) = &self.host {
+            match receiver.try_recv() {
+                Ok(Ok(stream)) => self.host = Host::Tcp(stream),
+                Err(mpsc::TryRecvError::Empty) => return,
+                result => {
+                    if let Ok(Err(e)) = result {
+                        eprintln!("Unable to connect to {}: {e}", self.connection);
+                    }
+                    self.host = Host::None;
+                    self.transmit_buffer.clear();
+                    return;
+                }
+            }
+        }
+
+        let receive = &mut self.receive_buffer;
+        let transmit = &mut self.transmit_buffer;
+        let connected = match &mut self.host {
+            Host::None => true,
+
+            // Resolved above
+            #[cfg(not(target_os = "wasi"))]
+            Host::Connecting(_) => true,
+
+            #[cfg(not(target_os = "wasi"))]
+            Host::Tcp(stream) => write_stream(stream, transmit) && read_stream(stream, receive),
+
+            #[cfg(not(target_os = "wasi"))]
+            Host::Listener(listener, stream) => {
+                if stream.is_none()
+                    && let Ok((accepted, _)) = listener.accept()
+                    && accepted.set_nonblocking(true).is_ok()
+                {
+                    let _ = accepted.set_nodelay(true);
+                    *stream = Some(accepted);
+                }
+
+                if let Some(connection) = stream {
+                    if !write_stream(connection, transmit) || !read_stream(connection, receive) {
+                        *stream = None;
+                    }
+                } else {
+                    transmit.clear();
+                }
+                true
+            }
+
+            #[cfg(unix)]
+            Host::Pty { master, .. } => {
+                // The data is dropped when the terminal program is not reading
+                if !write_stream(master, transmit) {
+                    transmit.clear();
+                }
+                read_stream(master, receive);
+                true
+            }
+
+            Host::File(file) => {
+                if !write_stream(file, transmit) {
+                    transmit.clear();
+                }
+                true
+            }
+
+            #[cfg(not(target_os = "wasi"))]
+            Host::Modem(modem) => {
+                modem.poll(receive, transmit);
+                true
+            }
+
+            #[cfg(test)]
+            Host::Buffer => true,
+        };
+
+        if !connected {
+            self.host = Host::None;
+            self.transmit_buffer.clear();
+        }
+    }
+
+    pub fn read_byte(&mut self) -> Option<u8> {
+        if self.receive_buffer.is_empty() {
+            self.poll();
+        }
+        self.receive_buffer.pop_front()
+    }
+
+    pub fn write_byte(&mut self, value: u8) {
+        if self.is_open() {
+            self.transmit_buffer.push_back(value);
+            self.poll();
+        }
+    }
+}
+
+/// MOS 6551 Asynchronous Communication Interface Adapter
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Acia6551 {
+    #[cfg_attr(feature = "serde_support", serde(skip))]
+    host: SerialHost,
+
+    receive_data: u8,
+    status: u8,
+    command: u8,
+    control: u8,
+    cycles: usize,
+    next_receive: usize,
+    transmit_ready: usize,
+    transmit_pending: bool,
+    irq_happen: Option<usize>,
+}
+
+impl Acia6551 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hardware reset. The host connection is kept
+    pub fn reset(&mut self) {
+        self.status &= !(STATUS_RDRF | STATUS_OVERRUN);
+        self.command = 0;
+        self.control = 0;
+        self.transmit_pending = false;
+        self.irq_happen = None;
+    }
+
+    pub fn set_connection(&mut self, connection: &SerialConnection) -> io::Result<()> {
+        self.host = SerialHost::default();
+        self.host = SerialHost::open(connection)?;
+        Ok(())
+    }
+
+    pub fn host(&self) -> &SerialHost {
+        &self.host
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.host.is_open() || self.transmit_pending
+    }
+
+    fn byte_cycles(&self) -> usize {
+        let baud = BAUD_RATES[(self.control & CONTROL_BAUD) as usize];
+        let stop_bits = if self.control & CONTROL_STOP_BITS != 0 {
+            2
+        } else {
+            1
+        };
+        let bits = 1 + self.data_bits() + stop_bits;
+        CPU_6502_MHZ * bits / baud
+    }
+
+    fn data_bits(&self) -> usize {
+        8 - ((self.control >> 5) & 0x03) as usize
+    }
+
+    fn set_irq(&mut self) {
+        if self.irq_happen.is_none() {
+            self.irq_happen = Some(self.cycles);
+        }
+    }
+
+    fn is_receiver_enabled(&self) -> bool {
+        self.command & COMMAND_DTR != 0
+    }
+
+    fn is_receive_irq_enabled(&self) -> bool {
+        self.is_receiver_enabled() && self.command & COMMAND_RX_IRQ_DISABLED == 0
+    }
+
+    fn is_transmit_irq_enabled(&self) -> bool {
+        self.command & COMMAND_TX_CONTROL == COMMAND_TX_IRQ
+    }
+
+    pub fn tick(&mut self, cycles: usize) {
+        self.cycles = cycles;
+
+        if self.transmit_pending && cycles >= self.transmit_ready {
+            self.transmit_pending = false;
+            if self.is_transmit_irq_enabled() {
+                self.set_irq();
+            }
+        }
+
+        if cycles < self.next_receive || !self.host.is_open() {
+            return;
+        }
+        self.next_receive = cycles + self.byte_cycles();
+
+        if self.status & STATUS_RDRF != 0 || !self.is_receiver_enabled() {
+            self.host.poll();
+            return;
+        }
+
+        if let Some(value) = self.host.read_byte() {
+            self.receive(value);
+        }
+    }
+
+    fn receive(&mut self, value: u8) {
+        self.receive_data = value;
+        self.status |= STATUS_RDRF;
+        if self.is_receive_irq_enabled() {
+            self.set_irq();
+        }
+
+        // Echo mode requires the transmitter interrupt to be disabled and RTS low
+        if self.command & COMMAND_ECHO != 0 && self.command & COMMAND_TX_CONTROL == 0 {
+            self.host.write_byte(value);
+        }
+    }
+
+    pub fn poll_irq(&self) -> Option<usize> {
+        self.irq_happen
+    }
+
+    fn read_status(&mut self) -> u8 {
+        let mut status = self.status & (STATUS_RDRF | STATUS_OVERRUN);
+        if !self.transmit_pending || self.cycles >= self.transmit_ready {
+            status |= STATUS_TDRE;
+        }
+        if !self.host.is_carrier() {
+            status |= STATUS_DCD | STATUS_DSR;
+        }
+        if self.irq_happen.take().is_some() {
+            status |= STATUS_IRQ;
+        }
+        status
+    }
+
+    pub fn read(&mut self, reg: u8) -> u8 {
+        match reg & 0x03 {
+            0 => {
+                self.status &= !(STATUS_RDRF | STATUS_OVERRUN);
+                self.receive_data
+            }
+            1 => self.read_status(),
+            2 => self.command,
+            _ => self.control,
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, value: u8) {
+        match reg & 0x03 {
+            0 => self.transmit(value),
+
+            // Programmed reset
+            1 => {
+                self.command &= 0xe0;
+                self.status &= !STATUS_OVERRUN;
+                self.irq_happen = None;
+            }
+            2 => self.command = value,
+            _ => self.control = value,
+        }
+    }
+
+    fn transmit(&mut self, value: u8) {
+        let mask = ((1u16 << self.data_bits()) - 1) as u8;
+        self.host.write_byte(value & mask);
+        self.transmit_pending = true;
+        self.transmit_ready = if self.host.is_open() {
+            self.cycles + self.byte_cycles()
+        } else {
+            self.cycles
+        };
+    }
+
+    /// Receive a byte without waiting for the next poll. Used by the firmware
+    pub fn read_data(&mut self) -> Option<u8> {
+        if self.status & STATUS_RDRF == 0
+            && self.host.is_open()
+            && let Some(value) = self.host.read_byte()
+        {
+            self.receive(value);
+        }
+
+        if self.status & STATUS_RDRF != 0 {
+            Some(self.read(0))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_transmit_ready(&self) -> bool {
+        !self.transmit_pending || self.cycles >= self.transmit_ready
+    }
+
+    pub fn has_receive_data(&mut self) -> bool {
+        if self.status & STATUS_RDRF == 0 && self.host.is_open() {
+            self.host.poll();
+            if let Some(value) = self.host.read_byte() {
+                self.receive(value);
+            }
+        }
+        self.status & STATUS_RDRF != 0
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct SuperSerialCard {
+    acia: Acia6551,
+    dip_switch: [u8; 2],
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    connection: SerialConnection,
+    status_request: u8,
+    input: bool,
+}
+
+impl SuperSerialCard {
+    pub fn new() -> Self {
+        SuperSerialCard {
+            acia: Acia6551::new(),
+            dip_switch: DEFAULT_DIP_SWITCH,
+            connection: SerialConnection::None,
+            status_request: 0,
+            input: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.acia.reset();
+        self.input = false;
+    }
+
+    pub fn tick(&mut self, cycles: usize) {
+        self.acia.tick(cycles);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.acia.is_active()
+    }
+
+    pub fn poll_irq(&self) -> Option<usize> {
+        self.acia.poll_irq()
+    }
+
+    pub fn set_connection(&mut self, connection: &SerialConnection) -> io::Result<()> {
+        self.connection = SerialConnection::None;
+        self.acia.set_connection(connection)?;
+        self.connection = connection.clone();
+        Ok(())
+    }
+
+    pub fn get_connection(&self) -> &SerialConnection {
+        &self.connection
+    }
+
+    /// The connection string, or the device path of the pseudo-terminal
+    pub fn get_host_name(&self) -> &str {
+        self.acia.host().name()
+    }
+
+    pub fn get_dip_switch(&self, index: usize) -> u8 {
+        self.dip_switch[index]
+    }
+
+    pub fn set_dip_switch(&mut self, index: usize, value: u8) {
+        self.dip_switch[index] = value;
+    }
+
+    // Program the ACIA from the DIP switches
+    fn firmware_init(&mut self) {
+        let [sw1, sw2] = self.dip_switch;
+        let mut control = (sw1 >> 4) | CONTROL_RX_CLOCK;
+        if sw2 & SW2_STOP_BITS != 0 {
+            control |= CONTROL_STOP_BITS;
+        }
+        if sw2 & SW2_DATA_BITS != 0 {
+            control |= 0x20;
+        }
+
+        // Parity odd = 001, even = 011 in bit 7-5 of the command register
+        let mut command = COMMAND_DTR | COMMAND_RX_IRQ_DISABLED | 0x08;
+        command |= match (sw2 & SW2_PARITY) >> 2 {
+            0x01 => 0x20,
+            0x03 => 0x60,
+            _ => 0x00,
+        };
+        if sw2 & SW2_INTERRUPTS != 0 {
+            command &= !COMMAND_RX_IRQ_DISABLED;
+        }
+
+        self.acia.write(3, control);
+        self.acia.write(2, command);
+    }
+
+    fn firmware_output(&mut self, value: u8) {
+        if !self.acia.is_receiver_enabled() {
+            self.firmware_init();
+        }
+        let value = value & 0x7f;
+        self.acia.transmit(value);
+        if value == 0x0d && self.dip_switch[1] & SW2_LINE_FEED != 0 {
+            self.acia.transmit(0x0a);
+        }
+    }
+
+    fn firmware_input(&mut self) -> u8 {
+        if !self.acia.is_receiver_enabled() {
+            self.firmware_init();
+        }
+        self.acia.read_data().map(|value| value | 0x80).unwrap_or(0)
+    }
+
+    // BASIC entry (V set) is an input call when KSW points to the card. The Pascal 1.0
+    // entries use the carry flag
+    fn is_input_entry(&self, mem: &Mmu, slot: u16, flags: u8) -> bool {
+        if flags & 0x40 == 0 {
+            return flags & 0x01 != 0;
+        }
+        is_input_hook(mem, slot)
+    }
+}
+
+impl Default for SuperSerialCard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Card for SuperSerialCard {
+    fn rom_access(&mut self, addr: u16, _value: u8, _write_flag: bool) -> u8 {
+        ROM[(addr & 0xff) as usize]
+    }
+
+    fn io_access(
+        &mut self,
+        mem: &mut Mmu,
+        _video: &mut Video,
+        addr: u16,
+        value: u8,
+        write_flag: bool,
+    ) -> u8 {
+        let slot = ((addr & 0x00ff) - 0x0080) >> 4;
+        let io_addr = ((addr & 0x00ff) - (slot << 4)) as u8 & 0x0f;
+
+        match io_addr {
+            0x01 if !write_flag => self.dip_switch[0],
+            0x02 if !write_flag => self.dip_switch[1],
+
+            // Pascal status: 0 = Ready for output, 1 = Input available
+            SSC_STATUS => {
+                if write_flag {
+                    self.status_request = value;
+                    0
+                } else {
+                    let ready = if self.status_request == 0 {
+                        self.acia.is_transmit_ready()
+                    } else {
+                        self.acia.has_receive_data()
+                    };
+                    if ready { 0x80 } else { 0 }
+                }
+            }
+
+            SSC_OUTPUT => {
+                if write_flag {
+                    self.firmware_output(value);
+                    0
+                } else if self.dip_switch[0] & SW1_COMMUNICATIONS == 0 {
+                    0x80
+                } else {
+                    0
+                }
+            }
+
+            SSC_INPUT if !write_flag => self.firmware_input(),
+
+            SSC_ENTRY => {
+                if write_flag {
+                    self.input = self.is_input_entry(mem, slot, value);
+                }
+                self.input as u8
+            }
+
+            SSC_INIT if write_flag => {
+                self.firmware_init();
+                0
+            }
+
+            0x08..=0x0b => {
+                if write_flag {
+                    self.acia.write(io_addr, value);
+                    0
+                } else {
+                    self.acia.read(io_addr)
+                }
+            }
+
+            _ => 0,
+        }
+    }
+}
+
+/// Returns true when the slot firmware at Cn00 is called through the input hook
+pub(crate) fn is_input_hook(mem: &Mmu, slot: u16) -> bool {
+    let entry = 0xc000 | (slot << 8);
+    let read_u16 = |addr: u16| u16::from_le_bytes([mem.mem_read(addr), mem.mem_read(addr + 1)]);
+    let csw = read_u16(CSWL);
+    let ksw = read_u16(KSWL);
+    if csw == entry {
+        return false;
+    }
+    if ksw == entry {
+        return true;
+    }
+
+    // DOS 3.3 keeps the I/O hooks in its own page
+    read_u16(DOS_KSWL) == entry && read_u16(DOS_CSWL) != entry
+}
+
+/// The two built-in serial ports of the Apple //c
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Apple2cSerial {
+    ports: [Acia6551; 2],
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    connections: [SerialConnection; 2],
+}
+
+impl Apple2cSerial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) {
+        for port in &mut self.ports {
+            port.reset();
+        }
+    }
+
+    pub fn tick(&mut self, cycles: usize) {
+        for port in &mut self.ports {
+            port.tick(cycles);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.ports.iter().any(Acia6551::is_active)
+    }
+
+    pub fn poll_irq(&self) -> Option<usize> {
+        self.ports.iter().find_map(Acia6551::poll_irq)
+    }
+
+    /// Connect port 1 (printer) or port 2 (modem)
+    pub fn set_connection(&mut self, port: usize, connection: &SerialConnection) -> io::Result<()> {
+        if !(1..=2).contains(&port) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid Apple //c serial port {port}"),
+            ));
+        }
+        self.connections[port - 1] = SerialConnection::None;
+        self.ports[port - 1].set_connection(connection)?;
+        self.connections[port - 1] = connection.clone();
+        Ok(())
+    }
+
+    pub fn get_connection(&self, port: usize) -> &SerialConnection {
+        &self.connections[port - 1]
+    }
+
+    /// The connection string, or the device path of the pseudo-terminal
+    pub fn get_host_name(&self, port: usize) -> &str {
+        self.ports[port - 1].host().name()
+    }
+
+    /// Returns None when the address is not one of the serial port registers
+    pub fn io_access(&mut self, addr: u16, value: u8, write_flag: bool) -> Option<u8> {
+        let port = match addr & 0xff {
+            0x98..=0x9f => &mut self.ports[0],
+            0xa8..=0xaf => &mut self.ports[1],
+            _ => return None,
+        };
+
+        let reg = (addr & 0x03) as u8;
+        if write_flag {
+            port.write(reg, value);
+            Some(0)
+        } else {
+            Some(port.read(reg))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Bus, IODevice, Mem};
+    use crate::cpu::CPU;
+
+    fn buffer_host() -> SerialHost {
+        SerialHost {
+            host: Host::Buffer,
+            name: "buffer".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_serial_connection() {
+        let connection: SerialConnection = "listen:6502".parse().unwrap();
+        assert_eq!(
+            connection,
+            SerialConnection::TcpServer("127.0.0.1:6502".to_string())
+        );
+        assert_eq!(connection.to_string(), "listen:127.0.0.1:6502");
+        assert_eq!(
+            "tcp:localhost:23".parse::<SerialConnection>().unwrap(),
+            SerialConnection::TcpClient("localhost:23".to_string())
+        );
+        assert_eq!(
+            "modem:6400".parse::<SerialConnection>().unwrap(),
+            SerialConnection::Modem(Some("127.0.0.1:6400".to_string()))
+        );
+        assert!("phone".parse::<SerialConnection>().is_err());
+    }
+
+    #[test]
+    fn acia_receive_interrupt() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.bus.register_device(IODevice::SuperSerial, 2);
+        cpu.bus.ssc.acia.host = buffer_host();
+
+        // 8N1 19200 baud, DTR on, receive interrupt enabled
+        cpu.bus.unclocked_addr_write(0xc0ab, 0x1f);
+        cpu.bus.unclocked_addr_write(0xc0aa, 0x09);
+        cpu.bus.ssc.acia.host.receive_buffer.push_back(b'A');
+
+        // The card is not polled when it is not in a slot
+        cpu.bus.unregister_device(2);
+        cpu.bus.tick();
+        assert!(cpu.bus.irq().is_none());
+        assert_eq!(cpu.bus.ssc.acia.host.receive_buffer.len(), 1);
+
+        cpu.bus.register_device(IODevice::SuperSerial, 2);
+        cpu.bus.tick();
+        assert!(cpu.bus.irq().is_some());
+        let status = cpu.bus.unclocked_addr_read(0xc0a9);
+        assert_eq!(
+            status & (STATUS_IRQ | STATUS_RDRF),
+            STATUS_IRQ | STATUS_RDRF
+        );
+        assert_eq!(cpu.bus.unclocked_addr_read(0xc0a8), b'A');
+        assert!(cpu.bus.irq().is_none());
+
+        cpu.bus.unclocked_addr_write(0xc0a8, b'Z');
+        assert_eq!(cpu.bus.ssc.acia.host.transmit_buffer, [b'Z']);
+    }
+
+    #[test]
+    fn firmware_output() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+        cpu.bus.register_device(IODevice::SuperSerial, 2);
+        cpu.bus.mem.intcxrom = false;
+        cpu.bus.ssc.acia.host = buffer_host();
+
+        // IORTS and CSW pointing to the slot 2 firmware
+        cpu.load(&[0x60], 0xff58);
+        cpu.load(&[0x00, 0xc2], 0x36);
+
+        // LDA #$C8; JSR $C200; NOP; BRK
+        cpu.load(&[0xa9, 0xc8, 0x20, 0x00, 0xc2, 0xea, 0x00], 0x300);
+        cpu.program_counter = 0x300;
+        cpu.run();
+
+        assert_eq!(cpu.register_a, 0xc8);
+        assert_eq!(cpu.bus.ssc.acia.host.transmit_buffer, [b'H']);
+    }
+
+    #[test]
+    fn apple2c_serial_ports() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.bus.set_apple2c(true);
+        cpu.bus.a2c_serial.ports[1].host = buffer_host();
+
+        // Port 1 has no host connection
+        cpu.bus.unclocked_addr_write(0xc09b, 0x1f);
+        cpu.bus.unclocked_addr_write(0xc09a, 0x0b);
+        assert_eq!(cpu.bus.unclocked_addr_read(0xc099) & STATUS_DCD, STATUS_DCD);
+
+        // Port 2 at 19200 baud with receive interrupt enabled
+        cpu.bus.unclocked_addr_write(0xc0ab, 0x1f);
+        cpu.bus.unclocked_addr_write(0xc0aa, 0x09);
+        assert_eq!(cpu.bus.unclocked_addr_read(0xc0a9) & STATUS_DCD, 0);
+        cpu.bus.a2c_serial.ports[1]
+            .host
+            .receive_buffer
+            .push_back(b'K');
+
+        cpu.bus.tick();
+        assert!(cpu.bus.irq().is_some());
+        assert_eq!(cpu.bus.unclocked_addr_read(0xc0ad) & STATUS_IRQ, STATUS_IRQ);
+        assert_eq!(cpu.bus.unclocked_addr_read(0xc0a8), b'K');
+
+        cpu.bus.unclocked_addr_write(0xc0a8, b'C');
+        assert_eq!(cpu.bus.a2c_serial.ports[1].host.transmit_buffer, [b'C']);
+    }
+
+    #[test]
+    fn tcp_client_connecting() {
+        let (sender, receiver) = mpsc::channel();
+        let mut host = SerialHost {
+            connection: SerialConnection::TcpClient("localhost:6502".to_string()),
+            host: Host::Connecting(receiver),
+            ..Default::default()
+        };
+
+        // The data is kept until the connection is made
+        assert!(host.is_open());
+        assert!(!host.is_carrier());
+        host.write_byte(b'A');
+        assert_eq!(host.transmit_buffer, [b'A']);
+
+        sender
+            .send(Err(io::Error::from(ErrorKind::ConnectionRefused)))
+            .unwrap();
+        host.poll();
+        assert!(!host.is_open());
+        assert!(host.transmit_buffer.is_empty());
+    }
+}
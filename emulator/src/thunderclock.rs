@@ -0,0 +1,685 @@
+use crate::audio::CPU_6502_MHZ;
+use crate::bus::Card;
+use crate::mmu::Mmu;
+use crate::serial::is_input_hook;
+use crate::video::Video;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::str::FromStr;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+#[cfg(feature = "web_time")]
+use web_time::SystemTime;
+
+#[cfg(not(feature = "web_time"))]
+use std::time::SystemTime;
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+/*
+Thunderclock Plus
+
+    C0n0        (w)   Bit 0    uPD1990 DATA IN
+                      Bit 1    uPD1990 CLK (shift on rising edge)
+                      Bit 2    uPD1990 STB (command latched on rising edge)
+                      Bit 5-3  uPD1990 command C2-C0
+                      Bit 6    Enable the timing pulse interrupt
+                (r)   Bit 7    uPD1990 DATA OUT
+                      Bit 5    Interrupt pending
+    C0n3-C0n5   (r/w) Firmware traps used by the slot ROM
+    C0n8        (r/w) Clear the interrupt
+
+uPD1990 commands
+
+    0   Register hold       DATA OUT = 1 Hz
+    1   Register shift      DATA OUT = LSB of the shift register
+    2   Time set            Load the counter from the shift register
+    3   Time read           Load the shift register from the counter
+    4   Timing pulse 64 Hz
+    5   Timing pulse 256 Hz
+    6   Timing pulse 2048 Hz
+
+The 40 bit shift register holds the seconds, minutes, hours and date in BCD followed
+by the day of week (0 = Sunday) and the month (1-12), least significant bit first.
+The chip does not keep the year.
+
+The slot ROM has the Thunderclock signature recognized by ProDOS, with the READ entry
+at Cn08 and the WRITE entry at Cn0B. READ places the time in the input buffer at $200
+and WRITE with a mode character in A selects the format of the time:
+
+    #   Numeric         "mo,dw,dt,hr,mn,sc"
+    %   12 hour         "WED OCT 19 01:23:45 PM"
+    &   24 hour         "WED OCT 19 13:23:45"
+
+IN#n reads the time in the selected format and PR#n accepts the mode characters.
+*/
+
+const CONTROL_DATA_IN: u8 = 0x01;
+const CONTROL_CLK: u8 = 0x02;
+const CONTROL_STB: u8 = 0x04;
+const CONTROL_COMMAND: u8 = 0x38;
+const CONTROL_IRQ_ENABLE: u8 = 0x40;
+
+const STATUS_DATA_OUT: u8 = 0x80;
+const STATUS_IRQ: u8 = 0x20;
+
+const COMMAND_HOLD: u8 = 0;
+const COMMAND_SHIFT: u8 = 1;
+const COMMAND_TIME_SET: u8 = 2;
+const COMMAND_TIME_READ: u8 = 3;
+const COMMAND_TP_64HZ: u8 = 4;
+const COMMAND_TP_256HZ: u8 = 5;
+const COMMAND_TP_2048HZ: u8 = 6;
+
+/// Timing pulse rates in Hz selectable by the uPD1990 commands
+pub const INTERRUPT_RATES: [usize; 3] = [64, 256, 2048];
+
+// Firmware traps
+const TC_WRITE: u8 = 0x03;
+const TC_READ: u8 = 0x04;
+const TC_BASIC: u8 = 0x05;
+const TC_CLEAR_IRQ: u8 = 0x08;
+
+const INPUT_BUFFER: u16 = 0x200;
+
+const MODE_NUMERIC: u8 = b'#';
+const MODE_12_HOUR: u8 = b'%';
+const MODE_24_HOUR: u8 = b'&';
+
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+const ROM: [u8; 256] = [
+    0x08, 0x78, 0x28, 0x2c, 0x58, 0xff, 0x70, 0x07, 0x38, 0xb0, 0x03, 0x18, 0x90, 0x00, 0xb8, 0x48,
+    0x8a, 0x48, 0x98, 0x48, 0x08, 0x78, 0x20, 0x58, 0xff, 0xba, 0xbd, 0x00, 0x01, 0x0a, 0x0a, 0x0a,
+    0x0a, 0xa8, 0xbd, 0x04, 0x01, 0x70, 0x12, 0x48, 0xbd, 0x01, 0x01, 0x4a, 0x68, 0x90, 0x05, 0x99,
+    0x84, 0xc0, 0xb0, 0x1a, 0x99, 0x83, 0xc0, 0x90, 0x15, 0x99, 0x85, 0xc0, 0xb9, 0x85, 0xc0, 0xf0,
+    0x0d, 0x48, 0xbc, 0x02, 0x01, 0xbd, 0x04, 0x01, 0x91, 0x28, 0x68, 0x9d, 0x04, 0x01, 0x28, 0x68,
+    0xa8, 0x68, 0xaa, 0x68, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Source of the time kept by the clock card
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum ClockSource {
+    /// Local time of the host
+    #[default]
+    Host,
+
+    /// Time that does not advance, in seconds since 1970-01-01 00:00:00
+    Fixed(i64),
+
+    /// Local time of the host adjusted by the number of seconds
+    Offset(i64),
+}
+
+impl ClockSource {
+    pub fn now(&self) -> PrimitiveDateTime {
+        match *self {
+            ClockSource::Host => host_time(),
+            ClockSource::Fixed(seconds) => from_timestamp(seconds),
+            ClockSource::Offset(seconds) => {
+                host_time().saturating_add(time::Duration::seconds(seconds))
+            }
+        }
+    }
+
+    /// Returns the source after the time is set to the value
+    fn set_time(&self, value: PrimitiveDateTime) -> Self {
+        match self {
+            ClockSource::Fixed(_) => ClockSource::Fixed(timestamp(value)),
+            _ => ClockSource::Offset(timestamp(value) - timestamp(host_time())),
+        }
+    }
+}
+
+impl FromStr for ClockSource {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Invalid clock source {s}. Expected host, fixed:YYYY-MM-DDTHH:MM:SS or offset:seconds"
+                ),
+            )
+        };
+
+        if s == "host" {
+            Ok(ClockSource::Host)
+        } else if let Some(value) = s.strip_prefix("fixed:") {
+            parse_date_time(value)
+                .map(|value| ClockSource::Fixed(timestamp(value)))
+                .ok_or_else(invalid)
+        } else if let Some(value) = s.strip_prefix("offset:") {
+            value
+                .parse::<i64>()
+                .map(ClockSource::Offset)
+                .map_err(|_| invalid())
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+impl fmt::Display for ClockSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ClockSource::Host => write!(f, "host"),
+            ClockSource::Fixed(seconds) => {
+                let value = from_timestamp(seconds);
+                write!(
+                    f,
+                    "fixed:{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                    value.year(),
+                    value.month() as u8,
+                    value.day(),
+                    value.hour(),
+                    value.minute(),
+                    value.second()
+                )
+            }
+            ClockSource::Offset(seconds) => write!(f, "offset:{seconds}"),
+        }
+    }
+}
+
+fn host_time() -> PrimitiveDateTime {
+    let utc = OffsetDateTime::UNIX_EPOCH
+        + SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(std::time::Duration::ZERO);
+    let now = if let Ok(offset) = UtcOffset::current_local_offset() {
+        utc.to_offset(offset)
+    } else {
+        utc
+    };
+    PrimitiveDateTime::new(now.date(), now.time())
+}
+
+fn from_timestamp(seconds: i64) -> PrimitiveDateTime {
+    let value = OffsetDateTime::from_unix_timestamp(seconds).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    PrimitiveDateTime::new(value.date(), value.time())
+}
+
+fn timestamp(value: PrimitiveDateTime) -> i64 {
+    value.assume_utc().unix_timestamp()
+}
+
+// Accepts YYYY-MM-DDTHH:MM[:SS] with either T or a space between the date and time
+fn parse_date_time(value: &str) -> Option<PrimitiveDateTime> {
+    let (date, time) = value.split_once(['T', ' '])?;
+    let date: Vec<&str> = date.split('-').collect();
+    let time: Vec<&str> = time.split(':').collect();
+    if date.len() != 3 || !(2..=3).contains(&time.len()) {
+        return None;
+    }
+
+    let month = Month::try_from(date[1].parse::<u8>().ok()?).ok()?;
+    let date =
+        Date::from_calendar_date(date[0].parse().ok()?, month, date[2].parse().ok()?).ok()?;
+    let second = if time.len() == 3 {
+        time[2].parse().ok()?
+    } else {
+        0
+    };
+    let time = Time::from_hms(time[0].parse().ok()?, time[1].parse().ok()?, second).ok()?;
+    Some(PrimitiveDateTime::new(date, time))
+}
+
+fn to_bcd(value: u8) -> u64 {
+    ((value / 10) << 4 | (value % 10)) as u64
+}
+
+fn from_bcd(value: u64) -> Option<u8> {
+    let (high, low) = ((value >> 4) & 0x0f, value & 0x0f);
+    if high > 9 || low > 9 {
+        None
+    } else {
+        Some((high * 10 + low) as u8)
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Thunderclock {
+    source: ClockSource,
+    shift_register: u64,
+    command: u8,
+    control: u8,
+    interrupt_rate: usize,
+    next_interrupt: usize,
+    irq_happen: Option<usize>,
+    cycles: usize,
+    mode: u8,
+    input: VecDeque<u8>,
+    basic_result: u8,
+}
+
+impl Thunderclock {
+    pub fn new() -> Self {
+        Thunderclock {
+            source: ClockSource::default(),
+            shift_register: 0,
+            command: COMMAND_HOLD,
+            control: 0,
+            interrupt_rate: INTERRUPT_RATES[0],
+            next_interrupt: 0,
+            irq_happen: None,
+            cycles: 0,
+            mode: MODE_12_HOUR,
+            input: VecDeque::new(),
+            basic_result: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.control = 0;
+        self.command = COMMAND_HOLD;
+        self.irq_happen = None;
+        self.next_interrupt = 0;
+        self.input.clear();
+    }
+
+    pub fn source(&self) -> ClockSource {
+        self.source
+    }
+
+    pub fn set_source(&mut self, source: ClockSource) {
+        self.source = source;
+    }
+
+    pub fn interrupt_rate(&self) -> usize {
+        self.interrupt_rate
+    }
+
+    /// Sets the rate of the timing pulse interrupt to 64, 256 or 2048 Hz
+    pub fn set_interrupt_rate(&mut self, rate: usize) -> io::Result<()> {
+        if !INTERRUPT_RATES.contains(&rate) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid interrupt rate {rate}. Expected 64, 256 or 2048"),
+            ));
+        }
+        self.interrupt_rate = rate;
+        self.next_interrupt = 0;
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.control & CONTROL_IRQ_ENABLE != 0
+    }
+
+    pub fn tick(&mut self, cycles: usize) {
+        self.cycles = cycles;
+
+        let period = CPU_6502_MHZ / self.interrupt_rate;
+        if self.next_interrupt == 0 {
+            self.next_interrupt = cycles + period;
+        } else if cycles >= self.next_interrupt {
+            self.next_interrupt = (self.next_interrupt + period).max(cycles + 1);
+            if self.irq_happen.is_none() {
+                self.irq_happen = Some(cycles);
+            }
+        }
+    }
+
+    pub fn poll_irq(&self) -> Option<usize> {
+        self.irq_happen
+    }
+
+    fn write_control(&mut self, value: u8) {
+        let rising = value & !self.control;
+
+        if rising & CONTROL_STB != 0 {
+            self.execute((value & CONTROL_COMMAND) >> 3);
+        }
+
+        if rising & CONTROL_CLK != 0 && self.command == COMMAND_SHIFT {
+            let data_in = (value & CONTROL_DATA_IN) as u64;
+            self.shift_register = (self.shift_register >> 1) | (data_in << 39);
+        }
+
+        if value & CONTROL_IRQ_ENABLE == 0 {
+            self.irq_happen = None;
+            self.next_interrupt = 0;
+        }
+
+        self.control = value;
+    }
+
+    fn read_status(&self) -> u8 {
+        let data_out = if self.command == COMMAND_SHIFT {
+            self.shift_register & 1 != 0
+        } else {
+            self.source.now().millisecond() < 500
+        };
+
+        let mut status = 0;
+        if data_out {
+            status |= STATUS_DATA_OUT;
+        }
+        if self.irq_happen.is_some() {
+            status |= STATUS_IRQ;
+        }
+        status
+    }
+
+    fn execute(&mut self, command: u8) {
+        self.command = command;
+        match command {
+            COMMAND_TIME_SET => self.set_time(),
+            COMMAND_TIME_READ => self.shift_register = self.time_register(),
+            COMMAND_TP_64HZ | COMMAND_TP_256HZ | COMMAND_TP_2048HZ => {
+                self.interrupt_rate = INTERRUPT_RATES[(command - COMMAND_TP_64HZ) as usize];
+                self.next_interrupt = 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn time_register(&self) -> u64 {
+        let now = self.source.now();
+        to_bcd(now.second())
+            | to_bcd(now.minute()) << 8
+            | to_bcd(now.hour()) << 16
+            | to_bcd(now.day()) << 24
+            | (now.weekday().number_days_from_sunday() as u64) << 32
+            | (now.month() as u64) << 36
+    }
+
+    fn set_time(&mut self) {
+        let register = self.shift_register;
+        let value = (|| {
+            let month = Month::try_from(((register >> 36) & 0x0f) as u8).ok()?;
+            let year = self.source.now().year();
+            let date = Date::from_calendar_date(year, month, from_bcd(register >> 24)?).ok()?;
+            let time = Time::from_hms(
+                from_bcd(register >> 16)?,
+                from_bcd(register >> 8)?,
+                from_bcd(register)?,
+            )
+            .ok()?;
+            Some(PrimitiveDateTime::new(date, time))
+        })();
+
+        if let Some(value) = value {
+            self.source = self.source.set_time(value);
+        }
+    }
+
+    fn set_mode(&mut self, value: u8) {
+        let mode = value & 0x7f;
+        if [MODE_NUMERIC, MODE_12_HOUR, MODE_24_HOUR].contains(&mode) {
+            self.mode = mode;
+            self.input.clear();
+        }
+    }
+
+    /// Returns the time formatted according to the firmware mode
+    pub fn time_string(&self) -> String {
+        let now = self.source.now();
+        let weekday = now.weekday().number_days_from_sunday();
+        let month = now.month() as u8;
+
+        match self.mode {
+            MODE_NUMERIC => format!(
+                "{:02},{:02},{:02},{:02},{:02},{:02}",
+                month,
+                weekday,
+                now.day(),
+                now.hour(),
+                now.minute(),
+                now.second()
+            ),
+            MODE_24_HOUR => format!(
+                "{} {} {:02} {:02}:{:02}:{:02}",
+                WEEKDAYS[weekday as usize],
+                MONTHS[month as usize - 1],
+                now.day(),
+                now.hour(),
+                now.minute(),
+                now.second()
+            ),
+            _ => {
+                let (hour, suffix) = match now.hour() {
+                    0 => (12, "AM"),
+                    hour @ 1..=11 => (hour, "AM"),
+                    12 => (12, "PM"),
+                    hour => (hour - 12, "PM"),
+                };
+                format!(
+                    "{} {} {:02} {:02}:{:02}:{:02} {}",
+                    WEEKDAYS[weekday as usize],
+                    MONTHS[month as usize - 1],
+                    now.day(),
+                    hour,
+                    now.minute(),
+                    now.second(),
+                    suffix
+                )
+            }
+        }
+    }
+
+    fn firmware_read(&self, mem: &mut Mmu) {
+        let text = self.time_string();
+        for (i, ch) in text.bytes().chain(std::iter::once(b'\r')).enumerate() {
+            mem.unclocked_addr_write(INPUT_BUFFER + i as u16, ch | 0x80);
+        }
+    }
+
+    fn firmware_basic(&mut self, mem: &Mmu, slot: u16, value: u8) -> u8 {
+        if !is_input_hook(mem, slot) {
+            self.set_mode(value);
+            return 0;
+        }
+
+        if self.input.is_empty() {
+            self.input.extend(self.time_string().bytes());
+            self.input.push_back(b'\r');
+        }
+        self.input.pop_front().unwrap_or(b'\r') | 0x80
+    }
+}
+
+impl Default for Thunderclock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Card for Thunderclock {
+    fn rom_access(&mut self, addr: u16, _value: u8, _write_flag: bool) -> u8 {
+        ROM[(addr & 0xff) as usize]
+    }
+
+    fn io_access(
+        &mut self,
+        mem: &mut Mmu,
+        _video: &mut Video,
+        addr: u16,
+        value: u8,
+        write_flag: bool,
+    ) -> u8 {
+        let slot = ((addr & 0x00ff) - 0x0080) >> 4;
+        let io_addr = ((addr & 0x00ff) - (slot << 4)) as u8 & 0x0f;
+
+        match io_addr {
+            0x00 => {
+                if write_flag {
+                    self.write_control(value);
+                    0
+                } else {
+                    self.read_status()
+                }
+            }
+
+            TC_WRITE if write_flag => {
+                self.set_mode(value);
+                0
+            }
+
+            TC_READ if write_flag => {
+                self.firmware_read(mem);
+                0
+            }
+
+            TC_BASIC => {
+                if write_flag {
+                    self.basic_result = self.firmware_basic(mem, slot, value);
+                }
+                self.basic_result
+            }
+
+            TC_CLEAR_IRQ => {
+                self.irq_happen = None;
+                0
+            }
+
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Bus, IODevice, Mem};
+    use crate::cpu::CPU;
+
+    fn fixed_clock() -> Thunderclock {
+        let mut clock = Thunderclock::new();
+        clock.set_source("fixed:2026-10-19T13:23:45".parse().unwrap());
+        clock
+    }
+
+    fn write_command(clock: &mut Thunderclock, command: u8) {
+        clock.write_control(command << 3);
+        clock.write_control(command << 3 | CONTROL_STB);
+        clock.write_control(command << 3);
+    }
+
+    #[test]
+    fn clock_source() {
+        assert_eq!("host".parse::<ClockSource>().unwrap(), ClockSource::Host);
+        assert_eq!(
+            "offset:-3600".parse::<ClockSource>().unwrap(),
+            ClockSource::Offset(-3600)
+        );
+        let source = "fixed:2026-10-19 13:23".parse::<ClockSource>().unwrap();
+        assert_eq!(source.to_string(), "fixed:2026-10-19T13:23:00");
+        assert!("fixed:2026-13-01T00:00".parse::<ClockSource>().is_err());
+        assert!("utc".parse::<ClockSource>().is_err());
+    }
+
+    #[test]
+    fn read_and_set_time() {
+        let mut clock = fixed_clock();
+        write_command(&mut clock, COMMAND_TIME_READ);
+        write_command(&mut clock, COMMAND_SHIFT);
+
+        let mut register = 0u64;
+        for bit in 0..40 {
+            if clock.read_status() & STATUS_DATA_OUT != 0 {
+                register |= 1 << bit;
+            }
+            clock.write_control((COMMAND_SHIFT << 3) | CONTROL_CLK);
+            clock.write_control(COMMAND_SHIFT << 3);
+        }
+        // Monday, October 19, 13:23:45
+        assert_eq!(register, 0x00a1_1913_2345);
+
+        // Shift in Friday, December 25, 08:30:00
+        for bit in 0..40 {
+            let data = ((0x00c5_2508_3000_u64 >> bit) & 1) as u8;
+            clock.write_control((COMMAND_SHIFT << 3) | data);
+            clock.write_control((COMMAND_SHIFT << 3) | data | CONTROL_CLK);
+        }
+        write_command(&mut clock, COMMAND_TIME_SET);
+        assert_eq!(clock.source().to_string(), "fixed:2026-12-25T08:30:00");
+    }
+
+    #[test]
+    fn firmware_modes() {
+        let mut clock = fixed_clock();
+        assert_eq!(clock.time_string(), "MON OCT 19 01:23:45 PM");
+        clock.set_mode(0x80 | MODE_24_HOUR);
+        assert_eq!(clock.time_string(), "MON OCT 19 13:23:45");
+        clock.set_mode(0x80 | MODE_NUMERIC);
+        assert_eq!(clock.time_string(), "10,01,19,13,23,45");
+    }
+
+    #[test]
+    fn timing_pulse_interrupt() {
+        let mut clock = fixed_clock();
+        write_command(&mut clock, COMMAND_TP_256HZ);
+        assert_eq!(clock.interrupt_rate(), 256);
+        assert!(!clock.is_active());
+
+        clock.write_control(CONTROL_IRQ_ENABLE);
+        assert!(clock.is_active());
+        clock.tick(1000);
+        assert_eq!(clock.poll_irq(), None);
+        clock.tick(1000 + CPU_6502_MHZ / 256);
+        assert_eq!(clock.poll_irq(), Some(1000 + CPU_6502_MHZ / 256));
+        assert_ne!(clock.read_status() & STATUS_IRQ, 0);
+
+        clock.write_control(0);
+        assert!(!clock.is_active());
+        assert_eq!(clock.read_status() & STATUS_IRQ, 0);
+    }
+
+    #[test]
+    fn firmware_read_time() {
+        let mut cpu = CPU::new(Bus::default());
+        cpu.reset();
+        cpu.bus.register_device(IODevice::Thunderclock, 4);
+        cpu.bus.mem.intcxrom = false;
+        cpu.bus
+            .thunderclock
+            .set_source("fixed:2026-10-19T13:23:45".parse().unwrap());
+
+        // IORTS and KSW pointing to the slot 4 firmware
+        cpu.load(&[0x60], 0xff58);
+        cpu.load(&[0x00, 0xc4], 0x38);
+
+        // LDX #$12; LDY #$34; LDA #$A3; JSR $C40B; JSR $C408; BRK
+        cpu.load(
+            &[
+                0xa2, 0x12, 0xa0, 0x34, 0xa9, 0xa3, 0x20, 0x0b, 0xc4, 0x20, 0x08, 0xc4, 0x00,
+            ],
+            0x300,
+        );
+        cpu.program_counter = 0x300;
+        cpu.run();
+
+        assert_eq!((cpu.register_x, cpu.register_y), (0x12, 0x34));
+        let text: Vec<u8> = (0x200..0x212)
+            .map(|addr| cpu.bus.unclocked_addr_read(addr) & 0x7f)
+            .collect();
+        assert_eq!(text, b"10,01,19,13,23,45\r");
+
+        // IN#4 returns the time one character at a time
+        // LDY #$00; LDA #$A0; JSR $C400; BRK
+        cpu.load(&[0xa0, 0x00, 0xa9, 0xa0, 0x20, 0x00, 0xc4, 0x00], 0x300);
+        cpu.program_counter = 0x300;
+        cpu.run();
+        assert_eq!(cpu.register_a, 0x80 | b'1');
+    }
+}
@@ -0,0 +1,1236 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{
+    IpAddr, Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpStream, ToSocketAddrs, UdpSocket,
+};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+User-mode network
+
+A virtual network behind a NAT gateway, similar to the user-mode network (slirp) of QEMU.
+The raw ethernet frames of the network cards are handled in the emulator, so no pcap,
+root access or real network interface is needed.
+
+    10.0.2.2    Gateway (MAC address 52:55:0A:00:02:02). Connections to the gateway are
+                forwarded to the host loopback address 127.0.0.1
+    10.0.2.3    DNS server. A queries are resolved with the host resolver and the other
+                queries are answered with NOTIMP
+    10.0.2.15   Address assigned to the guest by the DHCP server
+
+Frames handled
+
+    ARP         Requests for the gateway and DNS server addresses are answered
+    ICMP        Echo requests to the gateway and DNS server are answered
+    UDP         DHCP (port 67) and DNS (port 53) are answered by the gateway. Other
+                datagrams are sent from a host UDP socket opened for each guest port
+    TCP         Connections are forwarded to host TCP streams. One segment is in flight
+                to the guest at a time and it is sent again if not acknowledged
+
+Fragmented IP packets are ignored. The name lookups and the host TCP connections run on
+worker threads, and the DNS response and the SYN-ACK or RST are queued for the guest when
+they complete.
+*/
+
+const GATEWAY_MAC: [u8; 6] = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+const GATEWAY_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+const DNS_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 3);
+const GUEST_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETH_HEADER_SIZE: usize = 14;
+const FRAME_MIN_SIZE: usize = 60;
+
+// Ethernet, IPv4, hardware size 6, protocol size 4
+const ARP_HEADER: [u8; 6] = [0x00, 0x01, 0x08, 0x00, 0x06, 0x04];
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+const ARP_PACKET_SIZE: usize = 28;
+
+const IP_HEADER_SIZE: usize = 20;
+const IP_TTL: u8 = 64;
+const IP_DONT_FRAGMENT: u8 = 0x40;
+const IP_FRAGMENT_MASK: u16 = 0x3fff;
+const IP_PROTO_ICMP: u8 = 1;
+const IP_PROTO_TCP: u8 = 6;
+const IP_PROTO_UDP: u8 = 17;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_HEADER_SIZE: usize = 8;
+
+const UDP_HEADER_SIZE: usize = 8;
+const UDP_MAX_PAYLOAD: usize = 1472;
+
+const TCP_HEADER_SIZE: usize = 20;
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+const TCP_OPTION_END: u8 = 0;
+const TCP_OPTION_NOP: u8 = 1;
+const TCP_OPTION_MSS: u8 = 2;
+const TCP_MSS: u16 = 1460;
+const TCP_DEFAULT_MSS: usize = 536;
+const TCP_WINDOW: u16 = 8192;
+const TCP_ISN_INCREMENT: u32 = 0x0001_0000;
+const TCP_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(500);
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const BOOTP_REQUEST: u8 = 1;
+const BOOTP_REPLY: u8 = 2;
+const BOOTP_MIN_SIZE: usize = 300;
+const DHCP_MAGIC: [u8; 4] = [99, 130, 83, 99];
+const DHCP_OPTIONS_OFFSET: usize = 240;
+const DHCP_OPTION_PAD: u8 = 0;
+const DHCP_OPTION_SUBNET_MASK: u8 = 1;
+const DHCP_OPTION_ROUTER: u8 = 3;
+const DHCP_OPTION_DNS: u8 = 6;
+const DHCP_OPTION_LEASE_TIME: u8 = 51;
+const DHCP_OPTION_MESSAGE_TYPE: u8 = 53;
+const DHCP_OPTION_SERVER_ID: u8 = 54;
+const DHCP_OPTION_END: u8 = 255;
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+const DHCP_LEASE_TIME: u32 = 86400;
+
+const DNS_PORT: u16 = 53;
+const DNS_HEADER_SIZE: usize = 12;
+const DNS_MAX_LABEL: usize = 63;
+const DNS_QR: u8 = 0x80;
+const DNS_RA: u8 = 0x80;
+const DNS_OPCODE_RD: u8 = 0x79;
+const DNS_RCODE_SERVER_FAILURE: u8 = 2;
+const DNS_RCODE_NAME_ERROR: u8 = 3;
+const DNS_RCODE_NOT_IMPLEMENTED: u8 = 4;
+const DNS_TYPE_A: u16 = 1;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_NAME_POINTER: [u8; 2] = [0xc0, DNS_HEADER_SIZE as u8];
+const DNS_TTL: u32 = 60;
+
+fn be16(data: &[u8]) -> u16 {
+    u16::from_be_bytes([data[0], data[1]])
+}
+
+fn be32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn ipv4_addr(data: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(data[0], data[1], data[2], data[3])
+}
+
+// Address on the host for an address on the virtual network
+fn host_addr(addr: SocketAddrV4) -> SocketAddrV4 {
+    if *addr.ip() == GATEWAY_ADDR {
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, addr.port())
+    } else {
+        addr
+    }
+}
+
+// Address on the virtual network for an address on the host
+fn guest_addr(addr: SocketAddrV4) -> SocketAddrV4 {
+    if addr.ip().is_loopback() {
+        SocketAddrV4::new(GATEWAY_ADDR, addr.port())
+    } else {
+        addr
+    }
+}
+
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .fold(initial, |sum, word| sum + word);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, length: usize) -> u32 {
+    let addrs = [src.octets(), dst.octets()].concat();
+    addrs.chunks(2).map(|pair| be16(pair) as u32).sum::<u32>() + protocol as u32 + length as u32
+}
+
+fn ethernet_frame(dst: [u8; 6], src: [u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ETH_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    if frame.len() < FRAME_MIN_SIZE {
+        frame.resize(FRAME_MIN_SIZE, 0);
+    }
+    frame
+}
+
+fn ipv4_packet(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; IP_HEADER_SIZE];
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&((IP_HEADER_SIZE + payload.len()) as u16).to_be_bytes());
+    packet[6] = IP_DONT_FRAGMENT;
+    packet[8] = IP_TTL;
+    packet[9] = protocol;
+    packet[12..16].copy_from_slice(&src.octets());
+    packet[16..20].copy_from_slice(&dst.octets());
+    let sum = checksum(&packet, 0);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+fn udp_datagram(src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let length = UDP_HEADER_SIZE + payload.len();
+    let mut datagram = Vec::with_capacity(length);
+    datagram.extend_from_slice(&src.port().to_be_bytes());
+    datagram.extend_from_slice(&dst.port().to_be_bytes());
+    datagram.extend_from_slice(&(length as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+    let initial = pseudo_header_sum(*src.ip(), *dst.ip(), IP_PROTO_UDP, length);
+    let sum = match checksum(&datagram, initial) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    datagram
+}
+
+fn tcp_segment(
+    src: SocketAddrV4,
+    dst: SocketAddrV4,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    options: &[u8],
+    payload: &[u8],
+) -> Vec<u8> {
+    let header_size = TCP_HEADER_SIZE + options.len();
+    let mut segment = Vec::with_capacity(header_size + payload.len());
+    segment.extend_from_slice(&src.port().to_be_bytes());
+    segment.extend_from_slice(&dst.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.push(((header_size / 4) as u8) << 4);
+    segment.push(flags);
+    segment.extend_from_slice(&TCP_WINDOW.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(options);
+    segment.extend_from_slice(payload);
+    let initial = pseudo_header_sum(*src.ip(), *dst.ip(), IP_PROTO_TCP, segment.len());
+    let sum = checksum(&segment, initial);
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+    segment
+}
+
+// Maximum segment size option of the SYN segment
+fn tcp_mss(options: &[u8]) -> usize {
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            TCP_OPTION_END => break,
+            TCP_OPTION_NOP => i += 1,
+            kind => {
+                let len = *options.get(i + 1).unwrap_or(&0) as usize;
+                if len < 2 || i + len > options.len() {
+                    break;
+                }
+                if kind == TCP_OPTION_MSS && len == 4 {
+                    return be16(&options[i + 2..]) as usize;
+                }
+                i += len;
+            }
+        }
+    }
+    TCP_DEFAULT_MSS
+}
+
+fn dhcp_message_type(options: &[u8]) -> Option<u8> {
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            DHCP_OPTION_PAD => i += 1,
+            DHCP_OPTION_END => break,
+            code => {
+                let len = *options.get(i + 1)? as usize;
+                if code == DHCP_OPTION_MESSAGE_TYPE && len == 1 {
+                    return options.get(i + 2).copied();
+                }
+                i += 2 + len;
+            }
+        }
+    }
+    None
+}
+
+fn dhcp_reply(request: &[u8]) -> Option<Vec<u8>> {
+    if request.len() < DHCP_OPTIONS_OFFSET
+        || request[0] != BOOTP_REQUEST
+        || request[236..DHCP_OPTIONS_OFFSET] != DHCP_MAGIC
+    {
+        return None;
+    }
+
+    let message_type = match dhcp_message_type(&request[DHCP_OPTIONS_OFFSET..])? {
+        DHCP_DISCOVER => DHCP_OFFER,
+        DHCP_REQUEST => DHCP_ACK,
+        _ => return None,
+    };
+
+    let mut reply = vec![0; DHCP_OPTIONS_OFFSET];
+    reply[0] = BOOTP_REPLY;
+
+    // Hardware type, hardware address length, transaction id and flags
+    reply[1..3].copy_from_slice(&request[1..3]);
+    reply[4..8].copy_from_slice(&request[4..8]);
+    reply[10..12].copy_from_slice(&request[10..12]);
+    reply[16..20].copy_from_slice(&GUEST_ADDR.octets());
+    reply[20..24].copy_from_slice(&GATEWAY_ADDR.octets());
+    reply[28..44].copy_from_slice(&request[28..44]);
+    reply[236..DHCP_OPTIONS_OFFSET].copy_from_slice(&DHCP_MAGIC);
+
+    reply.extend_from_slice(&[DHCP_OPTION_MESSAGE_TYPE, 1, message_type]);
+    for (code, value) in [
+        (DHCP_OPTION_SERVER_ID, GATEWAY_ADDR.octets()),
+        (DHCP_OPTION_LEASE_TIME, DHCP_LEASE_TIME.to_be_bytes()),
+        (DHCP_OPTION_SUBNET_MASK, NETMASK.octets()),
+        (DHCP_OPTION_ROUTER, GATEWAY_ADDR.octets()),
+        (DHCP_OPTION_DNS, DNS_ADDR.octets()),
+    ] {
+        reply.extend_from_slice(&[code, value.len() as u8]);
+        reply.extend_from_slice(&value);
+    }
+    reply.push(DHCP_OPTION_END);
+    if reply.len() < BOOTP_MIN_SIZE {
+        reply.resize(BOOTP_MIN_SIZE, 0);
+    }
+    Some(reply)
+}
+
+// Returns the name, type and class of the question and the end of the question. Only
+// standard queries with a single question are answered
+fn dns_question(query: &[u8]) -> Option<(String, u16, u16, usize)> {
+    if query.len() < DNS_HEADER_SIZE || query[2] & DNS_QR != 0 || be16(&query[4..]) != 1 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut pos = DNS_HEADER_SIZE;
+    loop {
+        let len = *query.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        if len > DNS_MAX_LABEL {
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(query.get(pos..pos + len)?).to_string());
+        pos += len;
+    }
+    let question = query.get(pos..pos + 4)?;
+    Some((
+        labels.join("."),
+        be16(question),
+        be16(&question[2..]),
+        pos + 4,
+    ))
+}
+
+fn dns_response(query: &[u8], question_end: usize, rcode: u8, addrs: &[Ipv4Addr]) -> Vec<u8> {
+    let mut response = Vec::new();
+    response.extend_from_slice(&query[0..2]);
+    response.push(DNS_QR | (query[2] & DNS_OPCODE_RD));
+    response.push(DNS_RA | rcode);
+    response.extend_from_slice(&1u16.to_be_bytes());
+    response.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]);
+    response.extend_from_slice(&query[DNS_HEADER_SIZE..question_end]);
+    for addr in addrs {
+        response.extend_from_slice(&DNS_NAME_POINTER);
+        response.extend_from_slice(&DNS_TYPE_A.to_be_bytes());
+        response.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&DNS_TTL.to_be_bytes());
+        response.extend_from_slice(&4u16.to_be_bytes());
+        response.extend_from_slice(&addr.octets());
+    }
+    response
+}
+
+fn resolve(name: &str) -> io::Result<Vec<Ipv4Addr>> {
+    let mut addrs = Vec::new();
+    for addr in (name, 0).to_socket_addrs()? {
+        if let IpAddr::V4(ip) = addr.ip()
+            && !addrs.contains(&ip)
+        {
+            addrs.push(ip);
+        }
+    }
+    Ok(addrs)
+}
+
+fn connect(addr: SocketAddr) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&addr, TCP_CONNECT_TIMEOUT)?;
+    stream.set_nonblocking(true)?;
+    Ok(stream)
+}
+
+// Runs the job on a worker thread. The result is sent to the returned channel
+fn spawn<T, F>(job: F) -> Receiver<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // The receiver is dropped when the request is cancelled
+        let _ = sender.send(job());
+    });
+    receiver
+}
+
+// A DNS query waiting for the host resolver
+#[derive(Debug)]
+struct PendingDns {
+    guest_port: u16,
+    query: Vec<u8>,
+    question_end: usize,
+    result: Receiver<io::Result<Vec<Ipv4Addr>>>,
+}
+
+// A TCP connection from the guest waiting for the host connection
+#[derive(Debug)]
+struct PendingTcp {
+    guest_port: u16,
+    remote: SocketAddrV4,
+    seq: u32,
+    window: usize,
+    mss: usize,
+    result: Receiver<io::Result<TcpStream>>,
+}
+
+#[derive(Debug)]
+struct UdpBinding {
+    guest_port: u16,
+    socket: UdpSocket,
+}
+
+#[derive(Debug)]
+struct TcpConnection {
+    guest_port: u16,
+    remote: SocketAddrV4,
+    stream: TcpStream,
+
+    // Next sequence number expected from the guest
+    recv_next: u32,
+
+    // First sequence number sent to the guest and not acknowledged
+    send_unacked: u32,
+
+    // Data sent to the guest and not acknowledged
+    unacked: Vec<u8>,
+
+    guest_window: usize,
+    mss: usize,
+    sent_at: Instant,
+    host_closed: bool,
+    guest_closed: bool,
+    fin_sent: bool,
+    fin_acked: bool,
+}
+
+impl TcpConnection {
+    fn send_next(&self) -> u32 {
+        self.send_unacked
+            .wrapping_add(self.unacked.len() as u32)
+            .wrapping_add((self.fin_sent && !self.fin_acked) as u32)
+    }
+
+    fn acknowledge(&mut self, ack: u32) {
+        let acked = ack.wrapping_sub(self.send_unacked) as usize;
+        let in_flight = self.unacked.len() + (self.fin_sent && !self.fin_acked) as usize;
+        if acked == 0 || acked > in_flight {
+            return;
+        }
+
+        let data = acked.min(self.unacked.len());
+        self.unacked.drain(..data);
+        if acked > data {
+            self.fin_acked = true;
+        }
+        self.send_unacked = ack;
+    }
+
+    fn is_finished(&self) -> bool {
+        self.fin_acked && self.guest_closed
+    }
+}
+
+/// User-mode network behind a NAT gateway for the raw ethernet frames of the network
+/// cards. Frames sent by the guest are handled by `send` and the frames for the guest are
+/// returned by `receive`
+#[derive(Debug)]
+pub struct UserNetwork {
+    guest_mac: [u8; 6],
+    guest_ip: Ipv4Addr,
+    frames: VecDeque<Vec<u8>>,
+    udp: Vec<UdpBinding>,
+    tcp: Vec<TcpConnection>,
+    pending_dns: Vec<PendingDns>,
+    pending_tcp: Vec<PendingTcp>,
+    next_isn: u32,
+}
+
+impl Default for UserNetwork {
+    fn default() -> Self {
+        UserNetwork {
+            guest_mac: BROADCAST_MAC,
+            guest_ip: GUEST_ADDR,
+            frames: VecDeque::new(),
+            udp: Vec::new(),
+            tcp: Vec::new(),
+            pending_dns: Vec::new(),
+            pending_tcp: Vec::new(),
+            next_isn: 0x1000_0000,
+        }
+    }
+}
+
+impl UserNetwork {
+    pub fn new() -> Self {
+        UserNetwork::default()
+    }
+
+    /// Handles the ethernet frame sent by the guest
+    pub fn send(&mut self, frame: &[u8]) {
+        if frame.len() < ETH_HEADER_SIZE {
+            return;
+        }
+
+        self.guest_mac.copy_from_slice(&frame[6..12]);
+        let payload = &frame[ETH_HEADER_SIZE..];
+        match be16(&frame[12..]) {
+            ETHERTYPE_ARP => self.handle_arp(payload),
+            ETHERTYPE_IPV4 => self.handle_ipv4(payload),
+            _ => {}
+        }
+    }
+
+    /// Returns the next ethernet frame for the guest. The worker threads and the host
+    /// sockets are polled when no frame is pending
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        if self.frames.is_empty() {
+            self.poll_pending(Duration::ZERO);
+            self.poll_udp();
+            self.poll_tcp();
+        }
+        self.frames.pop_front()
+    }
+
+    fn queue_frame(&mut self, dst: [u8; 6], ethertype: u16, payload: &[u8]) {
+        self.frames
+            .push_back(ethernet_frame(dst, GATEWAY_MAC, ethertype, payload));
+    }
+
+    fn queue_ipv4(
+        &mut self,
+        dst_mac: [u8; 6],
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        protocol: u8,
+        payload: &[u8],
+    ) {
+        let packet = ipv4_packet(src, dst, protocol, payload);
+        self.queue_frame(dst_mac, ETHERTYPE_IPV4, &packet);
+    }
+
+    fn queue_to_guest(&mut self, src: Ipv4Addr, protocol: u8, payload: &[u8]) {
+        self.queue_ipv4(self.guest_mac, src, self.guest_ip, protocol, payload);
+    }
+
+    fn handle_arp(&mut self, packet: &[u8]) {
+        if packet.len() < ARP_PACKET_SIZE
+            || packet[0..6] != ARP_HEADER
+            || be16(&packet[6..]) != ARP_REQUEST
+        {
+            return;
+        }
+
+        let target = ipv4_addr(&packet[24..28]);
+        if target != GATEWAY_ADDR && target != DNS_ADDR {
+            return;
+        }
+
+        let mut reply = ARP_HEADER.to_vec();
+        reply.extend_from_slice(&ARP_REPLY.to_be_bytes());
+        reply.extend_from_slice(&GATEWAY_MAC);
+        reply.extend_from_slice(&packet[24..28]);
+        reply.extend_from_slice(&packet[8..18]);
+        self.queue_frame(self.guest_mac, ETHERTYPE_ARP, &reply);
+    }
+
+    fn handle_ipv4(&mut self, packet: &[u8]) {
+        if packet.len() < IP_HEADER_SIZE || packet[0] >> 4 != 4 {
+            return;
+        }
+
+        let header_size = ((packet[0] & 0x0f) as usize) * 4;
+        let total_size = be16(&packet[2..]) as usize;
+        if header_size < IP_HEADER_SIZE
+            || total_size < header_size
+            || total_size > packet.len()
+            || be16(&packet[6..]) & IP_FRAGMENT_MASK != 0
+        {
+            return;
+        }
+
+        let src = ipv4_addr(&packet[12..16]);
+        let dst = ipv4_addr(&packet[16..20]);
+        if !src.is_unspecified() {
+            self.guest_ip = src;
+        }
+
+        let payload = &packet[header_size..total_size];
+        match packet[9] {
+            IP_PROTO_ICMP => self.handle_icmp(dst, payload),
+            IP_PROTO_UDP => self.handle_udp(dst, payload),
+            IP_PROTO_TCP => self.handle_tcp(dst, payload),
+            _ => {}
+        }
+    }
+
+    fn handle_icmp(&mut self, dst: Ipv4Addr, message: &[u8]) {
+        if (dst == GATEWAY_ADDR || dst == DNS_ADDR)
+            && message.len() >= ICMP_HEADER_SIZE
+            && message[0] == ICMP_ECHO_REQUEST
+        {
+            let mut reply = message.to_vec();
+            reply[0] = ICMP_ECHO_REPLY;
+            reply[2..4].copy_from_slice(&[0, 0]);
+            let sum = checksum(&reply, 0);
+            reply[2..4].copy_from_slice(&sum.to_be_bytes());
+            self.queue_to_guest(dst, IP_PROTO_ICMP, &reply);
+        }
+    }
+
+    fn handle_udp(&mut self, dst: Ipv4Addr, datagram: &[u8]) {
+        if datagram.len() < UDP_HEADER_SIZE {
+            return;
+        }
+
+        let guest_port = be16(&datagram[0..]);
+        let dst_port = be16(&datagram[2..]);
+        let length = be16(&datagram[4..]) as usize;
+        if length < UDP_HEADER_SIZE || length > datagram.len() {
+            return;
+        }
+
+        let payload = &datagram[UDP_HEADER_SIZE..length];
+        if dst_port == DHCP_SERVER_PORT {
+            if let Some(reply) = dhcp_reply(payload) {
+                let datagram = udp_datagram(
+                    SocketAddrV4::new(GATEWAY_ADDR, DHCP_SERVER_PORT),
+                    SocketAddrV4::new(Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT),
+                    &reply,
+                );
+                self.queue_ipv4(
+                    BROADCAST_MAC,
+                    GATEWAY_ADDR,
+                    Ipv4Addr::BROADCAST,
+                    IP_PROTO_UDP,
+                    &datagram,
+                );
+            }
+        } else if dst == DNS_ADDR && dst_port == DNS_PORT {
+            self.handle_dns(guest_port, payload);
+        } else {
+            self.forward_udp(guest_port, SocketAddrV4::new(dst, dst_port), payload);
+        }
+    }
+
+    fn handle_dns(&mut self, guest_port: u16, query: &[u8]) {
+        let Some((name, qtype, qclass, question_end)) = dns_question(query) else {
+            return;
+        };
+
+        if qtype != DNS_TYPE_A || qclass != DNS_CLASS_IN {
+            let response = dns_response(query, question_end, DNS_RCODE_NOT_IMPLEMENTED, &[]);
+            self.queue_dns_response(guest_port, &response);
+            return;
+        }
+
+        self.pending_dns.push(PendingDns {
+            guest_port,
+            query: query.to_vec(),
+            question_end,
+            result: spawn(move || resolve(&name)),
+        });
+    }
+
+    fn queue_dns_response(&mut self, guest_port: u16, response: &[u8]) {
+        let datagram = udp_datagram(
+            SocketAddrV4::new(DNS_ADDR, DNS_PORT),
+            SocketAddrV4::new(self.guest_ip, guest_port),
+            response,
+        );
+        self.queue_to_guest(DNS_ADDR, IP_PROTO_UDP, &datagram);
+    }
+
+    // Queues the DNS responses and opens the TCP connections completed by the worker
+    // threads. The timeout is only used by the tests to wait for the workers
+    fn poll_pending(&mut self, timeout: Duration) {
+        let mut index = 0;
+        while index < self.pending_dns.len() {
+            let pending = &self.pending_dns[index];
+            let (rcode, addrs) = match pending.result.recv_timeout(timeout) {
+                Ok(Ok(addrs)) => (0, addrs),
+                Ok(Err(_)) => (DNS_RCODE_NAME_ERROR, Vec::new()),
+                Err(RecvTimeoutError::Disconnected) => (DNS_RCODE_SERVER_FAILURE, Vec::new()),
+                Err(RecvTimeoutError::Timeout) => {
+                    index += 1;
+                    continue;
+                }
+            };
+            let pending = self.pending_dns.remove(index);
+            let response = dns_response(&pending.query, pending.question_end, rcode, &addrs);
+            self.queue_dns_response(pending.guest_port, &response);
+        }
+
+        let mut index = 0;
+        while index < self.pending_tcp.len() {
+            let result = match self.pending_tcp[index].result.recv_timeout(timeout) {
+                Ok(result) => result.ok(),
+                Err(RecvTimeoutError::Disconnected) => None,
+                Err(RecvTimeoutError::Timeout) => {
+                    index += 1;
+                    continue;
+                }
+            };
+            let pending = self.pending_tcp.remove(index);
+            match result {
+                Some(stream) => self.open_tcp(pending, stream),
+                None => {
+                    let ack = pending.seq.wrapping_add(1);
+                    self.send_tcp_reset(pending.guest_port, pending.remote, 0, ack);
+                }
+            }
+        }
+    }
+
+    fn forward_udp(&mut self, guest_port: u16, remote: SocketAddrV4, payload: &[u8]) {
+        let index = match self.udp.iter().position(|b| b.guest_port == guest_port) {
+            Some(index) => index,
+            None => {
+                let socket = match UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))) {
+                    Ok(socket) => socket,
+                    Err(_) => return,
+                };
+                if socket.set_nonblocking(true).is_err() {
+                    return;
+                }
+                self.udp.push(UdpBinding { guest_port, socket });
+                self.udp.len() - 1
+            }
+        };
+        let _ = self.udp[index].socket.send_to(payload, host_addr(remote));
+    }
+
+    fn poll_udp(&mut self) {
+        let mut buffer = [0; UDP_MAX_PAYLOAD];
+        for i in 0..self.udp.len() {
+            let binding = &self.udp[i];
+            if let Ok((size, SocketAddr::V4(remote))) = binding.socket.recv_from(&mut buffer) {
+                let src = guest_addr(remote);
+                let dst = SocketAddrV4::new(self.guest_ip, binding.guest_port);
+                let datagram = udp_datagram(src, dst, &buffer[..size]);
+                self.queue_to_guest(*src.ip(), IP_PROTO_UDP, &datagram);
+            }
+        }
+    }
+
+    fn handle_tcp(&mut self, dst: Ipv4Addr, segment: &[u8]) {
+        if segment.len() < TCP_HEADER_SIZE {
+            return;
+        }
+
+        let guest_port = be16(&segment[0..]);
+        let remote = SocketAddrV4::new(dst, be16(&segment[2..]));
+        let seq = be32(&segment[4..]);
+        let ack = be32(&segment[8..]);
+        let header_size = ((segment[12] >> 4) as usize) * 4;
+        let flags = segment[13];
+        let window = be16(&segment[14..]) as usize;
+        if header_size < TCP_HEADER_SIZE || header_size > segment.len() {
+            return;
+        }
+
+        let payload = &segment[header_size..];
+        let index = self
+            .tcp
+            .iter()
+            .position(|c| c.guest_port == guest_port && c.remote == remote);
+
+        let is_pending = |p: &PendingTcp| p.guest_port == guest_port && p.remote == remote;
+        if flags & TCP_RST != 0 {
+            if let Some(index) = index {
+                self.tcp.remove(index);
+            }
+            self.pending_tcp.retain(|p| !is_pending(p));
+            return;
+        }
+
+        if flags & TCP_SYN != 0 {
+            match index {
+                // The SYN-ACK was lost
+                Some(index) if self.tcp[index].recv_next == seq.wrapping_add(1) => {
+                    self.send_syn_ack(index)
+                }
+                Some(_) => {}
+                // The host connection is not completed yet
+                None if self.pending_tcp.iter().any(is_pending) => {}
+                None => {
+                    let addr = SocketAddr::V4(host_addr(remote));
+                    self.pending_tcp.push(PendingTcp {
+                        guest_port,
+                        remote,
+                        seq,
+                        window,
+                        mss: tcp_mss(&segment[TCP_HEADER_SIZE..header_size]),
+                        result: spawn(move || connect(addr)),
+                    });
+                }
+            }
+            return;
+        }
+
+        let index = match index {
+            Some(index) => index,
+            None => {
+                let ack_seq = seq
+                    .wrapping_add(payload.len() as u32)
+                    .wrapping_add((flags & TCP_FIN != 0) as u32);
+                self.send_tcp_reset(guest_port, remote, ack, ack_seq);
+                return;
+            }
+        };
+
+        let connection = &mut self.tcp[index];
+        connection.guest_window = window;
+        if flags & TCP_ACK != 0 {
+            connection.acknowledge(ack);
+        }
+
+        if !payload.is_empty() || flags & TCP_FIN != 0 {
+            if seq == connection.recv_next && !connection.guest_closed {
+                // Partially written data is sent again by the guest
+                let written = match connection.stream.write(payload) {
+                    Ok(size) => size,
+                    Err(ref error) if error.kind() == ErrorKind::WouldBlock => 0,
+                    Err(_) => {
+                        let seq = connection.send_next();
+                        let recv_next = connection.recv_next;
+                        self.tcp.remove(index);
+                        self.send_tcp_reset(guest_port, remote, seq, recv_next);
+                        return;
+                    }
+                };
+                connection.recv_next = connection.recv_next.wrapping_add(written as u32);
+
+                if written == payload.len() && flags & TCP_FIN != 0 {
+                    connection.recv_next = connection.recv_next.wrapping_add(1);
+                    connection.guest_closed = true;
+                    let _ = connection.stream.shutdown(Shutdown::Write);
+                }
+            }
+            let seq = connection.send_next();
+            self.send_tcp(index, seq, TCP_ACK, &[]);
+        }
+
+        if self.tcp[index].is_finished() {
+            self.tcp.remove(index);
+        }
+    }
+
+    fn open_tcp(&mut self, pending: PendingTcp, stream: TcpStream) {
+        let isn = self.next_isn;
+        self.next_isn = self.next_isn.wrapping_add(TCP_ISN_INCREMENT);
+
+        self.tcp.push(TcpConnection {
+            guest_port: pending.guest_port,
+            remote: pending.remote,
+            stream,
+            recv_next: pending.seq.wrapping_add(1),
+            send_unacked: isn.wrapping_add(1),
+            unacked: Vec::new(),
+            guest_window: pending.window,
+            mss: pending.mss,
+            sent_at: Instant::now(),
+            host_closed: false,
+            guest_closed: false,
+            fin_sent: false,
+            fin_acked: false,
+        });
+        self.send_syn_ack(self.tcp.len() - 1);
+    }
+
+    fn send_syn_ack(&mut self, index: usize) {
+        let connection = &self.tcp[index];
+        let [mss_high, mss_low] = TCP_MSS.to_be_bytes();
+        let segment = tcp_segment(
+            connection.remote,
+            SocketAddrV4::new(self.guest_ip, connection.guest_port),
+            connection.send_unacked.wrapping_sub(1),
+            connection.recv_next,
+            TCP_SYN | TCP_ACK,
+            &[TCP_OPTION_MSS, 4, mss_high, mss_low],
+            &[],
+        );
+        self.queue_to_guest(*connection.remote.ip(), IP_PROTO_TCP, &segment);
+    }
+
+    fn send_tcp(&mut self, index: usize, seq: u32, flags: u8, payload: &[u8]) {
+        let connection = &self.tcp[index];
+        let segment = tcp_segment(
+            connection.remote,
+            SocketAddrV4::new(self.guest_ip, connection.guest_port),
+            seq,
+            connection.recv_next,
+            flags,
+            &[],
+            payload,
+        );
+        self.queue_to_guest(*connection.remote.ip(), IP_PROTO_TCP, &segment);
+    }
+
+    fn send_tcp_reset(&mut self, guest_port: u16, remote: SocketAddrV4, seq: u32, ack: u32) {
+        let dst = SocketAddrV4::new(self.guest_ip, guest_port);
+        let segment = tcp_segment(remote, dst, seq, ack, TCP_RST | TCP_ACK, &[], &[]);
+        self.queue_to_guest(*remote.ip(), IP_PROTO_TCP, &segment);
+    }
+
+    fn poll_tcp(&mut self) {
+        let mut index = 0;
+        while index < self.tcp.len() {
+            if self.poll_tcp_connection(index) {
+                index += 1;
+            } else {
+                self.tcp.remove(index);
+            }
+        }
+    }
+
+    // Sends the data from the host to the guest. Returns false when the connection is closed
+    fn poll_tcp_connection(&mut self, index: usize) -> bool {
+        let connection = &mut self.tcp[index];
+        let timeout = connection.sent_at.elapsed() >= TCP_RETRANSMIT_TIMEOUT;
+
+        if !connection.unacked.is_empty() {
+            if timeout {
+                connection.sent_at = Instant::now();
+                let seq = connection.send_unacked;
+                let data = connection.unacked.clone();
+                self.send_tcp(index, seq, TCP_PSH | TCP_ACK, &data);
+            }
+        } else if connection.fin_sent {
+            if !connection.fin_acked && timeout {
+                connection.sent_at = Instant::now();
+                let seq = connection.send_unacked;
+                self.send_tcp(index, seq, TCP_FIN | TCP_ACK, &[]);
+            }
+        } else if !connection.host_closed {
+            let size = connection.mss.min(connection.guest_window);
+            if size > 0 {
+                let mut buffer = vec![0; size];
+                match connection.stream.read(&mut buffer) {
+                    Ok(0) => connection.host_closed = true,
+                    Ok(read) => {
+                        buffer.truncate(read);
+                        connection.unacked = buffer.clone();
+                        connection.sent_at = Instant::now();
+                        let seq = connection.send_unacked;
+                        self.send_tcp(index, seq, TCP_PSH | TCP_ACK, &buffer);
+                    }
+                    Err(ref error) if error.kind() == ErrorKind::WouldBlock => {}
+                    Err(_) => {
+                        let (guest_port, remote) = (connection.guest_port, connection.remote);
+                        let (seq, ack) = (connection.send_next(), connection.recv_next);
+                        self.send_tcp_reset(guest_port, remote, seq, ack);
+                        return false;
+                    }
+                }
+            }
+        }
+
+        // The FIN is sent as soon as the host closes the connection
+        let connection = &mut self.tcp[index];
+        if connection.host_closed && !connection.fin_sent && connection.unacked.is_empty() {
+            connection.fin_sent = true;
+            connection.sent_at = Instant::now();
+            let seq = connection.send_unacked;
+            self.send_tcp(index, seq, TCP_FIN | TCP_ACK, &[]);
+        }
+
+        !self.tcp[index].is_finished()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{TcpListener, UdpSocket};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const GUEST_MAC: [u8; 6] = [0x00, 0x08, 0xdc, 0x01, 0x02, 0x03];
+    const GUEST_PORT: u16 = 1234;
+
+    fn guest_frame(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let packet = ipv4_packet(GUEST_ADDR, dst, protocol, payload);
+        ethernet_frame(GATEWAY_MAC, GUEST_MAC, ETHERTYPE_IPV4, &packet)
+    }
+
+    fn guest_tcp(remote: SocketAddrV4, seq: u32, ack: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let src = SocketAddrV4::new(GUEST_ADDR, GUEST_PORT);
+        let segment = tcp_segment(src, remote, seq, ack, flags, &[], payload);
+        guest_frame(*remote.ip(), IP_PROTO_TCP, &segment)
+    }
+
+    // Returns the IP payload of the next frame received by the guest
+    fn receive_ip_payload(network: &mut UserNetwork) -> Vec<u8> {
+        let frame = network.receive().expect("No frame received");
+        assert_eq!(frame[0..6], GUEST_MAC);
+        assert_eq!(be16(&frame[12..]), ETHERTYPE_IPV4);
+        let packet = &frame[ETH_HEADER_SIZE..];
+        assert_eq!(checksum(&packet[..IP_HEADER_SIZE], 0), 0);
+        let total_size = be16(&packet[2..]) as usize;
+        packet[IP_HEADER_SIZE..total_size].to_vec()
+    }
+
+    // Waits for the worker threads and returns the next frame
+    fn receive_pending(network: &mut UserNetwork) -> Vec<u8> {
+        network.poll_pending(TIMEOUT);
+        receive_ip_payload(network)
+    }
+
+    // The reads from the host sockets block until the data arrives
+    fn receive_from_host(network: &mut UserNetwork) -> Vec<u8> {
+        assert!(network.frames.is_empty());
+        set_host_blocking(network, true);
+        let payload = receive_ip_payload(network);
+        set_host_blocking(network, false);
+        payload
+    }
+
+    fn set_host_blocking(network: &UserNetwork, blocking: bool) {
+        for binding in &network.udp {
+            binding.socket.set_nonblocking(!blocking).unwrap();
+            binding.socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+        }
+        for connection in &network.tcp {
+            connection.stream.set_nonblocking(!blocking).unwrap();
+            connection.stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        }
+    }
+
+    fn dns_query(network: &mut UserNetwork, name: &[u8], qtype: u16) {
+        let mut query = vec![0xab, 0xcd, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        query.push(name.len() as u8);
+        query.extend_from_slice(name);
+        query.push(0);
+        query.extend_from_slice(&qtype.to_be_bytes());
+        query.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        let src = SocketAddrV4::new(GUEST_ADDR, GUEST_PORT);
+        let datagram = udp_datagram(src, SocketAddrV4::new(DNS_ADDR, DNS_PORT), &query);
+        network.send(&guest_frame(DNS_ADDR, IP_PROTO_UDP, &datagram));
+    }
+
+    #[test]
+    fn arp_and_dhcp() {
+        let mut network = UserNetwork::new();
+
+        let mut arp = ARP_HEADER.to_vec();
+        arp.extend_from_slice(&ARP_REQUEST.to_be_bytes());
+        arp.extend_from_slice(&GUEST_MAC);
+        arp.extend_from_slice(&GUEST_ADDR.octets());
+        arp.extend_from_slice(&[0; 6]);
+        arp.extend_from_slice(&GATEWAY_ADDR.octets());
+        network.send(&ethernet_frame(
+            BROADCAST_MAC,
+            GUEST_MAC,
+            ETHERTYPE_ARP,
+            &arp,
+        ));
+        let reply = network.receive().unwrap();
+        assert_eq!(reply[0..6], GUEST_MAC);
+        assert_eq!(be16(&reply[20..]), ARP_REPLY);
+        assert_eq!(reply[22..28], GATEWAY_MAC);
+        assert_eq!(reply[32..38], GUEST_MAC);
+
+        let mut discover = vec![0; DHCP_OPTIONS_OFFSET];
+        discover[0] = BOOTP_REQUEST;
+        discover[1] = 1;
+        discover[2] = 6;
+        discover[4..8].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        discover[28..34].copy_from_slice(&GUEST_MAC);
+        discover[236..DHCP_OPTIONS_OFFSET].copy_from_slice(&DHCP_MAGIC);
+        discover.extend_from_slice(&[DHCP_OPTION_MESSAGE_TYPE, 1, DHCP_DISCOVER, DHCP_OPTION_END]);
+        let datagram = udp_datagram(
+            SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, DHCP_CLIENT_PORT),
+            SocketAddrV4::new(Ipv4Addr::BROADCAST, DHCP_SERVER_PORT),
+            &discover,
+        );
+        let packet = ipv4_packet(
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::BROADCAST,
+            IP_PROTO_UDP,
+            &datagram,
+        );
+        network.send(&ethernet_frame(
+            BROADCAST_MAC,
+            GUEST_MAC,
+            ETHERTYPE_IPV4,
+            &packet,
+        ));
+
+        let offer = network.receive().unwrap();
+        assert_eq!(offer[0..6], BROADCAST_MAC);
+        let message = &offer[ETH_HEADER_SIZE + IP_HEADER_SIZE + UDP_HEADER_SIZE..];
+        assert_eq!(message[0], BOOTP_REPLY);
+        assert_eq!(message[4..8], [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(ipv4_addr(&message[16..20]), GUEST_ADDR);
+        assert_eq!(
+            dhcp_message_type(&message[DHCP_OPTIONS_OFFSET..]),
+            Some(DHCP_OFFER)
+        );
+    }
+
+    #[test]
+    fn dns_and_udp_forwarding() {
+        let mut network = UserNetwork::new();
+
+        // Query the A record of localhost
+        dns_query(&mut network, b"localhost", DNS_TYPE_A);
+        assert_eq!(network.pending_dns.len(), 1);
+        let datagram = receive_pending(&mut network);
+        assert_eq!(be16(&datagram[2..]), GUEST_PORT);
+        let response = &datagram[UDP_HEADER_SIZE..];
+        assert_eq!(response[0..2], [0xab, 0xcd]);
+        assert_eq!(response[3] & 0x0f, 0);
+        assert!(be16(&response[6..]) > 0);
+        assert_eq!(response[response.len() - 4..], [127, 0, 0, 1]);
+
+        // The AAAA queries are not implemented
+        dns_query(&mut network, b"localhost", 28);
+        let datagram = receive_ip_payload(&mut network);
+        let response = &datagram[UDP_HEADER_SIZE..];
+        assert_eq!(response[2] & DNS_QR, DNS_QR);
+        assert_eq!(response[3] & 0x0f, DNS_RCODE_NOT_IMPLEMENTED);
+        assert_eq!(be16(&response[6..]), 0);
+        assert_eq!(response.len(), DNS_HEADER_SIZE + 11 + 4);
+
+        // Datagrams to the gateway are sent to the host
+        let src = SocketAddrV4::new(GUEST_ADDR, GUEST_PORT);
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let remote = SocketAddrV4::new(GATEWAY_ADDR, port);
+        let datagram = udp_datagram(src, remote, b"ping");
+        network.send(&guest_frame(GATEWAY_ADDR, IP_PROTO_UDP, &datagram));
+
+        let mut buffer = [0; 16];
+        let (size, peer) = server.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"ping");
+        server.send_to(b"pong", peer).unwrap();
+
+        let datagram = receive_from_host(&mut network);
+        assert_eq!(be16(&datagram[0..]), port);
+        assert_eq!(be16(&datagram[2..]), GUEST_PORT);
+        assert_eq!(&datagram[UDP_HEADER_SIZE..], b"pong");
+    }
+
+    #[test]
+    fn tcp_forwarding() {
+        let mut network = UserNetwork::new();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let remote = SocketAddrV4::new(GATEWAY_ADDR, listener.local_addr().unwrap().port());
+
+        network.send(&guest_tcp(remote, 100, 0, TCP_SYN, &[]));
+
+        // The SYN sent again while the host connection is pending is ignored
+        network.send(&guest_tcp(remote, 100, 0, TCP_SYN, &[]));
+        let segment = receive_pending(&mut network);
+        assert_eq!(segment[13], TCP_SYN | TCP_ACK);
+        assert!(network.frames.is_empty() && network.pending_tcp.is_empty());
+        assert_eq!(be32(&segment[8..]), 101);
+        let mut server_seq = be32(&segment[4..]).wrapping_add(1);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        network.send(&guest_tcp(
+            remote,
+            101,
+            server_seq,
+            TCP_ACK | TCP_PSH,
+            b"hello",
+        ));
+        let segment = receive_ip_payload(&mut network);
+        assert_eq!(segment[13], TCP_ACK);
+        assert_eq!(be32(&segment[8..]), 106);
+
+        let mut buffer = [0; 5];
+        stream.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        stream.write_all(b"world").unwrap();
+        let segment = receive_from_host(&mut network);
+        assert_eq!(be32(&segment[4..]), server_seq);
+        assert_eq!(&segment[TCP_HEADER_SIZE..], b"world");
+        server_seq = server_seq.wrapping_add(5);
+        network.send(&guest_tcp(remote, 106, server_seq, TCP_ACK, &[]));
+
+        // The host closes the connection
+        drop(stream);
+        let segment = receive_from_host(&mut network);
+        assert_eq!(segment[13], TCP_FIN | TCP_ACK);
+        assert_eq!(be32(&segment[4..]), server_seq);
+
+        network.send(&guest_tcp(
+            remote,
+            106,
+            server_seq.wrapping_add(1),
+            TCP_FIN | TCP_ACK,
+            &[],
+        ));
+        let segment = receive_ip_payload(&mut network);
+        assert_eq!(segment[13], TCP_ACK);
+        assert_eq!(be32(&segment[8..]), 107);
+        assert!(network.tcp.is_empty());
+    }
+
+    #[test]
+    fn tcp_connection_refused() {
+        let mut network = UserNetwork::new();
+
+        // Nothing listens on the port of a closed listener
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let remote = SocketAddrV4::new(GATEWAY_ADDR, port);
+        network.send(&guest_tcp(remote, 100, 0, TCP_SYN, &[]));
+        let segment = receive_pending(&mut network);
+        assert_eq!(segment[13], TCP_RST | TCP_ACK);
+        assert_eq!(be32(&segment[8..]), 101);
+        assert!(network.tcp.is_empty() && network.pending_tcp.is_empty());
+    }
+}
@@ -0,0 +1,788 @@
+use crate::bus::Card;
+use crate::mmu::Mmu;
+use crate::usernet::UserNetwork;
+use crate::video::Video;
+
+#[cfg(feature = "pcap")]
+use crate::network::{PcapCapture, open_pcap_capture};
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+/*
+Uthernet (CS8900A) network card
+
+The CS8900A is used in I/O mode. The eight 16-bit I/O ports are mapped to C0n0-C0nF with
+the low byte of each port at the even address.
+
+    C0n0-C0n1   (r/w) Receive / transmit data port 0
+    C0n2-C0n3   (r/w) Receive / transmit data port 1
+    C0n4-C0n5   (w)   TxCMD
+    C0n6-C0n7   (w)   TxLength
+    C0n8-C0n9   (r)   Interrupt status queue
+    C0nA-C0nB   (r/w) PacketPage pointer: Bit 15 = auto increment, Bit 11-0 = address
+    C0nC-C0nD   (r/w) PacketPage data port 0
+    C0nE-C0nF   (r/w) PacketPage data port 1
+
+A 16-bit word of the data ports is transferred when both of its bytes have been accessed,
+in any order. The writes to the TxCMD, TxLength and PacketPage data ports take effect when
+the high byte is written.
+
+PacketPage registers
+
+    0000        (r)   Product identification code ($630E)
+    0002        (r)   Product revision
+    0102        (r/w) RxCFG: Bit 6 = skip the current received frame
+    0104        (r/w) RxCTL: Bit 7 = promiscuous, Bit 8 = RxOK, Bit 9 = multicast,
+                      Bit 10 = individual address, Bit 11 = broadcast
+    0106        (r/w) TxCFG
+    0108        (r)   TxCMD
+    010A        (r/w) BufCFG
+    0112        (r/w) LineCTL: Bit 6 = receiver on, Bit 7 = transmitter on
+    0114        (r/w) SelfCTL: Bit 6 = reset
+    0116        (r/w) BusCTL
+    0118        (r/w) TestCTL
+    0120        (r)   Interrupt status queue
+    0124        (r)   RxEvent: Bit 8 = frame received, Bit 9 = multicast, Bit 10 = individual
+                      address, Bit 11 = broadcast
+    0128        (r)   TxEvent: Bit 8 = frame transmitted. Cleared when read
+    012C        (r)   BufEvent
+    0134        (r)   LineST: Bit 7 = link OK
+    0136        (r)   SelfST: Bit 7 = initialization done
+    0138        (r)   BusST: Bit 8 = ready for transmit now
+    0144        (w)   TxCMD: Bit 13 = disable padding of short frames
+    0146        (w)   TxLength
+    0150-0157   (r/w) Logical address filter (ignored)
+    0158-015D   (r/w) Individual address (MAC address)
+    0400        (r)   RxStatus of the received frame
+    0402        (r)   RxLength of the received frame
+    0404-       (r)   Received frame
+
+A received frame is read from the data port as RxStatus, RxLength and the frame data. The
+frame is released once all its words are read or skipped, and the next frame is fetched
+from the network when RxEvent, the interrupt status queue or the data port is read. The
+frames to transmit are written to the data port after TxCMD and TxLength, and are sent
+once TxLength bytes are written.
+
+The frames are sent and received through the pcap interface (pcap feature), which is
+opened when the receiver or the transmitter is turned on. The user-mode network is used
+instead when it is selected or when the pcap interface cannot be opened.
+*/
+
+const PRODUCT_ID: u16 = 0x630e;
+const PRODUCT_REVISION: u16 = 0x0a00;
+
+// I/O ports
+const PORT_RX_TX_DATA0: u8 = 0x00;
+const PORT_RX_TX_DATA1: u8 = 0x01;
+const PORT_TX_CMD: u8 = 0x02;
+const PORT_TX_LENGTH: u8 = 0x03;
+const PORT_ISQ: u8 = 0x04;
+const PORT_PP_POINTER: u8 = 0x05;
+const PORT_PP_DATA0: u8 = 0x06;
+const PORT_PP_DATA1: u8 = 0x07;
+
+// PacketPage registers
+const PP_PRODUCT_ID: u16 = 0x0000;
+const PP_PRODUCT_REVISION: u16 = 0x0002;
+const PP_RX_CFG: u16 = 0x0102;
+const PP_RX_CTL: u16 = 0x0104;
+const PP_TX_CFG: u16 = 0x0106;
+const PP_TX_CMD_STATUS: u16 = 0x0108;
+const PP_BUF_CFG: u16 = 0x010a;
+const PP_LINE_CTL: u16 = 0x0112;
+const PP_SELF_CTL: u16 = 0x0114;
+const PP_BUS_CTL: u16 = 0x0116;
+const PP_TEST_CTL: u16 = 0x0118;
+const PP_ISQ: u16 = 0x0120;
+const PP_RX_EVENT: u16 = 0x0124;
+const PP_TX_EVENT: u16 = 0x0128;
+const PP_BUF_EVENT: u16 = 0x012c;
+const PP_RX_MISS: u16 = 0x0130;
+const PP_TX_COL: u16 = 0x0132;
+const PP_LINE_ST: u16 = 0x0134;
+const PP_SELF_ST: u16 = 0x0136;
+const PP_BUS_ST: u16 = 0x0138;
+const PP_TDR: u16 = 0x013c;
+const PP_TX_CMD: u16 = 0x0144;
+const PP_TX_LENGTH: u16 = 0x0146;
+const PP_FILTER: u16 = 0x0150;
+const PP_MAC: u16 = 0x0158;
+const PP_RX_FRAME: u16 = 0x0400;
+const PP_POINTER_MASK: u16 = 0x0fff;
+const PP_AUTO_INCREMENT: u16 = 0x8000;
+
+const RX_CFG_SKIP: u16 = 0x0040;
+
+const RX_CTL_PROMISCUOUS: u16 = 0x0080;
+const RX_CTL_MULTICAST: u16 = 0x0200;
+const RX_CTL_INDIVIDUAL: u16 = 0x0400;
+const RX_CTL_BROADCAST: u16 = 0x0800;
+
+const RX_EVENT_OK: u16 = 0x0100;
+const RX_EVENT_HASHED: u16 = 0x0200;
+const RX_EVENT_INDIVIDUAL: u16 = 0x0400;
+const RX_EVENT_BROADCAST: u16 = 0x0800;
+
+const TX_EVENT_OK: u16 = 0x0100;
+const TX_CMD_PAD_DISABLE: u16 = 0x2000;
+
+const LINE_CTL_RX_ON: u16 = 0x0040;
+const LINE_CTL_TX_ON: u16 = 0x0080;
+const LINE_ST_LINK_OK: u16 = 0x0080;
+const LINE_ST_10BT: u16 = 0x0200;
+const SELF_CTL_RESET: u16 = 0x0040;
+const SELF_ST_INIT_DONE: u16 = 0x0080;
+const BUS_ST_TX_BID_ERROR: u16 = 0x0080;
+const BUS_ST_READY_FOR_TX: u16 = 0x0100;
+
+// The register number is in the low 6 bits of the control and status registers
+const REG_MASK: u16 = 0x003f;
+const CONTROL_REGISTER_BASE: u16 = 0x00ff;
+
+const FRAME_HEADER_SIZE: usize = 14;
+const FRAME_MIN_SIZE: usize = 60;
+const FRAME_MAX_SIZE: usize = 1518;
+
+// Register number of the configuration and control registers
+fn control_register(addr: u16) -> u16 {
+    addr - CONTROL_REGISTER_BASE
+}
+
+// Register number of the status and event registers
+fn status_register(addr: u16) -> u16 {
+    addr - PP_ISQ
+}
+
+#[derive(Debug, Default)]
+enum Backend {
+    #[default]
+    None,
+    User(UserNetwork),
+
+    #[cfg(feature = "pcap")]
+    Pcap(PcapCapture),
+}
+
+impl Backend {
+    fn send(&mut self, frame: &[u8]) {
+        match self {
+            Backend::None => {}
+            Backend::User(network) => network.send(frame),
+
+            #[cfg(feature = "pcap")]
+            Backend::Pcap(capture) => {
+                if capture.0.sendpacket(frame).is_err() {
+                    *self = Backend::None;
+                }
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        match self {
+            Backend::None => None,
+            Backend::User(network) => network.receive(),
+
+            #[cfg(feature = "pcap")]
+            Backend::Pcap(capture) => match capture.0.next_packet() {
+                Ok(packet) => Some(packet.data.to_vec()),
+                Err(pcap::Error::TimeoutExpired) => None,
+                Err(_) => {
+                    *self = Backend::None;
+                    None
+                }
+            },
+        }
+    }
+}
+
+// 16-bit word of a data port accessed one byte at a time
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+struct WordLatch {
+    value: u16,
+    accessed: u8,
+}
+
+impl WordLatch {
+    // A new word is loaded for the first byte accessed or when a byte is accessed again
+    fn needs_load(&self, high: bool) -> bool {
+        self.accessed == 0 || self.accessed & (1 << high as u8) != 0
+    }
+
+    fn load(&mut self, value: u16) {
+        self.value = value;
+        self.accessed = 0;
+    }
+
+    // Marks the byte as accessed. Returns true when both bytes of the word are accessed
+    fn access(&mut self, high: bool) -> bool {
+        self.accessed |= 1 << high as u8;
+        if self.accessed == 0x03 {
+            self.accessed = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn byte(&self, high: bool) -> u8 {
+        self.value.to_le_bytes()[high as usize]
+    }
+
+    fn set_byte(&mut self, high: bool, value: u8) {
+        let mut bytes = self.value.to_le_bytes();
+        bytes[high as usize] = value;
+        self.value = u16::from_le_bytes(bytes);
+    }
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Uthernet {
+    pp_pointer: u16,
+    rx_cfg: u16,
+    rx_ctl: u16,
+    tx_cfg: u16,
+    buf_cfg: u16,
+    line_ctl: u16,
+    self_ctl: u16,
+    bus_ctl: u16,
+    test_ctl: u16,
+    tx_cmd: u16,
+    tx_length: u16,
+    tx_event: u16,
+    filter: [u8; 8],
+    mac: [u8; 6],
+
+    // RxStatus, RxLength and the data of the received frame
+    rx_event: u16,
+    rx_buffer: Vec<u8>,
+    rx_index: usize,
+    rx_latch: WordLatch,
+
+    tx_frame: Vec<u8>,
+    tx_latch: WordLatch,
+    isq_latch: WordLatch,
+    pp_latch: WordLatch,
+    port_latch: WordLatch,
+    interface: Option<String>,
+
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    user_network: bool,
+
+    #[cfg_attr(feature = "serde_support", serde(skip))]
+    backend: Backend,
+}
+
+impl Uthernet {
+    pub fn new() -> Self {
+        Uthernet::default()
+    }
+
+    pub fn reset(&mut self) {
+        let interface = self.interface.take();
+        *self = Uthernet {
+            interface,
+            user_network: self.user_network,
+            ..Default::default()
+        };
+    }
+
+    pub fn set_interface(&mut self, name: String) {
+        self.interface = Some(name);
+    }
+
+    pub fn get_interface(&self) -> Option<String> {
+        self.interface.clone()
+    }
+
+    /// Use the user-mode network instead of the pcap interface
+    pub fn set_user_network(&mut self, state: bool) {
+        self.user_network = state;
+    }
+
+    fn open_backend(&mut self) {
+        if !matches!(self.backend, Backend::None) {
+            return;
+        }
+
+        #[cfg(feature = "pcap")]
+        if !self.user_network
+            && let Some(capture) = open_pcap_capture(self.interface.as_deref())
+        {
+            self.backend = Backend::Pcap(capture);
+            return;
+        }
+
+        self.backend = Backend::User(UserNetwork::new());
+    }
+
+    fn has_received_frame(&self) -> bool {
+        self.rx_index < self.rx_buffer.len()
+    }
+
+    fn release_received_frame(&mut self) {
+        self.rx_event = 0;
+        self.rx_buffer.clear();
+        self.rx_index = 0;
+        self.rx_latch = WordLatch::default();
+    }
+
+    // Fetch the next frame accepted by the receive filter
+    fn poll_receive(&mut self) {
+        if self.has_received_frame() || self.line_ctl & LINE_CTL_RX_ON == 0 {
+            return;
+        }
+
+        while let Some(frame) = self.backend.receive() {
+            if self.deliver_frame(&frame) {
+                break;
+            }
+        }
+    }
+
+    // Returns true if the frame is accepted by the receive filter
+    fn deliver_frame(&mut self, frame: &[u8]) -> bool {
+        if frame.len() < FRAME_HEADER_SIZE || frame.len() > FRAME_MAX_SIZE {
+            return false;
+        }
+
+        let destination = &frame[..6];
+        let broadcast = destination == [0xff; 6];
+        let individual = destination == self.mac;
+        let multicast = destination[0] & 0x01 != 0 && !broadcast;
+
+        let mut event = RX_EVENT_OK | status_register(PP_RX_EVENT);
+        let accepted = if broadcast {
+            event |= RX_EVENT_BROADCAST;
+            self.rx_ctl & RX_CTL_BROADCAST != 0
+        } else if individual {
+            event |= RX_EVENT_INDIVIDUAL;
+            self.rx_ctl & RX_CTL_INDIVIDUAL != 0
+        } else if multicast {
+            event |= RX_EVENT_HASHED;
+            self.rx_ctl & RX_CTL_MULTICAST != 0
+        } else {
+            false
+        };
+
+        if !accepted && self.rx_ctl & RX_CTL_PROMISCUOUS == 0 {
+            return false;
+        }
+
+        self.release_received_frame();
+        self.rx_event = event;
+        self.rx_buffer.extend_from_slice(&event.to_le_bytes());
+        self.rx_buffer
+            .extend_from_slice(&(frame.len() as u16).to_le_bytes());
+        self.rx_buffer.extend_from_slice(frame);
+        if !self.rx_buffer.len().is_multiple_of(2) {
+            self.rx_buffer.push(0);
+        }
+        true
+    }
+
+    fn read_rx_data(&mut self, high: bool) -> u8 {
+        if self.rx_latch.needs_load(high) {
+            self.poll_receive();
+            let value = if self.has_received_frame() {
+                let index = self.rx_index;
+                self.rx_index += 2;
+                u16::from_le_bytes([self.rx_buffer[index], self.rx_buffer[index + 1]])
+            } else {
+                0
+            };
+            self.rx_latch.load(value);
+        }
+
+        let value = self.rx_latch.byte(high);
+        if self.rx_latch.access(high) && !self.has_received_frame() {
+            self.release_received_frame();
+        }
+        value
+    }
+
+    fn write_tx_data(&mut self, high: bool, value: u8) {
+        self.tx_latch.set_byte(high, value);
+        if !self.tx_latch.access(high) || self.tx_length == 0 {
+            return;
+        }
+
+        let length = self.tx_length as usize;
+        self.tx_frame
+            .extend_from_slice(&self.tx_latch.value.to_le_bytes());
+        if self.tx_frame.len() >= length {
+            self.tx_frame.truncate(length);
+            if self.tx_cmd & TX_CMD_PAD_DISABLE == 0 && self.tx_frame.len() < FRAME_MIN_SIZE {
+                self.tx_frame.resize(FRAME_MIN_SIZE, 0);
+            }
+
+            let frame = std::mem::take(&mut self.tx_frame);
+            if self.line_ctl & LINE_CTL_TX_ON != 0 {
+                self.backend.send(&frame);
+            }
+            self.tx_event = TX_EVENT_OK | status_register(PP_TX_EVENT);
+            self.tx_length = 0;
+        }
+    }
+
+    // Returns and clears the next pending event
+    fn read_isq(&mut self) -> u16 {
+        self.poll_receive();
+        if self.rx_event != 0 {
+            self.rx_event
+        } else {
+            std::mem::take(&mut self.tx_event)
+        }
+    }
+
+    fn read_packet_page(&mut self, addr: u16) -> u16 {
+        let addr = addr & PP_POINTER_MASK & !0x01;
+        match addr {
+            PP_PRODUCT_ID => PRODUCT_ID,
+            PP_PRODUCT_REVISION => PRODUCT_REVISION,
+            PP_RX_CFG => self.rx_cfg | control_register(PP_RX_CFG),
+            PP_RX_CTL => self.rx_ctl | control_register(PP_RX_CTL),
+            PP_TX_CFG => self.tx_cfg | control_register(PP_TX_CFG),
+            PP_TX_CMD_STATUS => self.tx_cmd | control_register(PP_TX_CMD_STATUS),
+            PP_BUF_CFG => self.buf_cfg | control_register(PP_BUF_CFG),
+            PP_LINE_CTL => self.line_ctl | control_register(PP_LINE_CTL),
+            PP_SELF_CTL => self.self_ctl | control_register(PP_SELF_CTL),
+            PP_BUS_CTL => self.bus_ctl | control_register(PP_BUS_CTL),
+            PP_TEST_CTL => self.test_ctl | control_register(PP_TEST_CTL),
+            PP_ISQ => self.read_isq(),
+
+            PP_RX_EVENT => {
+                self.poll_receive();
+                self.rx_event | status_register(PP_RX_EVENT)
+            }
+
+            PP_TX_EVENT => std::mem::take(&mut self.tx_event) | status_register(PP_TX_EVENT),
+            PP_BUF_EVENT | PP_RX_MISS | PP_TX_COL | PP_TDR => status_register(addr),
+            PP_LINE_ST => LINE_ST_LINK_OK | LINE_ST_10BT | status_register(PP_LINE_ST),
+            PP_SELF_ST => SELF_ST_INIT_DONE | status_register(PP_SELF_ST),
+
+            PP_BUS_ST => {
+                let status = if self.tx_length as usize > FRAME_MAX_SIZE {
+                    BUS_ST_TX_BID_ERROR
+                } else {
+                    BUS_ST_READY_FOR_TX
+                };
+                status | status_register(PP_BUS_ST)
+            }
+
+            PP_TX_CMD => self.tx_cmd,
+            PP_TX_LENGTH => self.tx_length,
+
+            PP_FILTER..PP_MAC => {
+                let index = (addr - PP_FILTER) as usize;
+                u16::from_le_bytes([self.filter[index], self.filter[index + 1]])
+            }
+
+            PP_MAC..0x015e => {
+                let index = (addr - PP_MAC) as usize;
+                u16::from_le_bytes([self.mac[index], self.mac[index + 1]])
+            }
+
+            PP_RX_FRAME..0x0a00 => {
+                self.poll_receive();
+                let index = (addr - PP_RX_FRAME) as usize;
+                match self.rx_buffer.get(index..index + 2) {
+                    Some(word) => u16::from_le_bytes([word[0], word[1]]),
+                    None => 0,
+                }
+            }
+
+            _ => 0,
+        }
+    }
+
+    fn write_packet_page(&mut self, addr: u16, value: u16) {
+        let addr = addr & PP_POINTER_MASK & !0x01;
+        let value_bits = value & !REG_MASK;
+        match addr {
+            PP_RX_CFG => {
+                self.rx_cfg = value_bits & !RX_CFG_SKIP;
+                if value & RX_CFG_SKIP != 0 {
+                    self.release_received_frame();
+                }
+            }
+
+            PP_RX_CTL => self.rx_ctl = value_bits,
+            PP_TX_CFG => self.tx_cfg = value_bits,
+            PP_BUF_CFG => self.buf_cfg = value_bits,
+
+            PP_LINE_CTL => {
+                self.line_ctl = value_bits;
+                if value & (LINE_CTL_RX_ON | LINE_CTL_TX_ON) != 0 {
+                    self.open_backend();
+                }
+            }
+
+            PP_SELF_CTL => {
+                if value & SELF_CTL_RESET != 0 {
+                    self.reset();
+                } else {
+                    self.self_ctl = value_bits;
+                }
+            }
+
+            PP_BUS_CTL => self.bus_ctl = value_bits,
+            PP_TEST_CTL => self.test_ctl = value_bits,
+
+            PP_TX_CMD => {
+                self.tx_cmd = value_bits;
+                self.tx_frame.clear();
+                self.tx_latch = WordLatch::default();
+            }
+
+            PP_TX_LENGTH => {
+                self.tx_length = value;
+                self.tx_frame.clear();
+                self.tx_latch = WordLatch::default();
+            }
+
+            PP_FILTER..PP_MAC => {
+                let index = (addr - PP_FILTER) as usize;
+                self.filter[index..index + 2].copy_from_slice(&value.to_le_bytes());
+            }
+
+            PP_MAC..0x015e => {
+                let index = (addr - PP_MAC) as usize;
+                self.mac[index..index + 2].copy_from_slice(&value.to_le_bytes());
+            }
+
+            _ => {}
+        }
+    }
+
+    fn auto_increment(&mut self) {
+        if self.pp_pointer & PP_AUTO_INCREMENT != 0 {
+            let addr = (self.pp_pointer + 2) & PP_POINTER_MASK;
+            self.pp_pointer = (self.pp_pointer & !PP_POINTER_MASK) | addr;
+        }
+    }
+}
+
+impl Card for Uthernet {
+    fn rom_access(&mut self, _addr: u16, value: u8, _write_flag: bool) -> u8 {
+        value
+    }
+
+    fn io_access(
+        &mut self,
+        _mmu: &mut Mmu,
+        _video: &mut Video,
+        addr: u16,
+        value: u8,
+        write_flag: bool,
+    ) -> u8 {
+        let slot = ((addr & 0x00ff) - 0x0080) >> 4;
+        let io_addr = ((addr & 0x00ff) - (slot << 4)) as u8 & 0x0f;
+        let port = io_addr >> 1;
+        let high = io_addr & 0x01 != 0;
+        let pp_addr = if port == PORT_PP_DATA1 {
+            self.pp_pointer.wrapping_add(2)
+        } else {
+            self.pp_pointer
+        };
+
+        if write_flag {
+            match port {
+                PORT_RX_TX_DATA0 | PORT_RX_TX_DATA1 => self.write_tx_data(high, value),
+
+                PORT_TX_CMD | PORT_TX_LENGTH | PORT_PP_DATA0 | PORT_PP_DATA1 => {
+                    self.port_latch.set_byte(high, value);
+                    if high {
+                        let addr = match port {
+                            PORT_TX_CMD => PP_TX_CMD,
+                            PORT_TX_LENGTH => PP_TX_LENGTH,
+                            _ => pp_addr,
+                        };
+                        self.write_packet_page(addr, self.port_latch.value);
+                        self.pp_latch = WordLatch::default();
+                        if port == PORT_PP_DATA0 {
+                            self.auto_increment();
+                        }
+                    }
+                }
+
+                PORT_PP_POINTER => {
+                    let mut bytes = self.pp_pointer.to_le_bytes();
+                    bytes[high as usize] = value;
+                    self.pp_pointer = u16::from_le_bytes(bytes);
+                    self.pp_latch = WordLatch::default();
+                }
+
+                _ => {}
+            }
+            return 0;
+        }
+
+        match port {
+            PORT_RX_TX_DATA0 | PORT_RX_TX_DATA1 => self.read_rx_data(high),
+
+            PORT_ISQ => {
+                if self.isq_latch.needs_load(high) {
+                    let value = self.read_isq();
+                    self.isq_latch.load(value);
+                }
+                let value = self.isq_latch.byte(high);
+                self.isq_latch.access(high);
+                value
+            }
+
+            PORT_PP_POINTER => self.pp_pointer.to_le_bytes()[high as usize],
+
+            PORT_TX_CMD => self.read_packet_page(PP_TX_CMD).to_le_bytes()[high as usize],
+            PORT_TX_LENGTH => self.read_packet_page(PP_TX_LENGTH).to_le_bytes()[high as usize],
+
+            _ => {
+                if self.pp_latch.needs_load(high) {
+                    let value = self.read_packet_page(pp_addr);
+                    self.pp_latch.load(value);
+                }
+                let value = self.pp_latch.byte(high);
+                if self.pp_latch.access(high) && port == PORT_PP_DATA0 {
+                    self.auto_increment();
+                }
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Bus, IODevice, Mem};
+
+    const MAC: [u8; 6] = [0x00, 0x08, 0x0d, 0x01, 0x02, 0x03];
+
+    fn uthernet_bus() -> Bus {
+        let mut bus = Bus::default();
+        bus.register_device(IODevice::Uthernet, 1);
+        bus
+    }
+
+    fn write_pp(bus: &mut Bus, addr: u16, value: u16) {
+        let [low, high] = addr.to_le_bytes();
+        bus.unclocked_addr_write(0xc09a, low);
+        bus.unclocked_addr_write(0xc09b, high);
+        let [low, high] = value.to_le_bytes();
+        bus.unclocked_addr_write(0xc09c, low);
+        bus.unclocked_addr_write(0xc09d, high);
+    }
+
+    fn read_pp(bus: &mut Bus, addr: u16) -> u16 {
+        let [low, high] = addr.to_le_bytes();
+        bus.unclocked_addr_write(0xc09a, low);
+        bus.unclocked_addr_write(0xc09b, high);
+        u16::from_le_bytes([
+            bus.unclocked_addr_read(0xc09c),
+            bus.unclocked_addr_read(0xc09d),
+        ])
+    }
+
+    fn init(bus: &mut Bus) {
+        for (i, pair) in MAC.chunks(2).enumerate() {
+            write_pp(
+                bus,
+                PP_MAC + 2 * i as u16,
+                u16::from_le_bytes([pair[0], pair[1]]),
+            );
+        }
+        // RxOKA, IndividualA and BroadcastA
+        write_pp(
+            bus,
+            PP_RX_CTL,
+            0x0100 | RX_CTL_INDIVIDUAL | RX_CTL_BROADCAST,
+        );
+        write_pp(bus, PP_LINE_CTL, LINE_CTL_RX_ON | LINE_CTL_TX_ON);
+    }
+
+    #[test]
+    fn packet_page_registers() {
+        let mut bus = uthernet_bus();
+        assert_eq!(read_pp(&mut bus, PP_PRODUCT_ID), PRODUCT_ID);
+        init(&mut bus);
+        assert_eq!(read_pp(&mut bus, PP_MAC + 2), 0x010d);
+        assert_eq!(read_pp(&mut bus, PP_LINE_CTL), 0x00d3);
+        assert_eq!(read_pp(&mut bus, PP_RX_EVENT), 0x0004);
+        assert_eq!(
+            read_pp(&mut bus, PP_BUS_ST) & BUS_ST_READY_FOR_TX,
+            BUS_ST_READY_FOR_TX
+        );
+
+        // Auto increment reads the MAC address a word at a time
+        bus.unclocked_addr_write(0xc09a, 0x58);
+        bus.unclocked_addr_write(0xc09b, 0x81);
+        let mut mac = vec![];
+        for _ in 0..3 {
+            mac.push(bus.unclocked_addr_read(0xc09c));
+            mac.push(bus.unclocked_addr_read(0xc09d));
+        }
+        assert_eq!(mac, MAC);
+
+        // Software reset
+        write_pp(&mut bus, PP_SELF_CTL, SELF_CTL_RESET);
+        assert_eq!(read_pp(&mut bus, PP_LINE_CTL), 0x0013);
+    }
+
+    #[test]
+    fn receive_and_transmit_frames() {
+        let mut bus = uthernet_bus();
+        init(&mut bus);
+
+        // Frames for another station are filtered out
+        let mut frame = vec![0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        frame.extend_from_slice(&[0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x08, 0x06, 0xaa]);
+        assert!(!bus.uthernet.deliver_frame(&frame));
+
+        frame[..6].copy_from_slice(&MAC);
+        assert!(bus.uthernet.deliver_frame(&frame));
+        let event = read_pp(&mut bus, PP_RX_EVENT);
+        assert_eq!(event, RX_EVENT_OK | RX_EVENT_INDIVIDUAL | 0x0004);
+
+        // Length is read with the high byte first
+        let status_low = bus.unclocked_addr_read(0xc090);
+        let status_high = bus.unclocked_addr_read(0xc091);
+        assert_eq!(u16::from_le_bytes([status_low, status_high]), event);
+        let length_high = bus.unclocked_addr_read(0xc091);
+        let length_low = bus.unclocked_addr_read(0xc090);
+        let length = u16::from_le_bytes([length_low, length_high]) as usize;
+        assert_eq!(length, frame.len());
+
+        let mut data = vec![];
+        for _ in 0..length.div_ceil(2) {
+            data.push(bus.unclocked_addr_read(0xc090));
+            data.push(bus.unclocked_addr_read(0xc091));
+        }
+        assert_eq!(&data[..length], &frame[..]);
+        assert_eq!(read_pp(&mut bus, PP_RX_EVENT), 0x0004);
+
+        // Skip the broadcast frame without reading it
+        frame[..6].copy_from_slice(&[0xff; 6]);
+        assert!(bus.uthernet.deliver_frame(&frame));
+        assert_eq!(
+            read_pp(&mut bus, PP_ISQ) & RX_EVENT_BROADCAST,
+            RX_EVENT_BROADCAST
+        );
+        write_pp(&mut bus, PP_RX_CFG, RX_CFG_SKIP);
+        assert_eq!(read_pp(&mut bus, PP_RX_EVENT), 0x0004);
+
+        // Transmit a short frame, padded to 60 bytes
+        bus.unclocked_addr_write(0xc094, 0xc0);
+        bus.unclocked_addr_write(0xc095, 0x00);
+        bus.unclocked_addr_write(0xc096, frame.len() as u8);
+        bus.unclocked_addr_write(0xc097, 0x00);
+        for pair in frame.chunks(2) {
+            bus.unclocked_addr_write(0xc090, pair[0]);
+            bus.unclocked_addr_write(0xc091, *pair.get(1).unwrap_or(&0));
+        }
+        assert_eq!(read_pp(&mut bus, PP_TX_EVENT), TX_EVENT_OK | 0x0008);
+        assert_eq!(read_pp(&mut bus, PP_TX_EVENT), 0x0008);
+    }
+}
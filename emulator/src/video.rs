@@ -459,7 +459,7 @@ const CHAR_APPLE2_ROM: [u8; 2048] = [
     0x80, 0x90, 0x88, 0x84, 0x82, 0x84, 0x88, 0x90, 0x80, 0x9c, 0xa2, 0x84, 0x88, 0x88, 0x80, 0x88,
 ];
 
-const CHAR_APPLE2E_ROM: [u8; 4096] = [
+pub(crate) const CHAR_APPLE2E_ROM: [u8; 4096] = [
     0xc7, 0xbb, 0xab, 0xa3, 0xa7, 0xbf, 0xc3, 0xff, 0xef, 0xd7, 0xbb, 0xbb, 0x83, 0xbb, 0xbb, 0xff,
     0x87, 0xbb, 0xbb, 0x87, 0xbb, 0xbb, 0x87, 0xff, 0xc7, 0xbb, 0xbf, 0xbf, 0xbf, 0xbb, 0xc7, 0xff,
     0x87, 0xbb, 0xbb, 0xbb, 0xbb, 0xbb, 0x87, 0xff, 0x83, 0xbf, 0xbf, 0x87, 0xbf, 0xbf, 0x83, 0xff,
@@ -1,9 +1,21 @@
 //#![windows_subsystem = "windows"]
 
+use emu6502::applesoft::{self, ArrayVariable, Variable};
 use emu6502::bus::Bus;
 use emu6502::bus::Dongle;
 use emu6502::bus::IODevice;
+use emu6502::cffa::CFFA_MAX_PARTITIONS;
+use emu6502::disk::{DiskDrive, TrackType, WozHardware};
+use emu6502::disktrack::TrackAnalysis;
+use emu6502::dostrace::DosTracer;
+use emu6502::harddisk::HD_MAX_UNITS;
+use emu6502::loader::{self, HostFile, LoadedProgram};
+use emu6502::mlitrace::MliTracer;
 use emu6502::mmu::AuxType;
+use emu6502::printer::PrinterModel;
+use emu6502::scsi::SCSI_MAX_TARGETS;
+use emu6502::serial::SerialConnection;
+use emu6502::thunderclock::ClockSource;
 use emu6502::video::{DisplayMode, Video};
 //use emu6502::bus::Mem;
 //use emu6502::trace::trace;
@@ -75,6 +87,7 @@ const NTSC_LUMA_BANDWIDTH: f32 = 2300000.0;
 const NTSC_CHROMA_BANDWIDTH: f32 = 600000.0;
 
 const DSK_PO_SIZE: u64 = 143360;
+const DSK_IMAGE_HEADER_SIZE: u64 = 0x100;
 
 const SPEED_FACTOR: u64 = 10;
 const SPEED: [u64; 5] = [10, 28, 40, 80, 10];
@@ -131,7 +144,15 @@ enum OpenFileDialog {
     None,
     Disk(u8),
     HardDisk(u8),
+    ExportDisk(u8),
+    ExportHardDisk(u8),
+    ExportDiskLog,
+    ExportMliTrace,
+    ExportDosTrace,
     Tape,
+    ExportTape,
+    LoadProgram(bool),
+    ExportBasicProgram,
 }
 
 #[derive(Default)]
@@ -169,6 +190,67 @@ struct InputState {
     prev_y: i32,
 }
 
+#[derive(Default)]
+struct DiskInspectorState {
+    show: bool,
+    drive: usize,
+    quarter_track: usize,
+    follow_head: bool,
+    analysis: Option<TrackAnalysis>,
+    analysis_key: Option<(usize, usize)>,
+}
+
+#[derive(Default)]
+struct BasicInspectorState {
+    show: bool,
+    listing: String,
+    variables: Vec<Variable>,
+    arrays: Vec<ArrayVariable>,
+    loaded: bool,
+}
+
+#[derive(Default)]
+struct TraceState {
+    mli: Option<MliTracer>,
+    dos: Option<DosTracer>,
+}
+
+impl TraceState {
+    fn is_enabled(&self) -> bool {
+        self.mli.as_ref().is_some_and(MliTracer::is_recording)
+            || self.dos.as_ref().is_some_and(DosTracer::is_recording)
+    }
+
+    fn step(&mut self, cpu: &mut CPU) {
+        if let Some(tracer) = self.mli.as_mut() {
+            tracer.step(cpu);
+        }
+        if let Some(tracer) = self.dos.as_mut() {
+            tracer.step(cpu);
+        }
+    }
+}
+
+struct PrinterState {
+    prefix: String,
+    pages: usize,
+}
+
+impl Default for PrinterState {
+    fn default() -> Self {
+        Self {
+            prefix: format!("printer_{}", Local::now().format("%Y-%m-%d_%H-%M-%S")),
+            pages: 0,
+        }
+    }
+}
+
+impl PrinterState {
+    fn capture_file(&self) -> String {
+        format!("{}.prn", self.prefix)
+    }
+}
+
 struct EmulatorState {
     video_subsystem: VideoSubsystem,
     audio_stream: Option<AudioStreamOwner>,
@@ -181,6 +263,10 @@ struct EmulatorState {
     save_screenshot: bool,
     file_dialog: OpenFileDialog,
     show_settings: bool,
+    disk_inspector: DiskInspectorState,
+    basic_inspector: BasicInspectorState,
+    trace: TraceState,
+    printer: PrinterState,
     model_changed: bool,
     prev_settings: Vec<usize>,
     current_settings: Vec<usize>,
@@ -210,6 +296,10 @@ impl EmulatorState {
             save_screenshot: false,
             file_dialog: OpenFileDialog::None,
             show_settings: false,
+            disk_inspector: DiskInspectorState::default(),
+            basic_inspector: BasicInspectorState::default(),
+            trace: TraceState::default(),
+            printer: PrinterState::default(),
             model_changed: false,
             prev_settings: Vec::new(),
             current_settings: Vec::new(),
@@ -542,29 +632,47 @@ FLAGS:
                                     apple2c0,apple2c3,apple2c4,apple2cp
     --d1 PATH          Set the file path for disk 1 drive at Slot 6 Drive 1
     --d2 PATH          Set the file path for disk 2 drive at Slot 6 Drive 2
+    --d3 PATH          Set the file path for disk 3 drive at Slot 5 Drive 1
+                       Requires a second Disk II controller (e.g. --s5 diskii)
+    --d4 PATH          Set the file path for disk 4 drive at Slot 5 Drive 2
     --h1 PATH          Set the file path for hard disk 1
     --h2 PATH          Set the file path for hard disk 2
+    --h3 .. --h8 PATH  Set the file path for SmartPort hard disk unit 3 to 8
+    --cffa1 .. --cffa8 PATH
+                       Set the file path for CFFA partition 1 to 8
+                       Requires a CFFA card in a slot (e.g. --s7 cffa)
+    --scsi0 .. --scsi6 PATH
+                       Set the file path for SCSI ID 0 to 6. iso, cdr and toast
+                       images are CD-ROM targets
+                       Requires a SCSI card in a slot (e.g. --s7 scsi)
     --s1 device        Device slot 1
-                       Value: none,harddisk,mboard,z80,mouse,parallel,ramfactor,
-                              diskii,diskii13,saturn,vidhd
+                       Value: none,harddisk,mboard,z80,mouse,parallel,serial,
+                              ramfactor,diskii,diskii13,saturn,vidhd,thunderclock,
+                              cffa,scsi,uthernet
     --s2 device        Device slot 2
-                       Value: none,harddisk,mboard,z80,mouse,parallel,ramfactor,
-                              diskii,diskii13,saturn,vidhd
+                       Value: none,harddisk,mboard,z80,mouse,parallel,serial,
+                              ramfactor,diskii,diskii13,saturn,vidhd,thunderclock,
+                              cffa,scsi,uthernet
     --s3 device        Device slot 3
-                       Value: none,harddisk,mboard,z80,mouse,parallel,ramfactor,
-                              diskii,diskii13,saturn,vidhd,videoterm
+                       Value: none,harddisk,mboard,z80,mouse,parallel,serial,
+                              ramfactor,diskii,diskii13,saturn,vidhd,videoterm,
+                              thunderclock,cffa,scsi,uthernet
     --s4 device        Device slot 4
-                       Value: none,harddisk,mboard,z80,mouse,parallel,ramfactor,
-                              diskii,diskii13,saturn,vidhd
+                       Value: none,harddisk,mboard,z80,mouse,parallel,serial,
+                              ramfactor,diskii,diskii13,saturn,vidhd,thunderclock,
+                              cffa,scsi,uthernet
     --s5 device        Device slot 5
-                       Value: none,harddisk,mboard,z80,mouse,parallel,ramfactor,
-                              diskii,diskii13,saturn,vidhd
+                       Value: none,harddisk,mboard,z80,mouse,parallel,serial,
+                              ramfactor,diskii,diskii13,saturn,vidhd,thunderclock,
+                              cffa,scsi,uthernet
     --s6 device        Device slot 6
-                       Value: none,harddisk,mboard,z80,mouse,parallel,ramfactor,
-                              diskii,diskii13,saturn,vidhd
+                       Value: none,harddisk,mboard,z80,mouse,parallel,serial,
+                              ramfactor,diskii,diskii13,saturn,vidhd,thunderclock,
+                              cffa,scsi,uthernet
     --s7 device        Device slot 7
-                       Value: none,harddisk,mboard,z80,mouse,parallel,ramfactor,
-                              diskii,diskii13,saturn,vidhd
+                       Value: none,harddisk,mboard,z80,mouse,parallel,serial,
+                              ramfactor,diskii,diskii13,saturn,vidhd,thunderclock,
+                              cffa,scsi,uthernet
     --weakbit rate     Set the random weakbit error rate (Default is 0.3)
     --opt_timing rate  Override the optimal timing (Default is 32)
     --rgb              Enable RGB mode (Default: RGB mode disabled)
@@ -580,18 +688,47 @@ FLAGS:
                        Value: speedstar, hayden, codewriter, robocom500,
                               robocom1000, robocom1500
     --list_interfaces  List all the network interfaces
-    --interface name   Set the interface name for Uthernet2
+    --interface name   Set the interface name for Uthernet and Uthernet2
                        Default is None. For e.g. eth0
+    --user_network     Use the user-mode network (NAT, DHCP and DNS) for the raw
+                       frames of Uthernet and Uthernet2 instead of pcap
+    --uthernet2_irq    Connect the interrupt line of Uthernet2 (Default: not connected)
+    --serial conn      Connect the Super Serial Card (Slot 2 if not in any slot)
+                       or the modem port (port 2) of the Apple //c
+                       Value: tcp:host:port, listen:port, pty, file:path,
+                              modem, modem:port (Hayes modem answering on port)
+    --serial1 conn     Connect the printer port (port 1) of the Apple //c
     --videoterm        Enable Videx Videoterm at slot 3
     --vidhd            Enable VidHD at slot 3
     --aux aux_type     Auxiliary Slot type. 
                        Supported values (ext80, std80, rw3, none)
     --exact_write      Enable exact track writing (No write to neighbor tracks)
     --noslot_clock off Disable noslot clock 
+    --clock source     Time source of the Thunderclock Plus
+                       Value: host, offset:seconds, fixed:YYYY-MM-DDTHH:MM:SS
     --disable_jitter   Disable disk jitter
+    --hl_disk          Service the DOS 3.3 RWTS and ProDOS Disk II driver calls
+                       directly from the disk image (High-level disk access)
+    --disk_log         Record the disk access log from power on. The log can be
+                       exported from the System menu as CSV or JSON
+    --fast_tape        Load the cassette tape records directly when the monitor
+                       READ routine is called (Applesoft and Integer BASIC LOAD)
+    --printer model    Printer emulation of the parallel card (epson, imagewriter)
+                       The output is captured to printer_<time>.prn and the
+                       printed pages are saved as printer_<time>_pageNNN.png
+    --mli_trace        Trace the ProDOS MLI calls from power on and print each
+                       call to stderr
+    --dos_trace        Trace the DOS 3.3 file manager and RWTS calls from power on
+                       and print each call to stderr
+    --overlay          Store disk writes in a sidecar .delta file and keep the
+                       original disk images unmodified
+    --woz_auto         Auto-select the model and aux memory using the WOZ
+                       metadata of the disk in drive 1
 
 ARGS:
     [disk 1]           Disk 1 file (woz, dsk, do, po file). Can be in gz format
+                       An m3u playlist or zip with several images is loaded
+                       as a disk set
     [disk 2]           Disk 2 file (woz, dsk, do, po file). Can be in gz format
 
 Function Keys:
@@ -599,6 +736,10 @@ Function Keys:
     Ctrl-Shift-F2      Disassemble current instructions
     Ctrl-Shift-F3      Dump track sector information
     Ctrl-Shift-F4      Dump disk WOZ information
+    Alt-F1 / Alt-Shift-F1
+                       Next / Previous disk in the disk set of Disk 1
+    Alt-F2 / Alt-Shift-F2
+                       Next / Previous disk in the disk set of Disk 2
     Ctrl-F1            Eject Disk 1
     Ctrl-F2            Eject Disk 2
     Ctrl-F3            Save state in YAML file
@@ -641,24 +782,11 @@ where
 {
     let path_ref = path.as_ref();
 
-    if let Some(ext) = path_ref.extension() {
-        if ext.eq_ignore_ascii_case(OsStr::new("2mg"))
-            || ext.eq_ignore_ascii_case(OsStr::new("hdv"))
-        {
+    if path_ref.extension().is_some() {
+        if is_harddisk_image(path_ref) {
             let drive = get_drive_number(loaded_device, IODevice::HardDisk);
             load_harddisk(cpu, path_ref, drive)?;
             loaded_device.push(IODevice::HardDisk);
-        } else if ext.eq_ignore_ascii_case(OsStr::new("po")) {
-            let size = std::fs::metadata(path_ref)?.len();
-            if size > DSK_PO_SIZE {
-                let drive = get_drive_number(loaded_device, IODevice::HardDisk);
-                load_harddisk(cpu, path_ref, drive)?;
-                loaded_device.push(IODevice::HardDisk);
-            } else {
-                let drive = get_drive_number(loaded_device, IODevice::Disk);
-                load_disk(cpu, path_ref, drive)?;
-                loaded_device.push(IODevice::Disk);
-            }
         } else {
             let drive = get_drive_number(loaded_device, IODevice::Disk);
             load_disk(cpu, path_ref, drive)?;
@@ -670,28 +798,170 @@ where
     Ok(())
 }
 
+// The 2mg, DiskCopy 4.2 and po images can be either a 5.25 floppy or a hard disk image.
+// Use the image size to decide where to mount the image
+fn is_harddisk_image(path: &Path) -> bool {
+    let Some(ext) = path.extension() else {
+        return false;
+    };
+
+    if ext.eq_ignore_ascii_case(OsStr::new("hdv")) {
+        return true;
+    }
+
+    let sized_image = ["po", "2mg", "2img", "dc", "dc42"]
+        .iter()
+        .any(|item| ext.eq_ignore_ascii_case(OsStr::new(item)));
+
+    sized_image
+        && fs::metadata(path)
+            .map(|metadata| metadata.len() > DSK_PO_SIZE + DSK_IMAGE_HEADER_SIZE)
+            .unwrap_or(false)
+}
+
+// Drive 1 and 2 are in the first Disk II controller, Drive 3 and 4 are in the second
+fn get_disk_drive(cpu: &CPU, drive: usize) -> (&DiskDrive, usize) {
+    if drive < 2 {
+        (&cpu.bus.disk, drive)
+    } else {
+        (&cpu.bus.disk2, drive - 2)
+    }
+}
+
+fn get_disk_drive_mut(cpu: &mut CPU, drive: usize) -> (&mut DiskDrive, usize) {
+    if drive < 2 {
+        (&mut cpu.bus.disk, drive)
+    } else {
+        (&mut cpu.bus.disk2, drive - 2)
+    }
+}
+
+fn has_second_disk_controller(cpu: &CPU) -> bool {
+    (1..8).any(|slot| {
+        let device = cpu.bus.io_slot[slot];
+        (device == IODevice::Disk || device == IODevice::Disk13)
+            && cpu.bus.disk_controller_index(slot) == 1
+    })
+}
+
 fn load_disk<P>(cpu: &mut CPU, path: P, drive: usize) -> Result<(), Box<dyn Error + Send + Sync>>
 where
     P: AsRef<Path>,
 {
-    let drv = &mut cpu.bus.disk;
+    let (drv, index) = get_disk_drive_mut(cpu, drive);
     let path_ref = path.as_ref();
     let drive_selected = drv.drive_selected();
-    drv.drive_select(drive);
-    drv.load_disk_image(path_ref)?;
-    drv.set_disk_filename(path_ref);
-    drv.set_loaded(true);
-    drv.drive_select(drive_selected);
+    drv.drive_select(index);
+    if DiskDrive::is_disk_set(path_ref) {
+        let result = drv.load_disk_set(path_ref);
+        drv.drive_select(drive_selected);
+        result?;
+    } else {
+        // Keep the disk set when the image is part of the set (e.g. restoring state)
+        if !drv.is_in_disk_set(index, &path_ref.display().to_string()) {
+            drv.clear_disk_set(index);
+        }
+        let result = drv.load_disk_image(path_ref);
+        if result.is_ok() {
+            drv.set_disk_filename(path_ref);
+            drv.set_loaded(true);
+        }
+        drv.drive_select(drive_selected);
+        result?;
+    }
+    check_woz_compatibility(cpu, drive);
     Ok(())
 }
 
+fn change_disk_in_set(cpu: &mut CPU, drive: usize, forward: bool) {
+    let (drv, index) = get_disk_drive_mut(cpu, drive);
+    if drv.get_disk_set(index).is_none() {
+        return;
+    }
+
+    let result = if forward {
+        drv.next_disk(index)
+    } else {
+        drv.prev_disk(index)
+    };
+
+    match result {
+        Ok(_) => {
+            if let Some(label) = drv.get_disk_set_label(index) {
+                eprintln!("Disk {} : {label}", drive + 1);
+            }
+            check_woz_compatibility(cpu, drive);
+        }
+        Err(e) => eprintln!("Unable to change disk in drive {} : {e}", drive + 1),
+    }
+}
+
+fn check_woz_compatibility(cpu: &CPU, drive: usize) {
+    let (drv, index) = get_disk_drive(cpu, drive);
+    let Some(metadata) = drv.get_woz_metadata(index) else {
+        return;
+    };
+
+    let title = metadata.title().unwrap_or("Disk");
+    if !metadata.is_compatible(cpu.get_woz_hardware()) {
+        eprintln!(
+            "Warning: {title} is not compatible with the current machine (compatible hardware = {:?})",
+            metadata.compatible_hardware()
+        );
+    }
+
+    let required_ram = metadata.required_ram_kb() as usize;
+    let available_ram =
+        if cpu.bus.mem.aux_type == AuxType::Empty || cpu.bus.mem.aux_type == AuxType::Std80 {
+            64
+        } else {
+            64 + cpu.bus.mem.get_aux_size() * 64
+        };
+    if required_ram > available_ram {
+        eprintln!("Warning: {title} requires {required_ram}K RAM");
+    }
+}
+
+// Select the model and configure the machine based on the WOZ metadata of drive 1
+fn woz_auto_configure(cpu: &mut CPU) {
+    let Some(metadata) = cpu.bus.disk.get_woz_metadata(0).cloned() else {
+        return;
+    };
+
+    if !metadata.is_compatible(cpu.get_woz_hardware()) {
+        let models: [(WozHardware, &[u8], u16, bool); 6] = [
+            (WozHardware::APPLE2E_ENHANCED, APPLE2EE_ROM, 0xc000, false),
+            (WozHardware::APPLE2E, APPLE2E_ROM, 0xc000, false),
+            (WozHardware::APPLE2_PLUS, APPLE2P_ROM, 0xd000, false),
+            (WozHardware::APPLE2, APPLE2_ROM, 0xd000, false),
+            (WozHardware::APPLE2C, APPLE2C4_ROM, 0xc000, true),
+            (WozHardware::APPLE2C_PLUS, APPLE2CP_ROM, 0xc000, true),
+        ];
+
+        if let Some((_, rom, offset, extended_rom)) = models
+            .into_iter()
+            .find(|(hardware, ..)| metadata.is_compatible(*hardware))
+        {
+            initialize_apple_system(cpu, rom, offset, extended_rom);
+            if offset == 0xd000 {
+                cpu.bus.mem.slotc3rom = true;
+                cpu.bus.mem.intcxrom = false;
+            }
+        } else {
+            eprintln!("Warning: No supported model is compatible with the disk");
+        }
+    }
+
+    cpu.bus.woz_auto_configure(0);
+}
+
 fn open_disk_dialog(cpu: &mut CPU, drive: usize) {
     let result = FileDialog::new()
         .add_filter(
             "Disk image",
             &[
-                "dsk", "do", "po", "nib", "woz", "nib.gz", "dsk.gz", "do.gz", "po.gz", "woz.gz",
-                "zip",
+                "dsk", "do", "po", "nib", "woz", "2mg", "dc", "dc42", "nib.gz", "dsk.gz", "do.gz",
+                "po.gz", "woz.gz", "2mg.gz", "zip", "m3u",
             ],
         )
         .pick_file();
@@ -705,7 +975,7 @@ fn open_disk_dialog(cpu: &mut CPU, drive: usize) {
 
 fn mount_tape(cpu: &mut CPU) {
     let result = FileDialog::new()
-        .add_filter("Tape image", &["wav"])
+        .add_filter("Tape image", &["wav", "bin", "bas", "ct2"])
         .save_file();
 
     let Some(file_path) = result else { return };
@@ -715,6 +985,63 @@ fn mount_tape(cpu: &mut CPU) {
     }
 }
 
+fn export_tape_dialog(cpu: &mut CPU) {
+    let result = FileDialog::new()
+        .add_filter("WAV", &["wav"])
+        .add_filter("Binary", &["bin"])
+        .add_filter("BASIC program", &["bas"])
+        .add_filter("CT2", &["ct2"])
+        .save_file();
+
+    let Some(file_path) = result else { return };
+    let result = cpu.bus.audio.save_tape_as(&file_path);
+    if let Err(e) = result {
+        eprintln!("Unable to export tape {} : {e}", file_path.display());
+    }
+}
+
+fn load_program_dialog(cpu: &mut CPU, clipboard_text: &mut String, run: bool) {
+    let result = FileDialog::new()
+        .add_filter(
+            "Program",
+            &["bin", "bas", "int", "txt", "as", "applesingle", "sys"],
+        )
+        .add_filter("All files", &["*"])
+        .pick_file();
+
+    let Some(file_path) = result else { return };
+    let result =
+        HostFile::from_file(&file_path).and_then(|file| loader::load_host_file(cpu, &file, None));
+    match result {
+        Ok(LoadedProgram::Binary(addr)) => {
+            if run {
+                cpu.program_counter = addr;
+            }
+        }
+        Ok(LoadedProgram::Applesoft) => {
+            if run && !loader::run_applesoft(cpu) {
+                eprintln!("Unable to run program: Applesoft ROM not found");
+            }
+        }
+        Ok(LoadedProgram::IntegerBasic) => {
+            if run {
+                clipboard_text.push_str("RUN\r");
+            }
+        }
+        Ok(LoadedProgram::IntegerBasicText(text)) => {
+            clipboard_text.push_str("NEW\r");
+            clipboard_text.push_str(&text);
+            if !text.ends_with('\r') {
+                clipboard_text.push('\r');
+            }
+            if run {
+                clipboard_text.push_str("RUN\r");
+            }
+        }
+        Err(e) => eprintln!("Unable to load program {} : {e}", file_path.display()),
+    }
+}
+
 fn load_harddisk<P>(
     cpu: &mut CPU,
     path: P,
@@ -736,7 +1063,7 @@ where
 
 fn open_harddisk_dialog(cpu: &mut CPU, drive: usize) {
     let result = FileDialog::new()
-        .add_filter("Disk image", &["hdv", "2mg", "po"])
+        .add_filter("Disk image", &["hdv", "2mg", "po", "dc", "dc42"])
         .pick_file();
 
     let Some(file_path) = result else { return };
@@ -750,12 +1077,90 @@ fn eject_harddisk(cpu: &mut CPU, drive: usize) {
     cpu.bus.harddisk.eject(drive);
 }
 
+fn export_disk_dialog(cpu: &mut CPU, drive: usize) {
+    let result = FileDialog::new()
+        .add_filter(
+            "Disk image",
+            &["dsk", "do", "po", "nib", "woz", "2mg", "dc"],
+        )
+        .save_file();
+
+    let Some(file_path) = result else { return };
+    let (drv, index) = get_disk_drive_mut(cpu, drive);
+    let result = drv.export_disk_image(index, &file_path);
+    if let Err(e) = result {
+        eprintln!("Unable to export disk {} : {e}", file_path.display());
+    }
+}
+
+fn export_harddisk_dialog(cpu: &mut CPU, drive: usize) {
+    let result = FileDialog::new()
+        .add_filter("Disk image", &["hdv", "2mg", "po", "dc"])
+        .save_file();
+
+    let Some(file_path) = result else { return };
+    let result = cpu.bus.harddisk.save_disk_image_as(drive, &file_path);
+    if let Err(e) = result {
+        eprintln!("Unable to export hard disk {} : {e}", file_path.display());
+    }
+}
+
+fn export_disk_log_dialog(cpu: &mut CPU) {
+    let result = FileDialog::new()
+        .add_filter("CSV", &["csv"])
+        .add_filter("JSON", &["json"])
+        .save_file();
+
+    let Some(file_path) = result else { return };
+    let mut log = cpu.bus.disk.get_access_log().cloned().unwrap_or_default();
+    if let Some(log2) = cpu.bus.disk2.get_access_log() {
+        log.merge(log2, 2);
+    }
+    if let Err(e) = log.save(&file_path) {
+        eprintln!("Unable to export disk log {} : {e}", file_path.display());
+    }
+}
+
+fn export_basic_program_dialog(cpu: &mut CPU) {
+    let result = FileDialog::new()
+        .add_filter("Applesoft program", &["bas", "txt"])
+        .save_file();
+
+    let Some(file_path) = result else { return };
+    let result = applesoft::list_applesoft(cpu).and_then(|text| fs::write(&file_path, text));
+    if let Err(e) = result {
+        eprintln!("Unable to export program {} : {e}", file_path.display());
+    }
+}
+
+fn export_mli_trace_dialog(tracer: Option<&MliTracer>) {
+    let result = FileDialog::new().add_filter("Text", &["txt"]).save_file();
+
+    let Some(file_path) = result else { return };
+    let Some(tracer) = tracer else { return };
+    if let Err(e) = tracer.save(&file_path) {
+        eprintln!("Unable to export MLI trace {} : {e}", file_path.display());
+    }
+}
+
+fn export_dos_trace_dialog(tracer: Option<&DosTracer>) {
+    let result = FileDialog::new().add_filter("Text", &["txt"]).save_file();
+
+    let Some(file_path) = result else { return };
+    let Some(tracer) = tracer else { return };
+    if let Err(e) = tracer.save(&file_path) {
+        eprintln!("Unable to export DOS trace {} : {e}", file_path.display());
+    }
+}
+
 fn eject_disk(cpu: &mut CPU, drive: usize) {
-    cpu.bus.disk.eject(drive);
+    let (drv, index) = get_disk_drive_mut(cpu, drive);
+    drv.eject(index);
 }
 
 fn is_disk_loaded(cpu: &CPU, drive: usize) -> bool {
-    cpu.bus.disk.is_loaded(drive)
+    let (drv, index) = get_disk_drive(cpu, drive);
+    drv.is_loaded(index)
 }
 
 fn is_harddisk_loaded(cpu: &CPU, drive: usize) -> bool {
@@ -763,13 +1168,30 @@ fn is_harddisk_loaded(cpu: &CPU, drive: usize) -> bool {
 }
 
 fn get_disk_filename(cpu: &CPU, drive: usize) -> Option<String> {
-    cpu.bus.disk.get_disk_filename(drive)
+    let (drv, index) = get_disk_drive(cpu, drive);
+    drv.get_disk_filename(index)
 }
 
 fn get_harddisk_filename(cpu: &CPU, drive: usize) -> Option<String> {
     cpu.bus.harddisk.get_disk_filename(drive)
 }
 
+fn is_cffa_loaded(cpu: &CPU, partition: usize) -> bool {
+    cpu.bus.cffa.partitions.is_loaded(partition)
+}
+
+fn get_cffa_filename(cpu: &CPU, partition: usize) -> Option<String> {
+    cpu.bus.cffa.partitions.get_disk_filename(partition)
+}
+
+fn is_scsi_loaded(cpu: &CPU, id: usize) -> bool {
+    cpu.bus.scsi.targets.is_loaded(id)
+}
+
+fn get_scsi_filename(cpu: &CPU, id: usize) -> Option<String> {
+    cpu.bus.scsi.targets.get_disk_filename(id)
+}
+
 fn register_device(cpu: &mut CPU, device: &str, slot: usize, mboard: &mut usize, saturn: &mut u8) {
     match device {
         "none" => cpu.bus.register_device(IODevice::None, slot),
@@ -784,6 +1206,11 @@ fn register_device(cpu: &mut CPU, device: &str, slot: usize, mboard: &mut usize,
         }
         "mouse" => cpu.bus.register_device(IODevice::Mouse, slot),
         "parallel" => cpu.bus.register_device(IODevice::Printer, slot),
+        "serial" => cpu.bus.register_device(IODevice::SuperSerial, slot),
+        "thunderclock" => cpu.bus.register_device(IODevice::Thunderclock, slot),
+        "cffa" => cpu.bus.register_device(IODevice::Cffa, slot),
+        "scsi" => cpu.bus.register_device(IODevice::Scsi, slot),
+        "uthernet" => cpu.bus.register_device(IODevice::Uthernet, slot),
         "ramfactor" => cpu.bus.register_device(IODevice::RamFactor, slot),
         #[cfg(feature = "z80")]
         "z80" => cpu.bus.register_device(IODevice::Z80, slot),
@@ -907,7 +1334,7 @@ fn load_serialized_image() -> Result<CPU, String> {
     };
 
     // Load the loaded disk into the new cpu
-    for drive in 0..2 {
+    for drive in 0..4 {
         if is_disk_loaded(&new_cpu, drive)
             && let Some(disk_filename) = get_disk_filename(&new_cpu, drive)
         {
@@ -916,6 +1343,9 @@ fn load_serialized_image() -> Result<CPU, String> {
                 eprintln!(".display()Unable to load disk {} : {e}", disk_filename);
             }
         }
+    }
+
+    for drive in 0..HD_MAX_UNITS {
         if is_harddisk_loaded(&new_cpu, drive)
             && let Some(disk_filename) = get_harddisk_filename(&new_cpu, drive)
         {
@@ -926,6 +1356,28 @@ fn load_serialized_image() -> Result<CPU, String> {
         }
     }
 
+    for partition in 0..CFFA_MAX_PARTITIONS {
+        if is_cffa_loaded(&new_cpu, partition)
+            && let Some(disk_filename) = get_cffa_filename(&new_cpu, partition)
+        {
+            let result = new_cpu.bus.cffa.load_partition(partition, &disk_filename);
+            if let Err(e) = result {
+                eprintln!("Unable to load disk {} : {e}", disk_filename);
+            }
+        }
+    }
+
+    for id in 0..SCSI_MAX_TARGETS {
+        if is_scsi_loaded(&new_cpu, id)
+            && let Some(disk_filename) = get_scsi_filename(&new_cpu, id)
+        {
+            let result = new_cpu.bus.scsi.load_target(id, &disk_filename);
+            if let Err(e) = result {
+                eprintln!("Unable to load disk {} : {e}", disk_filename);
+            }
+        }
+    }
+
     Ok(new_cpu)
 }
 
@@ -1153,8 +1605,9 @@ fn update_gpu_harddisk_status(
 ) {
     let harddisk_on;
     let disk_is_on = {
-        harddisk_on = cpu.bus.harddisk.is_busy();
-        cpu.bus.disk.is_motor_on() || harddisk_on
+        harddisk_on =
+            cpu.bus.harddisk.is_busy() || cpu.bus.cffa.is_busy() || cpu.bus.scsi.is_busy();
+        cpu.bus.disk.is_motor_on() || cpu.bus.disk2.is_motor_on() || harddisk_on
     };
 
     if disk_is_on {
@@ -1181,6 +1634,27 @@ fn update_gpu_harddisk_status(
     }
 }
 
+fn save_printer_pages(cpu: &mut CPU, state: &mut PrinterState) {
+    for page in cpu.bus.parallel.printer.take_pages() {
+        state.pages += 1;
+        let filename = format!("{}_page{:03}.png", state.prefix, state.pages);
+
+        let result = File::create(&filename).map(|output| {
+            PngEncoder::new(output).write_image(
+                &page.to_rgb(),
+                page.width as u32,
+                page.height as u32,
+                ColorType::Rgb8.into(),
+            )
+        });
+        if let Ok(Ok(())) = result {
+            eprintln!("Printer page saved as {filename}");
+        } else {
+            eprintln!("Unable to create {filename}");
+        }
+    }
+}
+
 #[cfg(feature = "serialization")]
 fn initialize_new_cpu(cpu: &mut CPU, state: &mut EmulatorState) {
     let mmu = &mut cpu.bus.mem;
@@ -1302,6 +1776,10 @@ fn function_key_processed(cpu: &mut CPU, event: &Event, state: &mut EmulatorStat
                     eject_disk(cpu, 0);
                 }
                 return true;
+            } else if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) {
+                let forward = !keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                change_disk_in_set(cpu, 0, forward);
+                return true;
             } else {
                 open_disk_dialog(cpu, 0);
                 return true;
@@ -1337,6 +1815,10 @@ fn function_key_processed(cpu: &mut CPU, event: &Event, state: &mut EmulatorStat
                     eject_disk(cpu, 1);
                 }
                 return true;
+            } else if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) {
+                let forward = !keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                change_disk_in_set(cpu, 1, forward);
+                return true;
             } else {
                 open_disk_dialog(cpu, 1);
                 return true;
@@ -1390,20 +1872,14 @@ fn function_key_processed(cpu: &mut CPU, event: &Event, state: &mut EmulatorStat
                 return true;
             } else {
                 state.speed.disk_mode_index = (state.speed.disk_mode_index + 1) % 3;
-                match state.speed.disk_mode_index {
-                    0 => {
-                        cpu.bus.disk.set_disk_sound_enable(true);
-                        cpu.bus.disk.set_disable_fast_disk(false);
-                    }
-                    1 => {
-                        cpu.bus.disk.set_disk_sound_enable(false);
-                        cpu.bus.disk.set_disable_fast_disk(false);
-                    }
-                    2 => {
-                        cpu.bus.disk.set_disk_sound_enable(false);
-                        cpu.bus.disk.set_disable_fast_disk(true);
-                    }
-                    _ => {}
+                let (disk_sound, disable_fast_disk) = match state.speed.disk_mode_index {
+                    0 => (true, false),
+                    1 => (false, false),
+                    _ => (false, true),
+                };
+                for disk in cpu.bus.disk_controllers_mut() {
+                    disk.set_disk_sound_enable(disk_sound);
+                    disk.set_disable_fast_disk(disable_fast_disk);
                 }
                 return true;
             }
@@ -1534,18 +2010,8 @@ fn function_key_processed(cpu: &mut CPU, event: &Event, state: &mut EmulatorStat
 
 fn handle_file_drop(cpu: &mut CPU, filename: &str) {
     let path = Path::new(filename);
-    if let Some(path_ext) = path.extension() {
-        let po_hd = if let Ok(metadata) = fs::metadata(path) {
-            path_ext.eq_ignore_ascii_case(OsStr::new("po")) && metadata.len() > DSK_PO_SIZE
-        } else {
-            false
-        };
-
-        let is_hard_disk = path_ext.eq_ignore_ascii_case(OsStr::new("2mg"))
-            || path_ext.eq_ignore_ascii_case(OsStr::new("hdv"))
-            || po_hd;
-
-        let result = if is_hard_disk {
+    if path.extension().is_some() {
+        let result = if is_harddisk_image(path) {
             load_harddisk(cpu, path, 0)
         } else {
             load_disk(cpu, path, 0)
@@ -1750,7 +2216,23 @@ fn render_frame(
                 match std::mem::replace(&mut state.file_dialog, OpenFileDialog::None) {
                     OpenFileDialog::Disk(disk) => open_disk_dialog(cpu, disk.into()),
                     OpenFileDialog::HardDisk(disk) => open_harddisk_dialog(cpu, disk.into()),
+                    OpenFileDialog::ExportDisk(disk) => export_disk_dialog(cpu, disk.into()),
+                    OpenFileDialog::ExportHardDisk(disk) => {
+                        export_harddisk_dialog(cpu, disk.into())
+                    }
+                    OpenFileDialog::ExportDiskLog => export_disk_log_dialog(cpu),
+                    OpenFileDialog::ExportMliTrace => {
+                        export_mli_trace_dialog(state.trace.mli.as_ref())
+                    }
+                    OpenFileDialog::ExportDosTrace => {
+                        export_dos_trace_dialog(state.trace.dos.as_ref())
+                    }
                     OpenFileDialog::Tape => mount_tape(cpu),
+                    OpenFileDialog::ExportTape => export_tape_dialog(cpu),
+                    OpenFileDialog::ExportBasicProgram => export_basic_program_dialog(cpu),
+                    OpenFileDialog::LoadProgram(run) => {
+                        load_program_dialog(cpu, &mut state.input.clipboard_text, run)
+                    }
                     OpenFileDialog::None => {}
                 }
             }
@@ -1772,6 +2254,16 @@ fn render_frame(
                 prepare_settings(cpu, ui, state);
             }
 
+            if state.disk_inspector.show {
+                prepare_disk_inspector(cpu, ui, &mut state.disk_inspector);
+            }
+
+            if state.basic_inspector.show
+                && prepare_basic_inspector(cpu, ui, &mut state.basic_inspector)
+            {
+                state.file_dialog = OpenFileDialog::ExportBasicProgram;
+            }
+
             if state.video.menu_bar_height > 0.0 {
                 let (w, h) = window.size();
                 prepare_statusbar(cpu, ui, state, w, h);
@@ -1878,10 +2370,14 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let mut _cpu_stats = CpuStats::new();
 
     // Enable save for disk
-    cpu.bus.disk.set_enable_save_disk(true);
+    for disk in cpu.bus.disk_controllers_mut() {
+        disk.set_enable_save_disk(true);
+    }
 
     // Enable save for hard disk
     cpu.bus.harddisk.set_enable_save_disk(true);
+    cpu.bus.cffa.partitions.set_enable_save_disk(true);
+    cpu.bus.scsi.targets.set_enable_save_disk(true);
 
     // Enable save for cassette
     cpu.bus.audio.set_enable_save_tape(true);
@@ -1897,12 +2393,16 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let mut key_caps = true;
     let mut scale = 1.5;
     let mut shift_mod = false;
+    let mut woz_auto = false;
+    let mut trace = TraceState::default();
     let exit_flag = parse_args(
         &mut cpu,
         &mut pargs,
         &mut key_caps,
         &mut scale,
         &mut shift_mod,
+        &mut woz_auto,
+        &mut trace,
     )?;
 
     if exit_flag {
@@ -1942,6 +2442,10 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         }
     }
 
+    if woz_auto {
+        woz_auto_configure(&mut cpu);
+    }
+
     // Create the SDL3 context
     let mut sdl_context = sdl3::init()?;
 
@@ -2041,11 +2545,18 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     emulator_state.video.prev_scale = scale;
     emulator_state.input.key_caps = key_caps;
     emulator_state.input.shift_mod = shift_mod;
+    emulator_state.trace = trace;
     emulator_state.dcyc = dcyc;
     emulator_state.previous_cycles = previous_cycles;
     emulator_state.prev_settings = get_slot_settings(&cpu);
     emulator_state.current_settings = emulator_state.prev_settings.clone();
 
+    let capture_file = emulator_state.printer.capture_file();
+    cpu.bus
+        .parallel
+        .printer
+        .set_capture_file(Some(capture_file));
+
     update_video_state(&mut cpu, &mut emulator_state);
 
     let mut adj_ms_offset = std::time::Duration::from_micros(0);
@@ -2058,7 +2569,13 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         'break_loop: loop {
             while emulator_state.dcyc < emulator_state.video.cpu_cycles {
                 let prev_cycle = cpu.bus.get_cycles();
-                if !cpu.step_with_callback(|_| {}) {
+                let running = if emulator_state.trace.is_enabled() {
+                    let trace = &mut emulator_state.trace;
+                    cpu.step_with_callback(|cpu| trace.step(cpu))
+                } else {
+                    cpu.step_with_callback(|_| {})
+                };
+                if !running {
                     break 'break_loop;
                 }
 
@@ -2081,6 +2598,10 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                     emulator_state.save_screenshot = false;
                 }
 
+                if cpu.bus.parallel.printer.has_pages() {
+                    save_printer_pages(&mut cpu, &mut emulator_state.printer);
+                }
+
                 cpu.bus.video.skip_update = false;
 
                 if !window.is_minimized() {
@@ -2173,6 +2694,13 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                     Ok(mut new_cpu) => {
                         emulator_state.previous_cycles = new_cpu.bus.get_cycles();
                         initialize_new_cpu(&mut new_cpu, &mut emulator_state);
+
+                        // The printer is not part of the saved state
+                        cpu.bus.parallel.printer.eject_page();
+                        save_printer_pages(&mut cpu, &mut emulator_state.printer);
+                        let printer = &mut new_cpu.bus.parallel.printer;
+                        printer.set_model(cpu.bus.parallel.printer.model());
+                        printer.set_capture_file(Some(emulator_state.printer.capture_file()));
                         cpu = new_cpu
                     }
                     Err(message) => {
@@ -2185,6 +2713,10 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         }
     }
 
+    // Feed out the last page
+    cpu.bus.parallel.printer.eject_page();
+    save_printer_pages(&mut cpu, &mut emulator_state.printer);
+
     /*
     #[cfg(target_os = "windows")]
     {
@@ -2204,6 +2736,8 @@ fn parse_args(
     key_caps: &mut bool,
     scale: &mut f32,
     shift_mod: &mut bool,
+    woz_auto: &mut bool,
+    trace: &mut TraceState,
 ) -> Result<bool, Box<dyn Error + Send + Sync>> {
     let mut ntsc_luma = NTSC_LUMA_BANDWIDTH;
     let mut ntsc_chroma = NTSC_CHROMA_BANDWIDTH;
@@ -2326,11 +2860,28 @@ fn parse_args(
     }
 
     if let Some(input_rate) = pargs.opt_value_from_str::<_, f32>("--weakbit")? {
-        cpu.bus.disk.set_random_one_rate(input_rate);
+        for disk in cpu.bus.disk_controllers_mut() {
+            disk.set_random_one_rate(input_rate);
+        }
     }
 
     if let Some(input_rate) = pargs.opt_value_from_str::<_, u8>("--opt_timing")? {
-        cpu.bus.disk.set_override_optimal_timing(input_rate);
+        for disk in cpu.bus.disk_controllers_mut() {
+            disk.set_override_optimal_timing(input_rate);
+        }
+    }
+
+    if pargs.contains("--woz_auto") {
+        *woz_auto = true;
+    }
+
+    if pargs.contains("--overlay") {
+        for disk in cpu.bus.disk_controllers_mut() {
+            disk.set_overlay_mode(true);
+        }
+        cpu.bus.harddisk.set_overlay_mode(true);
+        cpu.bus.cffa.partitions.set_overlay_mode(true);
+        cpu.bus.scsi.targets.set_overlay_mode(true);
     }
 
     load_drive_option(cpu, pargs, "--d1", 1, |cpu, path: &Path, index| {
@@ -2339,6 +2890,12 @@ fn parse_args(
     load_drive_option(cpu, pargs, "--d2", 2, |cpu, path: &Path, index| {
         load_disk(cpu, path, index - 1)
     })?;
+    load_drive_option(cpu, pargs, "--d3", 3, |cpu, path: &Path, index| {
+        load_disk(cpu, path, index - 1)
+    })?;
+    load_drive_option(cpu, pargs, "--d4", 4, |cpu, path: &Path, index| {
+        load_disk(cpu, path, index - 1)
+    })?;
     load_drive_option(cpu, pargs, "--h1", 1, |cpu, path: &Path, index| {
         load_harddisk(cpu, path, index - 1)
     })?;
@@ -2346,6 +2903,31 @@ fn parse_args(
         load_harddisk(cpu, path, index - 1)
     })?;
 
+    let smartport_flags = ["--h3", "--h4", "--h5", "--h6", "--h7", "--h8"];
+    for (i, flag) in smartport_flags.into_iter().enumerate() {
+        load_drive_option(cpu, pargs, flag, i + 3, |cpu, path: &Path, index| {
+            load_harddisk(cpu, path, index - 1)
+        })?;
+    }
+
+    let cffa_flags = [
+        "--cffa1", "--cffa2", "--cffa3", "--cffa4", "--cffa5", "--cffa6", "--cffa7", "--cffa8",
+    ];
+    for (i, flag) in cffa_flags.into_iter().enumerate() {
+        load_drive_option(cpu, pargs, flag, i + 1, |cpu, path: &Path, index| {
+            Ok(cpu.bus.cffa.load_partition(index - 1, path)?)
+        })?;
+    }
+
+    let scsi_flags = [
+        "--scsi0", "--scsi1", "--scsi2", "--scsi3", "--scsi4", "--scsi5", "--scsi6",
+    ];
+    for (id, flag) in scsi_flags.into_iter().enumerate() {
+        load_drive_option(cpu, pargs, flag, id, |cpu, path: &Path, id| {
+            Ok(cpu.bus.scsi.load_target(id, path)?)
+        })?;
+    }
+
     let mut slot_mboard = 0;
     let mut slot_saturn = 0;
 
@@ -2373,6 +2955,52 @@ fn parse_args(
         }
     }
 
+    for (flag, port) in [("--serial1", 1), ("--serial", 2)] {
+        let Some(value) = pargs.opt_value_from_str::<_, String>(flag)? else {
+            continue;
+        };
+
+        let connection = match value.parse::<SerialConnection>() {
+            Ok(connection) => connection,
+            Err(err) => {
+                eprintln!("{err}");
+                return Ok(true);
+            }
+        };
+
+        if cpu.is_apple2c() {
+            match cpu.bus.a2c_serial.set_connection(port, &connection) {
+                Ok(()) => eprintln!(
+                    "Serial port {port} connected to {}",
+                    cpu.bus.a2c_serial.get_host_name(port)
+                ),
+                Err(err) => eprintln!("Unable to open serial connection {connection} : {err}"),
+            }
+            continue;
+        }
+
+        if port == 1 {
+            eprintln!("--serial1 is only available for the Apple //c models");
+            return Ok(true);
+        }
+
+        if !cpu.bus.io_slot.contains(&IODevice::SuperSerial) {
+            register_device(cpu, "serial", 2, &mut slot_mboard, &mut slot_saturn);
+        }
+
+        match cpu.bus.ssc.set_connection(&connection) {
+            Ok(()) => eprintln!(
+                "Super Serial Card connected to {}",
+                cpu.bus.ssc.get_host_name()
+            ),
+            Err(err) => eprintln!("Unable to open serial connection {connection} : {err}"),
+        }
+    }
+
+    if (is_disk_loaded(cpu, 2) || is_disk_loaded(cpu, 3)) && !has_second_disk_controller(cpu) {
+        eprintln!("Disk 3 and Disk 4 require a second Disk II controller (e.g. --s5 diskii)");
+    }
+
     if slot_mboard > 2 {
         eprintln!("Maximum of two mockingboards supported");
         return Ok(true);
@@ -2439,9 +3067,19 @@ fn parse_args(
     }
 
     if let Some(name) = pargs.opt_value_from_str::<_, String>("--interface")? {
+        cpu.bus.uthernet.set_interface(name.clone());
         cpu.bus.uthernet2.set_interface(name);
     }
 
+    if pargs.contains("--user_network") {
+        cpu.bus.uthernet.set_user_network(true);
+        cpu.bus.uthernet2.set_user_network(true);
+    }
+
+    if pargs.contains("--uthernet2_irq") {
+        cpu.bus.uthernet2.set_irq_enabled(true);
+    }
+
     if pargs.contains("--list_interfaces") {
         let names = cpu.bus.uthernet2.list_interfaces();
         eprintln!("No of network interfaces found: {}", names.len());
@@ -2452,15 +3090,69 @@ fn parse_args(
     }
 
     if pargs.contains("--disk_sound") {
-        cpu.bus.disk.set_disk_sound_enable(false);
+        for disk in cpu.bus.disk_controllers_mut() {
+            disk.set_disk_sound_enable(false);
+        }
+    }
+
+    if pargs.contains("--hl_disk") {
+        for disk in cpu.bus.disk_controllers_mut() {
+            disk.set_high_level_disk(true);
+        }
+    }
+
+    if pargs.contains("--disk_log") {
+        for disk in cpu.bus.disk_controllers_mut() {
+            disk.set_access_log(true);
+        }
+    }
+
+    if pargs.contains("--fast_tape") {
+        cpu.bus.audio.set_fast_tape(true);
+    }
+
+    if let Some(source) = pargs.opt_value_from_str::<_, String>("--clock")? {
+        match source.parse::<ClockSource>() {
+            Ok(source) => cpu.bus.thunderclock.set_source(source),
+            Err(err) => {
+                eprintln!("{err}");
+                return Ok(true);
+            }
+        }
+    }
+
+    if let Some(model) = pargs.opt_value_from_str::<_, String>("--printer")? {
+        match model.parse::<PrinterModel>() {
+            Ok(model) => cpu.bus.parallel.printer.set_model(model),
+            Err(err) => {
+                eprintln!("{err}");
+                return Ok(true);
+            }
+        }
+    }
+
+    if pargs.contains("--mli_trace") {
+        let mut tracer = MliTracer::new();
+        tracer.set_echo(true);
+        trace.mli = Some(tracer);
+    }
+
+    if pargs.contains("--dos_trace") {
+        let mut tracer = DosTracer::new();
+        tracer.set_echo(true);
+        trace.dos = Some(tracer);
     }
 
     if pargs.contains("--exact_write") {
-        cpu.bus.disk.set_exact_write(true);
+        for disk in cpu.bus.disk_controllers_mut() {
+            disk.set_exact_write(true);
+        }
     }
 
     if pargs.contains("--disable_jitter") {
-        cpu.bus.disk.set_disable_disk_jitter(true);
+        for disk in cpu.bus.disk_controllers_mut() {
+            disk.set_disable_disk_jitter(true);
+        }
     }
 
     if let Some(aux_type) = pargs.opt_value_from_str::<_, String>("--aux")? {
@@ -2620,6 +3312,25 @@ fn prepare_system_menu(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorState)
 
         prepare_menu_for_disk(cpu, ui, state);
 
+        let inspector = state.disk_inspector.show;
+        build_toggle_menu_item(ui, "Disk Inspector", "", inspector, |value| {
+            state.disk_inspector.show = value;
+        });
+
+        let inspector = state.basic_inspector.show;
+        build_toggle_menu_item(ui, "BASIC Inspector", "", inspector, |value| {
+            state.basic_inspector.show = value;
+            state.basic_inspector.loaded = false;
+        });
+
+        prepare_disk_log_menu(cpu, ui, state);
+
+        prepare_mli_trace_menu(ui, state);
+
+        prepare_dos_trace_menu(ui, state);
+
+        prepare_printer_menu(cpu, ui);
+
         let noslot_clock = cpu.bus.get_noslot_clock();
         build_toggle_menu_item(ui, "Enable NoSlot Clock", "", noslot_clock, |_| {
             cpu.bus.set_noslot_clock(!noslot_clock);
@@ -2769,7 +3480,9 @@ fn prepare_audio_menu(cpu: &mut CPU, ui: &imgui::Ui) {
 
         let disk_sound = cpu.bus.disk.get_disk_sound_enabled();
         build_toggle_menu_item(ui, "Disk Sound", "", disk_sound, |new_state| {
-            cpu.bus.disk.set_disk_sound_enable(new_state);
+            for disk in cpu.bus.disk_controllers_mut() {
+                disk.set_disk_sound_enable(new_state);
+            }
         });
     })
 }
@@ -2928,9 +3641,24 @@ fn prepare_input_menu(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorState)
     ui.menu("Input", || {
         let fast_disk = !cpu.bus.disk.get_disable_fast_disk();
         build_toggle_menu_item(ui, "Fast Disk", "F5", fast_disk, |new_state| {
-            cpu.bus.disk.set_disable_fast_disk(!new_state);
+            for disk in cpu.bus.disk_controllers_mut() {
+                disk.set_disable_fast_disk(!new_state);
+            }
         });
 
+        let high_level_disk = cpu.bus.disk.is_high_level_disk();
+        build_toggle_menu_item(
+            ui,
+            "High-Level Disk (RWTS/ProDOS)",
+            "",
+            high_level_disk,
+            |new_state| {
+                for disk in cpu.bus.disk_controllers_mut() {
+                    disk.set_high_level_disk(new_state);
+                }
+            },
+        );
+
         ui.text("Weakbit");
         ui.same_line();
         let width = ui.push_item_width(-1.0);
@@ -2939,7 +3667,9 @@ fn prepare_input_menu(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorState)
             .flags(SliderFlags::ALWAYS_CLAMP)
             .build(&mut weakbit);
         width.end();
-        cpu.bus.disk.set_random_one_rate(weakbit);
+        for disk in cpu.bus.disk_controllers_mut() {
+            disk.set_random_one_rate(weakbit);
+        }
 
         ui.separator();
 
@@ -2971,6 +3701,11 @@ fn prepare_input_menu(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorState)
         );
 
         ui.separator();
+        let fast_tape = cpu.bus.audio.is_fast_tape();
+        build_toggle_menu_item(ui, "Fast Tape Load", "", fast_tape, |value| {
+            cpu.bus.audio.set_fast_tape(value);
+        });
+
         if ui
             .menu_item_config("Mount Tape")
             .shortcut("Ctrl-F8")
@@ -2986,6 +3721,19 @@ fn prepare_input_menu(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorState)
         {
             cpu.bus.audio.eject_tape();
         }
+
+        if ui.menu_item("Export Tape...") {
+            state.file_dialog = OpenFileDialog::ExportTape;
+        }
+
+        ui.separator();
+        if ui.menu_item("Load Program...") {
+            state.file_dialog = OpenFileDialog::LoadProgram(false);
+        }
+
+        if ui.menu_item("Load and Run Program...") {
+            state.file_dialog = OpenFileDialog::LoadProgram(true);
+        }
     })
 }
 
@@ -2997,6 +3745,8 @@ fn prepare_menu_for_disk(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorStat
         if ui.menu_item_config("Eject").shortcut("Ctrl-F1").build() {
             eject_disk(cpu, 0);
         }
+        prepare_disk_set_menu(cpu, ui, 0, "F1");
+        prepare_overlay_menu_for_disk(cpu, ui, state, 0);
     });
 
     ui.menu("Disk Drive 2", || {
@@ -3006,8 +3756,24 @@ fn prepare_menu_for_disk(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorStat
         if ui.menu_item_config("Eject").shortcut("Ctrl-F2").build() {
             eject_disk(cpu, 1);
         }
+        prepare_disk_set_menu(cpu, ui, 1, "F2");
+        prepare_overlay_menu_for_disk(cpu, ui, state, 1);
     });
 
+    if has_second_disk_controller(cpu) {
+        for drive in 2..4 {
+            ui.menu(format!("Disk Drive {}", drive + 1), || {
+                if ui.menu_item("Open") {
+                    state.file_dialog = OpenFileDialog::Disk(drive as u8);
+                }
+                if ui.menu_item("Eject") {
+                    eject_disk(cpu, drive);
+                }
+                prepare_overlay_menu_for_disk(cpu, ui, state, drive);
+            });
+        }
+    }
+
     ui.menu("Hard Drive 1", || {
         if ui.menu_item_config("Open").shortcut("F10").build() {
             state.file_dialog = OpenFileDialog::HardDisk(0);
@@ -3015,6 +3781,7 @@ fn prepare_menu_for_disk(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorStat
         if ui.menu_item_config("Eject").shortcut("Ctrl-F10").build() {
             eject_harddisk(cpu, 0);
         }
+        prepare_overlay_menu_for_harddisk(cpu, ui, state, 0);
     });
 
     ui.menu("Hard Drive 2", || {
@@ -3024,9 +3791,258 @@ fn prepare_menu_for_disk(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorStat
         if ui.menu_item_config("Eject").shortcut("Ctrl-F11").build() {
             eject_harddisk(cpu, 1);
         }
+        prepare_overlay_menu_for_harddisk(cpu, ui, state, 1);
+    });
+
+    ui.menu("SmartPort Units", || {
+        for drive in 2..cpu.bus.harddisk.num_units() {
+            ui.menu(format!("Hard Drive {}", drive + 1), || {
+                if ui.menu_item("Open") {
+                    state.file_dialog = OpenFileDialog::HardDisk(drive as u8);
+                }
+                if ui.menu_item("Eject") {
+                    eject_harddisk(cpu, drive);
+                }
+                prepare_overlay_menu_for_harddisk(cpu, ui, state, drive);
+            });
+        }
+    });
+}
+
+fn prepare_printer_menu(cpu: &mut CPU, ui: &imgui::Ui) {
+    ui.menu("Printer", || {
+        let printer = &mut cpu.bus.parallel.printer;
+        let model = printer.model();
+        build_toggle_menu_item(ui, "Epson", "", model == PrinterModel::Epson, |_| {
+            printer.set_model(PrinterModel::Epson);
+        });
+        build_toggle_menu_item(
+            ui,
+            "ImageWriter II",
+            "",
+            model == PrinterModel::ImageWriter,
+            |_| {
+                printer.set_model(PrinterModel::ImageWriter);
+            },
+        );
+
+        ui.separator();
+        if ui
+            .menu_item_config("Eject Page")
+            .enabled(printer.is_page_dirty())
+            .build()
+        {
+            printer.eject_page();
+        }
+    });
+}
+
+fn prepare_disk_log_menu(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorState) {
+    ui.menu("Disk Access Log", || {
+        let recording = cpu.bus.disk.get_access_log().is_some();
+        build_toggle_menu_item(ui, "Record", "", recording, |value| {
+            for disk in cpu.bus.disk_controllers_mut() {
+                disk.set_access_log(value);
+            }
+        });
+
+        let entries = cpu.bus.disk.get_access_log().map_or(0, |log| log.len())
+            + cpu.bus.disk2.get_access_log().map_or(0, |log| log.len());
+        if ui
+            .menu_item_config(format!("Clear ({entries} entries)"))
+            .enabled(entries > 0)
+            .build()
+        {
+            for disk in cpu.bus.disk_controllers_mut() {
+                disk.clear_access_log();
+            }
+        }
+
+        if ui
+            .menu_item_config("Export...")
+            .enabled(entries > 0)
+            .build()
+        {
+            state.file_dialog = OpenFileDialog::ExportDiskLog;
+        }
+    });
+}
+
+fn prepare_mli_trace_menu(ui: &imgui::Ui, state: &mut EmulatorState) {
+    ui.menu("ProDOS MLI Trace", || {
+        let recording = state
+            .trace
+            .mli
+            .as_ref()
+            .is_some_and(MliTracer::is_recording);
+        build_toggle_menu_item(ui, "Record", "", recording, |value| {
+            // The recorded calls are kept when the recording is stopped
+            if let Some(tracer) = state.trace.mli.as_mut() {
+                tracer.set_recording(value);
+            } else if value {
+                state.trace.mli = Some(MliTracer::new());
+            }
+        });
+
+        let calls = state
+            .trace
+            .mli
+            .as_ref()
+            .map_or(0, |tracer| tracer.calls().len());
+        if ui
+            .menu_item_config(format!("Clear ({calls} calls)"))
+            .enabled(calls > 0)
+            .build()
+            && let Some(tracer) = state.trace.mli.as_mut()
+        {
+            tracer.clear();
+        }
+
+        if ui.menu_item_config("Export...").enabled(calls > 0).build() {
+            state.file_dialog = OpenFileDialog::ExportMliTrace;
+        }
+    });
+}
+
+fn prepare_dos_trace_menu(ui: &imgui::Ui, state: &mut EmulatorState) {
+    ui.menu("DOS 3.3 Trace", || {
+        let recording = state
+            .trace
+            .dos
+            .as_ref()
+            .is_some_and(DosTracer::is_recording);
+        build_toggle_menu_item(ui, "Record", "", recording, |value| {
+            // The recorded calls are kept when the recording is stopped
+            if let Some(tracer) = state.trace.dos.as_mut() {
+                tracer.set_recording(value);
+            } else if value {
+                state.trace.dos = Some(DosTracer::new());
+            }
+        });
+
+        let calls = state
+            .trace
+            .dos
+            .as_ref()
+            .map_or(0, |tracer| tracer.calls().len());
+        if ui
+            .menu_item_config(format!("Clear ({calls} calls)"))
+            .enabled(calls > 0)
+            .build()
+            && let Some(tracer) = state.trace.dos.as_mut()
+        {
+            tracer.clear();
+        }
+
+        if ui.menu_item_config("Export...").enabled(calls > 0).build() {
+            state.file_dialog = OpenFileDialog::ExportDosTrace;
+        }
     });
 }
 
+fn prepare_disk_set_menu(cpu: &mut CPU, ui: &imgui::Ui, drive: usize, key: &str) {
+    let (drv, index) = get_disk_drive(cpu, drive);
+    let Some(disk_set) = drv.get_disk_set(index) else {
+        return;
+    };
+    let count = disk_set.images.len();
+
+    ui.separator();
+    if let Some(label) = drv.get_disk_set_label(index) {
+        ui.text_disabled(label);
+    }
+    if ui
+        .menu_item_config("Next Disk")
+        .shortcut(format!("Alt-{key}"))
+        .enabled(count > 1)
+        .build()
+    {
+        change_disk_in_set(cpu, drive, true);
+    }
+    if ui
+        .menu_item_config("Previous Disk")
+        .shortcut(format!("Alt-Shift-{key}"))
+        .enabled(count > 1)
+        .build()
+    {
+        change_disk_in_set(cpu, drive, false);
+    }
+}
+
+fn prepare_overlay_menu_for_disk(
+    cpu: &mut CPU,
+    ui: &imgui::Ui,
+    state: &mut EmulatorState,
+    drive: usize,
+) {
+    let loaded = is_disk_loaded(cpu, drive);
+    let (drv, index) = get_disk_drive_mut(cpu, drive);
+    let has_overlay = loaded && drv.has_overlay(index);
+
+    ui.separator();
+    if ui
+        .menu_item_config("Commit Overlay")
+        .enabled(has_overlay)
+        .build()
+        && let Err(e) = drv.commit_overlay(index)
+    {
+        eprintln!("Unable to commit disk overlay : {e}");
+    }
+    if ui
+        .menu_item_config("Revert Overlay")
+        .enabled(has_overlay)
+        .build()
+        && let Err(e) = drv.revert_overlay(index)
+    {
+        eprintln!("Unable to revert disk overlay : {e}");
+    }
+    if ui.menu_item_config("Export Image").enabled(loaded).build() {
+        state.file_dialog = OpenFileDialog::ExportDisk(drive as u8);
+    }
+}
+
+fn prepare_overlay_menu_for_harddisk(
+    cpu: &mut CPU,
+    ui: &imgui::Ui,
+    state: &mut EmulatorState,
+    drive: usize,
+) {
+    let loaded = is_harddisk_loaded(cpu, drive);
+    let has_overlay = loaded && cpu.bus.harddisk.has_overlay(drive);
+
+    let write_protect = loaded && cpu.bus.harddisk.is_write_protected(drive);
+    if ui
+        .menu_item_config("Write Protect")
+        .selected(write_protect)
+        .enabled(loaded)
+        .build()
+        && let Err(e) = cpu.bus.harddisk.set_write_protect(drive, !write_protect)
+    {
+        eprintln!("Unable to set write protect : {e}");
+    }
+
+    ui.separator();
+    if ui
+        .menu_item_config("Commit Overlay")
+        .enabled(has_overlay)
+        .build()
+        && let Err(e) = cpu.bus.harddisk.commit_overlay(drive)
+    {
+        eprintln!("Unable to commit hard disk overlay : {e}");
+    }
+    if ui
+        .menu_item_config("Revert Overlay")
+        .enabled(has_overlay)
+        .build()
+        && let Err(e) = cpu.bus.harddisk.revert_overlay(drive)
+    {
+        eprintln!("Unable to revert hard disk overlay : {e}");
+    }
+    if ui.menu_item_config("Export Image").enabled(loaded).build() {
+        state.file_dialog = OpenFileDialog::ExportHardDisk(drive as u8);
+    }
+}
+
 fn prepare_menu_for_state_management(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorState) {
     if ui
         .menu_item_config("Load State")
@@ -3243,6 +4259,276 @@ fn prepare_settings(cpu: &mut CPU, ui: &imgui::Ui, state: &mut EmulatorState) {
         });
 }
 
+fn refresh_basic_inspector(cpu: &mut CPU, state: &mut BasicInspectorState) {
+    state.listing = applesoft::list_applesoft(cpu).unwrap_or_else(|e| format!("{e}"));
+    state.variables = applesoft::simple_variables(cpu).unwrap_or_default();
+    state.arrays = applesoft::arrays(cpu).unwrap_or_default();
+    state.loaded = true;
+}
+
+// Returns true when the program export is requested
+fn prepare_basic_inspector(cpu: &mut CPU, ui: &imgui::Ui, state: &mut BasicInspectorState) -> bool {
+    const MAX_ARRAY_ELEMENTS: usize = 256;
+
+    if !state.loaded {
+        refresh_basic_inspector(cpu, state);
+    }
+
+    let mut export = false;
+    let mut opened = state.show;
+    ui.window("BASIC Inspector")
+        .opened(&mut opened)
+        .size([480.0, 480.0], imgui::Condition::FirstUseEver)
+        .build(|| {
+            if ui.button("Refresh") {
+                refresh_basic_inspector(cpu, state);
+            }
+            ui.same_line();
+            export = ui.button("Export Program...");
+
+            if ui.collapsing_header("Program", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+                ui.child_window("##basic_program")
+                    .size([0.0, 200.0])
+                    .border(true)
+                    .horizontal_scrollbar(true)
+                    .build(|| {
+                        for line in state.listing.lines() {
+                            ui.text(line);
+                        }
+                    });
+            }
+
+            if ui.collapsing_header("Variables", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+                for variable in &state.variables {
+                    ui.text(format!("{} = {}", variable.name, variable.value));
+                }
+            }
+
+            if ui.collapsing_header("Arrays", imgui::TreeNodeFlags::empty()) {
+                for (index, array) in state.arrays.iter().enumerate() {
+                    let dimensions: Vec<String> = array
+                        .dimensions
+                        .iter()
+                        .map(|size| size.saturating_sub(1).to_string())
+                        .collect();
+                    let label = format!("{}({})##array{index}", array.name, dimensions.join(","));
+                    ui.tree_node_config(label).build(|| {
+                        for (i, value) in array.values.iter().take(MAX_ARRAY_ELEMENTS).enumerate() {
+                            let subscripts: Vec<String> = array
+                                .subscripts(i)
+                                .iter()
+                                .map(|subscript| subscript.to_string())
+                                .collect();
+                            ui.text(format!(
+                                "{}({}) = {value}",
+                                array.name,
+                                subscripts.join(",")
+                            ));
+                        }
+                        if array.values.len() > MAX_ARRAY_ELEMENTS {
+                            ui.text(format!(
+                                "... {} more elements",
+                                array.values.len() - MAX_ARRAY_ELEMENTS
+                            ));
+                        }
+                    });
+                }
+            }
+        });
+    state.show = opened;
+    export
+}
+
+fn prepare_disk_inspector(cpu: &mut CPU, ui: &imgui::Ui, state: &mut DiskInspectorState) {
+    const COLOR_ERROR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+    const COLOR_WEAK: [f32; 4] = [1.0, 0.8, 0.3, 1.0];
+
+    let mut opened = state.show;
+    ui.window("Disk Inspector")
+        .opened(&mut opened)
+        .size([560.0, 480.0], imgui::Condition::FirstUseEver)
+        .build(|| {
+            let drives = if has_second_disk_controller(cpu) {
+                4
+            } else {
+                2
+            };
+            let items: Vec<String> = (1..=drives).map(|i| format!("Drive {i}")).collect();
+            state.drive = state.drive.min(drives - 1);
+            ui.set_next_item_width(120.0);
+            ui.combo_simple_string("##inspector_drive", &mut state.drive, &items);
+
+            let (disk_drive, drive) = get_disk_drive(cpu, state.drive);
+            let (head_track, head_bit) = disk_drive.get_head_position(drive);
+            let mut recording = disk_drive.get_latch_history().is_some();
+
+            ui.same_line();
+            ui.checkbox("Follow head", &mut state.follow_head);
+            ui.same_line();
+            if ui.checkbox("Record latch", &mut recording) {
+                get_disk_drive_mut(cpu, state.drive)
+                    .0
+                    .set_latch_history(recording);
+            }
+            ui.same_line();
+            let refresh = ui.button("Refresh");
+
+            ui.text(format!(
+                "Head: Track {:02}.{:02} Bit {}",
+                head_track / 4,
+                head_track % 4 * 25,
+                head_bit
+            ));
+
+            if state.follow_head {
+                state.quarter_track = head_track;
+            }
+
+            let (disk_drive, drive) = get_disk_drive(cpu, state.drive);
+            let tracks = disk_drive.get_quarter_track_info(drive);
+            if tracks.is_empty() {
+                ui.text("No disk");
+                state.analysis = None;
+                state.analysis_key = None;
+                return;
+            }
+
+            let key = (state.drive, state.quarter_track);
+            if refresh || state.analysis_key != Some(key) {
+                state.analysis = disk_drive.inspect_track(drive, state.quarter_track);
+                state.analysis_key = Some(key);
+            }
+
+            ui.child_window("##inspector_tracks")
+                .size([150.0, 0.0])
+                .border(true)
+                .build(|| {
+                    for (qt, tmap_track, track_type, bits) in &tracks {
+                        let flux = if *track_type == TrackType::Flux {
+                            "F"
+                        } else {
+                            ""
+                        };
+                        let label = format!(
+                            "{:02}.{:02} [{:02X}]{} {}",
+                            qt / 4,
+                            qt % 4 * 25,
+                            tmap_track,
+                            flux,
+                            bits
+                        );
+                        if ui
+                            .selectable_config(label)
+                            .selected(*qt == state.quarter_track)
+                            .build()
+                        {
+                            state.quarter_track = *qt;
+                            state.follow_head = false;
+                        }
+                    }
+                });
+
+            ui.same_line();
+            ui.child_window("##inspector_fields").build(|| {
+                let Some(analysis) = &state.analysis else {
+                    ui.text("Track not present");
+                    return;
+                };
+
+                ui.text(format!(
+                    "Bits: {}  Nibbles: {}  Bad checksums: {}",
+                    analysis.bit_count,
+                    analysis.nibble_count,
+                    analysis.bad_checksum_count()
+                ));
+                ui.text(format!(
+                    "Readable sectors: {:?}",
+                    analysis.readable_sectors()
+                ));
+
+                if ui.collapsing_header("Address fields", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+                    for field in &analysis.address_fields {
+                        let text = format!(
+                            "@{:6} {:?} V:{:3} T:{:2} S:{:2} Chk:{} Epi:{}",
+                            field.bit_offset,
+                            field.encoding,
+                            field.volume,
+                            field.track,
+                            field.sector,
+                            if field.checksum_ok { "ok" } else { "bad" },
+                            if field.epilogue_ok { "ok" } else { "bad" },
+                        );
+                        if field.checksum_ok && field.epilogue_ok {
+                            ui.text(text);
+                        } else {
+                            ui.text_colored(COLOR_ERROR, text);
+                        }
+                    }
+                }
+
+                if ui.collapsing_header("Data fields", imgui::TreeNodeFlags::DEFAULT_OPEN) {
+                    for field in &analysis.data_fields {
+                        let sector = field
+                            .sector
+                            .map_or("--".to_string(), |sector| format!("{sector:2}"));
+                        let text = format!(
+                            "@{:6} {:?} S:{} Len:{} Chk:{} Epi:{}",
+                            field.bit_offset,
+                            field.encoding,
+                            sector,
+                            field.bit_len,
+                            if field.checksum_ok { "ok" } else { "bad" },
+                            if field.epilogue_ok { "ok" } else { "bad" },
+                        );
+                        if field.checksum_ok && field.epilogue_ok {
+                            ui.text(text);
+                        } else {
+                            ui.text_colored(COLOR_ERROR, text);
+                        }
+                    }
+                }
+
+                if ui.collapsing_header("Sync gaps", imgui::TreeNodeFlags::empty()) {
+                    for gap in &analysis.sync_gaps {
+                        ui.text(format!(
+                            "@{:6} {} nibbles, {} bits",
+                            gap.bit_offset, gap.count, gap.bit_len
+                        ));
+                    }
+                }
+
+                if ui.collapsing_header("Weak bits", imgui::TreeNodeFlags::empty()) {
+                    for region in &analysis.weak_regions {
+                        ui.text_colored(
+                            COLOR_WEAK,
+                            format!("@{:6} {} bits", region.bit_offset, region.bit_len),
+                        );
+                    }
+                }
+
+                let (disk_drive, _) = get_disk_drive(cpu, state.drive);
+                if let Some(history) = disk_drive.get_latch_history()
+                    && ui.collapsing_header("Latch history", imgui::TreeNodeFlags::DEFAULT_OPEN)
+                {
+                    let values: Vec<f32> = history.iter().map(|item| item.latch as f32).collect();
+                    ui.plot_lines("##latch_history", &values)
+                        .graph_size([0.0, 80.0])
+                        .scale_min(128.0)
+                        .scale_max(255.0)
+                        .build();
+                    let recent: Vec<String> = history
+                        .iter()
+                        .rev()
+                        .take(16)
+                        .map(|item| format!("{:02X}", item.latch))
+                        .collect();
+                    ui.text(recent.join(" "));
+                }
+            });
+        });
+    state.show = opened;
+}
+
 fn prepare_statusbar(cpu: &CPU, ui: &imgui::Ui, state: &EmulatorState, width: u32, height: u32) {
     const PADDING_X: f32 = 13.0;
     const PADDING_Y: f32 = 2.0;